@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value as Yaml};
+use tau_engine::{Rule, RuleSet};
+
+// NOTE: Only used when the benchmarks feature is enabled...
+#[allow(dead_code)]
+pub fn load_rule(prefix: &str, name: &str) -> Rule {
+    let rule = if name.ends_with(".yml") {
+        name.to_owned()
+    } else {
+        format!("{}.yml", name)
+    };
+    let root = env!("CARGO_MANIFEST_DIR");
+    let path = Path::new(root).join(prefix).join("rules").join(rule);
+    Rule::loader()
+        .shake(false)
+        .load(&path)
+        .expect("invalid rule")
+}
+
+#[allow(dead_code)]
+pub fn load_shaken_rule(prefix: &str, name: &str) -> Rule {
+    let rule = if name.ends_with(".yml") {
+        name.to_owned()
+    } else {
+        format!("{}.yml", name)
+    };
+    let root = env!("CARGO_MANIFEST_DIR");
+    let path = Path::new(root).join(prefix).join("rules").join(rule);
+    Rule::loader()
+        //.coalesce(true)
+        //.rewrite(true)
+        .shake(true)
+        .load(&path)
+        .expect("invalid rule")
+}
+
+pub type Load = fn(&Path) -> Rule;
+
+// The loader configurations that are compared by the corpus benchmark, which must all match the
+// same documents.
+#[allow(dead_code)]
+pub const CONFIGURATIONS: &[(&str, Load)] = &[
+    ("default", |p| {
+        Rule::loader()
+            .range(true)
+            .shake(false)
+            .load(p)
+            .expect("invalid rule")
+    }),
+    ("shaken", |p| {
+        Rule::loader()
+            .range(true)
+            .shake(true)
+            .load(p)
+            .expect("invalid rule")
+    }),
+    ("rewritten", |p| {
+        Rule::loader()
+            .range(true)
+            .shake(true)
+            .rewrite(true)
+            .load(p)
+            .expect("invalid rule")
+    }),
+    ("coalesced", |p| {
+        Rule::loader()
+            .coalesce(true)
+            .range(true)
+            .shake(true)
+            .load(p)
+            .expect("invalid rule")
+    }),
+    ("shadowed", |p| {
+        Rule::loader()
+            .range(true)
+            .shake(true)
+            .shadow(true)
+            .load(p)
+            .expect("invalid rule")
+    }),
+];
+
+#[allow(dead_code)]
+pub fn load_corpus() -> Vec<Mapping> {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let path = Path::new(root).join("benches/corpus/documents.yml");
+    let contents = fs::read_to_string(path).expect("missing corpus, run `cargo xtask corpus`");
+    let documents: Vec<Yaml> = serde_yaml::from_str(&contents).expect("invalid corpus");
+    documents
+        .into_iter()
+        .map(|d| d.as_mapping().expect("invalid document").clone())
+        .collect()
+}
+
+#[allow(dead_code)]
+pub fn load_corpus_rules(load: Load) -> RuleSet {
+    let root = env!("CARGO_MANIFEST_DIR");
+    let mut paths: Vec<PathBuf> = fs::read_dir(Path::new(root).join("benches/corpus/rules"))
+        .expect("missing corpus rules")
+        .map(|e| e.expect("invalid entry").path())
+        .collect();
+    paths.sort();
+    let mut rules = RuleSet::new();
+    for path in paths {
+        let id = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let rule = load(&path);
+        assert!(rule.validate().unwrap(), "rule {} failed validation", id);
+        rules.insert(id, rule);
+    }
+    rules
+}
@@ -0,0 +1,85 @@
+//! Evaluates the synthetic corpus against its rules under each loader configuration.
+//!
+//! Unlike the `solve` benchmarks this runs on stable, so that optimiser changes can be compared
+//! across toolchains with `cargo xtask bench --nightly`. Before timing anything every
+//! configuration is checked to match exactly the same documents, as a faster but wrong optimiser
+//! is not an improvement.
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use tau_engine::{Object, RuleSet};
+
+mod common;
+
+// The minimum time to spend evaluating each configuration.
+const DURATION: Duration = Duration::from_secs(2);
+
+fn matches(rules: &RuleSet, documents: &[serde_yaml::Mapping]) -> Vec<(usize, String)> {
+    let mut matches = vec![];
+    for (i, document) in documents.iter().enumerate() {
+        for id in rules.matches(document) {
+            matches.push((i, id.to_owned()));
+        }
+    }
+    matches
+}
+
+fn report(name: &str, documents: usize, elapsed: Duration) {
+    let rate = documents as f64 / elapsed.as_secs_f64();
+    let per = elapsed.as_nanos() / documents as u128;
+    println!("{:<12} {:>12.0} docs/s {:>10} ns/doc", name, rate, per);
+}
+
+fn main() {
+    let documents = common::load_corpus();
+    println!(
+        "corpus: {} documents, {} configurations",
+        documents.len(),
+        common::CONFIGURATIONS.len() + 1
+    );
+
+    let mut expected = None;
+    for (name, load) in common::CONFIGURATIONS {
+        let rules = common::load_corpus_rules(*load);
+        let found = matches(&rules, &documents);
+        match &expected {
+            None => expected = Some(found),
+            Some(expected) => assert_eq!(
+                expected, &found,
+                "configuration '{}' does not match the same documents",
+                name
+            ),
+        }
+
+        let mut evaluated = 0;
+        let start = Instant::now();
+        while start.elapsed() < DURATION {
+            for document in &documents {
+                black_box(rules.matches(black_box(document)));
+            }
+            evaluated += documents.len();
+        }
+        report(name, evaluated, start.elapsed());
+    }
+
+    // Compiled programs are evaluated through a scan, which compiles the rules once per call.
+    let rules = common::load_corpus_rules(common::CONFIGURATIONS[1].1);
+    let found: Vec<(usize, String)> = rules
+        .scan(documents.iter().map(|d| d as &dyn Object))
+        .map(|(i, id)| (i, id.to_owned()))
+        .collect();
+    assert_eq!(
+        expected.as_ref(),
+        Some(&found),
+        "configuration 'compiled' does not match the same documents"
+    );
+    let mut evaluated = 0;
+    let start = Instant::now();
+    while start.elapsed() < DURATION {
+        for matched in rules.scan(black_box(documents.iter().map(|d| d as &dyn Object))) {
+            black_box(matched);
+        }
+        evaluated += documents.len();
+    }
+    report("compiled", evaluated, start.elapsed());
+}
@@ -8,6 +8,7 @@ mod common;
 #[cfg(feature = "benchmarks")]
 use test::Bencher;
 
+#[cfg(feature = "benchmarks")]
 fn print_rule(rule: &tau_engine::Rule) {
     println!("condition: {}", rule.detection.expression);
     let mut keys = rule
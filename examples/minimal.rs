@@ -0,0 +1,38 @@
+//! A small agent that tags lines read from stdin, used to track the size of a minimal build.
+//!
+//! ```text
+//! cargo run --example minimal --no-default-features --features minimal -- rule.yml < events.log
+//! ```
+use std::env;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process;
+
+use tau_engine::{LineFormat, Rule};
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: minimal <rule>");
+            process::exit(2);
+        }
+    };
+    let rule = match Rule::load(Path::new(&path)) {
+        Ok(rule) => rule,
+        Err(e) => {
+            eprintln!("could not load rule: {}", e);
+            process::exit(1);
+        }
+    };
+    let format = LineFormat::new();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if rule.matches(&format.line(&line)) {
+            println!("{}", line);
+        }
+    }
+}
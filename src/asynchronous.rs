@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::document::Document;
+use crate::engine::RandomState;
+use crate::rule::Detection;
+use crate::ruleset::{collect_fields, has_keywords};
+use crate::solver;
+use crate::value::Value;
+
+/// The future returned by `AsyncDocument::find`.
+pub type FindFuture<'a> = Pin<Box<dyn Future<Output = Option<Value<'a>>> + 'a>>;
+
+/// The future returned by `AsyncDocument::values`.
+pub type ValuesFuture<'a> = Pin<Box<dyn Future<Output = Vec<Value<'a>>> + 'a>>;
+
+/// A `Document` whose values may need to be awaited, i.e. when a field is resolved from an
+/// enrichment cache or a key value store.
+///
+/// The engine does not depend on an async runtime, so the futures can be driven by any of them.
+///
+/// # Implementations
+///
+/// ```
+/// use std::borrow::Cow;
+/// use std::collections::HashMap;
+///
+/// use tau_engine::{AsyncDocument, FindFuture, Value};
+///
+/// struct Remote {
+///     cache: HashMap<String, String>,
+/// }
+///
+/// impl AsyncDocument for Remote {
+///     fn find<'a>(&'a self, key: &'a str) -> FindFuture<'a> {
+///         Box::pin(async move {
+///             // A real implementation would await the store here.
+///             self.cache
+///                 .get(key)
+///                 .map(|v| Value::String(Cow::Borrowed(v.as_str())))
+///         })
+///     }
+/// }
+/// ```
+pub trait AsyncDocument {
+    /// Looks for a `Value` by key and resolves to it if found.
+    fn find<'a>(&'a self, key: &'a str) -> FindFuture<'a>;
+
+    /// Resolves to the values at the top level of the document, see `Document::values`.
+    ///
+    /// This is only awaited for detections that contain keyword searches.
+    ///
+    /// # Keywords
+    ///
+    /// Implementations that do not override this method can't be searched by keyword, meaning that
+    /// every keyword identifier always evaluates to false against them.
+    fn values(&self) -> ValuesFuture<'_> {
+        Box::pin(async { vec![] })
+    }
+}
+
+/// Evalutes an `AsyncDocument` with a provided detection.
+///
+/// Every field that the detection uses is resolved up front, concurrently, and the detection is
+/// then evaluated exactly as it would be by `solve`. This means that a field is resolved even when
+/// evaluation would have short circuited before needing it, trading some extra lookups for only
+/// waiting on the slowest of them once. When the detection contains keyword searches the values of
+/// the document are also resolved, see `AsyncDocument::values`.
+pub async fn solve_async(detection: &Detection, document: &dyn AsyncDocument) -> bool {
+    // NOTE: The fields are found in the order of the set, so it must not be seeded randomly when
+    // deterministic.
+    let mut fields = HashSet::with_hasher(RandomState::default());
+    collect_fields(&detection.expression, &detection.identifiers, &mut fields);
+    let fields: Vec<String> = fields.into_iter().collect();
+    let values = Join {
+        futures: fields.iter().map(|f| Some(document.find(f))).collect(),
+        values: vec![None; fields.len()],
+    }
+    .await;
+    let keywords = if has_keywords(detection) {
+        document.values().await
+    } else {
+        vec![]
+    };
+    let resolved = Resolved {
+        fields: fields.iter().map(String::as_str).zip(values).collect(),
+        keywords,
+    };
+    solver::solve(detection, &resolved)
+}
+
+// The resolved values of an `AsyncDocument`, which can be evaluated like any other document.
+struct Resolved<'a> {
+    fields: HashMap<&'a str, Option<Value<'a>>>,
+    keywords: Vec<Value<'a>>,
+}
+
+impl Document for Resolved<'_> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        self.fields.get(key).cloned().flatten()
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.keywords.clone()
+    }
+}
+
+// Polls each of the futures until they have all resolved.
+struct Join<'a> {
+    futures: Vec<Option<FindFuture<'a>>>,
+    values: Vec<Option<Value<'a>>>,
+}
+
+impl<'a> Future for Join<'a> {
+    type Output = Vec<Option<Value<'a>>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut pending = false;
+        for (future, value) in this.futures.iter_mut().zip(this.values.iter_mut()) {
+            if let Some(f) = future {
+                match f.as_mut().poll(cx) {
+                    Poll::Ready(v) => {
+                        *value = v;
+                        *future = None;
+                    }
+                    Poll::Pending => pending = true,
+                }
+            }
+        }
+        if pending {
+            Poll::Pending
+        } else {
+            Poll::Ready(std::mem::take(&mut this.values))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::borrow::Cow;
+    use std::cell::Cell;
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+
+    use crate::rule::Rule;
+
+    // Drives a future to completion by polling it in a loop, which is enough for futures that
+    // never wait on anything external.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let waker = unsafe { Waker::from_raw(raw()) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    // Yields once before resolving, like a lookup that has to wait on a store.
+    struct Yield(bool);
+
+    impl Future for Yield {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                Poll::Pending
+            }
+        }
+    }
+
+    struct Remote {
+        lookups: Cell<usize>,
+        values: HashMap<String, String>,
+    }
+
+    impl AsyncDocument for Remote {
+        fn find<'a>(&'a self, key: &'a str) -> FindFuture<'a> {
+            Box::pin(async move {
+                Yield(false).await;
+                self.lookups.set(self.lookups.get() + 1);
+                self.values
+                    .get(key)
+                    .map(|v| Value::String(Cow::Borrowed(v.as_str())))
+            })
+        }
+
+        fn values(&self) -> ValuesFuture<'_> {
+            Box::pin(async move {
+                Yield(false).await;
+                self.values
+                    .values()
+                    .map(|v| Value::String(Cow::Borrowed(v.as_str())))
+                    .collect()
+            })
+        }
+    }
+
+    #[test]
+    fn solve_async() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                user: admin
+              B:
+                host: '*.internal'
+              condition: A and not B
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let remote = |values: &[(&str, &str)]| Remote {
+            lookups: Cell::new(0),
+            values: values
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+
+        let document = remote(&[("user", "admin"), ("host", "www.example.com")]);
+        assert!(block_on(super::solve_async(&rule.detection, &document)));
+        assert_eq!(document.lookups.get(), 2);
+
+        let document = remote(&[("user", "admin"), ("host", "db.internal")]);
+        assert!(!block_on(rule.matches_async(&document)));
+
+        let document = remote(&[("host", "www.example.com")]);
+        assert!(!block_on(rule.matches_async(&document)));
+    }
+
+    #[test]
+    fn solve_async_keywords() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                - mimikatz
+              B:
+                user: admin
+              condition: A and B
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let document = Remote {
+            lookups: Cell::new(0),
+            values: vec![("user", "admin"), ("command", "run mimikatz.exe")]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        assert!(block_on(rule.matches_async(&document)));
+
+        struct Keyless;
+        impl AsyncDocument for Keyless {
+            fn find<'a>(&'a self, _: &'a str) -> FindFuture<'a> {
+                Box::pin(async { Some(Value::String(Cow::Borrowed("admin mimikatz"))) })
+            }
+        }
+        assert!(!block_on(rule.matches_async(&Keyless)));
+    }
+
+    #[cfg(feature = "deterministic")]
+    #[test]
+    fn solve_async_order() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                a: 1
+                b: 2
+                c: 3
+                d: 4
+                e: 5
+                f: 6
+                g: 7
+                h: 8
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        struct Recorder(std::cell::RefCell<Vec<String>>);
+        impl AsyncDocument for Recorder {
+            fn find<'a>(&'a self, key: &'a str) -> FindFuture<'a> {
+                self.0.borrow_mut().push(key.to_owned());
+                Box::pin(async { None })
+            }
+        }
+        let order = || {
+            let document = Recorder(Default::default());
+            block_on(rule.matches_async(&document));
+            document.0.into_inner()
+        };
+        let first = order();
+        for _ in 0..8 {
+            assert_eq!(order(), first);
+        }
+    }
+}
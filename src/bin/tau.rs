@@ -0,0 +1,85 @@
+//! A small command line for rule authors, which is built with the `cli` feature.
+//!
+//! ```text
+//! cargo install tau-engine --features cli
+//! tau new process-creation rules/encoded_powershell.yml
+//! tau convert sigma/rules rules --field Image=process.path
+//! ```
+//!
+//! When no path is given to `new` the scaffold is written to stdout, while `convert` writes the
+//! converted rules into the destination directory and prints its report to stdout.
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use tau_engine::{Rule, SigmaConverter, Template};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("new") if args.len() == 2 || args.len() == 3 => new(&args[1], args.get(2)),
+        Some("convert") if args.len() >= 3 => convert(&args[1], &args[2], &args[3..]),
+        _ => Err(format!(
+            "usage: tau new <{}> [path]\n       tau convert <sigma> <tau> [--field <from>=<to>]...",
+            Template::all()
+                .iter()
+                .map(|t| t.name())
+                .collect::<Vec<_>>()
+                .join("|")
+        )),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(2);
+    }
+}
+
+fn new(kind: &str, path: Option<&String>) -> Result<(), String> {
+    let template: Template = kind.parse().map_err(|e| format!("{}", e))?;
+    let scaffold = Rule::template(template);
+    match path {
+        // NOTE: Existing rules are never overwritten.
+        Some(path) => OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut f| f.write_all(scaffold.as_bytes()))
+            .map_err(|e| format!("could not create {}: {}", path, e)),
+        None => {
+            print!("{}", scaffold);
+            Ok(())
+        }
+    }
+}
+
+fn convert(src: &str, dst: &str, flags: &[String]) -> Result<(), String> {
+    let mut converter = SigmaConverter::new();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let mapping = match (flag.as_str(), flags.next()) {
+            ("--field", Some(mapping)) => mapping,
+            _ => return Err(format!("unexpected argument: {}", flag)),
+        };
+        match mapping.split_once('=') {
+            Some((from, to)) => converter = converter.field(from, to),
+            None => return Err(format!("invalid field mapping: {}", mapping)),
+        }
+    }
+    let report = converter
+        .convert_dir(Path::new(src), Path::new(dst))
+        .map_err(|e| format!("could not convert {}: {}", src, e))?;
+    for path in &report.converted {
+        println!("converted {}", path.display());
+    }
+    for (path, reason) in &report.skipped {
+        println!("skipped {} - {}", path.display(), reason);
+    }
+    for (path, losses) in &report.losses {
+        for loss in losses {
+            println!("lossy {} {}", path.display(), loss);
+        }
+    }
+    Ok(())
+}
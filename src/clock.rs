@@ -0,0 +1,32 @@
+// A monotonic clock for timing evaluation.
+//
+// `std::time::Instant` panics on `wasm32-unknown-unknown` as there is no time source without a
+// host, so on that target timings are recorded as zero rather than taking down the caller. The
+// same is done for `deterministic` builds, which must never read the clock.
+
+#[cfg(not(any(
+    feature = "deterministic",
+    all(target_arch = "wasm32", target_os = "unknown")
+)))]
+pub(crate) use std::time::Instant;
+
+#[cfg(any(
+    feature = "deterministic",
+    all(target_arch = "wasm32", target_os = "unknown")
+))]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Instant;
+
+#[cfg(any(
+    feature = "deterministic",
+    all(target_arch = "wasm32", target_os = "unknown")
+))]
+impl Instant {
+    pub(crate) fn now() -> Self {
+        Instant
+    }
+
+    pub(crate) fn elapsed(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
@@ -0,0 +1,637 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::diagnostic::Diagnostic;
+use crate::document::Document;
+use crate::engine::RandomState;
+use crate::parser::Expression;
+use crate::rule::{Detection, Rule};
+use crate::solver::{self, SolverResult};
+
+/// The comparison made against the number of matches within a window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// The count must equal the value.
+    Equal(u64),
+    /// The count must be greater than the value.
+    GreaterThan(u64),
+    /// The count must be greater than or equal to the value.
+    GreaterThanOrEqual(u64),
+    /// The count must be less than the value.
+    LessThan(u64),
+    /// The count must be less than or equal to the value.
+    LessThanOrEqual(u64),
+}
+
+impl Threshold {
+    fn exceeded(&self, count: u64) -> bool {
+        match *self {
+            Self::Equal(n) => count == n,
+            Self::GreaterThan(n) => count > n,
+            Self::GreaterThanOrEqual(n) => count >= n,
+            Self::LessThan(n) => count < n,
+            Self::LessThanOrEqual(n) => count <= n,
+        }
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Equal(n) => write!(f, "== {}", n),
+            Self::GreaterThan(n) => write!(f, "> {}", n),
+            Self::GreaterThanOrEqual(n) => write!(f, ">= {}", n),
+            Self::LessThan(n) => write!(f, "< {}", n),
+            Self::LessThanOrEqual(n) => write!(f, "<= {}", n),
+        }
+    }
+}
+
+/// A correlation over the matches of a rule.
+///
+/// Correlations are declared on a rule with the `correlate` key, in the form
+/// `count() [by <field>[, <field>...]] <op> <n> within <duration>`, where the operator is one of
+/// `==`, `>`, `>=`, `<` and `<=`, and the duration is a whole number followed by one of `ms`, `s`,
+/// `m`, `h` or `d`. For example, `count() by host.name > 5 within 10m` will only match once more
+/// than 5 documents from the same host have matched the detection within 10 minutes.
+///
+/// As correlations depend on the documents that came before, they are solved using a
+/// `Correlator` which holds the state of the windows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correlation {
+    /// The fields that the matches are grouped by.
+    pub by: Vec<String>,
+    /// The comparison made against the number of matches within the window.
+    pub threshold: Threshold,
+    /// The length of the sliding window.
+    pub within: Duration,
+}
+
+impl Correlation {
+    /// Parses a correlation from its string form.
+    pub fn parse(s: &str) -> crate::Result<Self> {
+        let invalid = || {
+            crate::error::rule_invalid(
+                Diagnostic::new("rule.invalid_correlation").with("correlation", s),
+            )
+        };
+        let rest = s.trim().strip_prefix("count()").ok_or_else(invalid)?;
+        let (rest, within) = rest.rsplit_once(" within ").ok_or_else(invalid)?;
+        let within = duration(within.trim()).ok_or_else(invalid)?;
+        let rest = rest.trim();
+        let i = rest.find(['<', '>', '=']).ok_or_else(invalid)?;
+        let operator = if rest[i + 1..].starts_with('=') {
+            &rest[i..i + 2]
+        } else {
+            &rest[i..i + 1]
+        };
+        let count = rest[i + operator.len()..]
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| invalid())?;
+        let threshold = match operator {
+            "==" => Threshold::Equal(count),
+            ">" => Threshold::GreaterThan(count),
+            ">=" => Threshold::GreaterThanOrEqual(count),
+            "<" => Threshold::LessThan(count),
+            "<=" => Threshold::LessThanOrEqual(count),
+            _ => return Err(invalid()),
+        };
+        let rest = rest[..i].trim();
+        let by = if rest.is_empty() {
+            vec![]
+        } else {
+            let fields = rest.strip_prefix("by ").ok_or_else(invalid)?;
+            let fields: Vec<String> = fields.split(',').map(|f| f.trim().to_owned()).collect();
+            if fields.iter().any(|f| f.is_empty() || f.contains(' ')) {
+                return Err(invalid());
+            }
+            fields
+        };
+        Ok(Self {
+            by,
+            threshold,
+            within,
+        })
+    }
+}
+
+impl fmt::Display for Correlation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "count()")?;
+        if !self.by.is_empty() {
+            write!(f, " by {}", self.by.join(", "))?;
+        }
+        write!(
+            f,
+            " {} within {}",
+            self.threshold,
+            format_duration(self.within)
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Correlation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Correlation::parse(&s).map_err(|e| de::Error::custom(format!("{:?}", e)))
+    }
+}
+
+impl Serialize for Correlation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// An ordered sequence of matches for a rule.
+///
+/// Sequences are declared on a rule with the `sequence` key, naming the identifiers in the
+/// detection that must match, in order, within the window. Matches can be grouped by fields, in
+/// which case every step must be matched by documents with the same values for those fields. A
+/// document must also match the rule's condition before it is considered for any of the steps.
+///
+/// ```text
+/// detection:
+///   create:
+///     event: process_create
+///   connect:
+///     event: network_connect
+///   condition: create or connect
+///
+/// sequence:
+///   steps: [create, connect]
+///   by: [pid]
+///   within: 5m
+/// ```
+///
+/// As with correlations, sequences are solved using a `Correlator`, and the rule only matches on
+/// the document that completes the sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence {
+    /// The fields that the matches are grouped by.
+    pub by: Vec<String>,
+    /// The identifiers that must match, in order.
+    pub steps: Vec<String>,
+    /// The maximum time between the first and last step.
+    pub within: Duration,
+
+    // The expressions for the steps, resolved when the rule is loaded.
+    expressions: Vec<Expression>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct RawSequence {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    by: Vec<String>,
+    steps: Vec<String>,
+    within: String,
+}
+
+impl Sequence {
+    // Resolves the steps against the detection's identifiers.
+    pub(crate) fn resolve(
+        &mut self,
+        identifiers: &HashMap<String, Expression>,
+    ) -> crate::Result<()> {
+        self.expressions = self
+            .steps
+            .iter()
+            .map(|s| {
+                identifiers.get(s).cloned().ok_or_else(|| {
+                    crate::error::rule_invalid(
+                        Diagnostic::new("rule.sequence_step_not_identifier").with("step", s),
+                    )
+                })
+            })
+            .collect::<crate::Result<Vec<_>>>()?;
+        Ok(())
+    }
+
+    pub(crate) fn map<F>(&mut self, f: F)
+    where
+        F: Fn(Expression) -> Expression,
+    {
+        self.expressions = std::mem::take(&mut self.expressions)
+            .into_iter()
+            .map(f)
+            .collect();
+    }
+
+    fn step<'a>(&'a self, i: usize, detection: &'a Detection) -> Option<&'a Expression> {
+        self.expressions
+            .get(i)
+            .or_else(|| detection.identifiers.get(&self.steps[i]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Sequence {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = RawSequence::deserialize(deserializer)?;
+        if raw.steps.len() < 2 {
+            return Err(de::Error::custom("a sequence must have at least two steps"));
+        }
+        let within = duration(raw.within.trim()).ok_or_else(|| {
+            de::Error::custom(format!("invalid sequence window - {}", raw.within))
+        })?;
+        Ok(Self {
+            by: raw.by,
+            steps: raw.steps,
+            within,
+            expressions: vec![],
+        })
+    }
+}
+
+impl Serialize for Sequence {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        RawSequence {
+            by: self.by.clone(),
+            steps: self.steps.clone(),
+            within: format_duration(self.within),
+        }
+        .serialize(serializer)
+    }
+}
+
+fn duration(s: &str) -> Option<Duration> {
+    let i = s.find(|c: char| !c.is_ascii_digit())?;
+    let n = s[..i].parse::<u64>().ok()?;
+    let ms = match &s[i..] {
+        "ms" => 1,
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return None,
+    };
+    Some(Duration::from_millis(n.checked_mul(ms)?))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let ms = duration.as_millis();
+    [
+        (86_400_000, "d"),
+        (3_600_000, "h"),
+        (60_000, "m"),
+        (1_000, "s"),
+    ]
+    .iter()
+    .find(|(n, _)| ms % *n == 0)
+    .map(|(n, u)| format!("{}{}", ms / n, u))
+    .unwrap_or_else(|| format!("{}ms", ms))
+}
+
+// State keyed by rule id and then by the values of the fields that the rule groups by.
+type Keyed<T> = HashMap<String, HashMap<Vec<Option<String>>, T, RandomState>, RandomState>;
+
+/// Holds the state needed to solve correlation rules across successive documents.
+///
+/// The state is keyed by rule id and then by the values of the fields that the rule groups by, with
+/// each key holding the times of the matches within its window. Documents must be provided in time
+/// order, or close to it, as matches are evicted once they fall out of the window of the latest
+/// match for their key.
+///
+/// # Example
+///
+/// ```
+/// use std::time::{Duration, SystemTime};
+///
+/// use tau_engine::{Correlator, Rule};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     event: logon_failure
+///   condition: A
+/// correlate: count() by host > 2 within 1m
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let event: Yaml = serde_yaml::from_str("{event: logon_failure, host: foo}").unwrap();
+/// let event = event.as_mapping().unwrap();
+///
+/// let mut correlator = Correlator::new();
+/// let start = SystemTime::UNIX_EPOCH;
+/// assert!(!correlator.solve("brute", &rule, event, start));
+/// assert!(!correlator.solve("brute", &rule, event, start + Duration::from_secs(10)));
+/// assert!(correlator.solve("brute", &rule, event, start + Duration::from_secs(20)));
+/// assert!(!correlator.solve("brute", &rule, event, start + Duration::from_secs(75)));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Correlator {
+    // The start of the latest partial sequence to have reached each step.
+    sequences: Keyed<Vec<Option<SystemTime>>>,
+    windows: Keyed<VecDeque<SystemTime>>,
+}
+
+impl Correlator {
+    /// Create a correlator with no state.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluates the rule against the provided `Document` at the given time, returning true if it
+    /// has matched.
+    ///
+    /// Rules without a correlation or sequence are solved as normal and do not hold any state.
+    pub fn solve(
+        &mut self,
+        id: &str,
+        rule: &Rule,
+        document: &dyn Document,
+        timestamp: SystemTime,
+    ) -> bool {
+        if let Some(sequence) = &rule.sequence {
+            return rule.matches(document) && self.advance(id, rule, sequence, document, timestamp);
+        }
+        let correlation = match &rule.correlate {
+            Some(correlation) => correlation,
+            None => return rule.matches(document),
+        };
+        if !rule.matches(document) {
+            return false;
+        }
+        let key = key(&correlation.by, document);
+        let window = self
+            .windows
+            .entry(id.to_owned())
+            .or_default()
+            .entry(key)
+            .or_default();
+        let i = window.partition_point(|t| *t <= timestamp);
+        window.insert(i, timestamp);
+        if let Some(latest) = window.back().copied() {
+            while window
+                .front()
+                .map(|t| *t + correlation.within <= latest)
+                .unwrap_or(false)
+            {
+                window.pop_front();
+            }
+        }
+        let count = window
+            .iter()
+            .filter(|t| **t <= timestamp && **t + correlation.within > timestamp)
+            .count();
+        correlation.threshold.exceeded(count as u64)
+    }
+
+    // Advances the partial sequences for the document's key, returning true if one completed.
+    fn advance(
+        &mut self,
+        id: &str,
+        rule: &Rule,
+        sequence: &Sequence,
+        document: &dyn Document,
+        timestamp: SystemTime,
+    ) -> bool {
+        let key = key(&sequence.by, document);
+        let starts = self
+            .sequences
+            .entry(id.to_owned())
+            .or_default()
+            .entry(key)
+            .or_insert_with(|| vec![None; sequence.steps.len() - 1]);
+        for start in starts.iter_mut() {
+            if start
+                .map(|s| s + sequence.within < timestamp)
+                .unwrap_or(false)
+            {
+                *start = None;
+            }
+        }
+        let identifiers = HashMap::new();
+        let last = sequence.steps.len() - 1;
+        let mut completed = false;
+        // NOTE: Steps are checked in reverse so that a single document can not satisfy more than
+        // one step of the same partial sequence.
+        for i in (0..=last).rev() {
+            let matched = sequence
+                .step(i, &rule.detection)
+                .map(|e| solver::solve_expression(e, &identifiers, document) == SolverResult::True)
+                .unwrap_or(false);
+            if !matched {
+                continue;
+            }
+            if i == 0 {
+                starts[0] = Some(timestamp);
+            } else if let Some(start) = starts[i - 1].take() {
+                if i == last {
+                    completed = true;
+                } else {
+                    starts[i] = starts[i].max(Some(start));
+                }
+            }
+        }
+        completed
+    }
+
+    /// Drops the state of any windows and sequences that have not seen a match within the given
+    /// period.
+    pub fn expire(&mut self, now: SystemTime, period: Duration) {
+        for windows in self.windows.values_mut() {
+            windows.retain(|_, w| w.back().map(|t| *t + period > now).unwrap_or(false));
+        }
+        self.windows.retain(|_, w| !w.is_empty());
+        for sequences in self.sequences.values_mut() {
+            sequences.retain(|_, s| s.iter().flatten().any(|t| *t + period > now));
+        }
+        self.sequences.retain(|_, s| !s.is_empty());
+    }
+
+    /// Clears all state held for the rule.
+    pub fn reset(&mut self, id: &str) {
+        self.sequences.remove(id);
+        self.windows.remove(id);
+    }
+}
+
+fn key(fields: &[String], document: &dyn Document) -> Vec<Option<String>> {
+    fields
+        .iter()
+        .map(|f| document.find(f).and_then(|v| v.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    #[test]
+    fn parse_correlations() {
+        let correlation = Correlation::parse("count() by host.name, user > 5 within 10m").unwrap();
+        assert_eq!(
+            correlation,
+            Correlation {
+                by: vec!["host.name".to_owned(), "user".to_owned()],
+                threshold: Threshold::GreaterThan(5),
+                within: Duration::from_secs(600),
+            }
+        );
+        assert_eq!(
+            correlation.to_string(),
+            "count() by host.name, user > 5 within 10m"
+        );
+        let correlation = Correlation::parse("count() >= 2 within 1500ms").unwrap();
+        assert_eq!(correlation.by, Vec::<String>::new());
+        assert_eq!(correlation.threshold, Threshold::GreaterThanOrEqual(2));
+        assert_eq!(correlation.to_string(), "count() >= 2 within 1500ms");
+        assert_eq!(
+            Correlation::parse("count() by a<=1 within 1h")
+                .unwrap()
+                .threshold,
+            Threshold::LessThanOrEqual(1)
+        );
+
+        for invalid in [
+            "count() > 5",
+            "count() by > 5 within 1m",
+            "count() by a b > 5 within 1m",
+            "count() by a > x within 1m",
+            "count() > 5 within 1y",
+            "sum(a) > 5 within 1m",
+        ] {
+            assert!(Correlation::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sequence() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              create:
+                event: process_create
+              connect:
+                event: network_connect
+              exit:
+                event: process_exit
+              condition: create or connect or exit
+            sequence:
+              steps: [create, connect, exit]
+              by: [pid]
+              within: 1m
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let document = |event: &str, pid: u64| {
+            serde_yaml::from_str::<Yaml>(&format!("{{event: {}, pid: {}}}", event, pid)).unwrap()
+        };
+        let at = |s: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+        let mut correlator = Correlator::new();
+        let mut solve = |event: &str, pid: u64, s: u64| {
+            let document = document(event, pid);
+            correlator.solve("a", &rule, document.as_mapping().unwrap(), at(s))
+        };
+
+        // Out of order steps do not progress the sequence.
+        assert!(!solve("network_connect", 1, 0));
+        assert!(!solve("process_create", 1, 1));
+        assert!(!solve("process_exit", 1, 2));
+        // Steps must share the same key.
+        assert!(!solve("network_connect", 2, 3));
+        assert!(!solve("network_connect", 1, 4));
+        assert!(solve("process_exit", 1, 5));
+        // Completed sequences are consumed.
+        assert!(!solve("process_exit", 1, 6));
+        // Sequences must complete within the window.
+        assert!(!solve("process_create", 3, 10));
+        assert!(!solve("network_connect", 3, 20));
+        assert!(!solve("process_exit", 3, 71));
+        // The latest start is kept.
+        assert!(!solve("process_create", 4, 100));
+        assert!(!solve("process_create", 4, 150));
+        assert!(!solve("network_connect", 4, 170));
+        assert!(solve("process_exit", 4, 205));
+    }
+
+    #[test]
+    fn sequence_invalid() {
+        let rule = |sequence: &str| {
+            Rule::from_str(&format!(
+                r#"
+                detection:
+                  A:
+                    foo: bar
+                  B:
+                    foo: baz
+                  condition: A or B
+                {}
+                true_positives: []
+                true_negatives: []
+                "#,
+                sequence
+            ))
+        };
+        assert!(rule("sequence: {steps: [A, B], within: 1m}").is_ok());
+        assert!(rule("sequence: {steps: [A, C], within: 1m}").is_err());
+        assert!(rule("sequence: {steps: [A], within: 1m}").is_err());
+        assert!(rule("sequence: {steps: [A, B], within: 1}").is_err());
+        assert!(rule(
+            "sequence: {steps: [A, B], within: 1m}\n                correlate: count() > 1 within 1m"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn solve() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                event: beacon
+              condition: A
+            correlate: count() by host > 2 within 1m
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let document = |s: &str| serde_yaml::from_str::<Yaml>(s).unwrap();
+        let foo = document("{event: beacon, host: foo}");
+        let bar = document("{event: beacon, host: bar}");
+        let other = document("{event: other, host: foo}");
+        let at = |s: u64| SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+
+        let mut correlator = Correlator::new();
+        assert!(!correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(0)));
+        assert!(!correlator.solve("a", &rule, bar.as_mapping().unwrap(), at(1)));
+        assert!(!correlator.solve("a", &rule, other.as_mapping().unwrap(), at(2)));
+        assert!(!correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(3)));
+        assert!(!correlator.solve("b", &rule, foo.as_mapping().unwrap(), at(4)));
+        assert!(correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(5)));
+        assert!(correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(59)));
+        assert!(!correlator.solve("a", &rule, bar.as_mapping().unwrap(), at(59)));
+        assert!(correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(62)));
+        assert!(!correlator.solve("a", &rule, foo.as_mapping().unwrap(), at(200)));
+
+        correlator.expire(at(250), Duration::from_secs(60));
+        assert_eq!(correlator.windows.len(), 1);
+        assert_eq!(correlator.windows["a"].len(), 1);
+        correlator.reset("a");
+        assert!(correlator.windows.is_empty());
+    }
+}
@@ -0,0 +1,628 @@
+#[cfg(not(feature = "std"))]
+use core::error::Error as StdError;
+use core::fmt;
+use core::iter::FromIterator;
+#[cfg(feature = "std")]
+use std::error::Error as StdError;
+
+use crate::collections::HashMap;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// A structured diagnostic, raised when a rule or condition fails to load.
+///
+/// Rather than a formatted string, a diagnostic is a stable code, such as
+/// `condition.unexpected_token`, along with the named parameters that describe the problem. This
+/// allows products that embed the engine to render rule-authoring feedback in the language of
+/// their users through a `Catalogue`, while the `Display` implementation always renders English.
+///
+/// A diagnostic can also be made up of related diagnostics, i.e. when validation fails it has one
+/// for each of the checks that failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    code: &'static str,
+    params: Vec<(&'static str, String)>,
+    position: Option<Position>,
+    related: Vec<Diagnostic>,
+}
+
+/// A position in the text of a condition, where both the line and the column start from one.
+///
+/// The column counts characters rather than bytes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// The line.
+    pub line: usize,
+    /// The column within the line.
+    pub column: usize,
+}
+
+impl Diagnostic {
+    pub(crate) fn new(code: &'static str) -> Self {
+        Self {
+            code,
+            params: vec![],
+            position: None,
+            related: vec![],
+        }
+    }
+
+    // NOTE: The innermost position wins, as that is where the problem was found.
+    pub(crate) fn at(mut self, position: Position) -> Self {
+        self.position.get_or_insert(position);
+        self
+    }
+
+    pub(crate) fn with<V: ToString>(mut self, name: &'static str, value: V) -> Self {
+        self.params.push((name, value.to_string()));
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn related(mut self, related: Vec<Diagnostic>) -> Self {
+        self.related = related;
+        self
+    }
+
+    /// Returns the code of this diagnostic.
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Returns the named parameters of this diagnostic, in the order they were given.
+    pub fn params(&self) -> &[(&'static str, String)] {
+        &self.params
+    }
+
+    /// Returns the value of the named parameter, if present.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the position in the condition that this diagnostic refers to, if any.
+    pub fn position(&self) -> Option<Position> {
+        self.position
+    }
+
+    /// Returns the diagnostics that make up this one.
+    pub fn causes(&self) -> &[Diagnostic] {
+        &self.related
+    }
+
+    /// Returns the English message for this diagnostic.
+    pub fn message(&self) -> String {
+        self.localise(&English)
+    }
+
+    /// Returns the message for this diagnostic using the provided catalogue, falling back to
+    /// English for any codes that it does not cover.
+    pub fn localise(&self, catalogue: &dyn Catalogue) -> String {
+        let message = catalogue
+            .message(self)
+            .or_else(|| English.message(self))
+            .unwrap_or_else(|| self.code.to_owned());
+        let message = match self.position {
+            Some(position) => Diagnostic::new("condition.position")
+                .with("message", message)
+                .with("line", position.line)
+                .with("column", position.column)
+                .localise(catalogue),
+            None => message,
+        };
+        if self.related.is_empty() {
+            return message;
+        }
+        // NOTE: An empty message means that the diagnostic is only a container for its causes.
+        let mut parts = vec![];
+        if !message.is_empty() {
+            parts.push(message);
+        }
+        parts.extend(self.related.iter().map(|d| d.localise(catalogue)));
+        parts.join(";")
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message())
+    }
+}
+
+impl StdError for Diagnostic {}
+
+#[cfg(all(feature = "regex", not(feature = "std")))]
+impl From<regex::Error> for Diagnostic {
+    fn from(error: regex::Error) -> Self {
+        Diagnostic::new("identifier.invalid_regex").with("error", error)
+    }
+}
+
+/// A source of human-readable messages for diagnostics.
+///
+/// Implementations return `None` for the codes that they do not cover, in which case the English
+/// message is used.
+pub trait Catalogue {
+    /// Returns the message for the provided diagnostic.
+    fn message(&self, diagnostic: &Diagnostic) -> Option<String>;
+}
+
+/// The built-in English catalogue.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct English;
+
+impl English {
+    /// Returns the codes and templates of every diagnostic, which can be used as the starting
+    /// point for a translation.
+    pub fn templates() -> &'static [(&'static str, &'static str)] {
+        TEMPLATES
+    }
+}
+
+impl Catalogue for English {
+    fn message(&self, diagnostic: &Diagnostic) -> Option<String> {
+        TEMPLATES
+            .binary_search_by(|(code, _)| code.cmp(&diagnostic.code))
+            .ok()
+            .map(|i| render(TEMPLATES[i].1, diagnostic))
+    }
+}
+
+/// A catalogue of message templates keyed by diagnostic code.
+///
+/// Templates refer to parameters by name in braces, i.e. `unknown lookup - {name}`, with `{{`
+/// and `}}` used for literal braces. This allows catalogues to be loaded from translation files.
+#[derive(Clone, Debug, Default)]
+pub struct Messages {
+    templates: HashMap<String, String>,
+}
+
+impl Messages {
+    /// Creates an empty catalogue.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the template for the provided code.
+    pub fn message<C: Into<String>, T: Into<String>>(mut self, code: C, template: T) -> Self {
+        self.templates.insert(code.into(), template.into());
+        self
+    }
+}
+
+impl Catalogue for Messages {
+    fn message(&self, diagnostic: &Diagnostic) -> Option<String> {
+        self.templates
+            .get(diagnostic.code)
+            .map(|t| render(t, diagnostic))
+    }
+}
+
+impl<C, T> FromIterator<(C, T)> for Messages
+where
+    C: Into<String>,
+    T: Into<String>,
+{
+    fn from_iter<I: IntoIterator<Item = (C, T)>>(iter: I) -> Self {
+        Self {
+            templates: iter
+                .into_iter()
+                .map(|(c, t)| (c.into(), t.into()))
+                .collect(),
+        }
+    }
+}
+
+// Renders a template, leaving any unknown parameters as they are.
+fn render(template: &str, diagnostic: &Diagnostic) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            rendered.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+        if tail.starts_with('{') {
+            if let Some(end) = tail.find('}') {
+                let name = &tail[1..end];
+                match diagnostic.param(name) {
+                    Some(value) => rendered.push_str(value),
+                    None => rendered.push_str(&tail[..=end]),
+                }
+                rest = &tail[end + 1..];
+                continue;
+            }
+        }
+        rendered.push_str(&tail[..1]);
+        rest = &tail[1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+// NOTE: This must be kept sorted by code, as it is binary searched.
+const TEMPLATES: &[(&str, &str)] = &[
+    (
+        "condition.counted_keywords",
+        "invalid condition: keywords cannot be counted - {identifier}",
+    ),
+    (
+        "condition.expected_column",
+        "NUD expected column identifier",
+    ),
+    ("condition.expected_comma", "NUD expected comma - '{token}'"),
+    ("condition.expected_infix", "LED expected token"),
+    (
+        "condition.expected_integer",
+        "NUD expected integer - '{token}'",
+    ),
+    (
+        "condition.expected_left_parenthesis",
+        "NUD expected left parenthesis - '{token}'",
+    ),
+    (
+        "condition.expected_negatable",
+        "NUD expected a negatable expression",
+    ),
+    (
+        "condition.expected_positive_integer",
+        "NUD expected positive integer - '{token}'",
+    ),
+    ("condition.expected_prefix", "NUD expected token"),
+    (
+        "condition.expected_right_parenthesis",
+        "NUD expected right parenthesis - '{token}'",
+    ),
+    ("condition.missing_comma", "NUD expected comma"),
+    ("condition.missing_integer", "NUD expected integer"),
+    (
+        "condition.missing_left_parenthesis",
+        "NUD expected left parenthesis",
+    ),
+    (
+        "condition.missing_right_parenthesis",
+        "NUD expected right parenthesis",
+    ),
+    (
+        "condition.not_solvable",
+        "invalid value: condition, not solveable - {expression}",
+    ),
+    (
+        "condition.position",
+        "{message} at line {line}, column {column}",
+    ),
+    (
+        "condition.trailing_tokens",
+        "failed to parse the following tokens - '{tokens}'",
+    ),
+    ("condition.unexpected_infix", "LED encountered - '{token}'"),
+    ("condition.unexpected_prefix", "NUD encountered - '{token}'"),
+    ("condition.unexpected_token", "encountered - '{token}'"),
+    (
+        "condition.unknown_identifier",
+        "invalid condition: identifier not found - {identifier}",
+    ),
+    (
+        "grok.nested_too_deeply",
+        "grok patterns are nested too deeply - {pattern}",
+    ),
+    ("grok.unknown_pattern", "unknown grok pattern - {pattern}"),
+    (
+        "grok.unterminated_reference",
+        "unterminated grok reference - {reference}",
+    ),
+    (
+        "harness.invalid_document",
+        "line {line} is not a valid JSON document - {error}",
+    ),
+    (
+        "identifier.cast_requires_integers",
+        "when casting to {cast} all expressions must be of type int",
+    ),
+    (
+        "identifier.cast_requires_strings",
+        "when casting to str all expressions must be of type str",
+    ),
+    ("identifier.empty_mapping", "failed to parse mapping"),
+    (
+        "identifier.empty_sequence",
+        "expected a non empty sequence of mappings, encountered - {value}",
+    ),
+    (
+        "identifier.every_not_numeric",
+        "every requires a numeric comparison, encountered - {key}",
+    ),
+    (
+        "identifier.expected_mapping_or_sequence",
+        "expected mapping or sequence, encountered - {value}",
+    ),
+    (
+        "identifier.expected_mapping_or_string",
+        "value must be a mapping or string, encountered - {key}",
+    ),
+    (
+        "identifier.expected_sequence_of_mappings",
+        "expected a sequence of mappings, encountered - {value}",
+    ),
+    (
+        "identifier.expected_sequence_of_strings",
+        "expected a sequence of keywords, encountered - {value}",
+    ),
+    (
+        "identifier.float_to_integer",
+        "float cannot be cast into an integer, encountered - {key}",
+    ),
+    (
+        "identifier.fuzzy_distance_too_large",
+        "fuzzy distance can be at most {limit}, encountered - {distance}",
+    ),
+    (
+        "identifier.fuzzy_without_value",
+        "fuzzy match requires a value",
+    ),
+    (
+        "identifier.integer_to_string",
+        "cannot cast integer to string, encountered - {key}",
+    ),
+    (
+        "identifier.invalid_key",
+        "mapping key must be a string, encountered - {key}",
+    ),
+    (
+        "identifier.invalid_keyword",
+        "keywords must be literal strings, encountered - {keyword}",
+    ),
+    (
+        "identifier.invalid_match_key",
+        "mapping key must be a string or valid match condition, encountered - {key}",
+    ),
+    (
+        "identifier.invalid_number",
+        "number must be a signed integer or float, encountered - {key}",
+    ),
+    ("identifier.invalid_regex", "invalid regex - {error}"),
+    (
+        "identifier.invalid_timestamp",
+        "invalid timestamp, encountered - {value}",
+    ),
+    (
+        "identifier.length_of_boolean",
+        "cannot compare a length to a boolean, encountered - {key}",
+    ),
+    (
+        "identifier.match_without_field",
+        "match condition mut contain a field, encountered - {key}",
+    ),
+    (
+        "identifier.match_without_sequence",
+        "match condition is only valid for sequences, encountered - {key}",
+    ),
+    (
+        "identifier.mixed_match_types",
+        "when using sequence modifiers the all expressions must be of the same type",
+    ),
+    (
+        "identifier.nested_with_modifier",
+        "nested mappings are not supported when casting or negating a field, encountered - {key}",
+    ),
+    (
+        "identifier.range_reversed",
+        "range must not end before it starts, encountered - {range}",
+    ),
+    (
+        "identifier.skeleton_not_string_value",
+        "skeleton can only be applied to strings, encountered - {key}",
+    ),
+    (
+        "identifier.string_to_integer",
+        "cannot cast string to integer, encountered - {key}",
+    ),
+    (
+        "identifier.time_not_string",
+        "time can only be applied to strings, encountered - {value}",
+    ),
+    ("identifier.unknown_lookup", "unknown lookup - {name}"),
+    (
+        "identifier.unsupported_feature",
+        "the {feature} feature is required for - {pattern}",
+    ),
+    (
+        "identifier.unterminated_glob_class",
+        "unterminated character class in glob - {pattern}",
+    ),
+    (
+        "identifier.wide_insensitive_unicode",
+        "case insensitive wide searches must be ascii, encountered - {pattern}",
+    ),
+    (
+        "identifier.wide_not_literal",
+        "wide can only be applied to literal searches, encountered - {pattern}",
+    ),
+    (
+        "identifier.wide_not_string_value",
+        "wide can only be applied to strings, encountered - {key}",
+    ),
+    (
+        "identifier.windash_not_string",
+        "windash can only be applied to string searches, encountered - {pattern}",
+    ),
+    (
+        "identifier.windash_not_string_value",
+        "windash can only be applied to strings, encountered - {key}",
+    ),
+    (
+        "identifier.windash_too_many_flags",
+        "windash can expand at most {limit} flags, encountered - {needle}",
+    ),
+    (
+        "rule.correlation_and_sequence",
+        "a rule can not have both a correlation and a sequence",
+    ),
+    ("rule.duplicate_tag", "duplicate tag - {tag}"),
+    (
+        "rule.empty_metadata",
+        "invalid value: {field}, must not be empty",
+    ),
+    (
+        "rule.extends_itself",
+        "invalid value: extends, '{id}' extends itself",
+    ),
+    (
+        "rule.extends_not_mapping",
+        "invalid value: extends, base is not a mapping",
+    ),
+    (
+        "rule.extends_not_string",
+        "invalid value: extends, expected a string",
+    ),
+    (
+        "rule.extends_unknown",
+        "invalid value: extends, unknown base '{id}'",
+    ),
+    (
+        "rule.extends_unreadable",
+        "invalid value: extends, failed to read '{id}' - {error}",
+    ),
+    (
+        "rule.invalid_correlation",
+        "invalid correlation - {correlation}",
+    ),
+    (
+        "rule.invalid_document",
+        "document {index} is not a valid rule - {error}",
+    ),
+    (
+        "rule.invalid_example",
+        "invalid value: {key}, example {index} is not a mapping",
+    ),
+    ("rule.invalid_filter", "failed to parse filter - {error}"),
+    (
+        "rule.invalid_merge",
+        "merge keys must hold a mapping or a sequence of mappings",
+    ),
+    (
+        "rule.invalid_metadata",
+        "invalid type: {field}, expected {expected}",
+    ),
+    (
+        "rule.invalid_weight",
+        "weight must be a positive number, encountered - {weight}",
+    ),
+    (
+        "rule.sequence_step_not_identifier",
+        "sequence step '{step}' is not an identifier",
+    ),
+    (
+        "rule.too_complex",
+        "rule exceeds the {kind} limit of {limit}",
+    ),
+    (
+        "rule.weight_unknown_identifier",
+        "weight given for an unknown identifier - {identifier}",
+    ),
+    (
+        "sigma.invalid_condition",
+        "condition must be a string, encountered - {condition}",
+    ),
+    (
+        "sigma.invalid_detection",
+        "missing or invalid detection block",
+    ),
+    (
+        "sigma.invalid_detection_key",
+        "detection keys must be strings, encountered - {key}",
+    ),
+    (
+        "sigma.invalid_selection_key",
+        "selection keys must be strings, encountered - {key}",
+    ),
+    ("sigma.missing_condition", "missing or invalid condition"),
+    (
+        "sigma.no_matching_identifiers",
+        "no identifiers match, encountered - {pattern}",
+    ),
+    (
+        "sigma.quantifier_without_target",
+        "quantifier is missing its target",
+    ),
+    (
+        "sigma.unknown_identifier",
+        "unknown identifier in condition, encountered - {identifier}",
+    ),
+    (
+        "template.unknown",
+        "unknown template - {template}, expected one of: {expected}",
+    ),
+    ("token.expected_equals", "expected '='"),
+    ("token.invalid_timestamp", "invalid timestamp '{literal}'"),
+    (
+        "token.unsupported_character",
+        "unsupported character '{character}'",
+    ),
+    (
+        "translate.unsupported",
+        "unable to translate into {backend}, encountered - {expression}",
+    ),
+    ("validation.failed", ""),
+    (
+        "validation.false_positive",
+        "failed to validate false positive check '{document}'",
+    ),
+    (
+        "validation.true_negative",
+        "failed to validate true negative check '{document}'",
+    ),
+    (
+        "validation.true_positive",
+        "failed to validate true positive check '{document}'",
+    ),
+];
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn english() {
+        assert!(TEMPLATES.windows(2).all(|w| w[0].0 < w[1].0));
+        let diagnostic = Diagnostic::new("identifier.unknown_lookup").with("name", "foo");
+        assert_eq!(diagnostic.to_string(), "unknown lookup - foo");
+        assert_eq!(diagnostic.param("name"), Some("foo"));
+    }
+
+    #[test]
+    fn localise() {
+        let messages = Messages::new().message(
+            "identifier.unknown_lookup",
+            "recherche inconnue - {name} {{}}",
+        );
+        let diagnostic = Diagnostic::new("identifier.unknown_lookup").with("name", "foo");
+        assert_eq!(
+            diagnostic.localise(&messages),
+            "recherche inconnue - foo {}"
+        );
+        let diagnostic = Diagnostic::new("identifier.fuzzy_without_value");
+        assert_eq!(diagnostic.localise(&messages), diagnostic.message());
+    }
+
+    #[test]
+    fn error() {
+        let err = crate::parser::parse_identifier(&serde_yaml::Value::Bool(true)).unwrap_err();
+        let diagnostic = err.diagnostic().unwrap();
+        let messages = Messages::new().message(diagnostic.code(), "ungültig - {value}");
+        assert_eq!(
+            err.localise(&messages),
+            format!(
+                "{}: ungültig - {}",
+                err.title(),
+                diagnostic.param("value").unwrap()
+            )
+        );
+        assert_eq!(err.localise(&English), err.to_string());
+    }
+}
@@ -1,4 +1,23 @@
-use crate::value::{Object, Value};
+use alloc::borrow::Cow;
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use core::cell::OnceCell;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+
+#[cfg(feature = "regex")]
+use regex::{Captures, Regex};
+
+use crate::collections::HashMap;
+#[cfg(feature = "std")]
+use crate::engine::RandomState;
+#[cfg(feature = "grok")]
+use crate::grok::Grok;
+#[cfg(feature = "regex")]
+use crate::parser::RegexError;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::value::{AsValue, Object, Value};
 
 /// A `Document` that can be evaluated by the solver.
 ///
@@ -35,6 +54,33 @@ use crate::value::{Object, Value};
 pub trait Document {
     /// Looks for a `Value` by key and returns it if found.
     fn find(&self, key: &str) -> Option<Value<'_>>;
+
+    /// Looks for a string `Value` by key and returns a lowercase copy of it if found.
+    ///
+    /// This is used by the searches that have been shadowed, see `RuleLoader::shadow`. By default
+    /// the copy is made on every call, which adapters that live for the duration of an evaluation,
+    /// such as `CachedDocument`, override so that it is only made once.
+    fn find_shadow(&self, key: &str) -> Option<Rc<str>> {
+        match self.find(key) {
+            Some(Value::String(s)) => Some(Rc::from(s.to_lowercase())),
+            _ => None,
+        }
+    }
+
+    /// Returns the values at the top level of the document.
+    ///
+    /// This is used by keyword searches, which are not tied to a field and instead walk every
+    /// value in the document. By default a document has no values, so that keyword searches never
+    /// match documents that can only be searched by key.
+    ///
+    /// # Keywords
+    ///
+    /// Implementations that do not override this method can't be searched by keyword, meaning that
+    /// every keyword identifier always evaluates to false against them, including when negated
+    /// within a condition, i.e. `not keywords` is always true.
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![]
+    }
 }
 
 impl Document for &dyn Object {
@@ -42,6 +88,10 @@ impl Document for &dyn Object {
     fn find(&self, key: &str) -> Option<Value<'_>> {
         Object::find(*self, key)
     }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.keys().iter().filter_map(|k| self.get(k)).collect()
+    }
 }
 
 impl<O: Object> Document for O {
@@ -49,13 +99,413 @@ impl<O: Object> Document for O {
     fn find(&self, key: &str) -> Option<Value<'_>> {
         Object::find(self, key)
     }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.keys().iter().filter_map(|k| self.get(k)).collect()
+    }
+}
+
+/// A `Document` adapter that memoises lookups.
+///
+/// Within a detection, and even more so across a `RuleSet`, the same fields are looked up many
+/// times for a single document. Wrapping the document in a `CachedDocument` means that each field
+/// is only resolved once, with any subsequent lookups, including those that are then cast, served
+/// from the cache. The cache lives for as long as the wrapper, so it should be created per document
+/// and dropped once evaluation is complete.
+///
+/// The same goes for the lowercase shadows of string fields, which are shared by all of the
+/// shadowed searches that are evaluated against the document.
+///
+/// `RuleSet` wraps documents in a `CachedDocument` when evaluating them.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{CachedDocument, Document};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let event: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+/// let event = event.as_mapping().unwrap();
+///
+/// let document = CachedDocument::new(event);
+/// assert_eq!(document.find("foo").unwrap().as_str(), Some("bar"));
+/// ```
+#[cfg(feature = "std")]
+pub struct CachedDocument<'a> {
+    cache: RefCell<HashMap<String, Option<Value<'a>>, RandomState>>,
+    document: &'a dyn Document,
+    shadows: RefCell<HashMap<String, Option<Rc<str>>, RandomState>>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> CachedDocument<'a> {
+    /// Wraps the document with an empty cache.
+    pub fn new(document: &'a dyn Document) -> Self {
+        Self {
+            cache: RefCell::new(HashMap::default()),
+            document,
+            shadows: RefCell::new(HashMap::default()),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Document for CachedDocument<'a> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if let Some(value) = self.cache.borrow().get(key) {
+            return value.clone();
+        }
+        let value = self.document.find(key);
+        self.cache
+            .borrow_mut()
+            .insert(key.to_owned(), value.clone());
+        value
+    }
+
+    fn find_shadow(&self, key: &str) -> Option<Rc<str>> {
+        if let Some(shadow) = self.shadows.borrow().get(key) {
+            return shadow.clone();
+        }
+        let shadow = match self.find(key) {
+            Some(Value::String(s)) => Some(Rc::from(s.to_lowercase())),
+            _ => None,
+        };
+        self.shadows
+            .borrow_mut()
+            .insert(key.to_owned(), shadow.clone());
+        shadow
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.document.values()
+    }
+}
+
+/// A `Document` adapter over a map of already flattened keys.
+///
+/// Many pipelines hand over events as flat maps, i.e. `{"process.name": "cmd.exe"}`. Wrapping
+/// such a map in a `FlatDocument` means that the dotted keys also resolve as if they were nested,
+/// so that `process` is an object holding `name`, and nested expressions and lookups into nested
+/// values keep working. Keys are always looked up as is first, and when a key is also the prefix
+/// of others, i.e. `process` and `process.name`, the value held by the key wins.
+///
+/// When the `.` within the keys should be treated as literal, the adapter can be made to only
+/// ever look up keys as is, descending into nested values the same way as a `HashMap` would.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # fn main() {
+/// use std::borrow::Cow;
+/// use std::collections::HashMap;
+///
+/// use tau_engine::{Document, FlatDocument, Value};
+///
+/// let mut event = HashMap::new();
+/// event.insert("process.name".to_owned(), Value::String(Cow::Borrowed("cmd.exe")));
+/// event.insert("process.pid".to_owned(), Value::Int(1024));
+///
+/// let document = FlatDocument::new(&event);
+/// assert_eq!(document.find("process.name").unwrap().as_str(), Some("cmd.exe"));
+/// assert!(document.find("process").unwrap().is_object());
+///
+/// let document = FlatDocument::new(&event).literal(true);
+/// assert!(document.find("process").is_none());
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+pub struct FlatDocument<'a, V> {
+    literal: bool,
+    map: &'a HashMap<String, V>,
+    tree: OnceCell<Branch<'a, V>>,
+}
+
+impl<'a, V: AsValue> FlatDocument<'a, V> {
+    /// Wraps the map, resolving its dotted keys as if they were nested.
+    pub fn new(map: &'a HashMap<String, V>) -> Self {
+        Self {
+            literal: false,
+            map,
+            tree: OnceCell::new(),
+        }
+    }
+
+    /// Set whether the `.` within the keys of the map is literal, disabling the nesting.
+    pub fn literal(mut self, literal: bool) -> Self {
+        self.literal = literal;
+        self
+    }
+
+    // NOTE: The tree is only built once a key can't be found as is.
+    fn tree(&self) -> &Branch<'a, V> {
+        self.tree.get_or_init(|| {
+            let mut root = Branch(BTreeMap::new());
+            for (key, value) in self.map {
+                root.insert(key, value);
+            }
+            root
+        })
+    }
+}
+
+impl<'a, V: AsValue> Document for FlatDocument<'a, V> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if let Some(value) = self.map.get(key) {
+            return Some(value.as_value());
+        }
+        if self.literal {
+            return Object::find(self.map, key);
+        }
+        Object::find(self.tree(), key)
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.map.values().map(|v| v.as_value()).collect()
+    }
+}
+
+// A prefix of the dotted keys within a `FlatDocument`.
+struct Branch<'a, V>(BTreeMap<&'a str, Node<'a, V>>);
+
+enum Node<'a, V> {
+    Branch(Branch<'a, V>),
+    Leaf(&'a V),
+}
+
+impl<'a, V> Branch<'a, V> {
+    fn insert(&mut self, key: &'a str, value: &'a V) {
+        let mut branch = self;
+        let mut key = key;
+        while let Some((segment, rest)) = key.split_once('.') {
+            let node = branch
+                .0
+                .entry(segment)
+                .or_insert_with(|| Node::Branch(Branch(BTreeMap::new())));
+            branch = match node {
+                Node::Branch(branch) => branch,
+                // NOTE: A key that is also a prefix of others wins.
+                Node::Leaf(_) => return,
+            };
+            key = rest;
+        }
+        branch.0.insert(key, Node::Leaf(value));
+    }
+}
+
+impl<'a, V: AsValue> Object for Branch<'a, V> {
+    fn get(&self, key: &str) -> Option<Value<'_>> {
+        self.0.get(key).map(|node| match node {
+            Node::Branch(branch) => Value::Object(branch),
+            Node::Leaf(value) => value.as_value(),
+        })
+    }
+
+    fn keys(&self) -> Vec<Cow<'_, str>> {
+        self.0.keys().map(|k| Cow::Borrowed(*k)).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
 }
 
-#[cfg(test)]
+/// A `Document` adapter that resolves arrays of name/value pairs as if they were objects.
+///
+/// Some sources, such as Windows event logs, encode objects as arrays of pairs, i.e.
+/// `[{"Name": "CommandLine", "Value": "..."}]`. Wrapping a `Document` in `Pairs` allows rules to
+/// address these fields naturally, as `EventData.CommandLine`, instead of having to search the
+/// array. Keys are always looked up as is first, so the adapter only changes the outcome of lookups
+/// that would otherwise have failed.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # fn main() {
+/// use tau_engine::{Document, Pairs};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let event: Yaml = serde_yaml::from_str(r#"
+/// EventData:
+/// - Name: CommandLine
+///   Value: whoami
+/// "#).unwrap();
+/// let event = event.as_mapping().unwrap();
+///
+/// let document = Pairs::new(event);
+/// assert_eq!(document.find("EventData.CommandLine").unwrap().as_str(), Some("whoami"));
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+pub struct Pairs<'a> {
+    document: &'a dyn Document,
+    name: String,
+    value: String,
+}
+
+impl<'a> Pairs<'a> {
+    /// Wraps the document, using `Name` and `Value` as the keys of each pair.
+    pub fn new(document: &'a dyn Document) -> Self {
+        Self {
+            document,
+            name: "Name".to_owned(),
+            value: "Value".to_owned(),
+        }
+    }
+
+    /// Set the keys that hold the name and value of each pair.
+    pub fn keys<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.name = name.into();
+        self.value = value.into();
+        self
+    }
+}
+
+impl<'a> Document for Pairs<'a> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if let Some(value) = self.document.find(key) {
+            return Some(value);
+        }
+        // NOTE: Try the longest path to an array first, with the rest of the key as the name.
+        for (i, _) in key.rmatch_indices('.') {
+            let (path, name) = (&key[..i], &key[i + 1..]);
+            let array = match self.document.find(path) {
+                Some(Value::Array(array)) => array,
+                _ => continue,
+            };
+            for pair in array.iter() {
+                if let Value::Object(pair) = pair {
+                    if pair
+                        .get(&self.name)
+                        .and_then(|n| n.as_str().map(|n| n == name))
+                        == Some(true)
+                    {
+                        return pair.get(&self.value);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.document.values()
+    }
+}
+
+/// The format of an unstructured source, used to wrap its lines as a `Document`.
+///
+/// Each line exposes a single implicit field, `raw` by default, holding the line as is. Patterns
+/// can be added to extract further fields from the line, with each named capture group becoming a
+/// field. Patterns are tried in the order that they were added, and the first to capture a field
+/// wins.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Document, LineFormat};
+///
+/// let format = LineFormat::new()
+///     .pattern(r"^(?P<host>\S+) sshd\[(?P<pid>\d+)\]")
+///     .unwrap();
+///
+/// let document = format.line("server01 sshd[1024]: Accepted publickey for root");
+/// assert_eq!(document.find("host").unwrap().as_str(), Some("server01"));
+/// assert_eq!(document.find("pid").unwrap().as_str(), Some("1024"));
+/// assert!(document.find("raw").unwrap().as_str().unwrap().ends_with("for root"));
+/// ```
+#[cfg(feature = "regex")]
+#[derive(Clone, Debug)]
+pub struct LineFormat {
+    field: String,
+    patterns: Vec<Regex>,
+}
+
+#[cfg(feature = "regex")]
+impl Default for LineFormat {
+    fn default() -> Self {
+        Self {
+            field: "raw".to_owned(),
+            patterns: vec![],
+        }
+    }
+}
+
+#[cfg(feature = "regex")]
+impl LineFormat {
+    /// Create a format with no patterns, exposing lines as `raw`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the field that holds the line as is.
+    pub fn field<S: Into<String>>(mut self, field: S) -> Self {
+        self.field = field.into();
+        self
+    }
+
+    /// Add a pattern whose named capture groups are extracted as fields.
+    pub fn pattern(mut self, pattern: &str) -> crate::Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| crate::error::parse_invalid_ident(RegexError::from(e)))?;
+        self.patterns.push(regex);
+        Ok(self)
+    }
+
+    /// Add a grok pattern, expanded using the provided library, whose fields are extracted.
+    #[cfg(feature = "grok")]
+    pub fn grok(self, grok: &Grok, pattern: &str) -> crate::Result<Self> {
+        self.pattern(&grok.expand(pattern)?)
+    }
+
+    /// Wraps the line as a `Document`.
+    pub fn line<'a>(&'a self, line: &'a str) -> Line<'a> {
+        Line {
+            captures: self
+                .patterns
+                .iter()
+                .filter_map(|p| p.captures(line))
+                .collect(),
+            field: &self.field,
+            line,
+        }
+    }
+}
+
+/// A line from an unstructured source, see `LineFormat`.
+#[cfg(feature = "regex")]
+pub struct Line<'a> {
+    captures: Vec<Captures<'a>>,
+    field: &'a str,
+    line: &'a str,
+}
+
+#[cfg(feature = "regex")]
+impl<'a> Document for Line<'a> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if key == self.field {
+            return Some(Value::String(Cow::Borrowed(self.line)));
+        }
+        self.captures
+            .iter()
+            .find_map(|c| c.name(key))
+            .map(|m| Value::String(Cow::Borrowed(m.as_str())))
+    }
+
+    // NOTE: The captures are all taken from the line, so it is the only value worth searching.
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![Value::String(Cow::Borrowed(self.line))]
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
-    use std::borrow::Cow;
+    use std::cell::Cell;
 
     struct Foo {
         bar: String,
@@ -69,6 +519,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn pairs() {
+        let event: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            Event:
+              EventData:
+              - Name: CommandLine
+                Value: whoami
+              - Name: Image
+                Value: cmd.exe
+              System:
+                EventID: 1
+            Data:
+            - key: foo.bar
+              val: baz
+            "#,
+        )
+        .unwrap();
+        let event = event.as_mapping().unwrap();
+
+        let document = Pairs::new(event);
+        assert_eq!(
+            document.find("Event.EventData.Image").unwrap().as_str(),
+            Some("cmd.exe")
+        );
+        assert_eq!(
+            document.find("Event.System.EventID").unwrap().as_u64(),
+            Some(1)
+        );
+        assert!(document.find("Event.EventData.User").is_none());
+        assert!(document.find("Data.foo.bar").is_none());
+
+        let document = Pairs::new(event).keys("key", "val");
+        assert_eq!(document.find("Data.foo.bar").unwrap().as_str(), Some("baz"));
+    }
+
+    #[test]
+    fn flat() {
+        let mut source = HashMap::new();
+        source.insert("ip".to_owned(), "10.0.0.1".to_owned());
+        let mut map = HashMap::new();
+        map.insert(
+            "process.name".to_owned(),
+            Value::String(Cow::Borrowed("cmd.exe")),
+        );
+        map.insert(
+            "process.parent.name".to_owned(),
+            Value::String(Cow::Borrowed("explorer.exe")),
+        );
+        map.insert("source".to_owned(), Value::Object(&source));
+        map.insert("user".to_owned(), Value::String(Cow::Borrowed("root")));
+        map.insert("user.id".to_owned(), Value::Int(0));
+
+        let document = FlatDocument::new(&map);
+        assert_eq!(
+            document.find("process.parent.name").unwrap().as_str(),
+            Some("explorer.exe")
+        );
+        let process = document.find("process").unwrap();
+        let process = process.as_object().unwrap();
+        assert_eq!(process.len(), 2);
+        assert_eq!(
+            process.find("parent.name").unwrap().as_str(),
+            Some("explorer.exe")
+        );
+        assert_eq!(
+            document.find("source.ip").unwrap().as_str(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(document.find("user").unwrap().as_str(), Some("root"));
+        assert_eq!(document.find("user.id").unwrap().as_i64(), Some(0));
+        assert!(document.find("process.pid").is_none());
+
+        let document = FlatDocument::new(&map).literal(true);
+        assert!(document.find("process").is_none());
+        assert!(document.find("process.parent").is_none());
+        assert_eq!(
+            document.find("process.name").unwrap().as_str(),
+            Some("cmd.exe")
+        );
+        assert_eq!(
+            document.find("source.ip").unwrap().as_str(),
+            Some("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn cached() {
+        struct Counter {
+            bar: String,
+            lookups: Cell<usize>,
+        }
+        impl Document for Counter {
+            fn find(&self, key: &str) -> Option<Value<'_>> {
+                self.lookups.set(self.lookups.get() + 1);
+                match key {
+                    "bar" => Some(Value::String(Cow::Borrowed(&self.bar))),
+                    _ => None,
+                }
+            }
+        }
+
+        let counter = Counter {
+            bar: "baz".to_owned(),
+            lookups: Cell::new(0),
+        };
+        let document = CachedDocument::new(&counter);
+        for _ in 0..3 {
+            assert_eq!(document.find("bar").unwrap().as_str(), Some("baz"));
+            assert!(document.find("qux").is_none());
+        }
+        assert_eq!(counter.lookups.get(), 2);
+
+        let counter = Counter {
+            bar: "BaZ".to_owned(),
+            lookups: Cell::new(0),
+        };
+        let document = CachedDocument::new(&counter);
+        let shadow = document.find_shadow("bar").unwrap();
+        assert_eq!(&*shadow, "baz");
+        assert!(Rc::ptr_eq(&shadow, &document.find_shadow("bar").unwrap()));
+        assert!(document.find_shadow("qux").is_none());
+        assert_eq!(document.find("bar").unwrap().as_str(), Some("BaZ"));
+        assert_eq!(counter.lookups.get(), 2);
+    }
+
+    #[test]
+    fn line() {
+        let format = LineFormat::new()
+            .field("message")
+            .pattern(r"^(?P<user>\w+) logged in")
+            .unwrap()
+            .pattern(r"from (?P<ip>[\d.]+)")
+            .unwrap()
+            .pattern(r"(?P<user>\w+) logged")
+            .unwrap();
+
+        let document = format.line("root logged in from 10.0.0.1");
+        assert_eq!(
+            document.find("message").unwrap().as_str(),
+            Some("root logged in from 10.0.0.1")
+        );
+        assert_eq!(document.find("user").unwrap().as_str(), Some("root"));
+        assert_eq!(document.find("ip").unwrap().as_str(), Some("10.0.0.1"));
+        assert!(document.find("raw").is_none());
+
+        let document = format.line("root logged out");
+        assert_eq!(document.find("user").unwrap().as_str(), Some("root"));
+        assert!(document.find("ip").is_none());
+
+        assert!(LineFormat::new().pattern("(").is_err());
+    }
+
     #[test]
     fn find() {
         let foo = Foo {
@@ -76,4 +679,26 @@ mod tests {
         };
         assert_eq!(foo.find("bar").unwrap().as_str().unwrap(), "baz");
     }
+
+    #[test]
+    fn values() {
+        let foo = Foo {
+            bar: "baz".to_owned(),
+        };
+        assert!(foo.values().is_empty());
+
+        let event: serde_yaml::Value = serde_yaml::from_str("{foo: bar, baz: [1, 2]}").unwrap();
+        let event = event.as_mapping().unwrap();
+        let document = CachedDocument::new(event);
+        let values = document.values();
+        assert_eq!(values.len(), 2);
+        assert!(values.iter().any(|v| v.as_str() == Some("bar")));
+        assert!(values.iter().any(|v| v.is_array()));
+
+        let format = LineFormat::new().pattern(r"^(?P<host>\S+)").unwrap();
+        let line = format.line("server01 sshd[1024]");
+        let values = line.values();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].as_str(), Some("server01 sshd[1024]"));
+    }
 }
@@ -0,0 +1,64 @@
+// The hasher state for maps that are built while evaluating.
+//
+// NOTE: `RandomState` seeds itself from the operating system's randomness, so deterministic
+// builds fall back to the fixed keys of `DefaultHasher` instead.
+#[cfg(feature = "deterministic")]
+pub(crate) type RandomState =
+    std::hash::BuildHasherDefault<std::collections::hash_map::DefaultHasher>;
+#[cfg(not(feature = "deterministic"))]
+pub(crate) type RandomState = std::collections::hash_map::RandomState;
+
+/// Guarantees that hold for the engine as a whole.
+///
+/// When built with the `deterministic` feature the engine performs no impure reads while
+/// evaluating: the wall clock is never read, so the durations in [`Stats`](crate::Stats) and
+/// [`Explanation`](crate::Explanation) are always zero, maps are hashed with fixed keys rather
+/// than being seeded from the operating system's randomness, and the environment is never read.
+/// The same rules and documents will therefore always be evaluated in exactly the same way.
+///
+/// Cargo features are additive, so a dependency can't switch the guarantee off, but it is easy to
+/// forget to switch it on. Calling [`Engine::assert_pure`] at start-up turns that mistake into a
+/// hard failure rather than a quietly unreplayable audit trail.
+///
+/// NOTE: This only covers the engine, a `Document` implementation that reads the clock itself is
+/// outside of its control.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::Engine;
+///
+/// if Engine::is_pure() {
+///     Engine::assert_pure();
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Engine;
+
+impl Engine {
+    /// Panics unless the engine was built with the `deterministic` feature.
+    pub fn assert_pure() {
+        assert!(
+            Self::is_pure(),
+            "tau-engine was not built with the `deterministic` feature, evaluation may read the \
+             clock and the operating system's randomness"
+        );
+    }
+
+    /// Returns true if the engine was built with the `deterministic` feature.
+    pub fn is_pure() -> bool {
+        cfg!(feature = "deterministic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pure() {
+        assert_eq!(Engine::is_pure(), cfg!(feature = "deterministic"));
+        let result = std::panic::catch_unwind(Engine::assert_pure);
+        assert_eq!(result.is_ok(), cfg!(feature = "deterministic"));
+    }
+}
@@ -1,8 +1,15 @@
+#[cfg(not(feature = "std"))]
+use core::error::Error as StdError;
+use core::fmt;
+#[cfg(feature = "std")]
 use std::error::Error as StdError;
-use std::fmt;
+
+use crate::diagnostic::{Catalogue, Diagnostic, Position};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 
 /// A `Result` alias where `Err` case is `tau_engine::Error`.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// The errors that may occur when using the Tau Engine.
 pub struct Error {
@@ -28,26 +35,34 @@ impl Error {
         self
     }
 
+    // Records where in the condition this error occurred, when it has a diagnostic to hold it.
+    pub(crate) fn at(mut self, position: Position) -> Error {
+        if let Some(source) = self.inner.source.take() {
+            self.inner.source = Some(match source.downcast::<Diagnostic>() {
+                Ok(diagnostic) => Box::new(diagnostic.at(position)),
+                Err(source) => source,
+            });
+        }
+        self
+    }
+
     /// Returns the kind of this error.
     pub fn kind(&self) -> &Kind {
         &self.inner.kind
     }
-}
 
-impl fmt::Debug for Error {
-    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut builder = fmt.debug_struct("tau_engine::Error");
-        builder.field("kind", &self.inner.kind);
-        if let Some(ref source) = self.inner.source {
-            builder.field("source", source);
-        }
-        builder.finish()
+    /// Returns the structured diagnostic behind this error, if it was raised by the engine rather
+    /// than by one of its dependencies, i.e. when reading YAML.
+    pub fn diagnostic(&self) -> Option<&Diagnostic> {
+        self.inner
+            .source
+            .as_ref()
+            .and_then(|s| s.downcast_ref::<Diagnostic>())
     }
-}
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let desc = match self.inner.kind {
+    /// Returns the English title of this error, which is determined by its kind.
+    pub fn title(&self) -> &'static str {
+        match self.inner.kind {
             Kind::Parse(Parse::InvalidIdentifier) => {
                 "an invalid identifier was encountered during parsing"
             }
@@ -69,7 +84,37 @@ impl fmt::Display for Error {
                 "an invalid number was encountered during tokenisation"
             }
             Kind::Validation => "failed to validate rule",
-        };
+        }
+    }
+
+    /// Renders this error with its explanation taken from the provided catalogue.
+    ///
+    /// The title is always in English, and errors from dependencies keep their own messages.
+    pub fn localise(&self, catalogue: &dyn Catalogue) -> String {
+        match (self.diagnostic(), &self.inner.source) {
+            (Some(diagnostic), _) => {
+                format!("{}: {}", self.title(), diagnostic.localise(catalogue))
+            }
+            (None, Some(source)) => format!("{}: {}", self.title(), source),
+            (None, None) => self.title().to_owned(),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut builder = fmt.debug_struct("tau_engine::Error");
+        builder.field("kind", &self.inner.kind);
+        if let Some(ref source) = self.inner.source {
+            builder.field("source", source);
+        }
+        builder.finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let desc = self.title();
         if let Some(ref source) = self.inner.source {
             write!(f, "{}: {}", desc, source)
         } else {
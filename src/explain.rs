@@ -0,0 +1,885 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::Hasher;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock::Instant;
+use crate::document::Document;
+use crate::parser::{Expression, Match, MatchType, Search};
+use crate::solver::{self, SolverResult};
+use crate::timestamp;
+use crate::tokeniser::BoolSym;
+use crate::value::Value;
+
+/// The version of the `Trace` format, which is bumped whenever its structure changes.
+pub const TRACE_VERSION: u32 = 1;
+
+/// An explanation of how a detection evaluated against a document.
+///
+/// The explanation mirrors the structure of the detection, with each node holding its outcome and,
+/// where it reads from the document, the value that it was evaluated against. Every node is
+/// evaluated, even those that the solver would have skipped, so that the explanation is complete.
+/// The `Display` implementation renders the explanation as an indented tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Explanation {
+    /// A description of the node.
+    pub node: String,
+    /// The operation performed by the node, i.e. `and`, `search` or `==`.
+    pub op: String,
+    /// The field that the node reads from the document, if any.
+    pub field: Option<String>,
+    /// The outcome of the node, where `None` means that the data it needed was missing.
+    pub outcome: Option<bool>,
+    /// The value that the node was evaluated against, if it reads from the document, after any
+    /// redaction.
+    pub value: Option<String>,
+    /// The keyed hash of the value, as hex, taken before any redaction, see `Redaction::key`.
+    pub value_hash: Option<String>,
+    /// The time taken to evaluate the node, including its children.
+    pub duration: Duration,
+    /// The nodes that make up this node.
+    pub children: Vec<Explanation>,
+}
+
+impl Explanation {
+    /// Flattens the explanation into a `Trace`.
+    pub fn trace(&self) -> Trace {
+        let mut nodes = vec![];
+        self.flatten(String::new(), &mut nodes);
+        Trace {
+            version: TRACE_VERSION,
+            nodes,
+        }
+    }
+
+    fn flatten(&self, id: String, nodes: &mut Vec<TraceNode>) {
+        nodes.push(TraceNode {
+            id: if id.is_empty() {
+                "/".to_owned()
+            } else {
+                id.clone()
+            },
+            label: self.node.clone(),
+            op: self.op.clone(),
+            field: self.field.clone(),
+            value_hash: self.value_hash.clone(),
+            outcome: self.outcome,
+            duration_ns: self.duration.as_nanos() as u64,
+        });
+        for (i, child) in self.children.iter().enumerate() {
+            child.flatten(format!("{}/{}", id, i), nodes);
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let outcome = match self.outcome {
+            Some(true) => "true",
+            Some(false) => "false",
+            None => "missing",
+        };
+        write!(
+            f,
+            "{:indent$}{} => {}",
+            "",
+            self.node,
+            outcome,
+            indent = depth * 2
+        )?;
+        if let Some(value) = &self.value {
+            write!(f, " (value: {})", value)?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+/// A policy for redacting the values held in an `Explanation`.
+///
+/// Explanations include the values that were read from the document, which can be large or
+/// sensitive, i.e. entire command lines. Redaction bounds what is included so that explanations,
+/// and their traces, can be shipped to centralised logging. The hash of each value is always
+/// taken before redaction, so that values can still be correlated across explanations.
+///
+/// Values are hashed with SipHash-2-4 under the policy's key. Without a secret key the hashes only
+/// obscure the values, as a value with few possibilities, such as a username, can be recovered by
+/// hashing guesses, so a key must be set with `Redaction::key` before hashes are shared.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Redaction, Rule};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     command: '*whoami*'
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let event: Yaml = serde_yaml::from_str("command: cmd.exe /c whoami").unwrap();
+/// let explanation = rule.detection.explain_evaluation_with(
+///     event.as_mapping().unwrap(),
+///     &Redaction::new().max_length(8),
+/// );
+/// assert_eq!(explanation.children[0].value.as_deref(), Some("\"cmd.exe..."));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Redaction {
+    hash_only: bool,
+    key: (u64, u64),
+    max_length: Option<usize>,
+}
+
+impl Redaction {
+    /// Create a redaction policy that leaves values untouched.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace values with their hash, so that no part of them is included.
+    pub fn hash_only(mut self, yes: bool) -> Self {
+        self.hash_only = yes;
+        self
+    }
+
+    /// Set the secret key that values are hashed with, which defaults to all zeros.
+    ///
+    /// Hashes can only be correlated between explanations that were redacted with the same key.
+    pub fn key(mut self, key: [u8; 16]) -> Self {
+        let (k0, k1) = key.split_at(8);
+        self.key = (
+            u64::from_le_bytes(k0.try_into().expect("could not split key")),
+            u64::from_le_bytes(k1.try_into().expect("could not split key")),
+        );
+        self
+    }
+
+    /// Set the maximum length of a value in characters, beyond which it is truncated.
+    pub fn max_length(mut self, length: usize) -> Self {
+        self.max_length = Some(length);
+        self
+    }
+
+    // NOTE: `SipHasher` is deprecated in favour of `DefaultHasher`, but unlike it, its keys can be
+    // set, which is what makes the hashes safe to share.
+    #[allow(deprecated)]
+    fn hash(&self, value: &str) -> String {
+        let mut hasher = std::hash::SipHasher::new_with_keys(self.key.0, self.key.1);
+        hasher.write(value.as_bytes());
+        format!("{:016x}", hasher.finish())
+    }
+
+    fn redact(&self, value: &str) -> String {
+        if self.hash_only {
+            return format!("#{}", self.hash(value));
+        }
+        match self.max_length {
+            Some(length) => match value.char_indices().nth(length) {
+                Some((i, _)) => format!("{}...", &value[..i]),
+                None => value.to_owned(),
+            },
+            None => value.to_owned(),
+        }
+    }
+}
+
+/// A machine readable trace of how a detection evaluated against a document.
+///
+/// A trace is a flattened `Explanation`, intended to be serialised, i.e. as JSON, so that traces
+/// can be collected and mined at scale. The nodes are ordered depth first, and each is identified
+/// by the path of child indices that leads to it from the root, where the root is `/`. Observed
+/// values are never included, only a keyed hash of them, so that traces redacted with a secret key
+/// can be shared without leaking the documents that produced them, see `Redaction`.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Rule, TRACE_VERSION};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     foo: bar
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let event: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+/// let trace = rule.detection.explain_evaluation(event.as_mapping().unwrap()).trace();
+/// assert_eq!(trace.version, TRACE_VERSION);
+/// assert_eq!(trace.nodes[1].id, "/0");
+/// assert_eq!(trace.nodes[1].field.as_deref(), Some("foo"));
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Trace {
+    /// The version of the format, see `TRACE_VERSION`.
+    pub version: u32,
+    /// The evaluated nodes, ordered depth first.
+    pub nodes: Vec<TraceNode>,
+}
+
+/// A single node within a `Trace`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TraceNode {
+    /// The path of the node from the root of the trace, i.e. `/0/1`.
+    pub id: String,
+    /// A description of the node.
+    pub label: String,
+    /// The operation performed by the node.
+    pub op: String,
+    /// The field that the node reads from the document, if any.
+    pub field: Option<String>,
+    /// The keyed hash of the rendered value, as hex, if the node reads from the document, see
+    /// `Redaction::key`.
+    pub value_hash: Option<String>,
+    /// The outcome of the node, where `None` means that the data it needed was missing.
+    pub outcome: Option<bool>,
+    /// The time taken to evaluate the node, including its children, in nanoseconds.
+    pub duration_ns: u64,
+}
+
+/// A value within a document that a detection matched on, see `Detection::explain_matches`.
+///
+/// These allow downstream systems to highlight or redact exactly the values that caused a match,
+/// rather than the whole of each field that the rule reads.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::Rule;
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     args: '*whoami*'
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let event: Yaml = serde_yaml::from_str("args: [cmd.exe, /c, whoami]").unwrap();
+/// let matches = rule.detection.explain_matches(event.as_mapping().unwrap());
+/// assert_eq!(matches.len(), 1);
+/// assert_eq!(matches[0].document_path, "args[2]");
+/// assert_eq!(matches[0].matched_value, "whoami");
+/// assert_eq!(matches[0].match_kind, "contains");
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Provenance {
+    /// The field as it is written in the rule, which is relative to any nested expression.
+    pub rule_field: String,
+    /// The path of the value within the document, where array elements are addressed by their
+    /// index, i.e. `Event.Data[1].Name`.
+    pub document_path: String,
+    /// The value that matched, after any redaction.
+    pub matched_value: String,
+    /// The kind of match, i.e. `exact`, `regex` or `>`.
+    pub match_kind: String,
+}
+
+pub(crate) fn explain(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    redaction: &Redaction,
+) -> Explanation {
+    let start = Instant::now();
+    let outcome = match solver::solve_expression(expression, identifiers, document) {
+        SolverResult::True => Some(true),
+        SolverResult::False => Some(false),
+        SolverResult::Missing => None,
+    };
+    let duration = start.elapsed();
+    let explain = |e: &Expression| explain(e, identifiers, document, redaction);
+    let (op, node, field, value, children) = match expression {
+        Expression::BooleanGroup(op, group) => (
+            symbol(op).to_owned(),
+            symbol(op).to_owned(),
+            None,
+            None,
+            group.iter().map(explain).collect(),
+        ),
+        Expression::BooleanExpression(left, op @ (BoolSym::And | BoolSym::Or), right) => (
+            symbol(op).to_owned(),
+            symbol(op).to_owned(),
+            None,
+            None,
+            vec![explain(left), explain(right)],
+        ),
+        Expression::BooleanExpression(left, op, right) => {
+            let fields: Vec<&String> = [left, right]
+                .iter()
+                .filter_map(|side| match &***side {
+                    Expression::Cast(f, _) | Expression::Field(f) => Some(f),
+                    _ => None,
+                })
+                .collect();
+            let values: Vec<String> = fields
+                .iter()
+                .map(|f| format!("{} = {}", f, lookup(document, f)))
+                .collect();
+            (
+                op.to_string(),
+                format!("{} {} {}", operand(left), op, operand(right)),
+                fields.first().map(|f| f.to_string()),
+                Some(values.join(", ")).filter(|v| !v.is_empty()),
+                vec![],
+            )
+        }
+        Expression::In(e, i) => membership(
+            document,
+            e,
+            format!(
+                "[{}]",
+                i.iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        ),
+        Expression::Range(e, x, y) => membership(document, e, format!("{}..{}", x, y)),
+        Expression::Identifier(i) => (
+            "identifier".to_owned(),
+            i.clone(),
+            None,
+            None,
+            identifiers.get(i).map(explain).into_iter().collect(),
+        ),
+        Expression::Match(Match::All, e) => (
+            "all".to_owned(),
+            "all".to_owned(),
+            None,
+            None,
+            vec![explain(e)],
+        ),
+        Expression::Match(Match::Every, e) => (
+            "every".to_owned(),
+            "every".to_owned(),
+            None,
+            None,
+            vec![explain(e)],
+        ),
+        Expression::Match(Match::Of(n), e) => (
+            "of".to_owned(),
+            format!("of {}", n),
+            None,
+            None,
+            vec![explain(e)],
+        ),
+        Expression::Negate(e) => (
+            "not".to_owned(),
+            "not".to_owned(),
+            None,
+            None,
+            vec![explain(e)],
+        ),
+        Expression::Nested(field, e) => {
+            let children = match document.find(field) {
+                Some(Value::Object(o)) => vec![explain_nested(e, identifiers, &o, redaction, None)],
+                Some(Value::Array(a)) => a
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, v)| match v {
+                        Value::Object(o) => {
+                            Some(explain_nested(e, identifiers, &o, redaction, Some(i)))
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            (
+                "nested".to_owned(),
+                field.clone(),
+                Some(field.clone()),
+                None,
+                children,
+            )
+        }
+        Expression::Search(search, field, cast) => {
+            let node = if *cast {
+                format!("str({}): {}", field, search)
+            } else {
+                format!("{}: {}", field, search)
+            };
+            (
+                "search".to_owned(),
+                node,
+                Some(field.clone()),
+                Some(lookup(document, field)),
+                vec![],
+            )
+        }
+        Expression::Keywords(search) => (
+            "keywords".to_owned(),
+            format!("keywords: {}", search),
+            None,
+            None,
+            vec![],
+        ),
+        e => ("constant".to_owned(), operand(e), None, None, vec![]),
+    };
+    Explanation {
+        node,
+        op,
+        field,
+        outcome,
+        value_hash: value.as_deref().map(|v| redaction.hash(v)),
+        value: value.map(|v| redaction.redact(&v)),
+        duration,
+        children,
+    }
+}
+
+// Explains an integer membership test, returning the same parts as `explain` does.
+fn membership(
+    document: &dyn Document,
+    expression: &Expression,
+    integers: String,
+) -> (
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Vec<Explanation>,
+) {
+    let field = match expression {
+        Expression::Cast(f, _) | Expression::Field(f) => Some(f.clone()),
+        _ => None,
+    };
+    let value = field.as_ref().map(|f| lookup(document, f));
+    (
+        "in".to_owned(),
+        format!("{} in {}", operand(expression), integers),
+        field,
+        value,
+        vec![],
+    )
+}
+
+fn explain_nested(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    redaction: &Redaction,
+    index: Option<usize>,
+) -> Explanation {
+    let explanation = explain(expression, identifiers, document, redaction);
+    match index {
+        Some(i) => Explanation {
+            node: format!("[{}]", i),
+            op: "index".to_owned(),
+            field: None,
+            outcome: explanation.outcome,
+            value: None,
+            value_hash: None,
+            duration: explanation.duration,
+            children: vec![explanation],
+        },
+        None => explanation,
+    }
+}
+
+// Collects the values that the expression matched on, descending only into the nodes that are
+// true, and skipping negations as whatever they matched on did not cause a match.
+pub(crate) fn provenance(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    redaction: &Redaction,
+    path: &str,
+    matches: &mut Vec<Provenance>,
+) {
+    // NOTE: Constants can't be solved, only the expressions that use them.
+    if !expression.is_solvable() && !matches!(expression, Expression::Search(_, _, _)) {
+        return;
+    }
+    if !matches!(
+        solver::solve_expression(expression, identifiers, document),
+        SolverResult::True
+    ) {
+        return;
+    }
+    let descend = |e: &Expression, matches: &mut Vec<Provenance>| match e {
+        Expression::Cast(f, _) | Expression::Field(f) => Some(f.clone()),
+        _ => {
+            provenance(e, identifiers, document, redaction, path, matches);
+            None
+        }
+    };
+    let (fields, kind) = match expression {
+        Expression::BooleanGroup(_, group) => {
+            for e in group {
+                descend(e, matches);
+            }
+            return;
+        }
+        Expression::BooleanExpression(left, op, right) => {
+            let fields: Vec<String> = [left, right]
+                .iter()
+                .filter_map(|side| descend(side, matches))
+                .collect();
+            (fields, op.to_string())
+        }
+        Expression::Identifier(i) => {
+            if let Some(e) = identifiers.get(i) {
+                descend(e, matches);
+            }
+            return;
+        }
+        Expression::In(e, _) => (descend(e, matches).into_iter().collect(), "in".to_owned()),
+        Expression::Match(_, e) => {
+            descend(e, matches);
+            return;
+        }
+        Expression::Nested(field, e) => {
+            match document.find(field) {
+                Some(Value::Object(o)) => {
+                    let path = format!("{}{}.", path, field);
+                    provenance(e, identifiers, &o, redaction, &path, matches);
+                }
+                Some(Value::Array(a)) => {
+                    for (i, v) in a.iter().enumerate() {
+                        if let Value::Object(o) = v {
+                            let path = format!("{}{}[{}].", path, field, i);
+                            provenance(e, identifiers, &o, redaction, &path, matches);
+                        }
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+        Expression::Range(e, _, _) => (
+            descend(e, matches).into_iter().collect(),
+            "range".to_owned(),
+        ),
+        Expression::Search(search, field, _) => (vec![field.clone()], kind(search).to_owned()),
+        _ => return,
+    };
+    for field in fields {
+        let mut push = |path: String, value: &Value| {
+            let value = match value {
+                Value::String(s) => s.to_string(),
+                v => render(v),
+            };
+            matches.push(Provenance {
+                rule_field: field.clone(),
+                document_path: path,
+                matched_value: redaction.redact(&value),
+                match_kind: kind.clone(),
+            });
+        };
+        match document.find(&field) {
+            // NOTE: Only the elements that match on their own are included, which are found by
+            // evaluating the expression against each of them in turn.
+            Some(Value::Array(a)) => {
+                for (i, v) in a.iter().enumerate() {
+                    let single = Single {
+                        field: &field,
+                        value: v,
+                    };
+                    if matches!(
+                        solver::solve_expression(expression, identifiers, &single),
+                        SolverResult::True
+                    ) {
+                        push(format!("{}{}[{}]", path, field, i), &single.value);
+                    }
+                }
+            }
+            Some(v) => push(format!("{}{}", path, field), &v),
+            None => {}
+        }
+    }
+}
+
+// A document holding a single field.
+struct Single<'a> {
+    field: &'a str,
+    value: Value<'a>,
+}
+
+impl Document for Single<'_> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if key == self.field {
+            Some(self.value.clone())
+        } else {
+            None
+        }
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        vec![self.value.clone()]
+    }
+}
+
+fn kind(search: &Search) -> &'static str {
+    match search {
+        // NOTE: A single needle is reported as the kind of search that it came from.
+        Search::AhoCorasick(_, contexts, _) if contexts.len() == 1 => match contexts[0] {
+            MatchType::Contains(_) => "contains",
+            MatchType::EndsWith(_) => "ends_with",
+            MatchType::Exact(_) => "exact",
+            MatchType::StartsWith(_) => "starts_with",
+        },
+        Search::AhoCorasick(_, _, _) => "aho_corasick",
+        Search::Any => "any",
+        Search::Contains(_) => "contains",
+        Search::EndsWith(_) => "ends_with",
+        Search::Exact(_) => "exact",
+        Search::Fuzzy(_, _, _) => "fuzzy",
+        Search::Glob(_, _) => "glob",
+        Search::Hex(_) => "hex",
+        Search::Lookup(_, _) => "lookup",
+        Search::Regex(_, _) => "regex",
+        Search::RegexSet(_, _) => "regex_set",
+        Search::Shadow(search) => kind(search),
+        Search::Skeleton(search) => kind(search),
+        Search::StartsWith(_) => "starts_with",
+        Search::Wide(_, _, _) => "wide",
+        #[cfg(feature = "fancy_regex")]
+        Search::FancyRegex(_, _) => "fancy_regex",
+    }
+}
+
+// NOTE: The hash is part of the trace format, so it must be stable across platforms and releases,
+// which rules out the standard library's hashers.
+fn lookup(document: &dyn Document, field: &str) -> String {
+    match document.find(field) {
+        Some(value) => render(&value),
+        None => "missing".to_owned(),
+    }
+}
+
+fn operand(expression: &Expression) -> String {
+    match expression {
+        Expression::Boolean(b) => b.to_string(),
+        Expression::Cast(f, m) => format!("{}({})", m, f),
+        Expression::Field(f) => f.clone(),
+        Expression::Float(n) => n.to_string(),
+        Expression::Integer(n) => n.to_string(),
+        Expression::Null => "null".to_owned(),
+        Expression::Timestamp(t) => timestamp::format(*t),
+        e => e.to_string(),
+    }
+}
+
+fn render(value: &Value<'_>) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Bool(b) => b.to_string(),
+        Value::Bytes(b) => format!(
+            "0x{}",
+            b.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+        ),
+        Value::Float(n) => n.to_string(),
+        Value::Int(n) => n.to_string(),
+        Value::UInt(n) => n.to_string(),
+        Value::String(s) => format!("{:?}", s),
+        Value::Timestamp(t) => timestamp::format(*t),
+        Value::Array(a) => format!(
+            "[{}]",
+            a.iter().map(|v| render(&v)).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(o) => {
+            let mut keys = o.keys();
+            keys.sort();
+            let fields: Vec<String> = keys
+                .iter()
+                .filter_map(|k| o.get(k).map(|v| format!("{}: {}", k, render(&v))))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+    }
+}
+
+fn symbol(op: &BoolSym) -> &'static str {
+    match op {
+        BoolSym::And => "and",
+        BoolSym::Or => "or",
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::Rule;
+
+    #[test]
+    fn explain() {
+        let rule = Rule::loader()
+            .shake(false)
+            .from_str(
+                r#"
+                detection:
+                  A:
+                    foo: bar
+                    baz: '*qux'
+                  B:
+                    nested:
+                      x: 1
+                  condition: A and not B and (int(num) > 3)
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap();
+        let document: Yaml =
+            serde_yaml::from_str("{foo: bar, baz: quux, nested: {x: 2}, num: '5'}").unwrap();
+        let explanation = rule
+            .detection
+            .explain_evaluation(document.as_mapping().unwrap());
+        assert_eq!(explanation.outcome, Some(false));
+        assert_eq!(
+            explanation.to_string(),
+            r#"and => false
+  and => false
+    A => false
+      and => false
+        foo: exact(bar) => true (value: "bar")
+        baz: ends_with(qux) => false (value: "quux")
+    not => true
+      B => false
+        nested => false
+          x == 1 => false (value: x = 2)
+  int(num) > 3 => true (value: num = "5")
+"#
+        );
+    }
+
+    #[test]
+    fn trace() {
+        let rule = Rule::loader()
+            .shake(false)
+            .from_str(
+                r#"
+                detection:
+                  A:
+                    foo: bar
+                  condition: not A
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap();
+        let document: Yaml = serde_yaml::from_str("{foo: a}").unwrap();
+        let trace = rule
+            .detection
+            .explain_evaluation(document.as_mapping().unwrap())
+            .trace();
+        assert_eq!(trace.version, TRACE_VERSION);
+        let nodes: Vec<(&str, &str, Option<&str>, Option<bool>)> = trace
+            .nodes
+            .iter()
+            .map(|n| (n.id.as_str(), n.op.as_str(), n.field.as_deref(), n.outcome))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ("/", "not", None, Some(true)),
+                ("/0", "identifier", None, Some(false)),
+                ("/0/0", "search", Some("foo"), Some(false)),
+            ]
+        );
+        let redaction = Redaction::new();
+        assert_eq!(trace.nodes[2].value_hash, Some(redaction.hash("\"a\"")));
+    }
+
+    #[test]
+    fn provenance() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                args: '*whoami*'
+                int(pid): '>100'
+              B:
+                user: root
+              C:
+                Event.Data:
+                  Name: Image
+                  Value: 'i*.EXE'
+              condition: A and not B and C
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let document: Yaml = serde_yaml::from_str(
+            r#"
+            args: [cmd.exe, /c, whoami, WHOAMI]
+            pid: '1024'
+            user: admin
+            Event:
+              Data:
+              - {Name: User, Value: admin}
+              - {Name: Image, Value: cmd.exe}
+            "#,
+        )
+        .unwrap();
+        let document = document.as_mapping().unwrap();
+        let mut matches: Vec<(String, String, String, String)> = rule
+            .detection
+            .explain_matches(document)
+            .into_iter()
+            .map(|p| (p.rule_field, p.document_path, p.matched_value, p.match_kind))
+            .collect();
+        matches.sort();
+        let expected: Vec<(String, String, String, String)> = vec![
+            ("Name", "Event.Data[1].Name", "Image", "exact"),
+            ("Value", "Event.Data[1].Value", "cmd.exe", "ends_with"),
+            ("args", "args[2]", "whoami", "contains"),
+            ("pid", "pid", "1024", ">"),
+        ]
+        .into_iter()
+        .map(|(a, b, c, d)| (a.to_owned(), b.to_owned(), c.to_owned(), d.to_owned()))
+        .collect();
+        assert_eq!(matches, expected);
+
+        let matches = rule
+            .detection
+            .explain_matches_with(document, &Redaction::new().hash_only(true));
+        assert!(matches.iter().all(|p| p.matched_value.starts_with('#')));
+
+        let document: Yaml = serde_yaml::from_str("{args: whoami, pid: 1, user: admin}").unwrap();
+        assert!(rule
+            .detection
+            .explain_matches(document.as_mapping().unwrap())
+            .is_empty());
+    }
+
+    #[test]
+    fn redact() {
+        let redaction = Redaction::new().max_length(3);
+        assert_eq!(redaction.redact("abc"), "abc");
+        assert_eq!(redaction.redact("abcd"), "abc...");
+        assert_eq!(redaction.redact("äöüß"), "äöü...");
+        let redaction = redaction.hash_only(true);
+        assert_eq!(redaction.redact("a"), "#96c20860cd93a249");
+        let keyed = redaction.clone().key(*b"0123456789abcdef");
+        assert_eq!(keyed.redact("a"), "#c686137e7f916674");
+        assert_ne!(keyed.redact("a"), redaction.redact("a"));
+    }
+}
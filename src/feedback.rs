@@ -0,0 +1,519 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value as Yaml};
+
+use crate::document::Document;
+use crate::identifier;
+use crate::parser::{self, Expression, Match};
+use crate::rule::Rule;
+use crate::ruleset;
+use crate::solver::{self, SolverResult};
+use crate::value::Value;
+
+/// An analyst's disposition of a document that was matched by a rule.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Disposition {
+    /// The match was correct.
+    TruePositive,
+    /// The match was incorrect.
+    FalsePositive,
+}
+
+/// The dispositions accumulated for a branch of a rule.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Tally {
+    /// The number of true positives attributed to the branch.
+    pub true_positives: u64,
+    /// The number of false positives attributed to the branch.
+    pub false_positives: u64,
+}
+
+impl Tally {
+    /// Returns the ratio of false positives to all dispositions, or `None` if there are none.
+    pub fn false_positive_rate(&self) -> Option<f64> {
+        let total = self.true_positives + self.false_positives;
+        if total == 0 {
+            return None;
+        }
+        Some(self.false_positives as f64 / total as f64)
+    }
+}
+
+/// Accumulates analyst dispositions for a rule, attributing them to the branches that triggered.
+///
+/// Branches are the leaf nodes of the rule's identifiers, as addressed by their stable ids (see
+/// `Node`). Dispositions are attributed by walking the evaluated condition, so a branch is only
+/// considered to have triggered when it matches the document on its own and its identifier was
+/// one of the reasons that the condition held. Identifiers that only contributed by being
+/// negated, i.e. `filter` in `A and not filter`, are never credited.
+pub struct Feedback {
+    // The leaf branches, as their id, the identifier that they belong to and their expression.
+    branches: Vec<(String, String, Expression)>,
+    condition: Expression,
+    identifiers: HashMap<String, Expression>,
+    empty: HashMap<String, Expression>,
+    tallies: BTreeMap<String, Tally>,
+    // The values seen for each tracked field, keyed by field.
+    values: BTreeMap<String, HashMap<Yaml, Tally>>,
+}
+
+/// A candidate filter for a rule, proposed from the values that only appear in false positives.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+    /// The field that the filter applies to.
+    pub field: String,
+    /// The values to filter, as they would be written in an identifier.
+    pub values: Vec<Yaml>,
+    /// The number of false positives that the filter would have removed.
+    pub false_positives: u64,
+}
+
+impl Suggestion {
+    /// Returns the suggestion as a YAML identifier named `filter`, intended to be reviewed before
+    /// being added to the rule and negated in its condition.
+    pub fn to_yaml(&self) -> String {
+        let mut values = Mapping::new();
+        values.insert(
+            Yaml::String(self.field.clone()),
+            Yaml::Sequence(self.values.clone()),
+        );
+        let mut filter = Mapping::new();
+        filter.insert(Yaml::String("filter".to_owned()), Yaml::Mapping(values));
+        let yaml = serde_yaml::to_string(&filter).expect("could not serialise suggestion");
+        match yaml.strip_prefix("---\n") {
+            Some(yaml) => yaml.to_owned(),
+            None => yaml,
+        }
+    }
+}
+
+impl Feedback {
+    /// Create a feedback loop for the provided rule.
+    ///
+    /// The branches are parsed with the same options as the rule, so that they match as they do
+    /// within it.
+    pub fn new(rule: &Rule) -> crate::Result<Self> {
+        let options = rule.detection.options();
+        let nodes = rule.nodes();
+        let mut branches = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            let leaf = match nodes.get(i + 1) {
+                Some(next) => !next.id.starts_with(&format!("{}/", node.id)),
+                None => true,
+            };
+            if leaf {
+                branches.push((
+                    node.id.clone(),
+                    node.identifier.clone(),
+                    parser::parse_identifier_with(&node.yaml, options)?,
+                ));
+            }
+        }
+        let mut fields = HashSet::new();
+        ruleset::collect_fields(
+            &rule.detection.expression,
+            &rule.detection.identifiers,
+            &mut fields,
+        );
+        Ok(Self {
+            branches,
+            condition: rule.detection.expression.clone(),
+            identifiers: rule.detection.identifiers.clone(),
+            empty: HashMap::new(),
+            tallies: BTreeMap::new(),
+            values: fields.into_iter().map(|f| (f, HashMap::new())).collect(),
+        })
+    }
+
+    /// Records a disposition for the document, returning the ids of the branches that it was
+    /// attributed to.
+    pub fn record(&mut self, document: &dyn Document, disposition: Disposition) -> Vec<&str> {
+        for (field, values) in &mut self.values {
+            let value = match document.find(field).as_ref().and_then(literal) {
+                Some(v) => v,
+                None => continue,
+            };
+            let tally = values.entry(value).or_default();
+            match disposition {
+                Disposition::TruePositive => tally.true_positives += 1,
+                Disposition::FalsePositive => tally.false_positives += 1,
+            }
+        }
+        let mut contributed = HashSet::new();
+        attribute(
+            &self.condition,
+            &self.identifiers,
+            document,
+            false,
+            &mut contributed,
+        );
+        let mut triggered = vec![];
+        for (id, identifier, expression) in &self.branches {
+            if !contributed.contains(identifier.as_str())
+                || solver::solve_expression(expression, &self.empty, document) != SolverResult::True
+            {
+                continue;
+            }
+            let tally = self.tallies.entry(id.clone()).or_default();
+            match disposition {
+                Disposition::TruePositive => tally.true_positives += 1,
+                Disposition::FalsePositive => tally.false_positives += 1,
+            }
+            triggered.push(id.as_str());
+        }
+        triggered
+    }
+
+    /// Proposes a filter for each tracked field, made up of the values that appeared in at least
+    /// `min` false positives and never in a true positive.
+    ///
+    /// Suggestions are ordered by the number of false positives that they would have removed.
+    pub fn suggest(&self, min: u64) -> Vec<Suggestion> {
+        let mut suggestions = vec![];
+        for (field, values) in &self.values {
+            let mut candidates = values
+                .iter()
+                .filter(|(_, t)| t.true_positives == 0 && t.false_positives >= min.max(1))
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                continue;
+            }
+            candidates.sort_by(|(v0, t0), (v1, t1)| {
+                t1.false_positives
+                    .cmp(&t0.false_positives)
+                    .then_with(|| format!("{:?}", v0).cmp(&format!("{:?}", v1)))
+            });
+            suggestions.push(Suggestion {
+                field: field.clone(),
+                false_positives: candidates.iter().map(|(_, t)| t.false_positives).sum(),
+                values: candidates.into_iter().map(|(v, _)| v.clone()).collect(),
+            });
+        }
+        suggestions.sort_by_key(|s| std::cmp::Reverse(s.false_positives));
+        suggestions
+    }
+
+    /// Track the values of additional fields, so that they can be proposed as filters.
+    ///
+    /// The fields used by the rule are always tracked.
+    pub fn track<I, S>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        for field in fields {
+            self.values.entry(field.into()).or_default();
+        }
+        self
+    }
+
+    /// Get the tally for a branch by its id.
+    pub fn tally(&self, id: &str) -> Option<&Tally> {
+        self.tallies.get(id)
+    }
+
+    /// Returns the tallies for all branches that have had dispositions attributed to them,
+    /// ordered by id.
+    pub fn tallies(&self) -> &BTreeMap<String, Tally> {
+        &self.tallies
+    }
+}
+
+// Collects the identifiers that the expression held because of, where `negated` is whether the
+// expression is beneath a negation. Only the nodes that held are descended into, and identifiers
+// are only collected when they held without being negated.
+fn attribute<'a>(
+    expression: &'a Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    negated: bool,
+    contributed: &mut HashSet<&'a str>,
+) {
+    let held = if negated {
+        SolverResult::False
+    } else {
+        SolverResult::True
+    };
+    if solver::solve_expression(expression, identifiers, document) != held {
+        return;
+    }
+    match expression {
+        Expression::BooleanGroup(_, group) => {
+            for expression in group {
+                attribute(expression, identifiers, document, negated, contributed);
+            }
+        }
+        Expression::BooleanExpression(left, _, right) => {
+            attribute(left, identifiers, document, negated, contributed);
+            attribute(right, identifiers, document, negated, contributed);
+        }
+        Expression::Identifier(identifier) if !negated => {
+            contributed.insert(identifier.as_str());
+        }
+        // NOTE: The members of a group within a match are counted individually, so the group
+        // itself is not evaluated, and `0 of` negates its members.
+        Expression::Match(m, e) => {
+            let negated = negated ^ (*m == Match::Of(0));
+            match e.as_ref() {
+                Expression::BooleanGroup(_, group) => {
+                    for expression in group {
+                        attribute(expression, identifiers, document, negated, contributed);
+                    }
+                }
+                e => attribute(e, identifiers, document, negated, contributed),
+            }
+        }
+        Expression::Negate(e) => attribute(e, identifiers, document, !negated, contributed),
+        _ => {}
+    }
+}
+
+// Converts a value into a literal that will match it exactly when used in an identifier.
+fn literal(value: &Value) -> Option<Yaml> {
+    match value {
+        Value::Bool(b) => Some(Yaml::Bool(*b)),
+        Value::Int(i) => Some(Yaml::Number((*i).into())),
+        Value::UInt(u) => Some(Yaml::Number((*u).into())),
+        Value::String(s) => {
+            // NOTE: Strings that could be mistaken for a pattern need to be quoted.
+            let special = s.starts_with(|c| "*?@~^i<>=\"'".contains(c))
+                || s.ends_with('*')
+                || identifier::range(s).is_some()
+                || parser::Hex::parse(s).is_some();
+            if !special {
+                Some(Yaml::String(s.to_string()))
+            } else if s.ends_with('"') {
+                Some(Yaml::String(format!("'{}'", s)))
+            } else {
+                Some(Yaml::String(format!("\"{}\"", s)))
+            }
+        }
+        Value::Null
+        | Value::Bytes(_)
+        | Value::Float(_)
+        | Value::Timestamp(_)
+        | Value::Array(_)
+        | Value::Object(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::RuleLoader;
+
+    #[test]
+    fn record() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo:
+                - foo
+                - bar
+              B:
+                baz: baz
+              condition: A and B
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let mut feedback = Feedback::new(&rule).unwrap();
+
+        let foo = serde_yaml::from_str::<Yaml>("{foo: foo, baz: baz}").unwrap();
+        let bar = serde_yaml::from_str::<Yaml>("{foo: bar, baz: baz}").unwrap();
+        assert_eq!(
+            feedback.record(foo.as_mapping().unwrap(), Disposition::TruePositive),
+            vec!["/A/foo/foo", "/B/baz"]
+        );
+        feedback.record(bar.as_mapping().unwrap(), Disposition::FalsePositive);
+        feedback.record(bar.as_mapping().unwrap(), Disposition::FalsePositive);
+
+        assert_eq!(
+            feedback.tally("/A/foo/bar").unwrap().false_positive_rate(),
+            Some(1.0)
+        );
+        assert_eq!(
+            feedback.tally("/B/baz").unwrap(),
+            &Tally {
+                true_positives: 1,
+                false_positives: 2,
+            }
+        );
+        assert!(feedback.tally("/A").is_none());
+        assert_eq!(feedback.tallies().len(), 3);
+    }
+
+    #[test]
+    fn record_negated() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: foo
+              B:
+                bar: bar
+              filter:
+                baz: baz
+              condition: (A and not filter) or (B and filter)
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let mut feedback = Feedback::new(&rule).unwrap();
+
+        // The filter was negated out and the second branch of the condition did not hold
+        let document = serde_yaml::from_str::<Yaml>("{foo: foo, bar: bar, baz: qux}").unwrap();
+        assert_eq!(
+            feedback.record(document.as_mapping().unwrap(), Disposition::FalsePositive),
+            vec!["/A/foo"]
+        );
+
+        let document = serde_yaml::from_str::<Yaml>("{foo: foo, bar: bar, baz: baz}").unwrap();
+        assert_eq!(
+            feedback.record(document.as_mapping().unwrap(), Disposition::FalsePositive),
+            vec!["/B/bar", "/filter/baz"]
+        );
+
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: foo
+              filter:
+                bar: bar
+              condition: A and not of(filter, 0)
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let mut feedback = Feedback::new(&rule).unwrap();
+        let document = serde_yaml::from_str::<Yaml>("{foo: foo, bar: bar}").unwrap();
+        assert_eq!(
+            feedback.record(document.as_mapping().unwrap(), Disposition::TruePositive),
+            vec!["/A/foo", "/filter/bar"]
+        );
+    }
+
+    #[test]
+    fn record_options() {
+        let rule = RuleLoader::new()
+            .range(true)
+            .from_str(
+                r#"
+                detection:
+                  A:
+                    port: 1..4
+                  condition: A
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap();
+        let mut feedback = Feedback::new(&rule).unwrap();
+        let document = serde_yaml::from_str::<Yaml>("{port: 2}").unwrap();
+        assert_eq!(
+            feedback.record(document.as_mapping().unwrap(), Disposition::TruePositive),
+            vec!["/A/port"]
+        );
+    }
+
+    #[test]
+    fn suggest() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                command: '*foo*'
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let mut feedback = Feedback::new(&rule).unwrap().track(vec!["user"]);
+
+        let documents = vec![
+            ("{command: foo, user: admin}", Disposition::TruePositive),
+            ("{command: foobar, user: svc}", Disposition::FalsePositive),
+            ("{command: foobar, user: svc}", Disposition::FalsePositive),
+            ("{command: ifoo, user: admin}", Disposition::FalsePositive),
+        ];
+        for (document, disposition) in documents {
+            let document = serde_yaml::from_str::<Yaml>(document).unwrap();
+            feedback.record(document.as_mapping().unwrap(), disposition);
+        }
+
+        let suggestions = feedback.suggest(1);
+        assert_eq!(
+            suggestions,
+            vec![
+                Suggestion {
+                    field: "command".to_owned(),
+                    values: vec![
+                        Yaml::String("foobar".to_owned()),
+                        Yaml::String("\"ifoo\"".to_owned())
+                    ],
+                    false_positives: 3,
+                },
+                Suggestion {
+                    field: "user".to_owned(),
+                    values: vec![Yaml::String("svc".to_owned())],
+                    false_positives: 2,
+                },
+            ]
+        );
+        assert_eq!(suggestions[1].to_yaml(), "filter:\n  user:\n    - svc\n");
+        assert_eq!(feedback.suggest(2).len(), 2);
+        assert_eq!(feedback.suggest(3).len(), 0);
+
+        // The suggestion must be usable within a rule
+        let filter = serde_yaml::from_str::<Yaml>(&suggestions[0].to_yaml()).unwrap();
+        let filter = parser::parse_identifier(&filter["filter"]).unwrap();
+        let document = serde_yaml::from_str::<Yaml>("{command: ifoo}").unwrap();
+        assert!(
+            crate::solver::solve_expression(
+                &filter,
+                &HashMap::new(),
+                document.as_mapping().unwrap()
+            ) == SolverResult::True
+        );
+    }
+
+    #[test]
+    fn literals() {
+        // NOTE: The literals must hold even when every optional pattern is enabled.
+        let options = parser::ParserOptions {
+            fuzzy: true,
+            glob: true,
+            hex: true,
+            range: true,
+            ..Default::default()
+        };
+        for value in ["foo", "^foo", "~foo", "*foo", "ifoo", "1..4", "{ 4D 5A }"] {
+            let literal = literal(&Value::String(value.into())).unwrap();
+            let mut identifier = Mapping::new();
+            identifier.insert(Yaml::String("foo".to_owned()), literal);
+            let identifier =
+                parser::parse_identifier_with(&Yaml::Mapping(identifier), &options).unwrap();
+            let document = serde_yaml::from_str::<Yaml>(&format!("{{foo: '{}'}}", value)).unwrap();
+            assert_eq!(
+                solver::solve_expression(
+                    &identifier,
+                    &HashMap::new(),
+                    document.as_mapping().unwrap()
+                ),
+                SolverResult::True,
+                "{}",
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+
+use crate::document::Document;
+use crate::limits::Rejection;
+use crate::value::{Object, Value};
+
+/// How arrays are handled when flattening.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArrayStrategy {
+    /// Each element becomes a field keyed by its index, i.e. `foo.0`.
+    Index,
+    /// Each element produces a separate record, keyed as if the array was not there.
+    ///
+    /// Every combination of the elements of sibling arrays produces a record, so the number of
+    /// records is bounded with `Flattener::records`.
+    Explode,
+}
+
+/// A segment of a path into a nested document.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PathSegment {
+    /// A key into an object.
+    Key(String),
+    /// An index into an array.
+    Index(usize),
+}
+
+/// A flattened document, mapping dotted keys to values.
+///
+/// The path that each key was flattened from is retained, as keys that contain the separator
+/// themselves mean that the key alone can not be reliably mapped back to the original document.
+#[derive(Clone, Default)]
+pub struct FlatRecord<'a> {
+    fields: BTreeMap<String, Value<'a>>,
+    paths: BTreeMap<String, Vec<PathSegment>>,
+}
+
+impl<'a> FlatRecord<'a> {
+    /// Returns the flattened fields, ordered by key.
+    pub fn fields(&self) -> &BTreeMap<String, Value<'a>> {
+        &self.fields
+    }
+
+    /// Returns the path in the original document that the key was flattened from.
+    pub fn path(&self, key: &str) -> Option<&[PathSegment]> {
+        self.paths.get(key).map(|p| p.as_slice())
+    }
+}
+
+impl<'a> Document for FlatRecord<'a> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        self.fields.get(key).cloned()
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.fields.values().cloned().collect()
+    }
+}
+
+/// Flattens nested documents into records with dotted keys.
+///
+/// Empty arrays and objects are kept as values so that no information is lost, everything else
+/// is flattened down to its scalar values.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{ArrayStrategy, Document, Flattener, PathSegment};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let event: Yaml = serde_yaml::from_str(r#"
+/// process:
+///   name: cmd.exe
+///   args: [/c, whoami]
+/// "#).unwrap();
+/// let event = event.as_mapping().unwrap();
+///
+/// let records = Flattener::new().flatten(event).unwrap();
+/// assert_eq!(records.len(), 1);
+/// assert_eq!(records[0].find("process.args.1").unwrap().as_str(), Some("whoami"));
+///
+/// let records = Flattener::new().arrays(ArrayStrategy::Explode).flatten(event).unwrap();
+/// assert_eq!(records.len(), 2);
+/// assert_eq!(records[1].find("process.args").unwrap().as_str(), Some("whoami"));
+/// assert_eq!(
+///     records[1].path("process.args").unwrap(),
+///     &[
+///         PathSegment::Key("process".to_owned()),
+///         PathSegment::Key("args".to_owned()),
+///         PathSegment::Index(1),
+///     ]
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Flattener {
+    arrays: ArrayStrategy,
+    records: usize,
+    separator: String,
+}
+
+impl Default for Flattener {
+    fn default() -> Self {
+        Self {
+            arrays: ArrayStrategy::Index,
+            records: 1024,
+            separator: ".".to_owned(),
+        }
+    }
+}
+
+impl Flattener {
+    /// Create a flattener that indexes arrays, separates keys with `.` and produces at most 1024
+    /// records from a document.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how arrays are flattened.
+    pub fn arrays(mut self, arrays: ArrayStrategy) -> Self {
+        self.arrays = arrays;
+        self
+    }
+
+    /// Set the maximum number of records that a document can be flattened into, beyond which it
+    /// is rejected with `Rejection::Records`.
+    ///
+    /// Exploding sibling arrays produces a record for every combination of their elements, so
+    /// without a bound a small document could produce an enormous number of records.
+    pub fn records(mut self, records: usize) -> Self {
+        self.records = records;
+        self
+    }
+
+    /// Set the separator used to join keys.
+    pub fn separator<S: Into<String>>(mut self, separator: S) -> Self {
+        self.separator = separator.into();
+        self
+    }
+
+    /// Flattens the object into records, there will only be more than one record when arrays are
+    /// exploded.
+    pub fn flatten<'a>(&self, object: &'a dyn Object) -> Result<Vec<FlatRecord<'a>>, Rejection> {
+        self.walk(Value::Object(object), "", &mut vec![])
+    }
+
+    fn walk<'a>(
+        &self,
+        value: Value<'a>,
+        key: &str,
+        path: &mut Vec<PathSegment>,
+    ) -> Result<Vec<FlatRecord<'a>>, Rejection> {
+        match value {
+            Value::Object(o) if o.len() > 0 => {
+                let mut keys = o.keys();
+                keys.sort();
+                let mut records = vec![FlatRecord::default()];
+                for k in keys {
+                    if let Some(v) = o.get(&k) {
+                        path.push(PathSegment::Key(k.to_string()));
+                        let children = self.walk(v, &self.join(key, &k), path)?;
+                        path.pop();
+                        records = self.product(records, children)?;
+                    }
+                }
+                Ok(records)
+            }
+            Value::Array(a) if a.len() > 0 => match self.arrays {
+                ArrayStrategy::Index => {
+                    let mut records = vec![FlatRecord::default()];
+                    for (i, v) in a.iter().enumerate() {
+                        path.push(PathSegment::Index(i));
+                        let children = self.walk(v, &self.join(key, &i.to_string()), path)?;
+                        path.pop();
+                        records = self.product(records, children)?;
+                    }
+                    Ok(records)
+                }
+                ArrayStrategy::Explode => {
+                    let mut records = vec![];
+                    for (i, v) in a.iter().enumerate() {
+                        path.push(PathSegment::Index(i));
+                        records.extend(self.walk(v, key, path)?);
+                        path.pop();
+                        if records.len() > self.records {
+                            return Err(Rejection::Records(self.records));
+                        }
+                    }
+                    Ok(records)
+                }
+            },
+            value => {
+                let mut record = FlatRecord::default();
+                record.fields.insert(key.to_owned(), value);
+                record.paths.insert(key.to_owned(), path.clone());
+                Ok(vec![record])
+            }
+        }
+    }
+
+    // Combines every record on the left with every record on the right, rejecting the document
+    // before doing so if that would produce too many records.
+    fn product<'a>(
+        &self,
+        left: Vec<FlatRecord<'a>>,
+        right: Vec<FlatRecord<'a>>,
+    ) -> Result<Vec<FlatRecord<'a>>, Rejection> {
+        let len = left.len().saturating_mul(right.len());
+        if len > self.records {
+            return Err(Rejection::Records(self.records));
+        }
+        let mut records = Vec::with_capacity(len);
+        for l in &left {
+            for r in &right {
+                let mut record = l.clone();
+                record.fields.extend(r.fields.clone());
+                record.paths.extend(r.paths.clone());
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    fn join(&self, prefix: &str, key: &str) -> String {
+        if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}{}{}", prefix, self.separator, key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    fn keys(record: &FlatRecord<'_>) -> Vec<(String, String)> {
+        record
+            .fields()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.to_string().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn flatten() {
+        let event: Yaml = serde_yaml::from_str(
+            r#"
+            a: 1
+            b:
+              c: [x, y]
+              d: {}
+            e:
+            - f: 2
+            - f: 3
+            "#,
+        )
+        .unwrap();
+        let event = event.as_mapping().unwrap();
+
+        let records = Flattener::new().separator("/").flatten(event).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            keys(&records[0]),
+            vec![
+                ("a".to_owned(), "1".to_owned()),
+                ("b/c/0".to_owned(), "x".to_owned()),
+                ("b/c/1".to_owned(), "y".to_owned()),
+                ("b/d".to_owned(), "".to_owned()),
+                ("e/0/f".to_owned(), "2".to_owned()),
+                ("e/1/f".to_owned(), "3".to_owned()),
+            ]
+        );
+        assert!(records[0].find("b/d").unwrap().is_object());
+
+        let records = Flattener::new()
+            .arrays(ArrayStrategy::Explode)
+            .flatten(event)
+            .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(
+            records.iter().map(keys).collect::<Vec<_>>(),
+            vec![
+                vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("b.c".to_owned(), "x".to_owned()),
+                    ("b.d".to_owned(), "".to_owned()),
+                    ("e.f".to_owned(), "2".to_owned()),
+                ],
+                vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("b.c".to_owned(), "x".to_owned()),
+                    ("b.d".to_owned(), "".to_owned()),
+                    ("e.f".to_owned(), "3".to_owned()),
+                ],
+                vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("b.c".to_owned(), "y".to_owned()),
+                    ("b.d".to_owned(), "".to_owned()),
+                    ("e.f".to_owned(), "2".to_owned()),
+                ],
+                vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("b.c".to_owned(), "y".to_owned()),
+                    ("b.d".to_owned(), "".to_owned()),
+                    ("e.f".to_owned(), "3".to_owned()),
+                ],
+            ]
+        );
+        assert_eq!(
+            records[3].path("e.f").unwrap(),
+            &[
+                PathSegment::Key("e".to_owned()),
+                PathSegment::Index(1),
+                PathSegment::Key("f".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn flatten_records() {
+        let event: Yaml =
+            serde_yaml::from_str("{a: [1, 2, 3], b: [1, 2, 3], c: [1, 2, 3]}").unwrap();
+        let event = event.as_mapping().unwrap();
+        let flattener = Flattener::new().arrays(ArrayStrategy::Explode);
+        assert_eq!(
+            flattener.clone().records(27).flatten(event).unwrap().len(),
+            27
+        );
+        assert_eq!(
+            flattener.records(26).flatten(event).err(),
+            Some(Rejection::Records(26))
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use crate::diagnostic::Diagnostic;
+
+// The maximum depth that patterns can reference other patterns, this guards against cycles.
+const MAX_DEPTH: usize = 32;
+
+// A subset of the patterns that ship with Logstash, rewritten where needed to avoid lookaround
+// which is not supported by the regex crate.
+const PATTERNS: &[(&str, &str)] = &[
+    ("USERNAME", r"[a-zA-Z0-9._-]+"),
+    ("USER", r"%{USERNAME}"),
+    ("EMAILLOCALPART", r"[a-zA-Z0-9._%+-]+"),
+    ("EMAILADDRESS", r"%{EMAILLOCALPART}@%{HOSTNAME}"),
+    ("INT", r"[+-]?[0-9]+"),
+    ("BASE10NUM", r"[+-]?(?:[0-9]+(?:\.[0-9]+)?|\.[0-9]+)"),
+    ("NUMBER", r"%{BASE10NUM}"),
+    ("BASE16NUM", r"[+-]?(?:0x)?[0-9A-Fa-f]+"),
+    ("POSINT", r"\b[1-9][0-9]*\b"),
+    ("NONNEGINT", r"\b[0-9]+\b"),
+    ("WORD", r"\b\w+\b"),
+    ("NOTSPACE", r"\S+"),
+    ("SPACE", r"\s*"),
+    ("DATA", r".*?"),
+    ("GREEDYDATA", r".*"),
+    ("QUOTEDSTRING", r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'"#),
+    (
+        "UUID",
+        r"[A-Fa-f0-9]{8}-(?:[A-Fa-f0-9]{4}-){3}[A-Fa-f0-9]{12}",
+    ),
+    ("MAC", r"(?:[A-Fa-f0-9]{2}[:-]){5}[A-Fa-f0-9]{2}"),
+    (
+        "IPV4",
+        r"(?:(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)\.){3}(?:25[0-5]|2[0-4][0-9]|[01]?[0-9][0-9]?)",
+    ),
+    ("IPV6", r"(?:[A-Fa-f0-9]{0,4}:){2,7}[A-Fa-f0-9]{0,4}"),
+    ("IP", r"%{IPV6}|%{IPV4}"),
+    (
+        "HOSTNAME",
+        r"\b[0-9A-Za-z][0-9A-Za-z-]{0,62}(?:\.[0-9A-Za-z][0-9A-Za-z-]{0,62})*\.?\b",
+    ),
+    ("IPORHOST", r"%{IP}|%{HOSTNAME}"),
+    ("HOSTPORT", r"%{IPORHOST}:%{POSINT}"),
+    ("UNIXPATH", r"(?:/[^/\s]*)+"),
+    ("WINPATH", r"(?:[A-Za-z]:|\\)(?:\\[^\\?*\s]*)+"),
+    ("PATH", r"%{UNIXPATH}|%{WINPATH}"),
+    ("URIPROTO", r"[A-Za-z][A-Za-z0-9+.-]+"),
+    (
+        "MONTH",
+        r"\b(?:[Jj]an(?:uary)?|[Ff]eb(?:ruary)?|[Mm]ar(?:ch)?|[Aa]pr(?:il)?|[Mm]ay|[Jj]un(?:e)?|[Jj]ul(?:y)?|[Aa]ug(?:ust)?|[Ss]ep(?:tember)?|[Oo]ct(?:ober)?|[Nn]ov(?:ember)?|[Dd]ec(?:ember)?)\b",
+    ),
+    ("MONTHNUM", r"0?[1-9]|1[0-2]"),
+    ("MONTHDAY", r"0[1-9]|[12][0-9]|3[01]|[1-9]"),
+    (
+        "DAY",
+        r"Mon(?:day)?|Tue(?:sday)?|Wed(?:nesday)?|Thu(?:rsday)?|Fri(?:day)?|Sat(?:urday)?|Sun(?:day)?",
+    ),
+    ("YEAR", r"(?:\d\d){1,2}"),
+    ("HOUR", r"2[0123]|[01]?[0-9]"),
+    ("MINUTE", r"[0-5][0-9]"),
+    ("SECOND", r"(?:[0-5]?[0-9]|60)(?:[:.,][0-9]+)?"),
+    ("TIME", r"%{HOUR}:%{MINUTE}(?::%{SECOND})?"),
+    ("ISO8601_TIMEZONE", r"Z|[+-]%{HOUR}(?::?%{MINUTE})"),
+    (
+        "TIMESTAMP_ISO8601",
+        r"%{YEAR}-%{MONTHNUM}-%{MONTHDAY}[T ]%{HOUR}:?%{MINUTE}(?::?%{SECOND})?%{ISO8601_TIMEZONE}?",
+    ),
+    ("SYSLOGTIMESTAMP", r"%{MONTH} +%{MONTHDAY} %{TIME}"),
+    ("PROG", r"[\x21-\x5a\x5c\x5e-\x7e]+"),
+    ("SYSLOGPROG", r"%{PROG:program}(?:\[%{POSINT:pid}\])?"),
+    ("SYSLOGHOST", r"%{IPORHOST}"),
+    (
+        "SYSLOGFACILITY",
+        r"<%{NONNEGINT:facility}.%{NONNEGINT:priority}>",
+    ),
+    (
+        "SYSLOGBASE",
+        r"%{SYSLOGTIMESTAMP:timestamp} (?:%{SYSLOGFACILITY} )?%{SYSLOGHOST:logsource} %{SYSLOGPROG}:",
+    ),
+    ("SYSLOGLINE", r"%{SYSLOGBASE} %{GREEDYDATA:message}"),
+    (
+        "LOGLEVEL",
+        r"[Aa]lert|ALERT|[Tt]race|TRACE|[Dd]ebug|DEBUG|[Nn]otice|NOTICE|[Ii]nfo|INFO|[Ww]arn?(?:ing)?|WARN?(?:ING)?|[Ee]rr?(?:or)?|ERR?(?:OR)?|[Cc]rit?(?:ical)?|CRIT?(?:ICAL)?|[Ff]atal|FATAL|[Ss]evere|SEVERE|EMERG(?:ENCY)?|[Ee]merg(?:ency)?",
+    ),
+];
+
+/// A library of named grok patterns.
+///
+/// Grok patterns are regular expressions that can reference other patterns by name, using
+/// `%{NAME}` to match a pattern and `%{NAME:field}` to extract what it matched as a field. A type
+/// can also be given, as in `%{NAME:field:int}`, but it is ignored as fields are always extracted
+/// as strings, use the `int` modifier in rules to cast them.
+///
+/// The library comes with a subset of the patterns that ship with Logstash, including those
+/// needed to parse classic syslog lines, and can be extended with custom patterns. Grok patterns
+/// are added to a `LineFormat` using `LineFormat::grok`.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Document, Grok, LineFormat};
+///
+/// let grok = Grok::new();
+/// let format = LineFormat::new()
+///     .grok(&grok, "%{SYSLOGLINE}")
+///     .unwrap();
+///
+/// let document = format.line("Mar  7 04:02:16 server01 sshd[1024]: Accepted publickey for root");
+/// assert_eq!(document.find("logsource").unwrap().as_str(), Some("server01"));
+/// assert_eq!(document.find("program").unwrap().as_str(), Some("sshd"));
+/// assert_eq!(document.find("pid").unwrap().as_str(), Some("1024"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Grok {
+    patterns: HashMap<String, String>,
+}
+
+impl Default for Grok {
+    fn default() -> Self {
+        Self {
+            patterns: PATTERNS
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Grok {
+    /// Create a library containing the built-in patterns.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pattern to the library, replacing any existing pattern with the same name.
+    pub fn pattern<N: Into<String>, P: Into<String>>(mut self, name: N, pattern: P) -> Self {
+        self.patterns.insert(name.into(), pattern.into());
+        self
+    }
+
+    /// Expands a grok pattern into a regular expression, with fields as named capture groups.
+    pub fn expand(&self, pattern: &str) -> crate::Result<String> {
+        self.expand_with_depth(pattern, 0)
+    }
+
+    fn expand_with_depth(&self, pattern: &str, depth: usize) -> crate::Result<String> {
+        if depth > MAX_DEPTH {
+            return Err(crate::error::parse_invalid_ident(
+                Diagnostic::new("grok.nested_too_deeply").with("pattern", pattern),
+            ));
+        }
+        let mut expanded = String::with_capacity(pattern.len());
+        let mut rest = pattern;
+        while let Some(start) = rest.find("%{") {
+            expanded.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("grok.unterminated_reference")
+                            .with("reference", &rest[start..]),
+                    ))
+                }
+            };
+            let mut parts = rest[start + 2..end].splitn(3, ':');
+            let name = parts.next().unwrap_or_default();
+            let field = parts.next().filter(|f| !f.is_empty());
+            let pattern = match self.patterns.get(name) {
+                Some(pattern) => self.expand_with_depth(pattern, depth + 1)?,
+                None => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("grok.unknown_pattern").with("pattern", name),
+                    ))
+                }
+            };
+            match field {
+                Some(field) => {
+                    expanded.push_str("(?P<");
+                    expanded.push_str(field);
+                    expanded.push('>');
+                }
+                None => expanded.push_str("(?:"),
+            }
+            expanded.push_str(&pattern);
+            expanded.push(')');
+            rest = &rest[end + 1..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use regex::Regex;
+
+    use crate::document::{Document, LineFormat};
+
+    #[test]
+    fn builtins() {
+        let grok = Grok::new();
+        for (name, _) in PATTERNS {
+            let pattern = grok.expand(&format!("%{{{}}}", name)).unwrap();
+            assert!(Regex::new(&pattern).is_ok(), "invalid pattern {}", name);
+        }
+    }
+
+    #[test]
+    fn expand() {
+        let grok = Grok::new().pattern("GREETING", "hello %{WORD:name}");
+        assert_eq!(
+            grok.expand("%{GREETING} %{INT:count:int}!").unwrap(),
+            r"(?:hello (?P<name>\b\w+\b)) (?P<count>[+-]?[0-9]+)!"
+        );
+        assert!(grok.expand("%{MISSING}").is_err());
+        assert!(grok.expand("%{WORD").is_err());
+        let grok = Grok::new().pattern("LOOP", "%{LOOP}");
+        assert!(grok.expand("%{LOOP}").is_err());
+    }
+
+    #[test]
+    fn syslog() {
+        let grok = Grok::new();
+        let format = LineFormat::new()
+            .grok(&grok, "%{SYSLOGLINE}")
+            .unwrap()
+            .grok(&grok, r"from %{IP:ip} port %{POSINT:port}")
+            .unwrap();
+        let document = format.line(
+            "Oct 15 09:14:01 bastion sshd[4242]: Failed password for root from 10.1.2.3 port 22 ssh2",
+        );
+        assert_eq!(
+            document.find("timestamp").unwrap().as_str(),
+            Some("Oct 15 09:14:01")
+        );
+        assert_eq!(
+            document.find("logsource").unwrap().as_str(),
+            Some("bastion")
+        );
+        assert_eq!(document.find("program").unwrap().as_str(), Some("sshd"));
+        assert_eq!(document.find("pid").unwrap().as_str(), Some("4242"));
+        assert_eq!(
+            document.find("message").unwrap().as_str(),
+            Some("Failed password for root from 10.1.2.3 port 22 ssh2")
+        );
+        assert_eq!(document.find("ip").unwrap().as_str(), Some("10.1.2.3"));
+        assert_eq!(document.find("port").unwrap().as_str(), Some("22"));
+    }
+}
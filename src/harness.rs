@@ -0,0 +1,364 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+
+use crate::clock::Instant;
+use crate::diagnostic::Diagnostic;
+use crate::document::CachedDocument;
+use crate::rule::Rule;
+use crate::ruleset::RuleSet;
+use crate::stats::ExpressionStats;
+
+/// The version of the `HarnessReport` format, which is bumped whenever its structure changes.
+pub const REPORT_VERSION: u32 = 1;
+
+/// Replays a corpus of documents against a set of rules, reporting how long each rule took, how
+/// often it matched and which of its expressions were the slowest.
+///
+/// The report is intended to be diffed between runs, so that performance regressions in a rule
+/// corpus are caught before it is deployed. Documents are evaluated through the `RuleSet`, exactly
+/// as `RuleSet::matches` would, so the prefilter and the scans shared between rules are included
+/// in the timings. Each rule that is evaluated is then evaluated again to time each of its
+/// expressions, so that the overhead of timing the expressions is not included in the timing of
+/// the rule.
+///
+/// Builds with the `deterministic` feature never read the clock, so every duration in their
+/// reports is zero, while the match counts are unaffected.
+///
+/// # Example
+///
+/// ```
+/// use std::io::Cursor;
+///
+/// use tau_engine::{Harness, Rule, RuleSet, REPORT_VERSION};
+///
+/// let mut rules = RuleSet::new();
+/// rules.insert("foo", Rule::from_str(r#"
+/// detection:
+///   A:
+///     foo: bar
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap());
+///
+/// let documents = Cursor::new("{\"foo\": \"bar\"}\n{\"foo\": \"baz\"}\n");
+/// let report = Harness::new().replay_set(&rules, documents).unwrap();
+/// assert_eq!(report.version, REPORT_VERSION);
+/// assert_eq!(report.documents, 2);
+/// assert_eq!(report.rules[0].id, "foo");
+/// assert_eq!(report.rules[0].matches, 1);
+/// assert_eq!(report.rules[0].slowest[0].evaluations, 2);
+///
+/// let json = serde_json::to_string(&report).unwrap();
+/// assert!(json.starts_with("{\"version\":1,"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Harness {
+    slowest: usize,
+}
+
+impl Default for Harness {
+    fn default() -> Self {
+        Self { slowest: 3 }
+    }
+}
+
+impl Harness {
+    /// Create a harness that reports the three slowest expressions of each rule.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of expressions to report for each rule, from the slowest.
+    pub fn slowest(mut self, count: usize) -> Self {
+        self.slowest = count;
+        self
+    }
+
+    /// Loads every rule in the directory, and its subdirectories, then replays the file of newline
+    /// delimited JSON documents against them.
+    ///
+    /// Rules are loaded from every `.yml` and `.yaml` file, in path order, and are identified by
+    /// their path relative to the directory. Files holding more than one rule suffix the path with
+    /// the index of each rule, i.e. `windows/process.yml#1`.
+    pub fn replay(&self, rules: &Path, documents: &Path) -> crate::Result<HarnessReport> {
+        let rules = load(rules)?;
+        let documents = File::open(documents).map_err(crate::error::rule_invalid)?;
+        self.replay_set(&rules, BufReader::new(documents))
+    }
+
+    /// Replays the newline delimited JSON documents against an existing set of rules, blank lines
+    /// are skipped.
+    ///
+    /// The rules are evaluated with the set's own configuration, so a set with the prefilter
+    /// enabled only times, and counts, the rules that the prefilter does not skip.
+    pub fn replay_set<R: BufRead>(
+        &self,
+        rules: &RuleSet,
+        documents: R,
+    ) -> crate::Result<HarnessReport> {
+        // NOTE: The programs are only used to break the timings down by expression.
+        let programs: Vec<_> = rules.iter().map(|(_, rule)| rule.compile()).collect();
+        let mut stats: Vec<_> = programs.iter().map(|p| p.stats()).collect();
+        let mut durations = vec![Duration::default(); programs.len()];
+        let mut matches = vec![0; programs.len()];
+        let mut count = 0;
+        let start = Instant::now();
+        for (i, line) in documents.lines().enumerate() {
+            let line = line.map_err(crate::error::rule_invalid)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            // NOTE: There is no error kind for documents, so they are raised as rule errors with a
+            // diagnostic that explains which line was invalid.
+            let document: Json = serde_json::from_str(&line).map_err(|e| {
+                crate::error::rule_invalid(
+                    Diagnostic::new("harness.invalid_document")
+                        .with("line", i + 1)
+                        .with("error", e),
+                )
+            })?;
+            let document = CachedDocument::new(&document);
+            for (j, timed) in rules.timed(&document).into_iter().enumerate() {
+                let (matched, duration) = match timed {
+                    Some(timed) => timed,
+                    None => continue,
+                };
+                if matched {
+                    matches[j] += 1;
+                }
+                durations[j] += duration;
+                programs[j].matches_with_stats(&document, &mut stats[j]);
+            }
+            count += 1;
+        }
+        let duration = start.elapsed();
+        Ok(HarnessReport {
+            version: REPORT_VERSION,
+            documents: count,
+            duration_ns: duration.as_nanos() as u64,
+            rules: rules
+                .iter()
+                .enumerate()
+                .map(|(j, (id, _))| RuleReport {
+                    id: id.to_owned(),
+                    matches: matches[j],
+                    duration_ns: durations[j].as_nanos() as u64,
+                    slowest: stats[j]
+                        .hottest()
+                        .into_iter()
+                        .take(self.slowest)
+                        .map(ExpressionReport::from)
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// The results of replaying a corpus of documents against a set of rules, see `Harness`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HarnessReport {
+    /// The version of the format, see `REPORT_VERSION`.
+    pub version: u32,
+    /// The number of documents that were replayed.
+    pub documents: u64,
+    /// The time taken to replay the corpus, in nanoseconds.
+    pub duration_ns: u64,
+    /// The results for each rule, in the order that they were evaluated.
+    pub rules: Vec<RuleReport>,
+}
+
+/// The results for a single rule within a `HarnessReport`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RuleReport {
+    /// The id of the rule.
+    pub id: String,
+    /// The number of documents that the rule matched.
+    pub matches: u64,
+    /// The total time spent evaluating the rule, in nanoseconds.
+    pub duration_ns: u64,
+    /// The rule's slowest expressions, from the slowest.
+    pub slowest: Vec<ExpressionReport>,
+}
+
+/// The results for a single expression of a rule within a `HarnessReport`, see `ExpressionStats`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ExpressionReport {
+    /// The expression that was evaluated.
+    pub expression: String,
+    /// The number of times that the expression was evaluated.
+    pub evaluations: u64,
+    /// The number of times that the expression evaluated to true.
+    pub matches: u64,
+    /// The total time spent evaluating the expression, in nanoseconds.
+    pub duration_ns: u64,
+}
+
+impl From<&ExpressionStats> for ExpressionReport {
+    fn from(stats: &ExpressionStats) -> Self {
+        Self {
+            expression: stats.expression.clone(),
+            evaluations: stats.evaluations,
+            matches: stats.matches,
+            duration_ns: stats.duration.as_nanos() as u64,
+        }
+    }
+}
+
+fn load(dir: &Path) -> crate::Result<RuleSet> {
+    let mut paths = vec![];
+    walk(dir, &mut paths)?;
+    paths.sort();
+    let mut set = RuleSet::new();
+    for path in paths {
+        let id = path
+            .strip_prefix(dir)
+            .unwrap_or(&path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let rules = Rule::load_all(&path)?;
+        if rules.len() == 1 {
+            set.insert(id, rules.into_iter().next().expect("could not get rule"));
+        } else {
+            for (i, rule) in rules.into_iter().enumerate() {
+                set.insert(format!("{}#{}", id, i), rule);
+            }
+        }
+    }
+    Ok(set)
+}
+
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) -> crate::Result<()> {
+    for entry in fs::read_dir(dir).map_err(crate::error::rule_invalid)? {
+        let path = entry.map_err(crate::error::rule_invalid)?.path();
+        if path.is_dir() {
+            walk(&path, paths)?;
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yml") | Some("yaml")
+        ) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn replay() {
+        let dir = std::env::temp_dir().join(format!("tau-engine-harness-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(
+            dir.join("foo.yml"),
+            r#"
+            detection:
+              A:
+                foo: bar
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("nested/multi.yaml"),
+            r#"
+detection:
+  A:
+    foo: 'ba*'
+  B:
+    bar: 'i*qux'
+  condition: A and B
+true_positives: []
+true_negatives: []
+---
+detection:
+  A:
+    bar: qux
+  condition: A
+true_positives: []
+true_negatives: []
+"#,
+        )
+        .unwrap();
+        fs::write(dir.join("README.md"), "not a rule").unwrap();
+        let documents = dir.join("documents.ndjson");
+        fs::write(
+            &documents,
+            "{\"foo\": \"bar\", \"bar\": \"QUX\"}\n\n{\"foo\": \"baz\", \"bar\": \"qux\"}\n",
+        )
+        .unwrap();
+
+        let report = Harness::new().slowest(1).replay(&dir, &documents);
+        fs::remove_dir_all(&dir).unwrap();
+        let report = report.unwrap();
+        assert_eq!(report.documents, 2);
+        assert_eq!(
+            report
+                .rules
+                .iter()
+                .map(|r| (r.id.as_str(), r.matches, r.slowest.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("foo.yml", 1, 1),
+                ("nested/multi.yaml#0", 2, 1),
+                ("nested/multi.yaml#1", 1, 1),
+            ]
+        );
+        assert_eq!(report.rules[0].slowest[0].evaluations, 2);
+        assert_eq!(report.rules[0].slowest[0].matches, 1);
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<HarnessReport>(&json).unwrap(),
+            report
+        );
+    }
+
+    #[test]
+    fn replay_prefilter() {
+        let mut rules = RuleSet::new().prefilter(true);
+        rules.insert(
+            "foo",
+            Rule::from_str(
+                r#"
+                detection:
+                  A:
+                    foo: bar
+                  condition: A
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap(),
+        );
+        let documents = Cursor::new("{\"foo\": \"bar\"}\n{\"foo\": \"baz\"}\n");
+        let report = Harness::new().replay_set(&rules, documents).unwrap();
+        assert_eq!(report.documents, 2);
+        assert_eq!(report.rules[0].matches, 1);
+        assert_eq!(report.rules[0].slowest[0].evaluations, 1);
+    }
+
+    #[test]
+    fn replay_invalid_document() {
+        let error = Harness::new()
+            .replay_set(&RuleSet::new(), Cursor::new("{}\n{foo\n"))
+            .unwrap_err();
+        let diagnostic = error.diagnostic().unwrap();
+        assert_eq!(diagnostic.code(), "harness.invalid_document");
+        assert_eq!(diagnostic.param("line"), Some("2"));
+    }
+}
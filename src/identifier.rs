@@ -1,4 +1,15 @@
-use regex::{Regex, RegexBuilder};
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::diagnostic::Diagnostic;
+use crate::limits::Complexity;
+use crate::parser::{Glob, Hex, Lookup, ParserOptions};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use crate::skeleton::skeleton;
+#[cfg(not(feature = "regex"))]
+use crate::unsupported::Regex;
 
 // Identifier string matching patterns.
 #[derive(Clone, Debug)]
@@ -13,8 +24,19 @@ pub enum Pattern {
     Exact(String),
     // `foo*`
     StartsWith(String),
+    // `~foo` or `~2~foo`
+    Fuzzy(String, usize),
+    // `^foo/**/*.txt`
+    Glob(Glob),
+    // `{ 4D 5A ?? }`
+    Hex(Hex),
+    // `@foo`
+    Lookup(Lookup),
     // `?foo`
     Regex(Regex),
+    // `?foo` where the regex requires lookaround or backreferences
+    #[cfg(feature = "fancy_regex")]
+    FancyRegex(fancy_regex::Regex),
     // `=1`
     Equal(i64),
     // `>1`
@@ -25,6 +47,8 @@ pub enum Pattern {
     LessThan(i64),
     // `<=1`
     LessThanOrEqual(i64),
+    // `1..10`
+    Range(i64, i64),
     // `=1.0`
     FEqual(f64),
     // `>1`
@@ -46,12 +70,131 @@ pub struct Identifier {
     pub pattern: Pattern,
 }
 
+// The characters that Windows command line flags can be prefixed with.
+const WINDASH: [char; 5] = ['-', '/', '\u{2013}', '\u{2014}', '\u{2015}'];
+
+// The most flags that will be expanded, as each one multiplies the variants by five.
+const WINDASH_FLAGS: usize = 5;
+
+// The largest fuzzy distance, as the cost of a search grows with it and larger distances match
+// almost anything.
+const FUZZY_DISTANCE: usize = 3;
+
+impl Identifier {
+    /// Expands the identifier into all of its windash variants, where any flag that is prefixed
+    /// with a dash or slash will be permuted with each of the other prefixes.
+    ///
+    /// At most five flags are expanded, giving 3125 variants, as any more are rejected.
+    #[cfg_attr(not(any(feature = "core", feature = "unstable")), allow(dead_code))]
+    pub fn windash(self) -> crate::Result<Vec<Identifier>> {
+        self.windash_with(&Complexity::default())
+    }
+
+    // Expands the identifier into its windash variants, checking their total length against the
+    // complexity limits before any of them are built.
+    pub(crate) fn windash_with(self, complexity: &Complexity) -> crate::Result<Vec<Identifier>> {
+        let ignore_case = self.ignore_case;
+        let (needle, wrap): (String, fn(String) -> Pattern) = match self.pattern {
+            Pattern::Contains(s) => (s, Pattern::Contains),
+            Pattern::EndsWith(s) => (s, Pattern::EndsWith),
+            Pattern::Exact(s) => (s, Pattern::Exact),
+            Pattern::StartsWith(s) => (s, Pattern::StartsWith),
+            pattern => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.windash_not_string")
+                        .with("pattern", format!("{:?}", pattern)),
+                ))
+            }
+        };
+        let flag = |c: char, previous: Option<char>| {
+            WINDASH.contains(&c) && previous.map(|p| p.is_whitespace()).unwrap_or(true)
+        };
+        let mut flags = 0;
+        let mut flag_bytes = 0;
+        let mut previous: Option<char> = None;
+        for c in needle.chars() {
+            if flag(c, previous) {
+                flags += 1;
+                flag_bytes += c.len_utf8();
+            }
+            previous = Some(c);
+        }
+        if flags > WINDASH_FLAGS {
+            return Err(crate::error::parse_invalid_ident(
+                Diagnostic::new("identifier.windash_too_many_flags")
+                    .with("limit", WINDASH_FLAGS)
+                    .with("needle", needle),
+            ));
+        }
+        // NOTE: Each flag is replaced by every dash in turn, so each dash is used by a fifth of
+        // the variants.
+        let variants = WINDASH.len().pow(flags as u32);
+        let dashes: usize = WINDASH.iter().map(|d| d.len_utf8()).sum();
+        complexity.needles(
+            variants * (needle.len() - flag_bytes) + flags * (variants / WINDASH.len()) * dashes,
+        )?;
+        let mut needles = vec![String::new()];
+        previous = None;
+        for c in needle.chars() {
+            if flag(c, previous) {
+                needles = needles
+                    .into_iter()
+                    .flat_map(|n| {
+                        WINDASH.iter().map(move |d| {
+                            let mut n = n.clone();
+                            n.push(*d);
+                            n
+                        })
+                    })
+                    .collect();
+            } else {
+                for n in &mut needles {
+                    n.push(c);
+                }
+            }
+            previous = Some(c);
+        }
+        Ok(needles
+            .into_iter()
+            .map(|n| Identifier {
+                ignore_case,
+                pattern: wrap(n),
+            })
+            .collect())
+    }
+
+    // Maps the literal text of the identifier to its skeleton, leaving any other pattern as it is.
+    #[cfg(feature = "std")]
+    pub(crate) fn skeleton(self) -> Identifier {
+        let map = |s: String| skeleton(&s).into_owned();
+        let pattern = match self.pattern {
+            Pattern::Contains(s) => Pattern::Contains(map(s)),
+            Pattern::EndsWith(s) => Pattern::EndsWith(map(s)),
+            Pattern::Exact(s) => Pattern::Exact(map(s)),
+            Pattern::StartsWith(s) => Pattern::StartsWith(map(s)),
+            Pattern::Fuzzy(s, d) => Pattern::Fuzzy(map(s), d),
+            pattern => pattern,
+        };
+        Identifier {
+            ignore_case: self.ignore_case,
+            pattern,
+        }
+    }
+}
+
 /// Parse data into an Identifier. This trait parses a Tau Engine identifier into an `Identifier`.
 pub trait IdentifierParser {
-    fn into_identifier(self) -> crate::Result<Identifier>;
+    #[cfg_attr(not(any(feature = "core", feature = "unstable")), allow(dead_code))]
+    fn into_identifier(self) -> crate::Result<Identifier>
+    where
+        Self: Sized,
+    {
+        self.into_identifier_with(&ParserOptions::default())
+    }
+    fn into_identifier_with(self, options: &ParserOptions) -> crate::Result<Identifier>;
 }
 impl IdentifierParser for String {
-    fn into_identifier(self) -> crate::Result<Identifier> {
+    fn into_identifier_with(self, options: &ParserOptions) -> crate::Result<Identifier> {
         let (insensitive, string) = if cfg!(feature = "ignore_case") {
             (true, &self[..])
         } else if let Some(s) = self.strip_prefix('i') {
@@ -60,12 +203,67 @@ impl IdentifierParser for String {
             (false, &self[..])
         };
         let pattern = if let Some(s) = string.strip_prefix('?') {
-            Pattern::Regex(
-                RegexBuilder::new(s)
-                    .case_insensitive(insensitive)
-                    .build()
-                    .map_err(crate::error::parse_invalid_ident)?,
-            )
+            match options.regex(s, insensitive) {
+                Ok(regex) => Pattern::Regex(regex),
+                // NOTE: The regex crate does not support lookaround or backreferences, so we fall
+                // back to a backtracking engine for the patterns that it fails to parse.
+                #[cfg(feature = "fancy_regex")]
+                Err(regex::Error::Syntax(_)) => {
+                    Pattern::FancyRegex(options.fancy_regex(s, insensitive)?)
+                }
+                Err(e) => return Err(crate::error::parse_invalid_ident(e)),
+            }
+        } else if let Some(name) = string
+            .strip_prefix('@')
+            .filter(|_| !options.lookups.is_empty())
+        {
+            match options.lookups.get(name) {
+                Some(set) => Pattern::Lookup(Lookup {
+                    name: name.to_owned(),
+                    set: set.clone(),
+                }),
+                None => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.unknown_lookup").with("name", name),
+                    ))
+                }
+            }
+        } else if let Some(s) = string.strip_prefix('~').filter(|_| options.fuzzy) {
+            // NOTE: The distance defaults to one, but can be set by prefixing it, i.e. `~2~foo`.
+            let (distance, s) = match s.split_once('~') {
+                Some((d, rest)) if !d.is_empty() && d.bytes().all(|b| b.is_ascii_digit()) => (
+                    d.parse::<usize>()
+                        .map_err(crate::error::parse_invalid_ident)?,
+                    rest,
+                ),
+                _ => (1, s),
+            };
+            if distance > FUZZY_DISTANCE {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.fuzzy_distance_too_large")
+                        .with("limit", FUZZY_DISTANCE)
+                        .with("distance", distance),
+                ));
+            }
+            if s.is_empty() {
+                return Err(crate::error::parse_invalid_ident(Diagnostic::new(
+                    "identifier.fuzzy_without_value",
+                )));
+            }
+            let s = if insensitive {
+                s.to_lowercase()
+            } else {
+                s.to_owned()
+            };
+            Pattern::Fuzzy(s, distance)
+        } else if let Some(s) = string.strip_prefix('^').filter(|_| options.glob) {
+            if insensitive {
+                Pattern::Glob(Glob::parse(&s.to_lowercase())?)
+            } else {
+                Pattern::Glob(Glob::parse(s)?)
+            }
+        } else if let Some(hex) = Hex::parse(string).filter(|_| options.hex) {
+            Pattern::Hex(hex)
         } else if let Some(s) = string.strip_prefix(">=") {
             if s.contains('.') {
                 Pattern::FGreaterThanOrEqual(
@@ -126,6 +324,13 @@ impl IdentifierParser for String {
                         .map_err(crate::error::parse_invalid_ident)?,
                 )
             }
+        } else if let Some((from, to)) = range(string).filter(|_| options.range) {
+            if from > to {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.range_reversed").with("range", string),
+                ));
+            }
+            Pattern::Range(from, to)
         } else if string == "*" {
             Pattern::Any
         } else if string.starts_with('*') && string.ends_with('*') {
@@ -173,10 +378,19 @@ impl IdentifierParser for String {
     }
 }
 
-#[cfg(test)]
+// Returns the bounds of an integer range of the form `from..to`, both of which are inclusive.
+pub(crate) fn range(string: &str) -> Option<(i64, i64)> {
+    let (from, to) = string.split_once("..")?;
+    Some((from.parse::<i64>().ok()?, to.parse::<i64>().ok()?))
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
     #[test]
     fn contains() {
         let identifier = "*foo*".to_owned().into_identifier().unwrap();
@@ -221,6 +435,78 @@ mod tests {
         }
     }
 
+    #[test]
+    fn range() {
+        let identifier = "1..4".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Exact(s) if s == "1..4"));
+
+        let options = ParserOptions {
+            range: true,
+            ..Default::default()
+        };
+        let identifier = "1024..49151"
+            .to_owned()
+            .into_identifier_with(&options)
+            .unwrap();
+        match identifier.pattern {
+            Pattern::Range(x, y) => {
+                assert_eq!(x, 1024);
+                assert_eq!(y, 49151);
+            }
+            _ => panic!("unexpected pattern"),
+        }
+        let identifier = "-1..1".to_owned().into_identifier_with(&options).unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Range(-1, 1)));
+        let identifier = "1.0..2".to_owned().into_identifier_with(&options).unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Exact(_)));
+        assert!("2..1".to_owned().into_identifier_with(&options).is_err());
+    }
+
+    #[test]
+    fn fuzzy() {
+        let identifier = "~svchost.exe".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Exact(s) if s == "~svchost.exe"));
+
+        let options = ParserOptions {
+            fuzzy: true,
+            ..Default::default()
+        };
+        let identifier = "~svchost.exe"
+            .to_owned()
+            .into_identifier_with(&options)
+            .unwrap();
+        match identifier.pattern {
+            Pattern::Fuzzy(x, d) => {
+                assert_eq!(x, "svchost.exe");
+                assert_eq!(d, 1);
+            }
+            _ => panic!("unexpected pattern"),
+        }
+        let identifier = "i~2~SvcHost.exe"
+            .to_owned()
+            .into_identifier_with(&options)
+            .unwrap();
+        match identifier.pattern {
+            Pattern::Fuzzy(x, d) => {
+                assert_eq!(x, "svchost.exe");
+                assert_eq!(d, 2);
+            }
+            _ => panic!("unexpected pattern"),
+        }
+        let identifier = "~1.2~3".to_owned().into_identifier_with(&options).unwrap();
+        match identifier.pattern {
+            Pattern::Fuzzy(x, d) => {
+                assert_eq!(x, "1.2~3");
+                assert_eq!(d, 1);
+            }
+            _ => panic!("unexpected pattern"),
+        }
+        assert!("~3~foo".to_owned().into_identifier_with(&options).is_ok());
+        assert!("~4~foo".to_owned().into_identifier_with(&options).is_err());
+        assert!("~".to_owned().into_identifier_with(&options).is_err());
+        assert!("~2~".to_owned().into_identifier_with(&options).is_err());
+    }
+
     #[test]
     fn greater_than() {
         let identifier = ">1".to_owned().into_identifier().unwrap();
@@ -276,6 +562,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn glob() {
+        let identifier = "^foo*".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::StartsWith(s) if s == "^foo"));
+
+        let options = ParserOptions {
+            glob: true,
+            ..Default::default()
+        };
+        let identifier = "^foo*".to_owned().into_identifier_with(&options).unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Glob(_)));
+    }
+
+    #[test]
+    fn hex() {
+        let identifier = "{ 4D 5A }".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Exact(s) if s == "{ 4D 5A }"));
+
+        let options = ParserOptions {
+            hex: true,
+            ..Default::default()
+        };
+        let identifier = "{ 4D 5A }"
+            .to_owned()
+            .into_identifier_with(&options)
+            .unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Hex(_)));
+    }
+
+    #[test]
+    fn lookup() {
+        let identifier = "@foo".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Exact(s) if s == "@foo"));
+
+        let mut options = ParserOptions::default();
+        options
+            .lookups
+            .insert("foo".to_owned(), Arc::new(HashSet::new()));
+        let identifier = "@foo".to_owned().into_identifier_with(&options).unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Lookup(_)));
+        assert!("@bar".to_owned().into_identifier_with(&options).is_err());
+    }
+
+    #[test]
+    fn windash() {
+        let identifier = "*cmd /c -k*".to_owned().into_identifier().unwrap();
+        let needles = identifier
+            .windash()
+            .unwrap()
+            .into_iter()
+            .map(|i| match i.pattern {
+                Pattern::Contains(x) => x,
+                _ => panic!("unexpected pattern"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(needles.len(), 25);
+        assert!(needles.contains(&"cmd /c -k".to_owned()));
+        assert!(needles.contains(&"cmd -c /k".to_owned()));
+        assert!(needles.contains(&"cmd \u{2013}c \u{2014}k".to_owned()));
+
+        let identifier = "a-b".to_owned().into_identifier().unwrap();
+        assert_eq!(identifier.windash().unwrap().len(), 1);
+
+        let identifier = "-a -b -c -d -e".to_owned().into_identifier().unwrap();
+        assert_eq!(identifier.windash().unwrap().len(), 3125);
+        let identifier = "-a -b -c -d -e -f".to_owned().into_identifier().unwrap();
+        let error = identifier.windash().unwrap_err();
+        assert_eq!(
+            error.diagnostic().unwrap().code(),
+            "identifier.windash_too_many_flags"
+        );
+    }
+
+    #[cfg(feature = "fancy_regex")]
+    #[test]
+    fn fancy_regex() {
+        let identifier = "?foo(?=bar)".to_owned().into_identifier().unwrap();
+        match identifier.pattern {
+            Pattern::FancyRegex(x) => {
+                assert_eq!(x.as_str(), "foo(?=bar)");
+            }
+            _ => panic!("unexpected pattern"),
+        }
+        let identifier = "?foo".to_owned().into_identifier().unwrap();
+        assert!(matches!(identifier.pattern, Pattern::Regex(_)));
+    }
+
     #[test]
     fn starts_with() {
         let identifier = "foo*".to_owned().into_identifier().unwrap();
@@ -3,7 +3,7 @@
 use std::borrow::Cow;
 
 use serde_json::map::Map;
-pub use serde_json::{Number, Value as Json};
+use serde_json::Value as Json;
 
 use crate::document::Document;
 use crate::value::{AsValue, Object, Value};
@@ -33,12 +33,19 @@ impl AsValue for Json {
 }
 
 impl Document for Json {
-    fn find(&self, key: &str) -> Option<Value> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
         if let Json::Object(o) = self {
             return Object::find(o, key);
         }
         None
     }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        match self {
+            Json::Object(o) => o.values().map(|v| v.as_value()).collect(),
+            _ => vec![],
+        }
+    }
 }
 
 impl Object for Map<String, Json> {
@@ -0,0 +1,454 @@
+use std::cell::OnceCell;
+
+use crate::document::Document;
+use crate::value::{Array, Value};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Literal {
+    Bool(bool),
+    Null,
+    Number(f64),
+    String(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equal,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    NotEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Filter {
+    // The path relative to the current element, i.e. `@.foo.bar`.
+    path: Vec<String>,
+    // When there is no comparison the filter just checks that the path exists.
+    comparison: Option<(Operator, Literal)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Step {
+    Child(String),
+    Filter(Filter),
+    Index(i64),
+    Wildcard,
+}
+
+/// A `Document` adapter that resolves JSONPath queries.
+///
+/// Keys that start with `$` are treated as JSONPath queries, allowing rules to address values that
+/// can not be reached with a plain field path, such as `$.Modules[?(@.Signed==false)].Name`. All
+/// other keys are passed through to the wrapped document untouched, so the adapter only needs to be
+/// used where rules make use of queries.
+///
+/// The following subset of JSONPath is supported:
+///
+/// - `.name` and `['name']` to select a child
+/// - `[n]` to select an element by index, negative indexes select from the end
+/// - `.*` and `[*]` to select all children
+/// - `[?(@.path)]` to select the elements that contain a path
+/// - `[?(@.path <op> literal)]` to select the elements whose path compares to a literal, where the
+///   operator is one of `==`, `!=`, `<`, `<=`, `>` and `>=`, and the literal is either a number,
+///   a quoted string, `true`, `false` or `null`
+///
+/// A query that can only ever select a single value returns that value, otherwise the selected
+/// values are returned as an array. A query that selects nothing, or that is invalid, is treated
+/// as a missing field.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Document, JsonPath};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let event: Yaml = serde_yaml::from_str(r#"
+/// Modules:
+/// - Name: foo.dll
+///   Signed: true
+/// - Name: bar.dll
+///   Signed: false
+/// "#).unwrap();
+/// let event = event.as_mapping().unwrap();
+///
+/// let document = JsonPath::new(event);
+/// let names = document.find("$.Modules[?(@.Signed==false)].Name").unwrap();
+/// let names = names.as_array().unwrap();
+/// assert_eq!(names.len(), 1);
+/// assert_eq!(names.iter().next().unwrap().as_str(), Some("bar.dll"));
+/// ```
+pub struct JsonPath<'a> {
+    document: &'a dyn Document,
+    // NOTE: Selections are kept in an append only list, as the values they hold are borrowed for
+    // as long as the adapter lives.
+    selections: OnceCell<Box<Selection<'a>>>,
+}
+
+impl<'a> JsonPath<'a> {
+    /// Wraps the document.
+    pub fn new(document: &'a dyn Document) -> Self {
+        Self {
+            document,
+            selections: OnceCell::new(),
+        }
+    }
+}
+
+impl<'a> Document for JsonPath<'a> {
+    fn find(&self, key: &str) -> Option<Value<'_>> {
+        if !key.starts_with('$') {
+            return self.document.find(key);
+        }
+        let mut cell = &self.selections;
+        let selection = loop {
+            match cell.get() {
+                Some(selection) if selection.key == key => break selection,
+                Some(selection) => cell = &selection.next,
+                None => break cell.get_or_init(|| Box::new(Selection::new(key, self.document))),
+            }
+        };
+        let values = selection.values.as_ref()?;
+        if selection.definite {
+            values.first().cloned()
+        } else if values.is_empty() {
+            None
+        } else {
+            Some(Value::Array(selection.as_ref()))
+        }
+    }
+
+    fn values(&self) -> Vec<Value<'_>> {
+        self.document.values()
+    }
+}
+
+struct Selection<'a> {
+    definite: bool,
+    key: String,
+    next: OnceCell<Box<Selection<'a>>>,
+    values: Option<Vec<Value<'a>>>,
+}
+
+impl<'a> Selection<'a> {
+    fn new(key: &str, document: &'a dyn Document) -> Self {
+        let steps = parse(key);
+        let definite = steps.as_ref().map(|s| is_definite(s)).unwrap_or(true);
+        Self {
+            definite,
+            key: key.to_owned(),
+            next: OnceCell::new(),
+            values: steps.map(|s| select(&s, document)),
+        }
+    }
+}
+
+impl<'a> Array for Selection<'a> {
+    fn iter(&self) -> Box<dyn Iterator<Item = Value<'_>> + '_> {
+        Box::new(self.values.iter().flatten().cloned())
+    }
+
+    fn len(&self) -> usize {
+        self.values.as_ref().map(|v| v.len()).unwrap_or(0)
+    }
+}
+
+fn is_definite(steps: &[Step]) -> bool {
+    steps
+        .iter()
+        .all(|s| matches!(s, Step::Child(_) | Step::Index(_)))
+}
+
+fn select<'a>(steps: &[Step], document: &'a dyn Document) -> Vec<Value<'a>> {
+    // NOTE: The root can only be accessed through a `Document`, so the first step must be a child.
+    let mut values = match steps.first() {
+        Some(Step::Child(name)) => document.find(name).into_iter().collect(),
+        _ => return vec![],
+    };
+    for step in &steps[1..] {
+        let mut next = vec![];
+        for value in values {
+            match (step, value) {
+                (Step::Child(name), Value::Object(o)) => next.extend(o.get(name)),
+                (Step::Index(i), Value::Array(a)) => {
+                    let i = if *i < 0 { a.len() as i64 + i } else { *i };
+                    if i >= 0 {
+                        next.extend(a.iter().nth(i as usize));
+                    }
+                }
+                (Step::Wildcard, Value::Array(a)) => next.extend(a.iter()),
+                (Step::Wildcard, Value::Object(o)) => {
+                    next.extend(o.keys().iter().filter_map(|k| o.get(k)))
+                }
+                (Step::Filter(f), Value::Array(a)) => {
+                    next.extend(a.iter().filter(|v| matches(f, v)))
+                }
+                _ => {}
+            }
+        }
+        values = next;
+    }
+    values
+}
+
+fn matches(filter: &Filter, value: &Value<'_>) -> bool {
+    let mut value = value.clone();
+    for name in &filter.path {
+        value = match value {
+            Value::Object(o) => match o.get(name) {
+                Some(v) => v,
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    let (operator, literal) = match &filter.comparison {
+        Some(comparison) => comparison,
+        None => return true,
+    };
+    let ordering = match (literal, &value) {
+        (Literal::Bool(x), Value::Bool(y)) => y.partial_cmp(x),
+        (Literal::Null, Value::Null) => Some(std::cmp::Ordering::Equal),
+        (Literal::Number(x), Value::Float(y)) => y.partial_cmp(x),
+        (Literal::Number(x), Value::Int(y)) => (*y as f64).partial_cmp(x),
+        (Literal::Number(x), Value::UInt(y)) => (*y as f64).partial_cmp(x),
+        (Literal::String(x), Value::String(y)) => Some(y.as_ref().cmp(x.as_str())),
+        _ => None,
+    };
+    match (operator, ordering) {
+        (Operator::NotEqual, None) => true,
+        (_, None) => false,
+        (Operator::Equal, Some(o)) => o.is_eq(),
+        (Operator::GreaterThan, Some(o)) => o.is_gt(),
+        (Operator::GreaterThanOrEqual, Some(o)) => o.is_ge(),
+        (Operator::LessThan, Some(o)) => o.is_lt(),
+        (Operator::LessThanOrEqual, Some(o)) => o.is_le(),
+        (Operator::NotEqual, Some(o)) => o.is_ne(),
+    }
+}
+
+fn parse(query: &str) -> Option<Vec<Step>> {
+    let mut rest = query.strip_prefix('$')?;
+    let mut steps = vec![];
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix(".*") {
+            steps.push(Step::Wildcard);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix('.') {
+            let (name, r) = name(r)?;
+            steps.push(Step::Child(name.to_owned()));
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("[*]") {
+            steps.push(Step::Wildcard);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("[?(") {
+            let (filter, r) = filter(r)?;
+            steps.push(Step::Filter(filter));
+            rest = r.strip_prefix(")]")?;
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']')?;
+            let inner = r[..end].trim();
+            match quoted(inner) {
+                Some((name, "")) => steps.push(Step::Child(name)),
+                Some(_) => return None,
+                None => steps.push(Step::Index(inner.parse().ok()?)),
+            }
+            rest = &r[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+fn filter(query: &str) -> Option<(Filter, &str)> {
+    let mut rest = query.trim_start().strip_prefix('@')?;
+    let mut path = vec![];
+    while let Some(r) = rest.strip_prefix('.') {
+        let (n, r) = name(r)?;
+        path.push(n.to_owned());
+        rest = r;
+    }
+    rest = rest.trim_start();
+    let operators = [
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<=", Operator::LessThanOrEqual),
+        (">=", Operator::GreaterThanOrEqual),
+        ("<", Operator::LessThan),
+        (">", Operator::GreaterThan),
+    ];
+    let operator = operators
+        .iter()
+        .find_map(|(s, o)| rest.strip_prefix(s).map(|r| (*o, r)));
+    let comparison = match operator {
+        Some((operator, r)) => {
+            let r = r.trim_start();
+            let (literal, r) = match quoted(r) {
+                Some((s, r)) => (Literal::String(s), r),
+                None => {
+                    let end = r.find(|c: char| c == ')' || c.is_whitespace())?;
+                    let literal = match &r[..end] {
+                        "true" => Literal::Bool(true),
+                        "false" => Literal::Bool(false),
+                        "null" => Literal::Null,
+                        n => Literal::Number(n.parse().ok()?),
+                    };
+                    (literal, &r[end..])
+                }
+            };
+            rest = r;
+            Some((operator, literal))
+        }
+        None => None,
+    };
+    Some((Filter { path, comparison }, rest.trim_start()))
+}
+
+fn name(query: &str) -> Option<(&str, &str)> {
+    let end = query
+        .find(|c: char| c == '.' || c == '[' || c.is_whitespace() || "=!<>)".contains(c))
+        .unwrap_or(query.len());
+    if end == 0 {
+        return None;
+    }
+    Some((&query[..end], &query[end..]))
+}
+
+fn quoted(query: &str) -> Option<(String, &str)> {
+    let quote = query.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let mut value = String::new();
+    let mut chars = query.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            c if c == quote => return Some((value, &query[i + 1..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::Rule;
+
+    #[test]
+    fn parse_queries() {
+        assert_eq!(
+            parse("$.foo['bar.baz'][0][*].*"),
+            Some(vec![
+                Step::Child("foo".to_owned()),
+                Step::Child("bar.baz".to_owned()),
+                Step::Index(0),
+                Step::Wildcard,
+                Step::Wildcard,
+            ])
+        );
+        assert_eq!(
+            parse("$.foo[?(@.bar.baz >= -1.5)][?(@.qux)][?(@ == 'a\\'b')]"),
+            Some(vec![
+                Step::Child("foo".to_owned()),
+                Step::Filter(Filter {
+                    path: vec!["bar".to_owned(), "baz".to_owned()],
+                    comparison: Some((Operator::GreaterThanOrEqual, Literal::Number(-1.5))),
+                }),
+                Step::Filter(Filter {
+                    path: vec!["qux".to_owned()],
+                    comparison: None,
+                }),
+                Step::Filter(Filter {
+                    path: vec![],
+                    comparison: Some((Operator::Equal, Literal::String("a'b".to_owned()))),
+                }),
+            ])
+        );
+        assert_eq!(parse("foo"), None);
+        assert_eq!(parse("$."), None);
+        assert_eq!(parse("$.foo[bar]"), None);
+        assert_eq!(parse("$.foo[?(@.bar == )]"), None);
+    }
+
+    #[test]
+    fn find() {
+        let event: Yaml = serde_yaml::from_str(
+            r#"
+            foo: bar
+            Modules:
+            - Name: foo.dll
+              Signed: true
+              Size: 10
+            - Name: bar.dll
+              Signed: false
+              Size: 20
+            - Name: baz.dll
+            "#,
+        )
+        .unwrap();
+        let event = event.as_mapping().unwrap();
+        let document = JsonPath::new(event);
+
+        let names = |key: &str| -> Option<Vec<String>> {
+            let value = document.find(key)?;
+            let array = value.as_array()?;
+            Some(array.iter().map(|v| v.to_string().unwrap()).collect())
+        };
+
+        assert_eq!(document.find("foo").unwrap().as_str(), Some("bar"));
+        assert_eq!(
+            document.find("$.Modules[-1].Name").unwrap().as_str(),
+            Some("baz.dll")
+        );
+        assert_eq!(
+            names("$.Modules[*].Name"),
+            Some(vec![
+                "foo.dll".to_owned(),
+                "bar.dll".to_owned(),
+                "baz.dll".to_owned()
+            ])
+        );
+        assert_eq!(
+            names("$.Modules[?(@.Signed==false)].Name"),
+            Some(vec!["bar.dll".to_owned()])
+        );
+        assert_eq!(
+            names("$.Modules[?(@.Signed)].Name"),
+            Some(vec!["foo.dll".to_owned(), "bar.dll".to_owned()])
+        );
+        assert_eq!(
+            names("$.Modules[?(@.Size > 10)].Name"),
+            Some(vec!["bar.dll".to_owned()])
+        );
+        assert!(document.find("$.Modules[?(@.Size > 20)].Name").is_none());
+        assert!(document.find("$.Modules[3]").is_none());
+        assert!(document.find("$.Modules[").is_none());
+    }
+
+    #[test]
+    fn solve() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                $.Modules[?(@.Signed==false)].Name: '*.dll'
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let signed: Yaml =
+            serde_yaml::from_str("Modules: [{Name: foo.dll, Signed: true}]").unwrap();
+        let unsigned: Yaml = serde_yaml::from_str(
+            "Modules: [{Name: foo.dll, Signed: true}, {Name: bar.dll, Signed: false}]",
+        )
+        .unwrap();
+        assert!(!rule.matches(&JsonPath::new(signed.as_mapping().unwrap())));
+        assert!(rule.matches(&JsonPath::new(unsigned.as_mapping().unwrap())));
+    }
+}
@@ -13,6 +13,9 @@
 //! - `true positives`: example documents that must evaluate to true for the given detection.
 //! - `true negatives`: example documents that must evaluate to false for the given detection.
 //!
+//! A rule can also list `false positives`, documents that it once matched in error and so must
+//! evaluate to false, which are checked along with the other examples by `Rule::validate`.
+//!
 //! The detection block is made up of a condition, and identifiers. This allows for simple but
 //! expressive rules, below is a brief summary (see [Rules](Rule) for more):
 //!
@@ -36,6 +39,11 @@
 //!
 //! Escaping can be achieved with a combination of `'` and `"`.
 //!
+//! An identifier can also be a sequence of keywords, rather than of Key/Value pairs, which matches
+//! if any string in the document, however deeply nested, contains one of them. Keywords must be
+//! literal, but can still be made case insensitive with the `i` prefix. As they are not counted,
+//! such an identifier cannot be used with `all` or with `of` for more than one match.
+//!
 //! ### Condition
 //!
 //! The condition is just a boolean expression and supports the following:
@@ -77,6 +85,8 @@
 //! This is an example of how you can tag a document against a provided rule:
 //!
 //! ```
+//! # #[cfg(feature = "std")]
+//! # fn main() {
 //! use std::borrow::Cow;
 //!
 //! use tau_engine::{Document, Rule, Value};
@@ -117,16 +127,126 @@
 //!
 //! // Evalute the document with the rule.
 //! assert_eq!(rule.matches(&foo), true);
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
 //! ```
 //!
+//! ## Translating
+//!
+//! Rules can also be translated into queries with a [`Translator`](Translator), so that they can
+//! be run against a datastore rather than in-process. SQL `WHERE` clauses and Elasticsearch's
+//! query DSL are supported, see [`Backend`](Backend).
+//!
 //! ## Features
 //!
 //! The following are a list of features that can be enabled or disabled:
+//! - **aho_corasick**: Enabled by `std`, this allows literal searches to be merged into
+//!   Aho-Corasick automatons, see [`no_std`](#no_std).
+//! - **async**: Allows rules to be solved against an [`AsyncDocument`], whose fields can be
+//!   awaited, i.e. when they are resolved from a key value store. No runtime is depended upon.
+//! - **cli**: Builds the `tau` binary, which scaffolds new rules from a [`Template`] and converts
+//!   directories of Sigma rules with the [`SigmaConverter`].
 //! - **core**: Exposes some of Tau Engine's internals.
+//! - **deterministic**: Guarantees that evaluation never reads the clock, the operating system's
+//!   randomness or the environment, see [`Engine`].
+//! - **fancy_regex**: Fall back to `fancy_regex` for regex identifiers that use lookaround or
+//!   backreferences, which are not supported by the `regex` crate.
 //! - **ignore_case**: Force the engine to always be case insensitive, this will ignore
-//! the `i` prefix and for that reason is not compatible with case sensitive rules.
+//!   the `i` prefix and for that reason is not compatible with case sensitive rules.
 //! - **json**: Enable serde json support, this will allow the tau-engine to solve on
-//! `serde_json::Value`.
+//!   `serde_json::Value`, and replay corpora of newline delimited JSON documents with the
+//!   [`Harness`].
+//! - **minimal**: Statically disables the engine's tracing events, for agents targeting tiny
+//!   binaries, see [Minimal Builds](#minimal-builds).
+//! - **regex**: Enabled by `std`, this allows regex identifiers, see [`no_std`](#no_std).
+//! - **std**: Enabled by default, this provides everything that needs the standard library,
+//!   including loading rules from YAML, see [`no_std`](#no_std).
+//! - **unicode**: Enabled by default, this pulls in the full Unicode tables for regex identifiers,
+//!   which classes such as `\p{Greek}` and `\p{Lu}` need. Unicode aware case insensitivity and
+//!   Perl classes are always available.
+//! - **unstable**: Exposes the AST, optimiser passes and solver internals in the `unstable`
+//!   module, which are not covered by semver.
+//!
+//! ## Minimal Builds
+//!
+//! The minimal build drops the optional dependencies and the larger Unicode tables, and is
+//! tested against the minimum supported Rust version, as declared by `rust-version`:
+//!
+//! ```toml
+//! [dependencies]
+//! tau-engine = { version = "1.0", default-features = false, features = ["minimal"] }
+//! ```
+//!
+//! NOTE: YAML support is not optional as rules are written in YAML.
+//!
+//! The MSRV and binary size targets are checked with `cargo xtask msrv` and `cargo xtask size`.
+//!
+//! ## no_std
+//!
+//! Without the `std` feature the crate is `no_std` and only needs `alloc`, for environments such
+//! as embedded agents, where the matching core, that is the expressions, the solver and the
+//! literal searches, is all that is built. As rules are written in YAML they can't be loaded, so
+//! expressions are instead built by hand, or parsed from conditions, through the `unstable`
+//! module. Regexes and Aho-Corasick automatons are opt in through the `regex` and `aho_corasick`
+//! features, where without `regex` the regex identifiers fail to parse:
+//!
+//! ```toml
+//! [dependencies]
+//! tau-engine = { version = "1.0", default-features = false, features = ["regex", "unstable"] }
+//! ```
+//!
+//! Without `std` there is no `HashMap`, so the core uses `BTreeMap` and `BTreeSet` in its place,
+//! there is no `SystemTime`, so timestamps are held in a `SystemTime` of the crate's own, and
+//! there is no tracing of rules. The `no_std` build needs Rust 1.81, as it relies on
+//! `core::error::Error`.
+//!
+//! NOTE: Until the next major version the crate always depended upon the standard library, so
+//! existing dependents that disable the default features must enable `std` when upgrading to keep
+//! loading rules.
+//!
+//! ## WebAssembly
+//!
+//! The crate builds for `wasm32-unknown-unknown` with any of its features, where the only
+//! difference is that, without a time source, the durations in `Stats` and `Explanation` are
+//! always zero. Bindings that load and solve rules over JSON from JavaScript, i.e. to preview
+//! rules in an editor, live in the `wasm` directory of the repository and are checked with
+//! `cargo xtask wasm`.
+//!
+//! ## Diagnostics
+//!
+//! Errors raised while loading rules carry a structured `Diagnostic`, a stable code such as
+//! `condition.unexpected_token` with named parameters, which can be rendered through a
+//! `Catalogue` so that rule-authoring feedback can be localised. Titles, as given by
+//! `Error::title`, are always English. `English::templates` lists every code and its English
+//! template as a starting point for translations:
+//!
+//! ```
+//! # #[cfg(feature = "std")]
+//! # fn main() {
+//! use std::collections::HashSet;
+//! use std::sync::Arc;
+//!
+//! use tau_engine::{Messages, Rule};
+//!
+//! let messages = Messages::new().message("identifier.unknown_lookup", "recherche inconnue - {name}");
+//! let error = Rule::loader()
+//!     .lookup("d", Arc::new(HashSet::new()))
+//!     .from_str("detection:\n  a:\n    b: '@c'\n  condition: a\n")
+//!     .unwrap_err();
+//! assert_eq!(error.diagnostic().unwrap().code(), "identifier.unknown_lookup");
+//! assert!(error.localise(&messages).ends_with("recherche inconnue - c"));
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
+//! ```
+//!
+//! ## Stability
+//!
+//! Everything exported from the root of the crate, such as `Rule`, `Document`, `RuleSet` and
+//! the results of solving, is the stable facade and follows semver. The `unstable` module, along
+//! with the older `core` module, exposes the internals that the facade is built upon, these may
+//! change in any release and so downstream crates that opt in should pin to an exact version.
 //!
 //!
 //! ### JSON
@@ -164,32 +284,161 @@
 //! ```
 
 #![cfg_attr(feature = "benchmarks", feature(test))]
+#![cfg_attr(not(feature = "std"), no_std)]
+// NOTE: Without std the core can only be reached through the unstable module.
+#![cfg_attr(not(any(feature = "std", feature = "unstable")), allow(dead_code))]
+
+extern crate alloc;
 
 #[cfg_attr(test, macro_use)]
 #[cfg(feature = "benchmarks")]
 extern crate test;
 
-pub use self::document::Document;
+#[cfg(feature = "async")]
+pub use self::asynchronous::{solve_async, AsyncDocument, FindFuture, ValuesFuture};
+#[cfg(feature = "std")]
+pub use self::correlation::{Correlation, Correlator, Sequence, Threshold};
+pub use self::diagnostic::{Catalogue, Diagnostic, English, Messages, Position};
+#[cfg(feature = "std")]
+pub use self::document::CachedDocument;
+pub use self::document::{Document, FlatDocument, Pairs};
+#[cfg(feature = "regex")]
+pub use self::document::{Line, LineFormat};
+#[cfg(feature = "std")]
+pub use self::engine::Engine;
 pub use self::error::{Error, Kind as ErrorKind};
-pub use self::rule::Rule;
-pub use self::solver::solve;
+#[cfg(feature = "std")]
+pub use self::explain::{Explanation, Provenance, Redaction, Trace, TraceNode, TRACE_VERSION};
+#[cfg(feature = "std")]
+pub use self::feedback::{Disposition, Feedback, Suggestion, Tally};
+#[cfg(feature = "std")]
+pub use self::flatten::{ArrayStrategy, FlatRecord, Flattener, PathSegment};
+#[cfg(feature = "grok")]
+pub use self::grok::Grok;
+#[cfg(feature = "json")]
+pub use self::harness::{ExpressionReport, Harness, HarnessReport, RuleReport, REPORT_VERSION};
+#[cfg(feature = "std")]
+pub use self::jsonpath::JsonPath;
+pub use self::limits::{Complexity, Limits, Rejection};
+#[cfg(feature = "std")]
+pub use self::loss::{Loss, LossKind};
+#[cfg(feature = "std")]
+pub use self::node::Node;
+#[cfg(feature = "std")]
+pub use self::policy::{Action, Policy, Resolution, Verdict};
+#[cfg(feature = "std")]
+pub use self::program::Program;
+#[cfg(feature = "std")]
+pub use self::reference::{solve_reference, Divergence};
+#[cfg(feature = "std")]
+pub use self::rule::{Metadata, Rule, RuleBuilder, RuleLoader, Severity, Status};
+#[cfg(feature = "std")]
+pub use self::ruleset::{compare, Delta, Evaluation, Matches, Routed, RuleSet, Scan};
+#[cfg(feature = "std")]
+pub use self::sigma::{Conversion, Converter as SigmaConverter, Report as ConversionReport};
+#[cfg(feature = "std")]
+pub use self::solver::{
+    solve, solve_scored, solve_with_budget, solve_with_deadline, Budget, Cancellation, Deadline,
+    Outcome, TRACE_TARGET,
+};
+#[cfg(feature = "std")]
+pub use self::stats::{ExpressionStats, Stats};
+#[cfg(feature = "std")]
+pub use self::template::Template;
+#[cfg(not(feature = "std"))]
+pub use self::timestamp::SystemTime;
+pub use self::tokeniser::{Dialect, Keywords};
+#[cfg(feature = "std")]
+pub use self::translate::{Backend, Translator};
 pub use self::value::{Array, AsValue, Object, Value};
 
 pub(crate) use error::Result;
 
+#[cfg(feature = "async")]
+mod asynchronous;
+#[cfg(feature = "std")]
+mod clock;
+#[cfg(feature = "std")]
+mod correlation;
+mod diagnostic;
 mod document;
+#[cfg(feature = "std")]
+mod engine;
 mod error;
+#[cfg(feature = "std")]
+mod explain;
+#[cfg(feature = "std")]
+mod feedback;
+#[cfg(feature = "std")]
+mod flatten;
+#[cfg(feature = "grok")]
+mod grok;
+#[cfg(feature = "json")]
+mod harness;
 mod identifier;
 #[cfg(feature = "json")]
 mod json;
+#[cfg(feature = "std")]
+mod jsonpath;
+mod limits;
+#[cfg(feature = "std")]
+mod loss;
+#[cfg(feature = "std")]
+mod node;
+#[cfg(feature = "std")]
 mod optimiser;
 mod parser;
+#[cfg(feature = "std")]
+mod placeholder;
+#[cfg(feature = "std")]
+mod policy;
+#[cfg(feature = "std")]
+mod prefilter;
+#[cfg(feature = "std")]
+mod program;
+#[cfg(feature = "std")]
+mod reference;
+#[cfg(feature = "std")]
 mod rule;
+#[cfg(feature = "std")]
+mod ruleset;
+#[cfg(feature = "std")]
+mod sigma;
+mod skeleton;
 mod solver;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod template;
+mod timestamp;
 mod tokeniser;
+#[cfg(feature = "std")]
+mod translate;
+#[cfg(not(all(feature = "aho_corasick", feature = "regex")))]
+mod unsupported;
 mod value;
+#[cfg(feature = "std")]
 mod yaml;
 
+// NOTE: Without std there is no hasher to seed, so the core falls back to the ordered collections,
+// which keep the same names so that both builds can share their code.
+mod collections {
+    #[cfg(not(feature = "std"))]
+    pub(crate) use alloc::collections::{BTreeMap as HashMap, BTreeSet as HashSet};
+    #[cfg(feature = "std")]
+    pub(crate) use std::collections::{HashMap, HashSet};
+}
+
+// The parts of the standard prelude that the core needs when it is built without std.
+#[cfg(not(feature = "std"))]
+mod prelude {
+    pub(crate) use alloc::borrow::ToOwned;
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec::Vec;
+    pub(crate) use alloc::{format, vec};
+}
+
 #[cfg(feature = "core")]
 /// Exposes some of Tau Engine's internals.
 pub mod core {
@@ -243,3 +492,60 @@ pub mod core {
         }
     }
 }
+
+#[cfg(feature = "unstable")]
+/// Exposes Tau Engine's internals, which are not covered by semver.
+///
+/// The items in this module can change in any release, including patch releases, as the engine
+/// evolves, so crates that depend upon them should pin to an exact version of Tau Engine.
+pub mod unstable {
+    /// The AST that rules are parsed into, along with the tokens and identifiers it is built from.
+    pub mod ast {
+        pub use crate::identifier::{Identifier, IdentifierParser, Pattern};
+        pub use crate::parser::{
+            fold_expression, walk_expression, Expression, Folder, Lookup, Match, MatchType,
+            ParserOptions, Search, Visitor,
+        };
+        #[cfg(feature = "std")]
+        pub use crate::parser::{parse_identifier, parse_identifier_with};
+        #[cfg(feature = "std")]
+        pub use crate::rule::Detection;
+        pub use crate::tokeniser::{BoolSym, DelSym, MatchSym, MiscSym, ModSym, Token, Tokeniser};
+
+        /// Parses the tokens of a condition into an expression.
+        pub fn parse(tokens: &[Token]) -> crate::Result<Expression> {
+            crate::parser::parse(tokens)
+        }
+    }
+
+    /// The optimisation passes that are run over expressions when rules are loaded.
+    #[cfg(feature = "std")]
+    pub mod optimiser {
+        pub use crate::optimiser::{coalesce, shake, shake_with};
+    }
+
+    /// The solver that evaluates expressions against documents.
+    pub mod solver {
+        use crate::collections::HashMap;
+        use crate::document::Document;
+        use crate::parser::Expression;
+        #[cfg(not(feature = "std"))]
+        use crate::prelude::*;
+
+        pub use crate::solver::SolverResult;
+
+        /// Evaluates a `Document` with the provided expression, and identifiers, returning the raw
+        /// result rather than collapsing `Missing` into false.
+        ///
+        /// # Panics
+        ///
+        /// This method will panic if an invalid expression is provided
+        pub fn solve_expression(
+            expression: &Expression,
+            identifiers: &HashMap<String, Expression>,
+            document: &dyn Document,
+        ) -> SolverResult {
+            crate::solver::solve_expression(expression, identifiers, document)
+        }
+    }
+}
@@ -0,0 +1,493 @@
+use core::fmt;
+
+#[cfg(feature = "std")]
+use crate::collections::HashMap;
+use crate::diagnostic::Diagnostic;
+#[cfg(feature = "std")]
+use crate::parser::{walk_expression, Expression, Search, Visitor};
+use crate::value::{Object, Value};
+
+// The size charged for values that are not strings, roughly the size of a machine word.
+const SCALAR_SIZE: usize = 8;
+
+/// The reason that a document was rejected, holding the limit that it exceeded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// The document is larger than the limit in bytes.
+    Bytes(usize),
+    /// The document is nested deeper than the limit.
+    Depth(usize),
+    /// The document has more fields than the limit.
+    Fields(usize),
+    /// The document explodes into more records than the limit, see `Flattener::records`.
+    Records(usize),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bytes(n) => write!(f, "document exceeds the limit of {} bytes", n),
+            Self::Depth(n) => write!(f, "document exceeds the depth limit of {}", n),
+            Self::Fields(n) => write!(f, "document exceeds the limit of {} fields", n),
+            Self::Records(n) => write!(f, "document exceeds the limit of {} records", n),
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for Rejection {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Rejection {}
+
+/// Limits on the size of the documents that will be evaluated.
+///
+/// The size of a document is estimated from the length of its keys and strings, with all other
+/// values charged at 8 bytes, while every key and array element counts as a field. Documents are
+/// walked only until a limit is exceeded, and an object or array whose length alone exceeds the
+/// remaining fields is rejected before any of its keys or elements are read, so with a field
+/// limit the cost of rejecting a huge document is bounded by the limits rather than by the
+/// document. Without one, the keys of each object that is walked are still collected up front, as
+/// `Object::keys` returns them all at once.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # fn main() {
+/// use tau_engine::{Limits, Rejection};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let limits = Limits::new().fields(2);
+///
+/// let event: Yaml = serde_yaml::from_str("{foo: bar, baz: [1, 2]}").unwrap();
+/// assert_eq!(limits.check(event.as_mapping().unwrap()), Err(Rejection::Fields(2)));
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    bytes: Option<usize>,
+    depth: Option<usize>,
+    fields: Option<usize>,
+}
+
+impl Limits {
+    /// Create a set of limits where nothing is limited.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum estimated size of a document in bytes.
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Set the maximum depth that a document can be nested to.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Set the maximum number of fields in a document.
+    pub fn fields(mut self, fields: usize) -> Self {
+        self.fields = Some(fields);
+        self
+    }
+
+    /// Checks the object against the limits, returning the first limit that it exceeds.
+    pub fn check(&self, object: &dyn Object) -> Result<(), Rejection> {
+        if self.bytes.is_none() && self.depth.is_none() && self.fields.is_none() {
+            return Ok(());
+        }
+        let mut usage = Usage {
+            bytes: 0,
+            fields: 0,
+        };
+        self.walk(&Value::Object(object), 0, &mut usage)
+    }
+
+    fn charge(&self, usage: &mut Usage, bytes: usize) -> Result<(), Rejection> {
+        usage.bytes += bytes;
+        usage.fields += 1;
+        match (self.bytes, self.fields) {
+            (Some(limit), _) if usage.bytes > limit => Err(Rejection::Bytes(limit)),
+            (_, Some(limit)) if usage.fields > limit => Err(Rejection::Fields(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    fn walk(&self, value: &Value<'_>, depth: usize, usage: &mut Usage) -> Result<(), Rejection> {
+        match value {
+            Value::Array(a) => {
+                self.enter(depth)?;
+                self.reserve(usage, a.len())?;
+                for v in a.iter() {
+                    self.charge(usage, 0)?;
+                    self.walk(&v, depth + 1, usage)?;
+                }
+            }
+            Value::Object(o) => {
+                self.enter(depth)?;
+                self.reserve(usage, o.len())?;
+                for k in o.keys() {
+                    self.charge(usage, k.len())?;
+                    if let Some(v) = o.get(&k) {
+                        self.walk(&v, depth + 1, usage)?;
+                    }
+                }
+            }
+            Value::String(s) => usage.bytes += s.len(),
+            _ => usage.bytes += SCALAR_SIZE,
+        }
+        match self.bytes {
+            Some(limit) if usage.bytes > limit => Err(Rejection::Bytes(limit)),
+            _ => Ok(()),
+        }
+    }
+
+    // Rejects a container up front when its length alone would exceed the field limit.
+    fn reserve(&self, usage: &Usage, len: usize) -> Result<(), Rejection> {
+        match self.fields {
+            Some(limit) if usage.fields.saturating_add(len) > limit => {
+                Err(Rejection::Fields(limit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn enter(&self, depth: usize) -> Result<(), Rejection> {
+        match self.depth {
+            Some(limit) if depth >= limit => Err(Rejection::Depth(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Usage {
+    bytes: usize,
+    fields: usize,
+}
+
+/// Limits on the complexity of the rules that will be loaded, see `RuleLoader::complexity`.
+///
+/// These protect services that load rules from untrusted sources, as a rule within the limits
+/// can't exhaust memory or stall while it is being compiled. The limits are enforced while the
+/// rule is being parsed, so that loading stops as soon as one is exceeded, and again once it has
+/// been optimised, as optimisations can merge searches. Within an identifier, the needles of each
+/// automaton, including any windash variants, and the regexes of each list are checked before they
+/// are built.
+///
+/// The depth is that of the condition, or of any identifier, on its own, the regexes include each
+/// pattern within a regex set, and the needle bytes are the total length of the needles in the
+/// Aho-Corasick automatons.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "std")]
+/// # fn main() {
+/// use tau_engine::{Complexity, Rule};
+///
+/// let rule = Rule::loader()
+///     .complexity(Complexity::new().regexes(1))
+///     .from_str(r#"
+/// detection:
+///   A:
+///     foo: '?bar'
+///     baz: '?qux'
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#);
+/// assert!(rule.is_err());
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Complexity {
+    depth: Option<usize>,
+    identifiers: Option<usize>,
+    needle_bytes: Option<usize>,
+    regexes: Option<usize>,
+}
+
+impl Complexity {
+    /// Create a set of limits where nothing is limited.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum depth of an expression.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Set the maximum number of identifiers in a rule's detection.
+    pub fn identifiers(mut self, identifiers: usize) -> Self {
+        self.identifiers = Some(identifiers);
+        self
+    }
+
+    /// Set the maximum total length in bytes of the needles in a rule's Aho-Corasick automatons.
+    pub fn needle_bytes(mut self, bytes: usize) -> Self {
+        self.needle_bytes = Some(bytes);
+        self
+    }
+
+    /// Set the maximum number of regexes in a rule.
+    pub fn regexes(mut self, regexes: usize) -> Self {
+        self.regexes = Some(regexes);
+        self
+    }
+
+    // Checks the detection's expression and identifiers against the limits.
+    #[cfg(feature = "std")]
+    pub(crate) fn check(
+        &self,
+        expression: &Expression,
+        identifiers: &HashMap<String, Expression>,
+    ) -> crate::Result<()> {
+        let mut cost = Cost {
+            identifiers: identifiers.len(),
+            ..Cost::default()
+        };
+        self.charge(&mut cost, expression)?;
+        for expression in identifiers.values() {
+            self.charge(&mut cost, expression)?;
+        }
+        Ok(())
+    }
+
+    // Adds the expression to the cost, checking the totals against the limits.
+    #[cfg(feature = "std")]
+    pub(crate) fn charge(&self, cost: &mut Cost, expression: &Expression) -> crate::Result<()> {
+        cost.depth = 0;
+        cost.visit_expression(expression);
+        self.exceeded("depth", self.depth, cost.max)?;
+        self.exceeded("identifiers", self.identifiers, cost.identifiers)?;
+        self.exceeded("needle bytes", self.needle_bytes, cost.needle_bytes)?;
+        self.exceeded("regexes", self.regexes, cost.regexes)
+    }
+
+    // Checks the nesting of a condition's parentheses, before it is parsed.
+    #[cfg(feature = "std")]
+    pub(crate) fn nesting(&self, nesting: usize) -> crate::Result<()> {
+        self.exceeded("depth", self.depth, nesting)
+    }
+
+    // Checks the total length of the needles for an automaton, before it is built.
+    pub(crate) fn needles(&self, bytes: usize) -> crate::Result<()> {
+        self.exceeded("needle bytes", self.needle_bytes, bytes)
+    }
+
+    // Checks the number of regexes in a list of values, before any of them are built.
+    #[cfg(feature = "std")]
+    pub(crate) fn patterns(&self, regexes: usize) -> crate::Result<()> {
+        self.exceeded("regexes", self.regexes, regexes)
+    }
+
+    fn exceeded(&self, kind: &str, limit: Option<usize>, used: usize) -> crate::Result<()> {
+        match limit {
+            Some(limit) if used > limit => Err(crate::error::rule_invalid(
+                Diagnostic::new("rule.too_complex")
+                    .with("kind", kind)
+                    .with("limit", limit),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// The complexity of a rule, as counted so far.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Cost {
+    depth: usize,
+    pub(crate) identifiers: usize,
+    max: usize,
+    needle_bytes: usize,
+    regexes: usize,
+}
+
+#[cfg(feature = "std")]
+impl Cost {
+    fn search(&mut self, search: &Search) {
+        match search {
+            Search::AhoCorasick(_, needles, _) => {
+                self.needle_bytes += needles.iter().map(|n| n.value().len()).sum::<usize>();
+            }
+            // NOTE: The needles are encoded as UTF-16LE, doubling their length.
+            Search::Wide(_, needles, _) => {
+                self.needle_bytes += needles.iter().map(|n| n.value().len() * 2).sum::<usize>();
+            }
+            Search::Regex(_, _) => self.regexes += 1,
+            Search::RegexSet(set, _) => self.regexes += set.len(),
+            #[cfg(feature = "fancy_regex")]
+            Search::FancyRegex(_, _) => self.regexes += 1,
+            Search::Shadow(search) | Search::Skeleton(search) => self.search(search),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Visitor for Cost {
+    fn visit_expression(&mut self, expression: &Expression) {
+        self.depth += 1;
+        self.max = self.max.max(self.depth);
+        walk_expression(self, expression);
+        self.depth -= 1;
+    }
+
+    fn visit_search(&mut self, search: &Search) {
+        self.search(search);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::Rule;
+
+    #[test]
+    fn check() {
+        let event: Yaml = serde_yaml::from_str(
+            r#"
+            foo: bar
+            baz:
+              qux: [1, 2, 3]
+            "#,
+        )
+        .unwrap();
+        let event = event.as_mapping().unwrap();
+
+        assert_eq!(Limits::new().check(event), Ok(()));
+        // 6 fields: foo, baz, qux and the three elements.
+        assert_eq!(Limits::new().fields(6).check(event), Ok(()));
+        assert_eq!(
+            Limits::new().fields(5).check(event),
+            Err(Rejection::Fields(5))
+        );
+        // 36 bytes: 'foo', 'bar', 'baz', 'qux' and three scalars.
+        assert_eq!(Limits::new().bytes(36).check(event), Ok(()));
+        assert_eq!(
+            Limits::new().bytes(35).check(event),
+            Err(Rejection::Bytes(35))
+        );
+        // The object, the nested object and the array.
+        assert_eq!(Limits::new().depth(3).check(event), Ok(()));
+        assert_eq!(
+            Limits::new().depth(2).check(event),
+            Err(Rejection::Depth(2))
+        );
+    }
+
+    #[test]
+    fn check_without_keys() {
+        struct Huge;
+        impl Object for Huge {
+            fn get(&self, _: &str) -> Option<Value<'_>> {
+                None
+            }
+            fn keys(&self) -> Vec<std::borrow::Cow<'_, str>> {
+                unreachable!()
+            }
+            fn len(&self) -> usize {
+                usize::MAX
+            }
+        }
+        assert_eq!(
+            Limits::new().fields(1_000).check(&Huge),
+            Err(Rejection::Fields(1_000))
+        );
+    }
+
+    #[test]
+    fn complexity() {
+        let rule = r#"
+        detection:
+          A:
+            foo: ['bar', 'baz', 'qux']
+            quux: '?corge'
+          B:
+            grault: '?garply'
+          condition: (A and (not B))
+        true_positives: []
+        true_negatives: []
+        "#;
+        let load = |complexity: Complexity| Rule::loader().complexity(complexity).from_str(rule);
+        let code = |complexity: Complexity| {
+            let err = load(complexity).unwrap_err();
+            let diagnostic = err.diagnostic().unwrap();
+            assert_eq!(diagnostic.code(), "rule.too_complex");
+            err.to_string()
+        };
+
+        assert!(load(Complexity::new()).is_ok());
+        assert!(load(
+            Complexity::new()
+                .depth(3)
+                .identifiers(2)
+                .needle_bytes(9)
+                .regexes(2)
+        )
+        .is_ok());
+        assert!(code(Complexity::new().identifiers(1)).contains("identifiers limit of 1"));
+        assert!(code(Complexity::new().regexes(1)).contains("regexes limit of 1"));
+        assert!(code(Complexity::new().needle_bytes(8)).contains("needle bytes limit of 8"));
+        // The parentheses are nested twice, while the condition and `A` are each three deep.
+        assert!(code(Complexity::new().depth(1)).contains("depth limit of 1"));
+        assert!(code(Complexity::new().depth(2)).contains("depth limit of 2"));
+    }
+
+    #[test]
+    fn complexity_before_build() {
+        let load = |complexity: Complexity, identifier: &str| {
+            Rule::loader().complexity(complexity).from_str(&format!(
+                "detection:\n  A:\n    {}\n  condition: A\ntrue_positives: []\ntrue_negatives: []\n",
+                identifier
+            ))
+        };
+        let kind = |complexity: Complexity, identifier: &str| {
+            let err = load(complexity, identifier).unwrap_err();
+            let diagnostic = err.diagnostic().unwrap();
+            assert_eq!(diagnostic.code(), "rule.too_complex");
+            diagnostic.param("kind").unwrap().to_owned()
+        };
+
+        // The 25 variants of `-a -b` hold 185 bytes between them.
+        let windash = "windash(foo): '*-a -b*'";
+        assert!(load(Complexity::new().needle_bytes(185), windash).is_ok());
+        assert_eq!(
+            kind(Complexity::new().needle_bytes(184), windash),
+            "needle bytes"
+        );
+        assert_eq!(
+            kind(
+                Complexity::new().needle_bytes(1024),
+                "windash(foo): '*-a -b -c -d -e*'"
+            ),
+            "needle bytes"
+        );
+        assert_eq!(
+            kind(Complexity::new().regexes(2), "foo: ['?a', 'i?b', '?c']"),
+            "regexes"
+        );
+        assert_eq!(
+            kind(Complexity::new().needle_bytes(5), "foo: ['*abc*', '*def*']"),
+            "needle bytes"
+        );
+        assert_eq!(
+            kind(Complexity::new().needle_bytes(5), "wide(foo): ['*abc*']"),
+            "needle bytes"
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+
+/// How a construct was handled when it could not be represented exactly.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LossKind {
+    /// The construct was converted, but may not behave exactly the same.
+    Approximated,
+    /// The construct could not be converted, and so neither could the rule containing it.
+    Unsupported,
+}
+
+/// A construct that could not be represented exactly when converting a rule to or from another
+/// format.
+///
+/// Losses are intended to be reviewed by hand, so they point at where the construct is in the
+/// source rule and explain why it could not be represented.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Loss {
+    /// Where the construct is in the source rule, as a JSON Pointer.
+    pub location: String,
+    /// The construct as it appears in the source rule.
+    pub construct: String,
+    /// How the construct was handled.
+    pub kind: LossKind,
+    /// Why the construct could not be represented exactly.
+    pub reason: String,
+}
+
+impl Loss {
+    pub(crate) fn approximated<A, B, C>(location: A, construct: B, reason: C) -> Self
+    where
+        A: Into<String>,
+        B: Into<String>,
+        C: Into<String>,
+    {
+        Self {
+            location: location.into(),
+            construct: construct.into(),
+            kind: LossKind::Approximated,
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn unsupported<A, B, C>(location: A, construct: B, reason: C) -> Self
+    where
+        A: Into<String>,
+        B: Into<String>,
+        C: Into<String>,
+    {
+        Self {
+            location: location.into(),
+            construct: construct.into(),
+            kind: LossKind::Unsupported,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Loss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            LossKind::Approximated => "approximated",
+            LossKind::Unsupported => "unsupported",
+        };
+        write!(
+            f,
+            "{}: {} '{}' - {}",
+            self.location, kind, self.construct, self.reason
+        )
+    }
+}
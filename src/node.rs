@@ -0,0 +1,227 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_yaml::{Mapping, Value as Yaml};
+
+/// A node within a rule's identifiers that can be referenced by a stable id.
+///
+/// The id of a node is a JSON Pointer into the detection block, built from identifier names and
+/// keys, where the elements of a sequence are addressed by their content rather than their
+/// position: a scalar by its value and a mapping by its keys, joined with `,`. For example,
+/// `/A/foo/bar` refers to the value `bar` for the key `foo` in identifier `A`. Should siblings
+/// share the same content, the later ones are suffixed with their occurrence, i.e. `bar#2`.
+///
+/// As ids are derived from names and values, rather than positions or the rule's parsed form,
+/// they are unaffected by unrelated edits to the detection and survive the rule being serialised
+/// and loaded again, allowing external tools to attach annotations to specific branches of a
+/// rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    /// The stable id of the node.
+    pub id: String,
+    /// The identifier that the node belongs to.
+    pub identifier: String,
+    /// The node as a standalone identifier, so that it can be parsed and evaluated in isolation.
+    pub yaml: Yaml,
+}
+
+enum Segment<'a> {
+    Index,
+    Key(&'a Yaml),
+}
+
+/// Collects the nodes for the provided identifiers, ordered by identifier and then depth first.
+pub(crate) fn collect(identifiers: &HashMap<String, Yaml>) -> Vec<Node> {
+    let identifiers: BTreeMap<&String, &Yaml> = identifiers.iter().collect();
+    let mut nodes = vec![];
+    for (identifier, yaml) in identifiers {
+        let id = format!("/{}", escape(identifier));
+        walk(identifier, id, yaml, &mut vec![], &mut nodes);
+    }
+    nodes
+}
+
+fn walk<'a>(
+    identifier: &str,
+    id: String,
+    yaml: &'a Yaml,
+    path: &mut Vec<Segment<'a>>,
+    nodes: &mut Vec<Node>,
+) {
+    // NOTE: The standalone form is built by wrapping the node in each of its parents, where a
+    // sequence is reduced to just the one element.
+    let standalone = path
+        .iter()
+        .rev()
+        .fold(yaml.clone(), |inner, segment| match segment {
+            Segment::Index => Yaml::Sequence(vec![inner]),
+            Segment::Key(key) => {
+                let mut mapping = Mapping::new();
+                mapping.insert((*key).clone(), inner);
+                Yaml::Mapping(mapping)
+            }
+        });
+    nodes.push(Node {
+        id: id.clone(),
+        identifier: identifier.to_owned(),
+        yaml: standalone,
+    });
+    match yaml {
+        Yaml::Mapping(mapping) => {
+            for (k, v) in mapping {
+                let key = match scalar(k) {
+                    Some(key) => escape(&key),
+                    None => continue,
+                };
+                path.push(Segment::Key(k));
+                walk(identifier, format!("{}/{}", id, key), v, path, nodes);
+                path.pop();
+            }
+        }
+        Yaml::Sequence(sequence) => {
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            for v in sequence {
+                let token = token(v);
+                let count = seen.entry(token.clone()).or_default();
+                *count += 1;
+                let token = match *count {
+                    1 => token,
+                    n => format!("{}#{}", token, n),
+                };
+                path.push(Segment::Index);
+                walk(identifier, format!("{}/{}", id, token), v, path, nodes);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+// Returns the reference token for an element of a sequence, which is derived from its content.
+fn token(yaml: &Yaml) -> String {
+    match yaml {
+        Yaml::Mapping(mapping) => mapping
+            .iter()
+            .filter_map(|(k, _)| scalar(k))
+            .map(|k| escape(&k))
+            .collect::<Vec<_>>()
+            .join(","),
+        Yaml::Sequence(_) => "[]".to_owned(),
+        yaml => scalar(yaml).map(|s| escape(&s)).unwrap_or_default(),
+    }
+}
+
+fn scalar(yaml: &Yaml) -> Option<String> {
+    match yaml {
+        Yaml::Bool(b) => Some(b.to_string()),
+        Yaml::Null => Some("null".to_owned()),
+        Yaml::Number(n) => Some(n.to_string()),
+        Yaml::String(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// Escapes a reference token as defined in RFC 6901.
+pub(crate) fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::parser;
+    use crate::rule::Rule;
+
+    const RULE: &str = r#"
+    detection:
+      A:
+        foo: foo
+        bar:
+        - bar
+        - baz
+      B:
+      - c: d
+      - nested:
+          e: f
+      x/y:
+        g: h
+      condition: A or B
+    true_positives: []
+    true_negatives: []
+    "#;
+
+    #[test]
+    fn ids() {
+        let rule = Rule::from_str(RULE).unwrap();
+        let ids = rule.nodes().iter().map(|n| &n.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                "/A",
+                "/A/foo",
+                "/A/bar",
+                "/A/bar/bar",
+                "/A/bar/baz",
+                "/B",
+                "/B/c",
+                "/B/c/c",
+                "/B/nested",
+                "/B/nested/nested",
+                "/B/nested/nested/e",
+                "/x~1y",
+                "/x~1y/g",
+            ]
+        );
+    }
+
+    #[test]
+    fn stable() {
+        // Inserting a value must not change the ids of its siblings
+        let rule = Rule::from_str(&RULE.replace("- bar\n", "- qux\n        - bar\n")).unwrap();
+        let node = rule.node("/A/bar/baz").unwrap();
+        assert_eq!(
+            node.yaml,
+            serde_yaml::from_str::<Yaml>("bar: [baz]").unwrap()
+        );
+        assert!(rule.node("/A/bar/qux").is_some());
+
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: [bar, bar, 1]
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let ids = rule.nodes().iter().map(|n| &n.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec!["/A", "/A/foo", "/A/foo/bar", "/A/foo/bar#2", "/A/foo/1"]
+        );
+    }
+
+    #[test]
+    fn reserialised() {
+        let rule = Rule::from_str(RULE).unwrap();
+        let yaml = serde_yaml::to_string(&rule).unwrap();
+        let reloaded = Rule::from_str(&yaml).unwrap();
+        assert_eq!(rule.nodes(), reloaded.nodes());
+    }
+
+    #[test]
+    fn standalone() {
+        let rule = Rule::from_str(RULE).unwrap();
+        let node = rule.node("/A/bar/baz").unwrap();
+        assert_eq!(node.identifier, "A");
+        assert_eq!(
+            node.yaml,
+            serde_yaml::from_str::<Yaml>("bar: [baz]").unwrap()
+        );
+        for node in rule.nodes() {
+            assert!(parser::parse_identifier(&node.yaml).is_ok(), "{}", node.id);
+        }
+    }
+}
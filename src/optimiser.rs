@@ -1,49 +1,119 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
-use aho_corasick::AhoCorasickBuilder;
-use regex::{RegexBuilder, RegexSetBuilder};
-
-use crate::parser::{Expression, Match, MatchType, Search};
+use crate::parser::{
+    fold_expression, walk_expression, Expression, Folder, Match, MatchType, ParserOptions, Search,
+    Visitor,
+};
 use crate::tokeniser::BoolSym;
 
 pub fn coalesce(expression: Expression, identifiers: &HashMap<String, Expression>) -> Expression {
-    match expression {
-        Expression::BooleanGroup(symbol, expressions) => {
-            let mut scratch = vec![];
-            for expression in expressions {
-                scratch.push(coalesce(expression, identifiers));
+    struct Coalesce<'a>(&'a HashMap<String, Expression>);
+    impl<'a> Folder for Coalesce<'a> {
+        fn fold_expression(&mut self, expression: Expression) -> Expression {
+            match expression {
+                Expression::Identifier(i) => {
+                    self.0.get(&i).expect("could not get identifier").clone()
+                }
+                // NOTE: An identifier that is not a group counts as one match, so once inlined it
+                // must not be mistaken for nested pairs whose elements are counted.
+                Expression::Match(Match::Of(n), e) if matches!(*e, Expression::Identifier(_)) => {
+                    match self.fold_expression(*e) {
+                        e @ Expression::BooleanGroup(_, _) => {
+                            Expression::Match(Match::Of(n), Box::new(e))
+                        }
+                        e => Expression::Match(Match::Of(n.min(1)), Box::new(e)),
+                    }
+                }
+                expression => fold_expression(self, expression),
             }
-            Expression::BooleanGroup(symbol, scratch)
         }
-        Expression::BooleanExpression(left, symbol, right) => {
-            let left = coalesce(*left, identifiers);
-            let right = coalesce(*right, identifiers);
-            Expression::BooleanExpression(Box::new(left), symbol, Box::new(right))
+    }
+    Coalesce(identifiers).fold_expression(expression)
+}
+
+// Returns the identifiers that are matched by `all` or `of` in the expression.
+pub(crate) fn matched(expression: &Expression) -> HashSet<String> {
+    struct Matched(HashSet<String>);
+    impl Visitor for Matched {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Match(_, e) = expression {
+                if let Expression::Identifier(i) = e.as_ref() {
+                    self.0.insert(i.clone());
+                }
+            }
+            walk_expression(self, expression);
         }
-        Expression::Identifier(i) => identifiers
-            .get(&i)
-            .expect("could not get identifier")
-            .clone(),
-        Expression::Match(symbol, expression) => {
-            Expression::Match(symbol, Box::new(coalesce(*expression, identifiers)))
+    }
+    let mut matched = Matched(HashSet::new());
+    matched.visit_expression(expression);
+    matched.0
+}
+
+// Shakes an expression that is matched by `all` or `of`.
+//
+// NOTE: The solver evaluates the members of a match one by one, where a merged search counts each
+// of its patterns and nested pairs count the elements of an array, so the members are shaken on
+// their own, merged searches are left as they are, and a group that shakes down into one of those
+// is kept as a group.
+pub(crate) fn shake_match(
+    expression: Expression,
+    rewrite: bool,
+    options: &ParserOptions,
+) -> Expression {
+    let keep = |expression: Expression| {
+        if let Expression::Search(ref s, _, _) = expression {
+            if s.patterns().is_some() {
+                return expression;
+            }
         }
-        Expression::Negate(expression) => {
-            Expression::Negate(Box::new(coalesce(*expression, identifiers)))
+        let group = matches!(expression, Expression::BooleanGroup(_, _));
+        match shake_with(expression, rewrite, options) {
+            shaken @ Expression::Nested(_, _) if group => {
+                Expression::BooleanGroup(BoolSym::Or, vec![shaken])
+            }
+            Expression::Search(s, f, c) if group && s.patterns().is_some() => {
+                Expression::BooleanGroup(BoolSym::Or, vec![Expression::Search(s, f, c)])
+            }
+            shaken => shaken,
         }
-        Expression::Nested(field, expression) => {
-            Expression::Nested(field, Box::new(coalesce(*expression, identifiers)))
+    };
+    match expression {
+        Expression::BooleanGroup(symbol, group) => {
+            Expression::BooleanGroup(symbol, group.into_iter().map(keep).collect())
+        }
+        expression => keep(expression),
+    }
+}
+
+#[cfg_attr(not(feature = "core"), allow(dead_code))]
+pub fn shadow(expression: Expression) -> Expression {
+    shadow_with(expression, &ParserOptions::default())
+}
+
+/// Shadows the expression's case insensitive searches, building any automatons with the options.
+pub fn shadow_with(expression: Expression, options: &ParserOptions) -> Expression {
+    struct Shadow<'a>(&'a ParserOptions);
+    impl Folder for Shadow<'_> {
+        fn fold_expression(&mut self, expression: Expression) -> Expression {
+            match expression {
+                Expression::Keywords(search) => Expression::Keywords(shadow_search(search, self.0)),
+                Expression::Search(search, field, cast) => {
+                    Expression::Search(shadow_search(search, self.0), field, cast)
+                }
+                expression => fold_expression(self, expression),
+            }
         }
-        Expression::Boolean(_)
-        | Expression::Cast(_, _)
-        | Expression::Field(_)
-        | Expression::Float(_)
-        | Expression::Integer(_)
-        | Expression::Null
-        | Expression::Search(_, _, _) => expression,
     }
+    Shadow(options).fold_expression(expression)
 }
 
+#[cfg_attr(not(any(feature = "core", feature = "unstable")), allow(dead_code))]
 pub fn shake(expression: Expression, rewrite: bool) -> Expression {
+    shake_with(expression, rewrite, &ParserOptions::default())
+}
+
+/// Shakes the expression, only merging searches in the ways that the options allow.
+pub fn shake_with(expression: Expression, rewrite: bool, options: &ParserOptions) -> Expression {
     match expression {
         Expression::BooleanGroup(symbol, expressions) => {
             let length = expressions.len();
@@ -51,15 +121,18 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                 BoolSym::And => {
                     let mut scratch = vec![];
                     for expression in expressions {
-                        let shaken = shake(expression, rewrite);
+                        let shaken = shake_with(expression, rewrite, options);
                         scratch.push(shaken);
                     }
                     scratch
                 }
                 BoolSym::Or => {
-                    let mut needles = HashMap::new();
-                    let mut nested = HashMap::new();
-                    let mut patterns = HashMap::new();
+                    // NOTE: Ordered maps are used so that the shaken expression is the same on every
+                    // run, which keeps the evaluation order, and anything derived from the
+                    // expression, stable.
+                    let mut needles = BTreeMap::new();
+                    let mut nested = BTreeMap::new();
+                    let mut patterns = BTreeMap::new();
 
                     // NOTE: Order is crucial here just like in the parser, thus we copy its ideal
                     // ordering.
@@ -74,7 +147,7 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                     let mut rest = vec![];
 
                     for expression in expressions {
-                        let shaken = shake(expression, rewrite);
+                        let shaken = shake_with(expression, rewrite, options);
 
                         match shaken {
                             Expression::Nested(field, expression) => {
@@ -133,98 +206,114 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                     }
 
                     for ((field, cast, insensitive), searches) in needles {
-                        if !insensitive && searches.len() == 1 {
-                            let search = searches.into_iter().next().expect("could not get search");
-                            match search.0 {
-                                MatchType::Contains(v) => {
-                                    contains.push(Expression::Search(
-                                        Search::Contains(v),
-                                        field,
-                                        cast,
-                                    ));
-                                }
-                                MatchType::EndsWith(v) => {
-                                    ends_with.push(Expression::Search(
-                                        Search::EndsWith(v),
-                                        field,
-                                        cast,
-                                    ));
-                                }
-                                MatchType::Exact(v) => {
-                                    exact.push(Expression::Search(Search::Exact(v), field, cast));
-                                }
-                                MatchType::StartsWith(v) => {
-                                    starts_with.push(Expression::Search(
-                                        Search::StartsWith(v),
-                                        field,
-                                        cast,
-                                    ));
-                                }
-                            };
+                        // NOTE: When merging is disabled each needle is kept as its own search.
+                        let searches = if options.aho_corasick {
+                            vec![searches]
                         } else {
-                            let (context, needles): (Vec<_>, Vec<_>) = searches.into_iter().unzip();
-                            let expression = Expression::Search(
-                                Search::AhoCorasick(
-                                    Box::new(
-                                        AhoCorasickBuilder::new()
-                                            .dfa(true)
-                                            .ascii_case_insensitive(insensitive)
-                                            .build(needles),
-                                    ),
-                                    context,
-                                    insensitive,
-                                ),
-                                field,
-                                cast,
-                            );
-                            aho.push(expression);
+                            searches.into_iter().map(|s| vec![s]).collect()
                         };
+                        for searches in searches {
+                            let field = field.clone();
+                            if !insensitive && searches.len() == 1 {
+                                let search =
+                                    searches.into_iter().next().expect("could not get search");
+                                match search.0 {
+                                    MatchType::Contains(v) => {
+                                        contains.push(Expression::Search(
+                                            Search::Contains(v),
+                                            field,
+                                            cast,
+                                        ));
+                                    }
+                                    MatchType::EndsWith(v) => {
+                                        ends_with.push(Expression::Search(
+                                            Search::EndsWith(v),
+                                            field,
+                                            cast,
+                                        ));
+                                    }
+                                    MatchType::Exact(v) => {
+                                        exact.push(Expression::Search(
+                                            Search::Exact(v),
+                                            field,
+                                            cast,
+                                        ));
+                                    }
+                                    MatchType::StartsWith(v) => {
+                                        starts_with.push(Expression::Search(
+                                            Search::StartsWith(v),
+                                            field,
+                                            cast,
+                                        ));
+                                    }
+                                };
+                            } else {
+                                let (context, needles): (Vec<_>, Vec<_>) =
+                                    searches.into_iter().unzip();
+                                let expression = Expression::Search(
+                                    Search::AhoCorasick(
+                                        Box::new(options.automaton(needles, insensitive)),
+                                        context,
+                                        insensitive,
+                                    ),
+                                    field,
+                                    cast,
+                                );
+                                aho.push(expression);
+                            };
+                        }
                     }
 
                     for (field, expressions) in nested {
                         let shaken = if expressions.len() == 1 {
-                            shake(
+                            shake_with(
                                 expressions
                                     .into_iter()
                                     .next()
                                     .expect("could not get expression"),
                                 rewrite,
+                                options,
                             )
                         } else {
-                            shake(Expression::BooleanGroup(symbol, expressions), rewrite)
+                            shake_with(
+                                Expression::BooleanGroup(symbol, expressions),
+                                rewrite,
+                                options,
+                            )
                         };
                         rest.push(Expression::Nested(field, Box::new(shaken)));
                     }
 
                     for ((field, cast, insensitive), patterns) in patterns {
-                        if patterns.len() == 1 {
-                            let pattern =
-                                patterns.into_iter().next().expect("could not get pattern");
-                            let expression = Expression::Search(
-                                Search::Regex(
-                                    RegexBuilder::new(&pattern)
-                                        .case_insensitive(insensitive)
-                                        .build()
-                                        .expect("could not build regex"),
-                                    insensitive,
-                                ),
-                                field,
-                                cast,
-                            );
-                            regex.push(expression);
+                        // NOTE: A merged set can exceed the size limits that each of its patterns
+                        // fit within, in which case they are left as separate regexes.
+                        let set = if patterns.len() > 1 && options.regex_set {
+                            options.regex_set(&patterns, insensitive).ok()
                         } else {
-                            let expression = Expression::Search(
-                                Search::RegexSet(
-                                    RegexSetBuilder::new(patterns)
-                                        .case_insensitive(insensitive)
-                                        .build()
-                                        .expect("could not build regex set"),
-                                    insensitive,
-                                ),
-                                field,
-                                cast,
-                            );
-                            regex_set.push(expression);
+                            None
+                        };
+                        match set {
+                            Some(set) => {
+                                regex_set.push(Expression::Search(
+                                    Search::RegexSet(set, insensitive),
+                                    field,
+                                    cast,
+                                ));
+                            }
+                            None => {
+                                for pattern in patterns {
+                                    regex.push(Expression::Search(
+                                        Search::Regex(
+                                            options
+                                                .regex(&pattern, insensitive)
+                                                .expect("could not build regex"),
+                                            insensitive,
+                                        ),
+                                        field.clone(),
+                                        cast,
+                                    ));
+                                }
+                            }
                         }
                     }
 
@@ -291,8 +380,16 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                 }
                 _ => unreachable!(),
             };
+            let mut expressions = expressions;
+            if let Some(constant) = fold(symbol, &mut expressions) {
+                return Expression::Boolean(constant);
+            }
             if expressions.len() != length {
-                shake(Expression::BooleanGroup(symbol, expressions), rewrite)
+                shake_with(
+                    Expression::BooleanGroup(symbol, expressions),
+                    rewrite,
+                    options,
+                )
             } else if expressions.len() == 1 {
                 expressions
                     .into_iter()
@@ -303,8 +400,11 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
             }
         }
         Expression::BooleanExpression(left, symbol, right) => {
-            let left = shake(*left, rewrite);
-            let right = shake(*right, rewrite);
+            let left = shake_with(*left, rewrite, options);
+            let right = shake_with(*right, rewrite, options);
+            if let Some(constant) = compare(&left, symbol, &right) {
+                return Expression::Boolean(constant);
+            }
             match (left, symbol, right) {
                 (
                     Expression::BooleanGroup(BoolSym::And, mut left),
@@ -312,16 +412,28 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                     Expression::BooleanGroup(BoolSym::And, right),
                 ) => {
                     left.extend(right);
-                    shake(Expression::BooleanGroup(BoolSym::And, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::And, left),
+                        rewrite,
+                        options,
+                    )
                 }
                 (Expression::BooleanGroup(BoolSym::And, mut left), BoolSym::And, right) => {
                     left.push(right);
-                    shake(Expression::BooleanGroup(BoolSym::And, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::And, left),
+                        rewrite,
+                        options,
+                    )
                 }
                 (left, BoolSym::And, Expression::BooleanGroup(BoolSym::And, right)) => {
                     let mut left = vec![left];
                     left.extend(right);
-                    shake(Expression::BooleanGroup(BoolSym::And, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::And, left),
+                        rewrite,
+                        options,
+                    )
                 }
                 (
                     Expression::BooleanGroup(BoolSym::Or, mut left),
@@ -329,86 +441,117 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
                     Expression::BooleanGroup(BoolSym::Or, right),
                 ) => {
                     left.extend(right);
-                    shake(Expression::BooleanGroup(BoolSym::Or, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::Or, left),
+                        rewrite,
+                        options,
+                    )
                 }
                 (Expression::BooleanGroup(BoolSym::Or, mut left), BoolSym::Or, right) => {
                     left.push(right);
-                    shake(Expression::BooleanGroup(BoolSym::Or, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::Or, left),
+                        rewrite,
+                        options,
+                    )
                 }
                 (left, BoolSym::Or, Expression::BooleanGroup(BoolSym::Or, right)) => {
                     let mut left = vec![left];
                     left.extend(right);
-                    shake(Expression::BooleanGroup(BoolSym::Or, left), rewrite)
+                    shake_with(
+                        Expression::BooleanGroup(BoolSym::Or, left),
+                        rewrite,
+                        options,
+                    )
                 }
-                (Expression::BooleanExpression(x, BoolSym::And, y), BoolSym::And, z) => shake(
+                (Expression::BooleanExpression(x, BoolSym::And, y), BoolSym::And, z) => shake_with(
                     Expression::BooleanGroup(BoolSym::And, vec![*x, *y, z]),
                     rewrite,
+                    options,
                 ),
-                (x, BoolSym::And, Expression::BooleanExpression(y, BoolSym::And, z)) => shake(
+                (x, BoolSym::And, Expression::BooleanExpression(y, BoolSym::And, z)) => shake_with(
                     Expression::BooleanGroup(BoolSym::And, vec![x, *y, *z]),
                     rewrite,
+                    options,
                 ),
-                (Expression::BooleanExpression(x, BoolSym::Or, y), BoolSym::Or, z) => shake(
+                (Expression::BooleanExpression(x, BoolSym::Or, y), BoolSym::Or, z) => shake_with(
                     Expression::BooleanGroup(BoolSym::Or, vec![*x, *y, z]),
                     rewrite,
+                    options,
                 ),
-                (x, BoolSym::Or, Expression::BooleanExpression(y, BoolSym::Or, z)) => shake(
+                (x, BoolSym::Or, Expression::BooleanExpression(y, BoolSym::Or, z)) => shake_with(
                     Expression::BooleanGroup(BoolSym::Or, vec![x, *y, *z]),
                     rewrite,
+                    options,
                 ),
-                (Expression::Negate(left), BoolSym::And, Expression::Negate(right)) => shake(
-                    Expression::Negate(Box::new(shake(
-                        Expression::BooleanExpression(left, BoolSym::Or, right),
+                // NOTE: A negated missing expression is false, so this only holds when neither
+                // side can be missing.
+                (Expression::Negate(left), BoolSym::And, Expression::Negate(right))
+                    if is_total(&left) && is_total(&right) =>
+                {
+                    shake_with(
+                        Expression::Negate(Box::new(shake_with(
+                            Expression::BooleanExpression(left, BoolSym::Or, right),
+                            rewrite,
+                            options,
+                        ))),
                         rewrite,
-                    ))),
-                    rewrite,
-                ),
+                        options,
+                    )
+                }
+                (left, BoolSym::And, right) | (left, BoolSym::Or, right)
+                    if matches!(left, Expression::Boolean(_))
+                        || matches!(right, Expression::Boolean(_))
+                        || left == right
+                        || is_negation(&left, &right)
+                        || is_negation(&right, &left) =>
+                {
+                    shake_with(
+                        Expression::BooleanGroup(symbol, vec![left, right]),
+                        rewrite,
+                        options,
+                    )
+                }
                 (left, _, right) => {
                     Expression::BooleanExpression(Box::new(left), symbol, Box::new(right))
                 }
             }
         }
         Expression::Match(m, expression) => {
-            // FIXME: Due to some unreachable code in the solver we need to keep the group in for
-            // now...
-            let expression = shake(*expression, rewrite);
-            Expression::Match(m, Box::new(expression))
-            //match expression {
-            //    Expression::BooleanGroup(_, _) => Expression::Match(m, Box::new(expression)),
-            //    _ => Expression::Match(
-            //        m,
-            //        Box::new(Expression::BooleanGroup(BoolSym::Or, vec![expression])),
-            //    ),
-            //}
+            Expression::Match(m, Box::new(shake_match(*expression, rewrite, options)))
         }
         Expression::Negate(expression) => {
-            let expression = shake(*expression, rewrite);
+            let expression = shake_with(*expression, rewrite, options);
             match expression {
-                Expression::BooleanGroup(BoolSym::Or, _) => shake(
+                Expression::Boolean(b) => Expression::Boolean(!b),
+                // NOTE: Unlike a negation, `of(0)` is missing when every member is missing, which
+                // changes the result once it is negated again, so the group must not be missing.
+                Expression::BooleanGroup(BoolSym::Or, _) if is_total(&expression) => shake_with(
                     Expression::Match(Match::Of(0), Box::new(expression)),
                     rewrite,
+                    options,
                 ),
-                Expression::Negate(inner) => shake(*inner, rewrite),
+                // NOTE: A negated missing expression is false, so a double negation only cancels
+                // out when it can't be missing.
+                Expression::Negate(inner) if is_total(&inner) => {
+                    shake_with(*inner, rewrite, options)
+                }
                 _ => Expression::Negate(Box::new(expression)),
             }
         }
         Expression::Nested(field, expression) => {
-            Expression::Nested(field, Box::new(shake(*expression, rewrite)))
+            Expression::Nested(field, Box::new(shake_with(*expression, rewrite, options)))
         }
         Expression::Search(Search::Regex(regex, insensitive), f, c) => {
             if rewrite {
-                let mut pattern = regex.as_str().to_owned();
-                if let Some(tail) = pattern.strip_prefix(".*") {
-                    pattern = tail.to_owned();
-                }
-                if let Some(head) = pattern.strip_suffix(".*") {
-                    pattern = head.to_owned();
+                let pattern = unwrap_wildcards(regex.as_str());
+                if let Some(search) = literal(&pattern, insensitive, options) {
+                    return Expression::Search(search, f, c);
                 }
                 Expression::Search(
                     Search::Regex(
-                        RegexBuilder::new(&pattern)
-                            .case_insensitive(insensitive)
-                            .build()
+                        options
+                            .regex(&pattern, insensitive)
                             .expect("could not build regex"),
                         insensitive,
                     ),
@@ -421,27 +564,37 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
         }
         Expression::Search(Search::RegexSet(regex, insensitive), f, c) => {
             if rewrite {
+                let mut literals = vec![];
                 let mut patterns = vec![];
                 for pattern in regex.patterns() {
-                    let mut pattern = pattern.to_owned();
-                    if let Some(tail) = pattern.strip_prefix(".*") {
-                        pattern = tail.to_owned();
-                    }
-                    if let Some(head) = pattern.strip_suffix(".*") {
-                        pattern = head.to_owned();
+                    let pattern = unwrap_wildcards(pattern);
+                    match literal(&pattern, insensitive, options) {
+                        Some(search) => literals.push(Expression::Search(search, f.clone(), c)),
+                        None => patterns.push(pattern),
                     }
-                    patterns.push(pattern);
                 }
-                Expression::Search(
+                let set = Expression::Search(
                     Search::RegexSet(
-                        RegexSetBuilder::new(patterns)
-                            .case_insensitive(insensitive)
-                            .build()
+                        options
+                            .regex_set(patterns.iter(), insensitive)
                             .expect("could not build regex"),
                         insensitive,
                     ),
                     f,
                     c,
+                );
+                if literals.is_empty() {
+                    return set;
+                }
+                // NOTE: Literals are pulled out into their own searches so that they can be
+                // coalesced with the rest of the group.
+                if !patterns.is_empty() {
+                    literals.push(set);
+                }
+                shake_with(
+                    Expression::BooleanGroup(BoolSym::Or, literals),
+                    rewrite,
+                    options,
                 )
             } else {
                 Expression::Search(Search::RegexSet(regex, insensitive), f, c)
@@ -452,9 +605,241 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
         | Expression::Field(_)
         | Expression::Float(_)
         | Expression::Identifier(_)
+        | Expression::In(_, _)
         | Expression::Integer(_)
+        | Expression::Keywords(_)
         | Expression::Null
-        | Expression::Search(_, _, _) => expression,
+        | Expression::Range(_, _, _)
+        | Expression::Search(_, _, _)
+        | Expression::Timestamp(_) => expression,
+    }
+}
+
+// Rewrites a case insensitive literal search into a case sensitive one over lowercase needles, so
+// that it can be evaluated against the lowercase shadow of the field.
+pub(crate) fn shadow_search(search: Search, options: &ParserOptions) -> Search {
+    match search {
+        Search::AhoCorasick(_, contexts, true) => {
+            let mut contexts: Vec<MatchType> = contexts
+                .into_iter()
+                .map(|c| match c {
+                    MatchType::Contains(s) => MatchType::Contains(s.to_lowercase()),
+                    MatchType::EndsWith(s) => MatchType::EndsWith(s.to_lowercase()),
+                    MatchType::Exact(s) => MatchType::Exact(s.to_lowercase()),
+                    MatchType::StartsWith(s) => MatchType::StartsWith(s.to_lowercase()),
+                })
+                .collect();
+            let search = if contexts.len() == 1 {
+                match contexts.remove(0) {
+                    MatchType::Contains(s) => Search::Contains(s),
+                    MatchType::EndsWith(s) => Search::EndsWith(s),
+                    MatchType::Exact(s) => Search::Exact(s),
+                    MatchType::StartsWith(s) => Search::StartsWith(s),
+                }
+            } else {
+                let needles: Vec<String> = contexts.iter().map(|c| c.value().clone()).collect();
+                Search::AhoCorasick(Box::new(options.automaton(needles, false)), contexts, false)
+            };
+            Search::Shadow(Box::new(search))
+        }
+        Search::Fuzzy(value, distance, true) => {
+            Search::Shadow(Box::new(Search::Fuzzy(value, distance, false)))
+        }
+        Search::Glob(glob, true) => Search::Shadow(Box::new(Search::Glob(glob, false))),
+        Search::Lookup(lookup, true) => Search::Shadow(Box::new(Search::Lookup(lookup, false))),
+        search => search,
+    }
+}
+
+// Returns the equivalent string search for a regex that is just a literal, which can optionally be
+// anchored to the start and/or end of the value.
+fn literal(pattern: &str, insensitive: bool, options: &ParserOptions) -> Option<Search> {
+    let (start, pattern) = match pattern.strip_prefix('^') {
+        Some(p) => (true, p),
+        None => (false, pattern),
+    };
+    let (end, pattern) = match pattern.strip_suffix('$') {
+        Some(p) => (true, p),
+        None => (false, pattern),
+    };
+    let mut value = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // NOTE: `\<` and `\>` are word boundaries rather than escaped characters.
+            '\\' => match chars.next() {
+                Some(c) if c.is_ascii_punctuation() && c != '<' && c != '>' => value.push(c),
+                _ => return None,
+            },
+            '$' | '(' | ')' | '*' | '+' | '.' | '?' | '[' | ']' | '^' | '{' | '|' | '}' => {
+                return None
+            }
+            _ => value.push(c),
+        }
+    }
+    if value.is_empty() {
+        return None;
+    }
+    if insensitive {
+        // NOTE: Case insensitive regexes are unicode aware, so we can only swap them for an ascii
+        // case insensitive search when no character has a non-ascii case variant, i.e. `k` and
+        // the Kelvin sign.
+        if !value.is_ascii() || value.contains(['k', 'K', 's', 'S']) {
+            return None;
+        }
+        let value = value.to_lowercase();
+        let context = match (start, end) {
+            (true, true) => MatchType::Exact(value.clone()),
+            (true, false) => MatchType::StartsWith(value.clone()),
+            (false, true) => MatchType::EndsWith(value.clone()),
+            (false, false) => MatchType::Contains(value.clone()),
+        };
+        return Some(Search::AhoCorasick(
+            Box::new(options.automaton(vec![value], true)),
+            vec![context],
+            true,
+        ));
+    }
+    Some(match (start, end) {
+        (true, true) => Search::Exact(value),
+        (true, false) => Search::StartsWith(value),
+        (false, true) => Search::EndsWith(value),
+        (false, false) => Search::Contains(value),
+    })
+}
+
+// Strips the leading and trailing wildcards from a regex, as they have no effect on whether it
+// matches.
+fn unwrap_wildcards(pattern: &str) -> String {
+    let mut pattern = pattern;
+    if let Some(tail) = pattern.strip_prefix(".*") {
+        // NOTE: Don't break apart a lazy wildcard, i.e. `.*?`
+        if !tail.starts_with('?') {
+            pattern = tail;
+        }
+    }
+    if let Some(head) = pattern.strip_suffix(".*") {
+        // NOTE: Don't strip an escaped dot, i.e. `\\.*`
+        let escapes = head.chars().rev().take_while(|c| *c == '\\').count();
+        if escapes % 2 == 0 {
+            pattern = head;
+        }
+    }
+    pattern.to_owned()
+}
+
+// NOTE: The solver can evaluate an expression as missing, which behaves as false unless it is
+// negated, in which case it remains false. So only comparisons between constants are folded, as a
+// comparison against a field is missing when the field is, even if it could never be true.
+
+// Evaluates a comparison if its result is known ahead of time.
+fn compare(left: &Expression, symbol: BoolSym, right: &Expression) -> Option<bool> {
+    match (left, symbol, right) {
+        (Expression::Integer(x), _, Expression::Integer(y)) => match symbol {
+            BoolSym::Equal => Some(x == y),
+            BoolSym::GreaterThan => Some(x > y),
+            BoolSym::GreaterThanOrEqual => Some(x >= y),
+            BoolSym::LessThan => Some(x < y),
+            BoolSym::LessThanOrEqual => Some(x <= y),
+            BoolSym::And
+            | BoolSym::Contains
+            | BoolSym::EndsWith
+            | BoolSym::Or
+            | BoolSym::StartsWith => None,
+        },
+        (Expression::Float(x), _, Expression::Float(y)) => match symbol {
+            BoolSym::Equal => Some(x == y),
+            BoolSym::GreaterThan => Some(x > y),
+            BoolSym::GreaterThanOrEqual => Some(x >= y),
+            BoolSym::LessThan => Some(x < y),
+            BoolSym::LessThanOrEqual => Some(x <= y),
+            BoolSym::And
+            | BoolSym::Contains
+            | BoolSym::EndsWith
+            | BoolSym::Or
+            | BoolSym::StartsWith => None,
+        },
+        (_, _, _) => None,
+    }
+}
+
+// Removes constants and duplicates from a group, returning the value of the group if it can be
+// determined without evaluating it.
+//
+// NOTE: A group that is never true is either false or missing, which differ once negated, so it is
+// only folded to false when none of the members that would be evaluated can be missing. For the
+// same reason a false member is only dropped from an `or` when another member can't be missing.
+fn fold(symbol: BoolSym, expressions: &mut Vec<Expression>) -> Option<bool> {
+    let mut unique: Vec<Expression> = Vec::with_capacity(expressions.len());
+    let mut never = false;
+    for expression in expressions.drain(..) {
+        match (symbol, &expression) {
+            // NOTE: An `and` stops at its first false member, so anything after it is unused.
+            (BoolSym::And, Expression::Boolean(false)) => {
+                never = true;
+                break;
+            }
+            (BoolSym::And, Expression::Boolean(true)) => continue,
+            (BoolSym::Or, Expression::Boolean(true)) => return Some(true),
+            (BoolSym::Or, Expression::Boolean(false)) => {
+                never = true;
+                continue;
+            }
+            _ => {}
+        }
+        if !unique.contains(&expression) {
+            unique.push(expression);
+        }
+    }
+    let total = unique.iter().all(is_total);
+    for x in &unique {
+        for y in &unique {
+            if !is_negation(x, y) {
+                continue;
+            }
+            match symbol {
+                BoolSym::And if total => return Some(false),
+                // NOTE: If `y` can be missing then so can `x`, which would be false
+                BoolSym::Or if is_total(y) => return Some(true),
+                _ => {}
+            }
+        }
+    }
+    if never {
+        match symbol {
+            BoolSym::And if total => return Some(false),
+            BoolSym::Or if unique.iter().any(is_total) => {}
+            _ if unique.is_empty() => return Some(false),
+            _ => unique.push(Expression::Boolean(false)),
+        }
+    }
+    if unique.is_empty() {
+        return Some(symbol == BoolSym::And);
+    }
+    *expressions = unique;
+    None
+}
+
+// Returns true if the expression is the negation of the other.
+fn is_negation(expression: &Expression, other: &Expression) -> bool {
+    match expression {
+        Expression::Negate(e) => e.as_ref() == other,
+        Expression::Match(Match::Of(0), e) => {
+            e.as_ref() == other && matches!(other, Expression::BooleanGroup(BoolSym::Or, _))
+        }
+        _ => false,
+    }
+}
+
+// Returns true if the expression can never be evaluated as missing.
+fn is_total(expression: &Expression) -> bool {
+    match expression {
+        Expression::Boolean(_) | Expression::Negate(_) => true,
+        Expression::BooleanGroup(BoolSym::And, g) => g.iter().all(is_total),
+        Expression::BooleanGroup(BoolSym::Or, g) => g.iter().any(is_total),
+        Expression::BooleanExpression(l, BoolSym::And, r) => is_total(l) && is_total(r),
+        Expression::BooleanExpression(l, BoolSym::Or, r) => is_total(l) || is_total(r),
+        _ => false,
     }
 }
 
@@ -462,6 +847,11 @@ pub fn shake(expression: Expression, rewrite: bool) -> Expression {
 mod tests {
     use super::*;
 
+    use aho_corasick::{AhoCorasick, AhoCorasickKind};
+    use regex::{RegexBuilder, RegexSetBuilder};
+
+    use crate::tokeniser::ModSym;
+
     #[test]
     fn coalesce_basic() {
         let mut identifiers = HashMap::new();
@@ -486,30 +876,103 @@ mod tests {
         assert_eq!(coalesced, expected);
     }
 
+    #[test]
+    fn shadow_insensitive() {
+        let insensitive = |contexts: Vec<MatchType>| {
+            let needles: Vec<String> = contexts.iter().map(|c| c.value().clone()).collect();
+            Search::AhoCorasick(
+                Box::new(
+                    AhoCorasick::builder()
+                        .ascii_case_insensitive(true)
+                        .kind(Some(AhoCorasickKind::DFA))
+                        .build(needles)
+                        .unwrap(),
+                ),
+                contexts,
+                true,
+            )
+        };
+        let expression = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Search(
+                    insensitive(vec![MatchType::Exact("Foo".to_owned())]),
+                    "a".to_owned(),
+                    false,
+                ),
+                Expression::Negate(Box::new(Expression::Search(
+                    insensitive(vec![
+                        MatchType::Contains("BAR".to_owned()),
+                        MatchType::EndsWith("Baz".to_owned()),
+                    ]),
+                    "b".to_owned(),
+                    false,
+                ))),
+                Expression::Search(Search::Exact("Qux".to_owned()), "c".to_owned(), false),
+            ],
+        );
+
+        let shadowed = shadow(expression);
+
+        let expected = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Search(
+                    Search::Shadow(Box::new(Search::Exact("foo".to_owned()))),
+                    "a".to_owned(),
+                    false,
+                ),
+                Expression::Negate(Box::new(Expression::Search(
+                    Search::Shadow(Box::new(Search::AhoCorasick(
+                        Box::new(
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
+                                .build(vec!["bar", "baz"])
+                                .unwrap(),
+                        ),
+                        vec![
+                            MatchType::Contains("bar".to_owned()),
+                            MatchType::EndsWith("baz".to_owned()),
+                        ],
+                        false,
+                    ))),
+                    "b".to_owned(),
+                    false,
+                ))),
+                Expression::Search(Search::Exact("Qux".to_owned()), "c".to_owned(), false),
+            ],
+        );
+
+        assert_eq!(shadowed, expected);
+    }
+
     #[test]
     fn shake_and_nots() {
+        // NOTE: A negation can't be missing, which is what allows the rewrite.
+        let total = |i: &str| Expression::Negate(Box::new(Expression::Identifier(i.to_owned())));
+
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Negate(Box::new(Expression::Null))),
+            Box::new(Expression::Negate(Box::new(total("A")))),
             BoolSym::And,
-            Box::new(Expression::Negate(Box::new(Expression::Null))),
+            Box::new(Expression::Negate(Box::new(total("B")))),
         );
         let shaken = shake(expression, false);
 
         let expected = Expression::Negate(Box::new(Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(total("A")),
             BoolSym::Or,
-            Box::new(Expression::Null),
+            Box::new(total("B")),
         )));
 
         assert_eq!(shaken, expected);
 
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Negate(Box::new(Expression::Null))),
+            Box::new(Expression::Negate(Box::new(total("A")))),
             BoolSym::And,
             Box::new(Expression::BooleanExpression(
-                Box::new(Expression::Negate(Box::new(Expression::Null))),
+                Box::new(Expression::Negate(Box::new(total("B")))),
                 BoolSym::And,
-                Box::new(Expression::Negate(Box::new(Expression::Null))),
+                Box::new(Expression::Negate(Box::new(total("C")))),
             )),
         );
         let shaken = shake(expression, false);
@@ -518,44 +981,55 @@ mod tests {
             Match::Of(0),
             Box::new(Expression::BooleanGroup(
                 BoolSym::Or,
-                vec![Expression::Null, Expression::Null, Expression::Null],
+                vec![total("A"), total("B"), total("C")],
             )),
         );
 
         assert_eq!(shaken, expected);
+
+        // NOTE: Whereas a negated missing expression is false, so these are left as they are.
+        let expression =
+            Expression::BooleanExpression(Box::new(total("A")), BoolSym::And, Box::new(total("B")));
+        let shaken = shake(expression.clone(), false);
+
+        assert_eq!(shaken, expression);
     }
 
     #[test]
     fn shake_ands() {
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::And,
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("B".to_owned())),
         );
         let shaken = shake(expression, false);
 
         let expected = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::And,
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("B".to_owned())),
         );
 
         assert_eq!(shaken, expected);
 
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::And,
             Box::new(Expression::BooleanExpression(
-                Box::new(Expression::Null),
+                Box::new(Expression::Identifier("B".to_owned())),
                 BoolSym::And,
-                Box::new(Expression::Null),
+                Box::new(Expression::Identifier("C".to_owned())),
             )),
         );
         let shaken = shake(expression, false);
 
         let expected = Expression::BooleanGroup(
             BoolSym::And,
-            vec![Expression::Null, Expression::Null, Expression::Null],
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Identifier("B".to_owned()),
+                Expression::Identifier("C".to_owned()),
+            ],
         );
 
         assert_eq!(shaken, expected);
@@ -564,34 +1038,38 @@ mod tests {
     #[test]
     fn shake_ors() {
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::Or,
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("B".to_owned())),
         );
         let shaken = shake(expression, false);
 
         let expected = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::Or,
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("B".to_owned())),
         );
 
         assert_eq!(shaken, expected);
 
         let expression = Expression::BooleanExpression(
-            Box::new(Expression::Null),
+            Box::new(Expression::Identifier("A".to_owned())),
             BoolSym::Or,
             Box::new(Expression::BooleanExpression(
-                Box::new(Expression::Null),
+                Box::new(Expression::Identifier("B".to_owned())),
                 BoolSym::Or,
-                Box::new(Expression::Null),
+                Box::new(Expression::Identifier("C".to_owned())),
             )),
         );
         let shaken = shake(expression, false);
 
         let expected = Expression::BooleanGroup(
             BoolSym::Or,
-            vec![Expression::Null, Expression::Null, Expression::Null],
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Identifier("B".to_owned()),
+                Expression::Identifier("C".to_owned()),
+            ],
         );
 
         assert_eq!(shaken, expected);
@@ -634,11 +1112,16 @@ mod tests {
             "ids".to_owned(),
             Box::new(Expression::Search(
                 Search::AhoCorasick(
-                    Box::new(AhoCorasickBuilder::new().dfa(true).build(vec![
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bca",
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bcb",
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bcc",
-                    ])),
+                    Box::new(
+                        AhoCorasick::builder()
+                            .kind(Some(AhoCorasickKind::DFA))
+                            .build(vec![
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bca",
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bcb",
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bcc",
+                            ])
+                            .unwrap(),
+                    ),
                     vec![
                         MatchType::Exact("e2ec14cb-299e-4adf-bb09-04a6a8417bca".to_owned()),
                         MatchType::Exact("e2ec14cb-299e-4adf-bb09-04a6a8417bcb".to_owned()),
@@ -663,14 +1146,15 @@ mod tests {
                 Expression::Search(
                     Search::AhoCorasick(
                         Box::new(
-                            AhoCorasickBuilder::new()
-                                .dfa(true)
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
                                 .ascii_case_insensitive(false)
                                 .build(vec![
                                     "Quick".to_owned(),
                                     "Brown".to_owned(),
                                     "Fox".to_owned(),
-                                ]),
+                                ])
+                                .unwrap(),
                         ),
                         vec![
                             MatchType::Contains("Quick".to_owned()),
@@ -685,14 +1169,15 @@ mod tests {
                 Expression::Search(
                     Search::AhoCorasick(
                         Box::new(
-                            AhoCorasickBuilder::new()
-                                .dfa(true)
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
                                 .ascii_case_insensitive(true)
                                 .build(vec![
                                     "quick".to_owned(),
                                     "brown".to_owned(),
                                     "fox".to_owned(),
-                                ]),
+                                ])
+                                .unwrap(),
                         ),
                         vec![
                             MatchType::Contains("quick".to_owned()),
@@ -784,8 +1269,8 @@ mod tests {
                 Expression::Search(
                     Search::AhoCorasick(
                         Box::new(
-                            AhoCorasickBuilder::new()
-                                .dfa(true)
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
                                 .ascii_case_insensitive(false)
                                 .build(vec![
                                     "Quick".to_owned(),
@@ -795,7 +1280,8 @@ mod tests {
                                     "bar".to_owned(),
                                     "baz".to_owned(),
                                     "foobar".to_owned(),
-                                ]),
+                                ])
+                                .unwrap(),
                         ),
                         vec![
                             MatchType::Contains("Quick".to_owned()),
@@ -814,14 +1300,15 @@ mod tests {
                 Expression::Search(
                     Search::AhoCorasick(
                         Box::new(
-                            AhoCorasickBuilder::new()
-                                .dfa(true)
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
                                 .ascii_case_insensitive(true)
                                 .build(vec![
                                     "quick".to_owned(),
                                     "brown".to_owned(),
                                     "fox".to_owned(),
-                                ]),
+                                ])
+                                .unwrap(),
                         ),
                         vec![
                             MatchType::Contains("quick".to_owned()),
@@ -872,6 +1359,30 @@ mod tests {
         assert_eq!(shaken, expected);
     }
 
+    #[test]
+    fn shake_deterministic() {
+        let search =
+            |search: Search, field: &str| Expression::Search(search, field.to_owned(), false);
+        let f = RegexBuilder::new("^f").build().unwrap();
+        let b = RegexBuilder::new("^b").build().unwrap();
+        let mut expressions = vec![];
+        for field in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            expressions.push(search(Search::Contains("foo".to_owned()), field));
+            expressions.push(search(Search::Contains("bar".to_owned()), field));
+            expressions.push(search(Search::Regex(f.clone(), false), field));
+            expressions.push(search(Search::Regex(b.clone(), false), field));
+            expressions.push(Expression::Nested(
+                field.to_owned(),
+                Box::new(search(Search::Exact("baz".to_owned()), "x")),
+            ));
+        }
+        let expression = Expression::BooleanGroup(BoolSym::Or, expressions);
+        let expected = shake(expression.clone(), false).to_string();
+        for _ in 0..16 {
+            assert_eq!(shake(expression.clone(), false).to_string(), expected);
+        }
+    }
+
     #[test]
     fn shake_nested() {
         let expression = Expression::Nested(
@@ -903,11 +1414,16 @@ mod tests {
             "ids".to_owned(),
             Box::new(Expression::Search(
                 Search::AhoCorasick(
-                    Box::new(AhoCorasickBuilder::new().dfa(true).build(vec![
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bca",
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bcb",
-                        "e2ec14cb-299e-4adf-bb09-04a6a8417bcc",
-                    ])),
+                    Box::new(
+                        AhoCorasick::builder()
+                            .kind(Some(AhoCorasickKind::DFA))
+                            .build(vec![
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bca",
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bcb",
+                                "e2ec14cb-299e-4adf-bb09-04a6a8417bcc",
+                            ])
+                            .unwrap(),
+                    ),
                     vec![
                         MatchType::Exact("e2ec14cb-299e-4adf-bb09-04a6a8417bca".to_owned()),
                         MatchType::Exact("e2ec14cb-299e-4adf-bb09-04a6a8417bcb".to_owned()),
@@ -929,7 +1445,10 @@ mod tests {
             Match::All,
             Box::new(Expression::BooleanGroup(
                 BoolSym::Or,
-                vec![Expression::Null, Expression::Null],
+                vec![
+                    Expression::Identifier("A".to_owned()),
+                    Expression::Identifier("B".to_owned()),
+                ],
             )),
         );
         let shaken = shake(expression, false);
@@ -938,7 +1457,10 @@ mod tests {
             Match::All,
             Box::new(Expression::BooleanGroup(
                 BoolSym::Or,
-                vec![Expression::Null, Expression::Null],
+                vec![
+                    Expression::Identifier("A".to_owned()),
+                    Expression::Identifier("B".to_owned()),
+                ],
             )),
         );
 
@@ -947,26 +1469,232 @@ mod tests {
 
     #[test]
     fn shake_negate() {
+        let expression = Expression::Negate(Box::new(Expression::Negate(Box::new(
+            Expression::Negate(Box::new(Expression::Null)),
+        ))));
+        let shaken = shake(expression, false);
+
+        let expected = Expression::Negate(Box::new(Expression::Null));
+
+        assert_eq!(shaken, expected);
+
+        // NOTE: A negated missing expression is false, so the negations can't cancel out.
         let expression =
             Expression::Negate(Box::new(Expression::Negate(Box::new(Expression::Null))));
+        let shaken = shake(expression.clone(), false);
+
+        assert_eq!(shaken, expression);
+    }
+
+    #[test]
+    fn shake_negate_nested() {
+        let group = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Search(
+                    Search::Contains("beacon".to_owned()),
+                    "tags".to_owned(),
+                    false,
+                ),
+                Expression::Nested(
+                    "tags".to_owned(),
+                    Box::new(Expression::Search(
+                        Search::Exact("malware".to_owned()),
+                        "name".to_owned(),
+                        false,
+                    )),
+                ),
+            ],
+        );
+        let expression = Expression::Negate(Box::new(group.clone()));
         let shaken = shake(expression, false);
 
-        let expected = Expression::Null;
+        let expected = Expression::Negate(Box::new(group));
+
+        assert_eq!(shaken, expected);
+    }
+
+    #[test]
+    fn shake_constants() {
+        let expression = Expression::BooleanExpression(
+            Box::new(Expression::Integer(1)),
+            BoolSym::Equal,
+            Box::new(Expression::Integer(1)),
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::Boolean(true);
+
+        assert_eq!(shaken, expected);
+
+        // NOTE: `int(a)` could be missing, which differs from false once negated
+        let expression = Expression::BooleanExpression(
+            Box::new(Expression::Identifier("A".to_owned())),
+            BoolSym::Or,
+            Box::new(Expression::BooleanExpression(
+                Box::new(Expression::Cast("a".to_owned(), ModSym::Int)),
+                BoolSym::GreaterThan,
+                Box::new(Expression::Integer(i64::MAX)),
+            )),
+        );
+        let shaken = shake(expression.clone(), false);
+
+        assert_eq!(shaken, expression);
+
+        // NOTE: `A` could be missing, in which case the group is missing rather than false
+        let expression = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::BooleanExpression(
+                    Box::new(Expression::Integer(1)),
+                    BoolSym::GreaterThan,
+                    Box::new(Expression::Integer(2)),
+                ),
+                Expression::Identifier("B".to_owned()),
+            ],
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Boolean(false),
+            ],
+        );
+
+        assert_eq!(shaken, expected);
+
+        let expression = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Negate(Box::new(Expression::Identifier("A".to_owned()))),
+                Expression::Boolean(false),
+            ],
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::Negate(Box::new(Expression::Identifier("A".to_owned())));
+
+        assert_eq!(shaken, expected);
+
+        let expression = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Negate(Box::new(Expression::BooleanExpression(
+                    Box::new(Expression::Float(1.0)),
+                    BoolSym::LessThan,
+                    Box::new(Expression::Float(0.5)),
+                ))),
+            ],
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::Identifier("A".to_owned());
+
+        assert_eq!(shaken, expected);
+    }
+
+    #[test]
+    fn shake_duplicates() {
+        let expression = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Identifier("B".to_owned()),
+                Expression::Identifier("A".to_owned()),
+            ],
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Identifier("B".to_owned()),
+            ],
+        );
+
+        assert_eq!(shaken, expected);
+
+        let expression = Expression::BooleanExpression(
+            Box::new(Expression::Identifier("A".to_owned())),
+            BoolSym::And,
+            Box::new(Expression::Identifier("A".to_owned())),
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::Identifier("A".to_owned());
 
         assert_eq!(shaken, expected);
     }
 
+    #[test]
+    fn shake_tautologies() {
+        // NOTE: `A` could be missing in which case the group is missing rather than false
+        let expression = Expression::BooleanExpression(
+            Box::new(Expression::Identifier("A".to_owned())),
+            BoolSym::And,
+            Box::new(Expression::Negate(Box::new(Expression::Identifier(
+                "A".to_owned(),
+            )))),
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Negate(Box::new(Expression::Identifier("A".to_owned()))),
+            ],
+        );
+
+        assert_eq!(shaken, expected);
+
+        // NOTE: `A` could be missing in which case neither side would be true
+        let expression = Expression::BooleanExpression(
+            Box::new(Expression::Identifier("A".to_owned())),
+            BoolSym::Or,
+            Box::new(Expression::Negate(Box::new(Expression::Identifier(
+                "A".to_owned(),
+            )))),
+        );
+        let shaken = shake(expression, false);
+
+        let expected = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Identifier("A".to_owned()),
+                Expression::Negate(Box::new(Expression::Identifier("A".to_owned()))),
+            ],
+        );
+
+        assert_eq!(shaken, expected);
+
+        // NOTE: A negation can never be missing
+        let mut expressions = vec![
+            Expression::Negate(Box::new(Expression::Identifier("A".to_owned()))),
+            Expression::Negate(Box::new(Expression::Negate(Box::new(
+                Expression::Identifier("A".to_owned()),
+            )))),
+        ];
+        assert_eq!(fold(BoolSym::Or, &mut expressions.clone()), Some(true));
+        assert_eq!(fold(BoolSym::And, &mut expressions), Some(false));
+    }
+
     #[test]
     fn rewrite_regex() {
         let expression = Expression::Search(
-            Search::Regex(RegexBuilder::new(".*foo.*").build().unwrap(), false),
+            Search::Regex(RegexBuilder::new(".*fo+.*").build().unwrap(), false),
             "name".to_owned(),
             false,
         );
         let shaken = shake(expression, true);
 
         let expected = Expression::Search(
-            Search::Regex(RegexBuilder::new("foo").build().unwrap(), false),
+            Search::Regex(RegexBuilder::new("fo+").build().unwrap(), false),
             "name".to_owned(),
             false,
         );
@@ -975,7 +1703,7 @@ mod tests {
 
         let expression = Expression::Search(
             Search::RegexSet(
-                RegexSetBuilder::new(vec![".*foo.*"]).build().unwrap(),
+                RegexSetBuilder::new(vec![".*fo+.*"]).build().unwrap(),
                 false,
             ),
             "name".to_owned(),
@@ -984,11 +1712,109 @@ mod tests {
         let shaken = shake(expression, true);
 
         let expected = Expression::Search(
-            Search::RegexSet(RegexSetBuilder::new(vec!["foo"]).build().unwrap(), false),
+            Search::RegexSet(RegexSetBuilder::new(vec!["fo+"]).build().unwrap(), false),
+            "name".to_owned(),
+            false,
+        );
+
+        assert_eq!(shaken, expected);
+    }
+
+    #[test]
+    fn rewrite_regex_literals() {
+        let search = |pattern: &str, insensitive: bool| {
+            Expression::Search(
+                Search::Regex(
+                    RegexBuilder::new(pattern)
+                        .case_insensitive(insensitive)
+                        .build()
+                        .unwrap(),
+                    insensitive,
+                ),
+                "name".to_owned(),
+                false,
+            )
+        };
+
+        let cases = vec![
+            ("^foo$", Search::Exact("foo".to_owned())),
+            ("^foo", Search::StartsWith("foo".to_owned())),
+            ("foo$", Search::EndsWith("foo".to_owned())),
+            (".*foo.*", Search::Contains("foo".to_owned())),
+            (r"^a\.b$", Search::Exact("a.b".to_owned())),
+        ];
+        for (pattern, expected) in cases {
+            let shaken = shake(search(pattern, false), true);
+            assert_eq!(
+                shaken,
+                Expression::Search(expected, "name".to_owned(), false)
+            );
+        }
+
+        let shaken = shake(search("^FOO", true), true);
+        let expected = Expression::Search(
+            Search::AhoCorasick(
+                Box::new(
+                    AhoCorasick::builder()
+                        .ascii_case_insensitive(true)
+                        .kind(Some(AhoCorasickKind::DFA))
+                        .build(vec!["foo"])
+                        .unwrap(),
+                ),
+                vec![MatchType::StartsWith("foo".to_owned())],
+                true,
+            ),
             "name".to_owned(),
             false,
         );
+        assert_eq!(shaken, expected);
+
+        for pattern in [r"^\d$", "fo+", r"\bfoo", "a|b", r"foo\.*", ".*?foo"] {
+            let shaken = shake(search(pattern, false), true);
+            assert!(matches!(shaken, Expression::Search(Search::Regex(..), ..)));
+        }
+        // NOTE: The Kelvin sign matches `k` when case insensitive
+        let shaken = shake(search("kelvin", true), true);
+        assert!(matches!(shaken, Expression::Search(Search::Regex(..), ..)));
 
+        let expression = Expression::Search(
+            Search::RegexSet(
+                RegexSetBuilder::new(vec!["^foo$", "^bar$", "fo+"])
+                    .build()
+                    .unwrap(),
+                false,
+            ),
+            "name".to_owned(),
+            false,
+        );
+        let shaken = shake(expression, true);
+        let expected = Expression::BooleanGroup(
+            BoolSym::Or,
+            vec![
+                Expression::Search(
+                    Search::AhoCorasick(
+                        Box::new(
+                            AhoCorasick::builder()
+                                .kind(Some(AhoCorasickKind::DFA))
+                                .build(vec!["foo", "bar"])
+                                .unwrap(),
+                        ),
+                        vec![
+                            MatchType::Exact("foo".to_owned()),
+                            MatchType::Exact("bar".to_owned()),
+                        ],
+                        false,
+                    ),
+                    "name".to_owned(),
+                    false,
+                ),
+                Expression::Search(
+                    Search::Regex(RegexBuilder::new("fo+").build().unwrap(), false),
+                    "name".to_owned(),
+                    false,
+                ),
+            ],
+        );
         assert_eq!(shaken, expected);
     }
 
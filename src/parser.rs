@@ -1,15 +1,38 @@
-use std::convert::TryFrom;
-use std::fmt;
-use std::iter::Iterator;
-use std::iter::Peekable;
+use alloc::sync::Arc;
+use core::convert::TryFrom;
+use core::fmt;
+use core::iter::Iterator;
+use core::iter::Peekable;
 
-use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
-use regex::{Regex, RegexSet, RegexSetBuilder};
+#[cfg(feature = "aho_corasick")]
+use aho_corasick::AhoCorasick;
+#[cfg(feature = "std")]
+use aho_corasick::AhoCorasickKind;
+#[cfg(feature = "std")]
+use regex::RegexSetBuilder;
+#[cfg(feature = "regex")]
+use regex::{Regex, RegexBuilder, RegexSet};
+#[cfg(feature = "std")]
 use serde_yaml::{Mapping, Value as Yaml};
 use tracing::debug;
 
+use crate::collections::{HashMap, HashSet};
+use crate::diagnostic::{Diagnostic, Position};
+#[cfg(feature = "std")]
 use crate::identifier::{Identifier, IdentifierParser, Pattern};
-use crate::tokeniser::{BoolSym, DelSym, MatchSym, MiscSym, ModSym, Token, Tokeniser};
+use crate::limits::Complexity;
+#[cfg(feature = "std")]
+use crate::optimiser;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::timestamp::{self, SystemTime};
+#[cfg(feature = "std")]
+use crate::tokeniser::Tokeniser;
+use crate::tokeniser::{BoolSym, DelSym, Dialect, Keywords, MatchSym, MiscSym, ModSym, Token};
+#[cfg(not(feature = "aho_corasick"))]
+use crate::unsupported::AhoCorasick;
+#[cfg(not(feature = "regex"))]
+use crate::unsupported::{Regex, RegexSet};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum MatchType {
@@ -27,9 +50,275 @@ impl MatchType {
     }
 }
 
+/// A named set of strings that values can be tested for membership of.
+#[derive(Clone)]
+pub struct Lookup {
+    pub name: String,
+    pub set: Arc<HashSet<String>>,
+}
+
+impl fmt::Debug for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // NOTE: Lookups can hold millions of values, so we do not want them in any output.
+        write!(f, "Lookup({}, {} values)", self.name, self.set.len())
+    }
+}
+
+/// A hex pattern of bytes, where either nibble of a byte can be a wildcard, i.e. `{ 4D 5A ?? ?0 }`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hex {
+    // NOTE: The bytes are stored masked, so that a window matches when each of its bytes, once
+    // masked, is equal to the pattern's.
+    bytes: Vec<u8>,
+    masks: Vec<u8>,
+}
+
+impl Hex {
+    /// Parses a hex pattern, returning `None` if the string is not one.
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let inner = pattern.strip_prefix('{')?.strip_suffix('}')?;
+        let mut bytes = vec![];
+        let mut masks = vec![];
+        for token in inner.split_whitespace() {
+            let token = token.as_bytes();
+            if token.len() != 2 {
+                return None;
+            }
+            let (mut byte, mut mask) = (0, 0);
+            for c in token {
+                let (b, m) = match c {
+                    b'?' => (0, 0),
+                    c => ((*c as char).to_digit(16)? as u8, 0xf),
+                };
+                byte = byte << 4 | b;
+                mask = mask << 4 | m;
+            }
+            bytes.push(byte);
+            masks.push(mask);
+        }
+        if bytes.is_empty() {
+            return None;
+        }
+        Some(Self { bytes, masks })
+    }
+
+    /// Returns true if the pattern is found anywhere within the bytes.
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        // NOTE: Patterns are short, so a naive scan is fine and avoids anchoring on wildcards.
+        haystack.windows(self.bytes.len()).any(|w| {
+            w.iter()
+                .zip(&self.masks)
+                .map(|(b, m)| b & m)
+                .eq(self.bytes.iter().copied())
+        })
+    }
+
+    pub(crate) fn bytes(&self) -> impl Iterator<Item = (u8, u8)> + '_ {
+        self.bytes.iter().copied().zip(self.masks.iter().copied())
+    }
+}
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nibble = |b: u8, m: u8| {
+            if m == 0 {
+                '?'
+            } else {
+                core::char::from_digit(b as u32, 16)
+                    .expect("nibble is a hex digit")
+                    .to_ascii_uppercase()
+            }
+        };
+        f.write_str("{")?;
+        for (b, m) in self.bytes() {
+            write!(f, " {}{}", nibble(b >> 4, m >> 4), nibble(b & 0xf, m & 0xf))?;
+        }
+        f.write_str(" }")
+    }
+}
+
+/// A glob pattern, i.e. `C:\Windows\**\*.exe`, which must match the whole of a value.
+///
+/// `?` matches any character and `*` any run of characters, neither of which will match a path
+/// separator (`/` or `\`), while `**` matches across them. A `**` that is followed by a separator
+/// can also match no directories at all. `[abc]`, `[a-z]` and `[!abc]` match any character that
+/// is, or is not, in the class, which is also how the special characters are matched literally.
+#[derive(Clone, Debug)]
+pub struct Glob {
+    pattern: String,
+    tokens: Vec<GlobToken>,
+}
+
+#[derive(Clone, Debug)]
+enum GlobToken {
+    // `?`
+    Any,
+    // `[a-z]` or `[!a-z]`
+    Class(bool, Vec<(char, char)>),
+    // `**/`
+    Directories,
+    // `**`
+    Globstar,
+    Literal(char),
+    // `*`
+    Star,
+}
+
+impl Glob {
+    /// Compiles a glob pattern.
+    pub fn parse(pattern: &str) -> crate::Result<Self> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    i += 1;
+                    match chars.get(i + 1) {
+                        Some(c) if separator(*c) => {
+                            i += 1;
+                            GlobToken::Directories
+                        }
+                        _ => GlobToken::Globstar,
+                    }
+                }
+                '*' => GlobToken::Star,
+                '?' => GlobToken::Any,
+                '[' => {
+                    let mut j = i + 1;
+                    let negated = chars.get(j) == Some(&'!');
+                    if negated {
+                        j += 1;
+                    }
+                    // NOTE: A `]` straight after the opening bracket is part of the class, so that
+                    // it can be matched literally.
+                    let mut ranges = vec![];
+                    let mut first = true;
+                    loop {
+                        match chars.get(j) {
+                            Some(']') if !first => break,
+                            Some(c) => {
+                                if chars.get(j + 1) == Some(&'-')
+                                    && chars.get(j + 2).map(|e| *e != ']').unwrap_or(false)
+                                {
+                                    ranges.push((*c, chars[j + 2]));
+                                    j += 3;
+                                } else {
+                                    ranges.push((*c, *c));
+                                    j += 1;
+                                }
+                            }
+                            None => {
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.unterminated_glob_class")
+                                        .with("pattern", pattern),
+                                ))
+                            }
+                        }
+                        first = false;
+                    }
+                    i = j;
+                    GlobToken::Class(negated, ranges)
+                }
+                c => GlobToken::Literal(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            tokens,
+        })
+    }
+
+    /// Returns the glob pattern.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns true if the pattern matches the whole of the value.
+    pub fn is_match(&self, value: &str) -> bool {
+        // NOTE: Rather than backtracking, we track every position in the value that the tokens
+        // so far could have matched up to, which keeps matching linear in the size of the pattern.
+        let chars: Vec<char> = value.chars().collect();
+        let mut states = vec![false; chars.len() + 1];
+        states[0] = true;
+        for token in &self.tokens {
+            let mut next = vec![false; chars.len() + 1];
+            match token {
+                GlobToken::Any => {
+                    for (i, c) in chars.iter().enumerate() {
+                        next[i + 1] = states[i] && !separator(*c);
+                    }
+                }
+                GlobToken::Class(negated, ranges) => {
+                    for (i, c) in chars.iter().enumerate() {
+                        let found = ranges.iter().any(|(s, e)| s <= c && c <= e);
+                        next[i + 1] =
+                            states[i] && found != *negated && !(*negated && separator(*c));
+                    }
+                }
+                GlobToken::Directories => {
+                    let mut seen = false;
+                    for j in 0..=chars.len() {
+                        next[j] = states[j] || (j > 0 && seen && separator(chars[j - 1]));
+                        seen |= states[j];
+                    }
+                }
+                GlobToken::Globstar => {
+                    let mut reachable = false;
+                    for j in 0..=chars.len() {
+                        reachable |= states[j];
+                        next[j] = reachable;
+                    }
+                }
+                GlobToken::Literal(l) => {
+                    for (i, c) in chars.iter().enumerate() {
+                        next[i + 1] = states[i] && c == l;
+                    }
+                }
+                GlobToken::Star => {
+                    let mut reachable = false;
+                    for j in 0..=chars.len() {
+                        if j > 0 && separator(chars[j - 1]) {
+                            reachable = false;
+                        }
+                        reachable |= states[j];
+                        next[j] = reachable;
+                    }
+                }
+            }
+            if !next.iter().any(|s| *s) {
+                return false;
+            }
+            states = next;
+        }
+        states[chars.len()]
+    }
+}
+
+impl PartialEq for Glob {
+    fn eq(&self, other: &Glob) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+#[inline]
+fn separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Match {
     All,
+    // NOTE: Wraps a numeric comparison that must hold for every element of an array.
+    Every,
     Of(u64),
 }
 
@@ -40,9 +329,32 @@ pub enum Search {
     Contains(String),
     EndsWith(String),
     Exact(String),
+    Fuzzy(String, usize, bool),
+    Glob(Glob, bool),
+    Hex(Hex),
+    Lookup(Lookup, bool),
     Regex(Regex, bool),
     RegexSet(RegexSet, bool),
+    Shadow(Box<Search>),
+    // NOTE: The needles are already skeletons, so only the value is mapped before searching it.
+    Skeleton(Box<Search>),
     StartsWith(String),
+    // NOTE: The needles are encoded as UTF-16LE, so that they can be found within raw bytes.
+    Wide(Box<AhoCorasick>, Vec<MatchType>, bool),
+    #[cfg(feature = "fancy_regex")]
+    FancyRegex(fancy_regex::Regex, bool),
+}
+impl Search {
+    /// Returns the number of patterns when the search is over several of them, which must be
+    /// counted individually when matching a number of a group's searches.
+    pub(crate) fn patterns(&self) -> Option<usize> {
+        match self {
+            Self::AhoCorasick(_, m, _) | Self::Wide(_, m, _) => Some(m.len()),
+            Self::RegexSet(s, _) => Some(s.patterns().len()),
+            Self::Shadow(s) | Self::Skeleton(s) => s.patterns(),
+            _ => None,
+        }
+    }
 }
 impl fmt::Display for Search {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -54,6 +366,10 @@ impl fmt::Display for Search {
             Self::Contains(s) => write!(f, "contains({})", s),
             Self::EndsWith(s) => write!(f, "ends_with({})", s),
             Self::Exact(s) => write!(f, "exact({})", s),
+            Self::Fuzzy(s, d, i) => write!(f, "{}fuzzy({}, {})", if *i { "i" } else { "" }, s, d),
+            Self::Glob(g, i) => write!(f, "{}glob({})", if *i { "i" } else { "" }, g),
+            Self::Hex(h) => write!(f, "hex({})", h),
+            Self::Lookup(l, i) => write!(f, "{}lookup({})", if *i { "i" } else { "" }, l.name),
             Self::Regex(s, i) => write!(f, "{}regex({})", if *i { "i" } else { "" }, s),
             Self::RegexSet(s, i) => write!(
                 f,
@@ -61,7 +377,14 @@ impl fmt::Display for Search {
                 if *i { "i" } else { "" },
                 s.patterns()
             ),
+            Self::Shadow(s) => write!(f, "shadow({})", s),
+            Self::Skeleton(s) => write!(f, "skeleton({})", s),
             Self::StartsWith(s) => write!(f, "starts_with({})", s),
+            Self::Wide(_, t, i) => write!(f, "{}wide({:?})", if *i { "i" } else { "" }, t),
+            #[cfg(feature = "fancy_regex")]
+            Self::FancyRegex(s, i) => {
+                write!(f, "{}fancy_regex({})", if *i { "i" } else { "" }, s)
+            }
         }
     }
 }
@@ -73,18 +396,32 @@ impl PartialEq for Search {
             (Search::Contains(s0), Search::Contains(s1)) => s0 == s1,
             (Search::EndsWith(s0), Search::EndsWith(s1)) => s0 == s1,
             (Search::Exact(s0), Search::Exact(s1)) => s0 == s1,
+            (Search::Fuzzy(s0, d0, i0), Search::Fuzzy(s1, d1, i1)) => {
+                s0 == s1 && d0 == d1 && i0 == i1
+            }
+            (Search::Glob(g0, i0), Search::Glob(g1, i1)) => g0 == g1 && i0 == i1,
+            (Search::Hex(h0), Search::Hex(h1)) => h0 == h1,
+            (Search::Lookup(l0, i0), Search::Lookup(l1, i1)) => l0.name == l1.name && i0 == i1,
             (Search::Regex(r0, i0), Search::Regex(r1, i1)) => {
                 r0.as_str() == r1.as_str() && i0 == i1
             }
             (Search::RegexSet(r0, i0), Search::RegexSet(r1, i1)) => {
                 r0.patterns() == r1.patterns() && i0 == i1
             }
+            (Search::Shadow(s0), Search::Shadow(s1)) => s0 == s1,
+            (Search::Skeleton(s0), Search::Skeleton(s1)) => s0 == s1,
             (Search::StartsWith(s0), Search::StartsWith(s1)) => s0 == s1,
+            (Search::Wide(_, m0, i0), Search::Wide(_, m1, i1)) => m0 == m1 && i0 == i1,
+            #[cfg(feature = "fancy_regex")]
+            (Search::FancyRegex(r0, i0), Search::FancyRegex(r1, i1)) => {
+                r0.as_str() == r1.as_str() && i0 == i1
+            }
             (_, _) => false,
         }
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     BooleanGroup(BoolSym, Vec<Expression>),
@@ -94,12 +431,18 @@ pub enum Expression {
     Field(String),
     Float(f64),
     Identifier(String),
+    // NOTE: The integers are sorted and deduplicated so that they can be binary searched.
+    In(Box<Expression>, Vec<i64>),
     Integer(i64),
+    // NOTE: A search without a field, matching any string anywhere within the document.
+    Keywords(Search),
     Match(Match, Box<Expression>),
     Negate(Box<Expression>),
     Nested(String, Box<Expression>),
     Null,
+    Range(Box<Expression>, i64, i64),
     Search(Search, String, bool),
+    Timestamp(SystemTime),
 }
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -119,18 +462,182 @@ impl fmt::Display for Expression {
             Self::Field(s) => write!(f, "field({})", s),
             Self::Float(n) => write!(f, "float({})", n),
             Self::Identifier(s) => write!(f, "identifier({})", s),
+            Self::In(e, i) => write!(
+                f,
+                "in({}, [{}])",
+                e,
+                i.iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
             Self::Integer(i) => write!(f, "int({})", i),
+            Self::Keywords(s) => write!(f, "keywords({})", s),
             Self::Match(Match::All, e) => {
                 write!(f, "all({})", e)
             }
+            Self::Match(Match::Every, e) => write!(f, "every({})", e),
             Self::Match(Match::Of(i), e) => write!(f, "of({}, {})", e, i),
             Self::Negate(e) => write!(f, "negate({})", e),
             Self::Nested(s, e) => write!(f, "nested({}, {})", s, e),
             Self::Null => write!(f, "null"),
+            Self::Range(e, x, y) => write!(f, "range({}, {}..{})", e, x, y),
             Self::Search(e, s, c) => write!(f, "search({}, {}, {})", s, e, c),
+            Self::Timestamp(t) => write!(f, "timestamp({})", timestamp::format(*t)),
         }
     }
 }
+// NOTE: Without std the errors from regex don't implement `Error`, so they are carried as a
+// diagnostic instead.
+#[cfg(feature = "std")]
+pub(crate) type RegexError = regex::Error;
+#[cfg(not(feature = "std"))]
+pub(crate) type RegexError = Diagnostic;
+
+/// Options that control how rules are parsed.
+#[derive(Clone, Debug)]
+pub struct ParserOptions {
+    /// Whether literal searches on the same field are merged into a single Aho-Corasick automaton.
+    pub aho_corasick: bool,
+    /// Whether integer comparisons coerce the values of fields, as if they were cast with `int`.
+    pub coerce: bool,
+    /// The limits on the complexity of rules.
+    pub complexity: Complexity,
+    /// Whether Aho-Corasick automatons are built as DFAs, which are faster but use more memory.
+    pub dfa: bool,
+    /// The dialect that rules are written in.
+    pub dialect: Dialect,
+    /// Whether values prefixed with `~` are parsed as fuzzy searches, rather than as literals.
+    pub fuzzy: bool,
+    /// Whether values prefixed with `^` are parsed as globs, rather than as literals.
+    pub glob: bool,
+    /// Whether values such as `{ 4D 5A }` are parsed as hex patterns, rather than as literals.
+    pub hex: bool,
+    /// Alternative spellings for the keywords used in conditions.
+    pub keywords: Keywords,
+    /// Whether values such as `1..10` are parsed as integer ranges, rather than as literals.
+    pub range: bool,
+    /// Whether regexes on the same field are merged into a single regex set.
+    pub regex_set: bool,
+    /// The approximate size limit, in bytes, of a compiled regex.
+    pub regex_size_limit: Option<usize>,
+    /// The approximate size limit, in bytes, of the cache used by a regex's lazy DFA.
+    pub regex_dfa_size_limit: Option<usize>,
+    /// The named sets that can be referenced by lookups.
+    pub lookups: HashMap<String, Arc<HashSet<String>>>,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            aho_corasick: true,
+            coerce: false,
+            complexity: Complexity::default(),
+            dfa: true,
+            dialect: Dialect::default(),
+            fuzzy: false,
+            glob: false,
+            hex: false,
+            keywords: Keywords::default(),
+            range: false,
+            regex_set: true,
+            regex_size_limit: None,
+            regex_dfa_size_limit: None,
+            lookups: HashMap::new(),
+        }
+    }
+}
+
+impl ParserOptions {
+    #[cfg(feature = "std")]
+    pub(crate) fn automaton<I, P>(&self, needles: I, insensitive: bool) -> AhoCorasick
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<[u8]>,
+    {
+        let needles: Vec<P> = needles.into_iter().collect();
+        let mut builder = AhoCorasick::builder();
+        builder.ascii_case_insensitive(insensitive);
+        if self.dfa {
+            // NOTE: A DFA can refuse to build when the needles are too large, in which case we let
+            // the automaton pick the best kind that will fit.
+            if let Ok(automaton) = builder.kind(Some(AhoCorasickKind::DFA)).build(&needles) {
+                return automaton;
+            }
+            builder.kind(None);
+        } else {
+            builder.kind(Some(AhoCorasickKind::NoncontiguousNFA));
+        }
+        builder
+            .build(&needles)
+            .expect("could not build aho-corasick automaton")
+    }
+
+    #[cfg(feature = "regex")]
+    pub(crate) fn regex(&self, pattern: &str, insensitive: bool) -> Result<Regex, RegexError> {
+        let mut builder = RegexBuilder::new(pattern);
+        builder.case_insensitive(insensitive);
+        if let Some(limit) = self.regex_size_limit {
+            builder.size_limit(limit);
+        }
+        if let Some(limit) = self.regex_dfa_size_limit {
+            builder.dfa_size_limit(limit);
+        }
+        let regex = builder.build();
+        #[cfg(not(feature = "std"))]
+        let regex = regex.map_err(Diagnostic::from);
+        regex
+    }
+
+    #[cfg(not(feature = "regex"))]
+    pub(crate) fn regex(&self, pattern: &str, _: bool) -> Result<Regex, RegexError> {
+        Err(Diagnostic::new("identifier.unsupported_feature")
+            .with("feature", "regex")
+            .with("pattern", pattern))
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn regex_set<I, S>(
+        &self,
+        patterns: I,
+        insensitive: bool,
+    ) -> Result<RegexSet, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut builder = RegexSetBuilder::new(patterns);
+        builder.case_insensitive(insensitive);
+        if let Some(limit) = self.regex_size_limit {
+            builder.size_limit(limit);
+        }
+        if let Some(limit) = self.regex_dfa_size_limit {
+            builder.dfa_size_limit(limit);
+        }
+        builder.build()
+    }
+
+    #[cfg(feature = "fancy_regex")]
+    pub(crate) fn fancy_regex(
+        &self,
+        pattern: &str,
+        insensitive: bool,
+    ) -> crate::Result<fancy_regex::Regex> {
+        let mut builder = if insensitive {
+            fancy_regex::RegexBuilder::new(&format!("(?i){}", pattern))
+        } else {
+            fancy_regex::RegexBuilder::new(pattern)
+        };
+        if let Some(limit) = self.regex_size_limit {
+            builder.delegate_size_limit(limit);
+        }
+        if let Some(limit) = self.regex_dfa_size_limit {
+            builder.delegate_dfa_size_limit(limit);
+        }
+        builder.build().map_err(crate::error::parse_invalid_ident)
+    }
+}
+
 impl Expression {
     pub fn is_solvable(&self) -> bool {
         match self {
@@ -139,32 +646,255 @@ impl Expression {
             | Self::Field(_)
             | Self::Float(_)
             | Self::Integer(_)
+            | Self::Keywords(_)
             | Self::Null
-            | Self::Search(_, _, _) => false,
+            | Self::Search(_, _, _)
+            | Self::Timestamp(_) => false,
             Self::BooleanGroup(_, _)
             | Self::BooleanExpression(_, _, _)
             | Self::Identifier(_)
+            | Self::In(_, _)
             | Self::Match(_, _)
             | Self::Negate(_)
-            | Self::Nested(_, _) => true,
+            | Self::Nested(_, _)
+            | Self::Range(_, _, _) => true,
+        }
+    }
+
+    /// Renders the expression as an indented tree, with one node per line and the kind of each
+    /// search annotated.
+    pub fn to_pretty_string(&self) -> String {
+        let mut pretty = String::new();
+        self.pretty(None, 0, &mut pretty);
+        pretty
+    }
+
+    /// Renders the expression as an indented tree, like `to_pretty_string`, with each identifier
+    /// followed by the expression that it resolves to.
+    pub fn to_pretty_string_with(&self, identifiers: &HashMap<String, Expression>) -> String {
+        let mut pretty = String::new();
+        self.pretty(Some(identifiers), 0, &mut pretty);
+        pretty
+    }
+
+    fn pretty(
+        &self,
+        identifiers: Option<&HashMap<String, Expression>>,
+        depth: usize,
+        pretty: &mut String,
+    ) {
+        let line = match self {
+            Self::BooleanGroup(o, _) | Self::BooleanExpression(_, o, _) => o.to_string(),
+            Self::Boolean(b) => format!("bool: {}", b),
+            Self::Cast(s, t) => format!("cast: {}({})", t, s),
+            Self::Field(s) => format!("field: {}", s),
+            Self::Float(n) => format!("float: {}", n),
+            Self::Identifier(s) => format!("identifier: {}", s),
+            Self::In(_, i) => format!(
+                "in: [{}]",
+                i.iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+            Self::Integer(i) => format!("int: {}", i),
+            Self::Keywords(e) => format!("keywords: {}", e),
+            Self::Match(Match::All, _) => "all".to_owned(),
+            Self::Match(Match::Every, _) => "every".to_owned(),
+            Self::Match(Match::Of(i), _) => format!("of: {}", i),
+            Self::Negate(_) => "not".to_owned(),
+            Self::Nested(s, _) => format!("nested: {}", s),
+            Self::Null => "null".to_owned(),
+            Self::Range(_, x, y) => format!("range: {}..{}", x, y),
+            Self::Search(e, s, true) => format!("search: str({}) {}", s, e),
+            Self::Search(e, s, false) => format!("search: {} {}", s, e),
+            Self::Timestamp(t) => format!("timestamp: {}", timestamp::format(*t)),
+        };
+        for _ in 0..depth {
+            pretty.push_str("  ");
+        }
+        pretty.push_str(&line);
+        pretty.push('\n');
+        match self {
+            Self::BooleanGroup(_, g) => {
+                for e in g {
+                    e.pretty(identifiers, depth + 1, pretty);
+                }
+            }
+            Self::BooleanExpression(l, _, r) => {
+                l.pretty(identifiers, depth + 1, pretty);
+                r.pretty(identifiers, depth + 1, pretty);
+            }
+            Self::Identifier(s) => {
+                if let Some(e) = identifiers.and_then(|i| i.get(s)) {
+                    e.pretty(identifiers, depth + 1, pretty);
+                }
+            }
+            Self::In(e, _)
+            | Self::Match(_, e)
+            | Self::Negate(e)
+            | Self::Nested(_, e)
+            | Self::Range(e, _, _) => e.pretty(identifiers, depth + 1, pretty),
+            Self::Boolean(_)
+            | Self::Cast(_, _)
+            | Self::Field(_)
+            | Self::Float(_)
+            | Self::Integer(_)
+            | Self::Keywords(_)
+            | Self::Null
+            | Self::Search(_, _, _)
+            | Self::Timestamp(_) => {}
         }
     }
 }
 
+/// A visitor over an `Expression`, allowing it to be walked without having to match on every
+/// variant.
+///
+/// By default each method walks into the expression's children, so implementors only need to
+/// override the methods for the parts of the tree that they are interested in.
+pub trait Visitor {
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression);
+    }
+    fn visit_field(&mut self, _field: &str) {}
+    fn visit_identifier(&mut self, _identifier: &str) {}
+    fn visit_search(&mut self, _search: &Search) {}
+}
+
+/// Walks the children of the expression with the provided visitor.
+pub fn walk_expression<V: Visitor + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match expression {
+        Expression::BooleanGroup(_, group) => {
+            for expression in group {
+                visitor.visit_expression(expression);
+            }
+        }
+        Expression::BooleanExpression(left, _, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::Cast(field, _) | Expression::Field(field) => visitor.visit_field(field),
+        Expression::Identifier(identifier) => visitor.visit_identifier(identifier),
+        Expression::In(expression, _)
+        | Expression::Match(_, expression)
+        | Expression::Negate(expression)
+        | Expression::Range(expression, _, _) => visitor.visit_expression(expression),
+        Expression::Nested(field, expression) => {
+            visitor.visit_field(field);
+            visitor.visit_expression(expression);
+        }
+        Expression::Keywords(search) => visitor.visit_search(search),
+        Expression::Search(search, field, _) => {
+            visitor.visit_field(field);
+            visitor.visit_search(search);
+        }
+        Expression::Boolean(_)
+        | Expression::Float(_)
+        | Expression::Integer(_)
+        | Expression::Null
+        | Expression::Timestamp(_) => {}
+    }
+}
+
+/// A folder over an `Expression`, allowing it to be rewritten without having to match on every
+/// variant.
+///
+/// By default each method rebuilds the expression from its folded children, so implementors only
+/// need to override the methods for the parts of the tree that they want to rewrite.
+pub trait Folder {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        fold_expression(self, expression)
+    }
+    fn fold_field(&mut self, field: String) -> String {
+        field
+    }
+    fn fold_identifier(&mut self, identifier: String) -> String {
+        identifier
+    }
+    fn fold_search(&mut self, search: Search) -> Search {
+        search
+    }
+}
+
+/// Rebuilds the expression from its children as folded by the provided folder.
+pub fn fold_expression<F: Folder + ?Sized>(folder: &mut F, expression: Expression) -> Expression {
+    match expression {
+        Expression::BooleanGroup(symbol, group) => Expression::BooleanGroup(
+            symbol,
+            group
+                .into_iter()
+                .map(|e| folder.fold_expression(e))
+                .collect(),
+        ),
+        Expression::BooleanExpression(left, symbol, right) => Expression::BooleanExpression(
+            Box::new(folder.fold_expression(*left)),
+            symbol,
+            Box::new(folder.fold_expression(*right)),
+        ),
+        Expression::Cast(field, symbol) => Expression::Cast(folder.fold_field(field), symbol),
+        Expression::Field(field) => Expression::Field(folder.fold_field(field)),
+        Expression::Identifier(identifier) => {
+            Expression::Identifier(folder.fold_identifier(identifier))
+        }
+        Expression::In(expression, integers) => {
+            Expression::In(Box::new(folder.fold_expression(*expression)), integers)
+        }
+        Expression::Match(m, expression) => {
+            Expression::Match(m, Box::new(folder.fold_expression(*expression)))
+        }
+        Expression::Negate(expression) => {
+            Expression::Negate(Box::new(folder.fold_expression(*expression)))
+        }
+        Expression::Nested(field, expression) => Expression::Nested(
+            folder.fold_field(field),
+            Box::new(folder.fold_expression(*expression)),
+        ),
+        Expression::Range(expression, from, to) => {
+            Expression::Range(Box::new(folder.fold_expression(*expression)), from, to)
+        }
+        Expression::Keywords(search) => Expression::Keywords(folder.fold_search(search)),
+        Expression::Search(search, field, cast) => {
+            Expression::Search(folder.fold_search(search), folder.fold_field(field), cast)
+        }
+        Expression::Boolean(_)
+        | Expression::Float(_)
+        | Expression::Integer(_)
+        | Expression::Null
+        | Expression::Timestamp(_) => expression,
+    }
+}
+
 // Pratt Parser used to parse the token stream
 //
 // Left-Denotation (LED) - how an operator consumes to the right with a left-context
 // Null-Denotation (NUD) - how an operator consumes to the right with no left-context
 
 pub(crate) fn parse(tokens: &[Token]) -> crate::Result<Expression> {
+    parse_at(tokens, &[])
+}
+
+// Parses the tokens, where errors report the position of the token that they were raised at when
+// the positions of the tokens in the condition are provided.
+pub(crate) fn parse_at(tokens: &[Token], positions: &[Position]) -> crate::Result<Expression> {
     let mut it = tokens.iter().peekable();
-    let expression = parse_expr(&mut it, 0)?;
+    let at = |e: crate::Error, i: usize| match positions.get(i) {
+        Some(position) => e.at(*position),
+        None => e,
+    };
+    // NOTE: Errors are raised on the last token that was consumed.
+    let expression =
+        parse_expr(&mut it, 0).map_err(|e| at(e, (tokens.len() - it.len()).saturating_sub(1)))?;
     if it.peek().is_some() {
+        let i = tokens.len() - it.len();
         let remaining = it.collect::<Vec<&Token>>();
-        return Err(crate::error::parse_invalid_expr(format!(
-            "failed to parse the following tokens - '{:?}'",
-            remaining
-        )));
+        return Err(at(
+            crate::error::parse_invalid_expr(
+                Diagnostic::new("condition.trailing_tokens")
+                    .with("tokens", format!("{:?}", remaining)),
+            ),
+            i,
+        ));
     }
 
     debug!("parsed '{:?}' into '{:?}'", tokens, expression);
@@ -193,35 +923,51 @@ where
     match it.next() {
         Some(t) => match *t {
             Token::Operator(ref s) => {
-                let symbol = match *s {
-                    BoolSym::And => BoolSym::And,
-                    BoolSym::Equal => BoolSym::Equal,
-                    BoolSym::GreaterThan => BoolSym::GreaterThan,
-                    BoolSym::GreaterThanOrEqual => BoolSym::GreaterThanOrEqual,
-                    BoolSym::LessThan => BoolSym::LessThan,
-                    BoolSym::LessThanOrEqual => BoolSym::LessThanOrEqual,
-                    BoolSym::Or => BoolSym::Or,
-                };
+                let symbol = *s;
                 let right = parse_expr(it, t.binding_power())?;
                 // Handle special limited cases
                 match symbol {
+                    BoolSym::Contains | BoolSym::EndsWith | BoolSym::StartsWith => {
+                        match left {
+                            Expression::Cast(_, ModSym::Str) => {}
+                            _ => {
+                                return Err(crate::error::parse_led_preceding(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
+                            }
+                        }
+                        match right {
+                            Expression::Cast(_, ModSym::Str) => {}
+                            _ => {
+                                return Err(crate::error::parse_led_following(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
+                            }
+                        }
+                    }
                     BoolSym::Equal => {
                         match left {
-                            Expression::Cast(_, _) | Expression::Integer(_) => {}
+                            Expression::Cast(_, _)
+                            | Expression::Integer(_)
+                            | Expression::Timestamp(_) => {}
                             _ => {
-                                return Err(crate::error::parse_led_preceding(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_led_preceding(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                         match right {
-                            Expression::Cast(_, _) | Expression::Integer(_) => {}
+                            Expression::Cast(_, _)
+                            | Expression::Integer(_)
+                            | Expression::Timestamp(_) => {}
                             _ => {
-                                return Err(crate::error::parse_led_following(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_led_following(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                         // Type enforcement
@@ -236,11 +982,27 @@ where
                             ) => {}
                             (Expression::Cast(_, ModSym::Int), Expression::Integer(_)) => {}
                             (Expression::Integer(_), Expression::Cast(_, ModSym::Int)) => {}
+                            (
+                                Expression::Cast(_, ModSym::Int | ModSym::Len),
+                                Expression::Cast(_, ModSym::Len),
+                            ) => {}
+                            (
+                                Expression::Cast(_, ModSym::Len),
+                                Expression::Cast(_, ModSym::Int),
+                            ) => {}
+                            (Expression::Cast(_, ModSym::Len), Expression::Integer(_)) => {}
+                            (Expression::Integer(_), Expression::Cast(_, ModSym::Len)) => {}
+                            (
+                                Expression::Cast(_, ModSym::Time),
+                                Expression::Cast(_, ModSym::Time),
+                            ) => {}
+                            (Expression::Cast(_, ModSym::Time), Expression::Timestamp(_)) => {}
+                            (Expression::Timestamp(_), Expression::Cast(_, ModSym::Time)) => {}
                             (_, _) => {
-                                return Err(crate::error::parse_invalid_expr(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_invalid_expr(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                     }
@@ -249,21 +1011,25 @@ where
                     | BoolSym::LessThan
                     | BoolSym::LessThanOrEqual => {
                         match left {
-                            Expression::Cast(_, _) | Expression::Integer(_) => {}
+                            Expression::Cast(_, _)
+                            | Expression::Integer(_)
+                            | Expression::Timestamp(_) => {}
                             _ => {
-                                return Err(crate::error::parse_led_preceding(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_led_preceding(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                         match right {
-                            Expression::Cast(_, _) | Expression::Integer(_) => {}
+                            Expression::Cast(_, _)
+                            | Expression::Integer(_)
+                            | Expression::Timestamp(_) => {}
                             _ => {
-                                return Err(crate::error::parse_led_following(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_led_following(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                         // Type enforcement
@@ -274,11 +1040,31 @@ where
                             ) => {}
                             (Expression::Cast(_, ModSym::Int), Expression::Integer(_)) => {}
                             (Expression::Integer(_), Expression::Cast(_, ModSym::Int)) => {}
+                            (
+                                Expression::Cast(_, ModSym::Int | ModSym::Len),
+                                Expression::Cast(_, ModSym::Len),
+                            ) => {}
+                            (
+                                Expression::Cast(_, ModSym::Len),
+                                Expression::Cast(_, ModSym::Int),
+                            ) => {}
+                            (Expression::Cast(_, ModSym::Len), Expression::Integer(_)) => {}
+                            (Expression::Integer(_), Expression::Cast(_, ModSym::Len)) => {}
+                            (
+                                Expression::Cast(_, ModSym::Str),
+                                Expression::Cast(_, ModSym::Str),
+                            ) => {}
+                            (
+                                Expression::Cast(_, ModSym::Time),
+                                Expression::Cast(_, ModSym::Time),
+                            ) => {}
+                            (Expression::Cast(_, ModSym::Time), Expression::Timestamp(_)) => {}
+                            (Expression::Timestamp(_), Expression::Cast(_, ModSym::Time)) => {}
                             (_, _) => {
-                                return Err(crate::error::parse_invalid_expr(format!(
-                                    "encountered - '{:?}'",
-                                    t
-                                )));
+                                return Err(crate::error::parse_invalid_expr(
+                                    Diagnostic::new("condition.unexpected_token")
+                                        .with("token", format!("{:?}", t)),
+                                ));
                             }
                         }
                     }
@@ -296,12 +1082,14 @@ where
             | Token::Integer(_)
             | Token::Miscellaneous(_)
             | Token::Modifier(_)
-            | Token::Match(_) => Err(crate::error::parse_invalid_token(format!(
-                "LED encountered - '{:?}'",
-                t
-            ))),
+            | Token::Match(_)
+            | Token::Timestamp(_) => Err(crate::error::parse_invalid_token(
+                Diagnostic::new("condition.unexpected_infix").with("token", format!("{:?}", t)),
+            )),
         },
-        None => Err(crate::error::parse_invalid_token("LED expected token")),
+        None => Err(crate::error::parse_invalid_token(Diagnostic::new(
+            "condition.expected_infix",
+        ))),
     }
 }
 
@@ -331,13 +1119,17 @@ where
                         }
                         parse(&tokens)
                     }
-                    DelSym::Comma | DelSym::RightParenthesis => Err(
-                        crate::error::parse_invalid_token(format!("NUD encountered - '{:?}'", t)),
-                    ),
+                    DelSym::Comma | DelSym::RightParenthesis => {
+                        Err(crate::error::parse_invalid_token(
+                            Diagnostic::new("condition.unexpected_prefix")
+                                .with("token", format!("{:?}", t)),
+                        ))
+                    }
                 },
                 Token::Float(ref n) => Ok(Expression::Float(*n)),
                 Token::Identifier(ref n) => Ok(Expression::Identifier(n.to_string())),
                 Token::Integer(ref n) => Ok(Expression::Integer(*n)),
+                Token::Timestamp(ref t) => Ok(Expression::Timestamp(*t)),
                 Token::Miscellaneous(ref m) => match *m {
                     MiscSym::Not => {
                         let right = parse_expr(it, t.binding_power())?;
@@ -346,165 +1138,74 @@ where
                             | Expression::BooleanExpression(_, _, _)
                             | Expression::Boolean(_)
                             | Expression::Identifier(_)
-                            | Expression::Match(_, _)
-                            | Expression::Negate(_)
-                            | Expression::Nested(_, _)
-                            | Expression::Search(_, _, _) => {}
-                            _ => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected a negatable expression",
-                                ))
-                            }
-                        }
-                        Ok(Expression::Negate(Box::new(right)))
-                    }
-                },
-                Token::Modifier(ref m) => match *m {
-                    ModSym::Int => {
-                        // We expect Int(column_identifier)
-                        if let Some(t) = it.next() {
-                            match *t {
-                                Token::Delimiter(DelSym::LeftParenthesis) => {}
-                                _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected left parenthesis - '{:?}'",
-                                        t
-                                    )));
-                                }
-                            }
-                        } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected left parenthesis",
-                            ));
-                        }
-                        let token = match it.next() {
-                            Some(t) => t,
-                            None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected column identifier",
-                                ));
-                            }
-                        };
-                        if let Some(t) = it.next() {
-                            match *t {
-                                Token::Delimiter(DelSym::RightParenthesis) => {}
-                                _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected right parenthesis - '{:?}'",
-                                        t
-                                    )));
-                                }
-                            }
-                        } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected right parenthesis",
-                            ));
-                        }
-                        match *token {
-                            Token::Identifier(ref s) => {
-                                Ok(Expression::Cast(s.to_string(), ModSym::Int))
-                            }
-                            _ => Err(crate::error::parse_invalid_token(
-                                "NUD expected column identifier",
-                            )),
-                        }
-                    }
-                    ModSym::Not => {
-                        // We expect Int(column_identifier)
-                        if let Some(t) = it.next() {
-                            match *t {
-                                Token::Delimiter(DelSym::LeftParenthesis) => {}
-                                _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected left parenthesis - '{:?}'",
-                                        t
-                                    )));
-                                }
-                            }
-                        } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected left parenthesis",
-                            ));
-                        }
-                        let token = match it.next() {
-                            Some(t) => t,
-                            None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected column identifier",
-                                ));
-                            }
-                        };
-                        if let Some(t) = it.next() {
-                            match *t {
-                                Token::Delimiter(DelSym::RightParenthesis) => {}
-                                _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected right parenthesis - '{:?}'",
-                                        t
-                                    )));
-                                }
-                            }
-                        } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected right parenthesis",
-                            ));
-                        }
-                        match *token {
-                            Token::Identifier(ref s) => {
-                                Ok(Expression::Cast(s.to_string(), ModSym::Not))
+                            | Expression::Match(_, _)
+                            | Expression::Negate(_)
+                            | Expression::Nested(_, _)
+                            | Expression::Search(_, _, _) => {}
+                            _ => {
+                                return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                    "condition.expected_negatable",
+                                )))
                             }
-                            _ => Err(crate::error::parse_invalid_token(
-                                "NUD expected column identifier",
-                            )),
                         }
+                        Ok(Expression::Negate(Box::new(right)))
                     }
-                    ModSym::Str => {
-                        // We expect string(column_identifier)
+                },
+                Token::Modifier(ref m) => match *m {
+                    ModSym::Int
+                    | ModSym::Len
+                    | ModSym::Not
+                    | ModSym::Skeleton
+                    | ModSym::Str
+                    | ModSym::Time
+                    | ModSym::Wide
+                    | ModSym::Windash => {
+                        // We expect modifier(column_identifier)
                         if let Some(t) = it.next() {
                             match *t {
                                 Token::Delimiter(DelSym::LeftParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected left parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_left_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected left parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_left_parenthesis",
+                            )));
                         }
                         let token = match it.next() {
                             Some(t) => t,
                             None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected column identifier",
-                                ));
+                                return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                    "condition.expected_column",
+                                )));
                             }
                         };
                         if let Some(t) = it.next() {
                             match *t {
                                 Token::Delimiter(DelSym::RightParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected right parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_right_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected right parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_right_parenthesis",
+                            )));
                         }
                         match *token {
                             Token::Identifier(ref s) => {
-                                Ok(Expression::Cast(s.to_string(), ModSym::Str))
+                                Ok(Expression::Cast(s.to_string(), m.clone()))
                             }
-                            _ => Err(crate::error::parse_invalid_token(
-                                "NUD expected column identifier",
-                            )),
+                            _ => Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.expected_column",
+                            ))),
                         }
                     }
                 },
@@ -515,48 +1216,48 @@ where
                             match *t {
                                 Token::Delimiter(DelSym::LeftParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected left parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_left_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected left parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_left_parenthesis",
+                            )));
                         }
                         let token = match it.next() {
                             Some(t) => t,
                             None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected column identifier",
-                                ));
+                                return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                    "condition.expected_column",
+                                )));
                             }
                         };
                         if let Some(t) = it.next() {
                             match *t {
                                 Token::Delimiter(DelSym::RightParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected right parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_right_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected right parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_right_parenthesis",
+                            )));
                         }
                         match *token {
                             Token::Identifier(ref s) => Ok(Expression::Match(
                                 Match::All,
                                 Box::new(Expression::Identifier(s.to_string())),
                             )),
-                            _ => Err(crate::error::parse_invalid_token(
-                                "NUD expected column identifier",
-                            )),
+                            _ => Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.expected_column",
+                            ))),
                         }
                     }
                     MatchSym::Of => {
@@ -565,151 +1266,223 @@ where
                             match *t {
                                 Token::Delimiter(DelSym::LeftParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected left parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_left_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected left parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_left_parenthesis",
+                            )));
                         }
                         let token = match it.next() {
                             Some(t) => t,
                             None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected column identifier",
-                                ));
+                                return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                    "condition.expected_column",
+                                )));
                             }
                         };
                         if let Some(t) = it.next() {
                             match *t {
                                 Token::Delimiter(DelSym::Comma) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected comma - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_comma")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token("NUD expected comma"));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_comma",
+                            )));
                         }
                         let count = match it.next() {
                             Some(t) => match t {
                                 Token::Integer(c) => match u64::try_from(*c) {
                                     Ok(u) => u,
                                     Err(_) => {
-                                        return Err(crate::error::parse_invalid_token(format!(
-                                            "NUD expected positive integer - '{:?}'",
-                                            t
-                                        )));
+                                        return Err(crate::error::parse_invalid_token(
+                                            Diagnostic::new("condition.expected_positive_integer")
+                                                .with("token", format!("{:?}", t)),
+                                        ));
                                     }
                                 },
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected integer - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_integer")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             },
                             None => {
-                                return Err(crate::error::parse_invalid_token(
-                                    "NUD expected integer",
-                                ));
+                                return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                    "condition.missing_integer",
+                                )));
                             }
                         };
                         if let Some(t) = it.next() {
                             match *t {
                                 Token::Delimiter(DelSym::RightParenthesis) => {}
                                 _ => {
-                                    return Err(crate::error::parse_invalid_token(format!(
-                                        "NUD expected right parenthesis - '{:?}'",
-                                        t
-                                    )));
+                                    return Err(crate::error::parse_invalid_token(
+                                        Diagnostic::new("condition.expected_right_parenthesis")
+                                            .with("token", format!("{:?}", t)),
+                                    ));
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_token(
-                                "NUD expected right parenthesis",
-                            ));
+                            return Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.missing_right_parenthesis",
+                            )));
                         }
                         match *token {
                             Token::Identifier(ref s) => Ok(Expression::Match(
                                 Match::Of(count),
                                 Box::new(Expression::Identifier(s.to_string())),
                             )),
-                            _ => Err(crate::error::parse_invalid_token(
-                                "NUD expected column identifier",
-                            )),
+                            _ => Err(crate::error::parse_invalid_token(Diagnostic::new(
+                                "condition.expected_column",
+                            ))),
                         }
                     }
                 },
-                Token::Operator(_) => Err(crate::error::parse_invalid_token(format!(
-                    "NUD encountered - '{:?}'",
-                    t
-                ))),
+                Token::Operator(_) => Err(crate::error::parse_invalid_token(
+                    Diagnostic::new("condition.unexpected_prefix")
+                        .with("token", format!("{:?}", t)),
+                )),
             }
         }
-        None => Err(crate::error::parse_invalid_token("NUD expected token")),
+        None => Err(crate::error::parse_invalid_token(Diagnostic::new(
+            "condition.expected_prefix",
+        ))),
     }
 }
 
+#[cfg(feature = "std")]
+#[cfg_attr(not(any(feature = "core", feature = "unstable")), allow(dead_code))]
 pub fn parse_identifier(yaml: &Yaml) -> crate::Result<Expression> {
+    parse_identifier_with(yaml, &ParserOptions::default())
+}
+
+#[cfg(feature = "std")]
+pub fn parse_identifier_with(yaml: &Yaml, options: &ParserOptions) -> crate::Result<Expression> {
+    let expression = parse_yaml(yaml, options)?;
+    if options.coerce {
+        return Ok(Coerce.fold_expression(expression));
+    }
+    Ok(expression)
+}
+
+// Rewrites integer comparisons against uncast fields into comparisons against integer casts, which
+// parse numeric strings and booleans.
+//
+// NOTE: Float comparisons are left as written, as an integer cast would truncate their values.
+#[cfg(feature = "std")]
+struct Coerce;
+
+#[cfg(feature = "std")]
+impl Folder for Coerce {
+    fn fold_expression(&mut self, expression: Expression) -> Expression {
+        let cast = |e: Box<Expression>| match *e {
+            Expression::Field(field) => Box::new(Expression::Cast(field, ModSym::Int)),
+            e => Box::new(e),
+        };
+        match expression {
+            Expression::BooleanExpression(left, symbol, right)
+                if matches!(
+                    symbol,
+                    BoolSym::Equal
+                        | BoolSym::GreaterThan
+                        | BoolSym::GreaterThanOrEqual
+                        | BoolSym::LessThan
+                        | BoolSym::LessThanOrEqual
+                ) && matches!(
+                    (left.as_ref(), right.as_ref()),
+                    (Expression::Field(_), Expression::Integer(_))
+                        | (Expression::Integer(_), Expression::Field(_))
+                ) =>
+            {
+                Expression::BooleanExpression(cast(left), symbol, cast(right))
+            }
+            Expression::In(e, integers) => Expression::In(cast(e), integers),
+            Expression::Range(e, from, to) => Expression::Range(cast(e), from, to),
+            expression => fold_expression(self, expression),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn parse_yaml(yaml: &Yaml, options: &ParserOptions) -> crate::Result<Expression> {
     match yaml {
-        Yaml::Mapping(m) => parse_mapping(m),
+        Yaml::Mapping(m) => parse_mapping(m, options),
         Yaml::Sequence(s) => {
-            // We allow a sequence of maps only on the root
+            // We allow a sequence of maps, or of keywords, only on the root
             let mut it = s.iter();
             match it.next() {
                 Some(v) => match &v {
                     Yaml::Mapping(m) => {
-                        let mut expressions = vec![parse_mapping(m)?];
+                        let mut expressions = vec![parse_mapping(m, options)?];
                         for value in it {
                             // NOTE: A sequence can only be one type
                             if let Yaml::Mapping(mapping) = value {
-                                expressions.push(parse_mapping(mapping)?);
+                                expressions.push(parse_mapping(mapping, options)?);
                             } else {
-                                return Err(crate::error::parse_invalid_ident(format!(
-                                    "expected a sequence of mappings, encountered - {:?}",
-                                    yaml
-                                )));
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.expected_sequence_of_mappings")
+                                        .with("value", format!("{:?}", yaml)),
+                                ));
                             }
                         }
                         Ok(Expression::BooleanGroup(BoolSym::Or, expressions))
                     }
-                    _ => Err(crate::error::parse_invalid_ident(format!(
-                        "expected a sequence of mappings, encountered - {:?}",
-                        yaml
-                    ))),
+                    Yaml::String(_) => parse_keywords(s, options),
+                    _ => Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.expected_sequence_of_mappings")
+                            .with("value", format!("{:?}", yaml)),
+                    )),
                 },
-                None => Err(crate::error::parse_invalid_ident(format!(
-                    "expected a non empty sequence of mappings, encountered - {:?}",
-                    yaml
-                ))),
+                None => Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.empty_sequence")
+                        .with("value", format!("{:?}", yaml)),
+                )),
             }
         }
-        _ => Err(crate::error::parse_invalid_ident(format!(
-            "expected mapping or sequence, encountered - {:?}",
-            yaml
-        ))),
+        _ => Err(crate::error::parse_invalid_ident(
+            Diagnostic::new("identifier.expected_mapping_or_sequence")
+                .with("value", format!("{:?}", yaml)),
+        )),
     }
 }
 
 // TODO: Extract common code and try to make this function a little bit more readable
-fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
+#[cfg(feature = "std")]
+fn parse_mapping(mapping: &Mapping, options: &ParserOptions) -> crate::Result<Expression> {
     let mut expressions = vec![];
     for (k, v) in mapping {
         let mut misc: Option<ModSym> = None;
+        // NOTE: `every(k)` requires a numeric comparison to hold for every element of an array,
+        // so we strip it from the key and wrap the expression once it has been parsed.
+        let every = match k {
+            Yaml::String(s) => s.strip_prefix("every(").and_then(|s| s.strip_suffix(')')),
+            _ => None,
+        };
+        let key = every.map(|s| Yaml::String(s.to_owned()));
+        let k = key.as_ref().unwrap_or(k);
         let (e, f) = match k {
+            // NOTE: JSONPath queries are passed through untouched, as they are resolved by the
+            // `JsonPath` adapter rather than the solver.
+            Yaml::String(s) if s.starts_with('$') => (Expression::Field(s.clone()), s.clone()),
             Yaml::String(s) => {
                 // NOTE: Tokenise splits on whitespace, but this is undesired for keys, merge them
                 // back together
                 let mut identifier = vec![];
                 let mut tokens = vec![];
-                for token in s.tokenise()? {
+                let keywords = options.dialect.keywords(&Keywords::default());
+                for token in s.tokenise_with(&keywords)? {
                     match token {
                         Token::Identifier(s) => identifier.push(s),
                         _ => {
@@ -731,49 +1504,118 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         misc = Some(s.clone());
                         match s {
                             ModSym::Int => (Expression::Cast(f.clone(), s), f),
+                            ModSym::Len => (Expression::Cast(f.clone(), s), f),
                             ModSym::Not => (Expression::Field(f.clone()), f),
+                            ModSym::Skeleton => (Expression::Field(f.clone()), f),
                             ModSym::Str => (Expression::Cast(f.clone(), s), f),
+                            ModSym::Time => (Expression::Cast(f.clone(), s), f),
+                            ModSym::Wide => (Expression::Field(f.clone()), f),
+                            ModSym::Windash => (Expression::Field(f.clone()), f),
                         }
                     }
                     Expression::Identifier(s) => (Expression::Field(s.clone()), s),
                     Expression::Match(m, i) => {
-                        if let Yaml::Sequence(_) = v {
+                        // NOTE: `of(k, x)` over nested pairs counts the elements of the array that
+                        // satisfy them.
+                        let counted = matches!(m, Match::Of(_)) && v.is_mapping();
+                        if let (true, Expression::Identifier(s)) = (counted, i.as_ref()) {
+                            (
+                                Expression::Match(m, Box::new(Expression::Field(s.clone()))),
+                                s.clone(),
+                            )
+                        } else if let Yaml::Sequence(_) = v {
                             match *i {
                                 Expression::Identifier(s) => (
                                     Expression::Match(m, Box::new(Expression::Field(s.clone()))),
                                     s,
                                 ),
                                 _ => {
-                                    return Err(crate::error::parse_invalid_ident(format!(
-                                        "match condition mut contain a field, encountered - {:?}",
-                                        k
-                                    )))
+                                    return Err(crate::error::parse_invalid_ident(
+                                        Diagnostic::new("identifier.match_without_field")
+                                            .with("key", format!("{:?}", k)),
+                                    ))
                                 }
                             }
                         } else {
-                            return Err(crate::error::parse_invalid_ident(format!(
-                                "match condition is only valid for sequences, encountered - {:?}",
-                                k
-                            )));
+                            return Err(crate::error::parse_invalid_ident(
+                                Diagnostic::new("identifier.match_without_sequence")
+                                    .with("key", format!("{:?}", k)),
+                            ));
                         }
                     }
                     _ => {
-                        return Err(crate::error::parse_invalid_ident(format!(
-                        "mapping key must be a string or valid match condition, encountered - {:?}",
-                        k
-                    )))
+                        return Err(crate::error::parse_invalid_ident(
+                            Diagnostic::new("identifier.invalid_match_key")
+                                .with("key", format!("{:?}", k)),
+                        ))
                     }
                 };
                 (e, s)
             }
             _ => {
-                return Err(crate::error::parse_invalid_ident(format!(
-                    "mapping key must be a string, encountered - {:?}",
-                    k
-                )))
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.invalid_key").with("key", format!("{:?}", k)),
+                ))
+            }
+        };
+        // NOTE: Windash expands a needle into many, so we just treat it as a sequence
+        let sequence;
+        let v = if let Some(ModSym::Windash) = misc {
+            match v {
+                Yaml::String(_) => {
+                    sequence = Yaml::Sequence(vec![v.clone()]);
+                    &sequence
+                }
+                Yaml::Sequence(s) if s.iter().all(|v| v.is_string()) => v,
+                _ => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.windash_not_string_value")
+                            .with("key", format!("{:?}", k)),
+                    ));
+                }
             }
+        } else {
+            v
         };
+        // NOTE: Skeleton maps the needles once they have been parsed, so that confusable sigils
+        // are left as literals, and wraps the resulting searches below so that the values are
+        // mapped when solving.
+        if let Some(ModSym::Skeleton) = misc {
+            match v {
+                Yaml::String(_) => {}
+                Yaml::Sequence(s) if s.iter().all(|v| v.is_string()) => {}
+                _ => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.skeleton_not_string_value")
+                            .with("key", format!("{:?}", k)),
+                    ));
+                }
+            }
+        }
+        if let Some(ModSym::Wide) = misc {
+            let needles = match v {
+                Yaml::String(s) => vec![s.clone()],
+                Yaml::Sequence(s) if s.iter().all(|v| v.is_string()) => s
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_owned))
+                    .collect(),
+                _ => {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.wide_not_string_value")
+                            .with("key", format!("{:?}", k)),
+                    ));
+                }
+            };
+            expressions.push(wide(&f, needles, options)?);
+            continue;
+        }
         let expression = match v {
+            _ if misc == Some(ModSym::Time) => parse_time(&e, v)?,
+            Yaml::Bool(_) if misc == Some(ModSym::Len) => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.length_of_boolean").with("key", format!("{:?}", k)),
+                ));
+            }
             Yaml::Bool(b) => {
                 if let Some(ModSym::Int) = misc {
                     Expression::BooleanExpression(
@@ -803,11 +1645,11 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         )
                     }
                 } else if let Some(i) = n.as_f64() {
-                    if let Some(ModSym::Int) = misc {
-                        return Err(crate::error::parse_invalid_ident(format!(
-                            "float cannot be cast into an integer, encountered - {:?}",
-                            k
-                        )));
+                    if let Some(ModSym::Int | ModSym::Len) = misc {
+                        return Err(crate::error::parse_invalid_ident(
+                            Diagnostic::new("identifier.float_to_integer")
+                                .with("key", format!("{:?}", k)),
+                        ));
                     } else if let Some(ModSym::Str) = misc {
                         Expression::Search(Search::Exact(i.to_string()), f.to_owned(), true)
                     } else {
@@ -818,10 +1660,10 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         )
                     }
                 } else {
-                    return Err(crate::error::parse_invalid_ident(format!(
-                        "number must be a signed integer or float, encountered - {:?}",
-                        k
-                    )));
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.invalid_number")
+                            .with("key", format!("{:?}", k)),
+                    ));
                 }
             }
             Yaml::Null => Expression::BooleanExpression(
@@ -830,7 +1672,10 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 Box::new(Expression::Null),
             ),
             Yaml::String(ref s) => {
-                let identifier = s.to_owned().into_identifier()?;
+                let mut identifier = s.to_owned().into_identifier_with(options)?;
+                if let Some(ModSym::Skeleton) = misc {
+                    identifier = identifier.skeleton();
+                }
                 let mut cast = false;
                 if let Some(ref m) = misc {
                     if let ModSym::Str = m {
@@ -842,12 +1687,25 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         | Pattern::Contains(_)
                         | Pattern::EndsWith(_)
                         | Pattern::Exact(_)
+                        | Pattern::Fuzzy(_, _)
+                        | Pattern::Glob(_)
+                        | Pattern::Hex(_)
+                        | Pattern::Lookup(_)
                         | Pattern::StartsWith(_) => {
-                            if let ModSym::Int = m {
-                                return Err(crate::error::parse_invalid_ident(format!(
-                                    "cannot cast string to integer, encountered - {:?}",
-                                    k
-                                )));
+                            if let ModSym::Int | ModSym::Len = m {
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.string_to_integer")
+                                        .with("key", format!("{:?}", k)),
+                                ));
+                            }
+                        }
+                        #[cfg(feature = "fancy_regex")]
+                        Pattern::FancyRegex(_) => {
+                            if let ModSym::Int | ModSym::Len = m {
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.string_to_integer")
+                                        .with("key", format!("{:?}", k)),
+                                ));
                             }
                         }
                         Pattern::Equal(_)
@@ -855,16 +1713,17 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         | Pattern::GreaterThanOrEqual(_)
                         | Pattern::LessThan(_)
                         | Pattern::LessThanOrEqual(_)
+                        | Pattern::Range(_, _)
                         | Pattern::FEqual(_)
                         | Pattern::FGreaterThan(_)
                         | Pattern::FGreaterThanOrEqual(_)
                         | Pattern::FLessThan(_)
                         | Pattern::FLessThanOrEqual(_) => {
                             if let ModSym::Str = m {
-                                return Err(crate::error::parse_invalid_ident(format!(
-                                    "cannot cast integer to string, encountered - {:?}",
-                                    k
-                                )));
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.integer_to_string")
+                                        .with("key", format!("{:?}", k)),
+                                ));
                             }
                         }
                     }
@@ -875,6 +1734,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         BoolSym::Equal,
                         Box::new(Expression::Integer(i)),
                     ),
+                    Pattern::Range(x, y) => Expression::Range(Box::new(e.clone()), x, y),
                     Pattern::GreaterThan(i) => Expression::BooleanExpression(
                         Box::new(e.clone()),
                         BoolSym::GreaterThan,
@@ -921,23 +1781,36 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         Box::new(Expression::Float(i)),
                     ),
                     Pattern::Any => Expression::Search(Search::Any, f.to_owned(), cast),
+                    Pattern::Hex(h) => Expression::Search(Search::Hex(h), f.to_owned(), cast),
+                    Pattern::Fuzzy(s, d) => Expression::Search(
+                        Search::Fuzzy(s, d, identifier.ignore_case),
+                        f.to_owned(),
+                        cast,
+                    ),
+                    Pattern::Glob(g) => Expression::Search(
+                        Search::Glob(g, identifier.ignore_case),
+                        f.to_owned(),
+                        cast,
+                    ),
+                    Pattern::Lookup(l) => Expression::Search(
+                        Search::Lookup(l, identifier.ignore_case),
+                        f.to_owned(),
+                        cast,
+                    ),
                     Pattern::Regex(c) => Expression::Search(
                         Search::Regex(c, identifier.ignore_case),
                         f.to_owned(),
                         cast,
                     ),
+                    #[cfg(feature = "fancy_regex")]
+                    Pattern::FancyRegex(c) => Expression::Search(
+                        Search::FancyRegex(c, identifier.ignore_case),
+                        f.to_owned(),
+                        cast,
+                    ),
                     Pattern::Contains(c) => Expression::Search(
                         if identifier.ignore_case {
-                            Search::AhoCorasick(
-                                Box::new(
-                                    AhoCorasickBuilder::new()
-                                        .ascii_case_insensitive(true)
-                                        .dfa(true)
-                                        .build(vec![c.clone()]),
-                                ),
-                                vec![MatchType::Contains(c)],
-                                true,
-                            )
+                            insensitive(vec![MatchType::Contains(c)], options)
                         } else {
                             Search::Contains(c)
                         },
@@ -946,16 +1819,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     ),
                     Pattern::EndsWith(c) => Expression::Search(
                         if identifier.ignore_case {
-                            Search::AhoCorasick(
-                                Box::new(
-                                    AhoCorasickBuilder::new()
-                                        .ascii_case_insensitive(true)
-                                        .dfa(true)
-                                        .build(vec![c.clone()]),
-                                ),
-                                vec![MatchType::EndsWith(c)],
-                                true,
-                            )
+                            insensitive(vec![MatchType::EndsWith(c)], options)
                         } else {
                             Search::EndsWith(c)
                         },
@@ -964,16 +1828,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     ),
                     Pattern::Exact(c) => Expression::Search(
                         if !c.is_empty() && identifier.ignore_case {
-                            Search::AhoCorasick(
-                                Box::new(
-                                    AhoCorasickBuilder::new()
-                                        .ascii_case_insensitive(true)
-                                        .dfa(true)
-                                        .build(vec![c.clone()]),
-                                ),
-                                vec![MatchType::Exact(c)],
-                                true,
-                            )
+                            insensitive(vec![MatchType::Exact(c)], options)
                         } else {
                             Search::Exact(c)
                         },
@@ -982,16 +1837,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     ),
                     Pattern::StartsWith(c) => Expression::Search(
                         if identifier.ignore_case {
-                            Search::AhoCorasick(
-                                Box::new(
-                                    AhoCorasickBuilder::new()
-                                        .ascii_case_insensitive(true)
-                                        .dfa(true)
-                                        .build(vec![c.clone()]),
-                                ),
-                                vec![MatchType::StartsWith(c)],
-                                true,
-                            )
+                            insensitive(vec![MatchType::StartsWith(c)], options)
                         } else {
                             Search::StartsWith(c)
                         },
@@ -1001,13 +1847,20 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 }
             }
             Yaml::Mapping(ref m) => {
-                if misc.is_some() {
-                    return Err(crate::error::parse_invalid_ident(format!(
-                        "nested mappings are not supported when casting or negating a field, encountered - {:?}",
-                        k
-                    )));
+                // NOTE: `not(k)` is negated below, which means that no element of the array
+                // satisfies the mapping.
+                if misc.is_some() && misc != Some(ModSym::Not) {
+                    return Err(crate::error::parse_invalid_ident(
+                        Diagnostic::new("identifier.nested_with_modifier")
+                            .with("key", format!("{:?}", k)),
+                    ));
+                }
+                let nested = Expression::Nested(f.to_owned(), Box::new(parse_mapping(m, options)?));
+                if let Expression::Match(m, _) = e {
+                    Expression::Match(m, Box::new(nested))
+                } else {
+                    nested
                 }
-                Expression::Nested(f.to_owned(), Box::new(parse_mapping(m)?))
             }
             Yaml::Sequence(ref s) => {
                 // TODO: This block could probably be cleaned...
@@ -1030,6 +1883,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 let mut contains: Vec<Identifier> = vec![];
                 let mut regex: Vec<Identifier> = vec![];
                 let mut rest: Vec<Expression> = vec![]; // NOTE: Don't care about speed of numbers atm
+                let mut integers: Vec<i64> = vec![];
 
                 let mut boolean = false;
                 let mut cast = false;
@@ -1043,8 +1897,15 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     e.clone()
                 };
 
+                options.complexity.patterns(regexes(s))?;
                 for value in s {
                     let identifier = match value {
+                        Yaml::Bool(_) if misc == Some(ModSym::Len) => {
+                            return Err(crate::error::parse_invalid_ident(
+                                Diagnostic::new("identifier.length_of_boolean")
+                                    .with("key", format!("{:?}", k)),
+                            ));
+                        }
                         Yaml::Bool(b) => {
                             if let Some(ModSym::Int) = misc {
                                 number = true;
@@ -1087,19 +1948,15 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                                     });
                                 } else {
                                     number = true;
-                                    rest.push(Expression::BooleanExpression(
-                                        Box::new(e.clone()),
-                                        BoolSym::Equal,
-                                        Box::new(Expression::Integer(i)),
-                                    ));
+                                    integers.push(i);
                                 }
                                 continue;
                             } else if let Some(i) = n.as_f64() {
-                                if let Some(ModSym::Int) = misc {
-                                    return Err(crate::error::parse_invalid_ident(format!(
-                                        "float cannot be cast into an integer, encountered - {:?}",
-                                        k
-                                    )));
+                                if let Some(ModSym::Int | ModSym::Len) = misc {
+                                    return Err(crate::error::parse_invalid_ident(
+                                        Diagnostic::new("identifier.float_to_integer")
+                                            .with("key", format!("{:?}", k)),
+                                    ));
                                 } else if let Some(ModSym::Str) = misc {
                                     string = true;
                                     exact.push(Identifier {
@@ -1116,177 +1973,242 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                                 }
                                 continue;
                             }
-                            return Err(crate::error::parse_invalid_ident(format!(
-                                "number must be a signed integer or float, encountered - {:?}",
-                                k
-                            )));
-                        }
-                        Yaml::String(s) => s.clone().into_identifier()?,
-
-                        Yaml::Mapping(m) => {
-                            if misc.is_some() {
-                                return Err(crate::error::parse_invalid_ident(format!(
-                                    "nested mappings are not supported when casting or negating a field, encountered - {:?}",
-                                    k
-                                )));
+                            return Err(crate::error::parse_invalid_ident(
+                                Diagnostic::new("identifier.invalid_number")
+                                    .with("key", format!("{:?}", k)),
+                            ));
+                        }
+                        Yaml::String(s) if misc == Some(ModSym::Skeleton) => {
+                            s.clone().into_identifier_with(options)?.skeleton()
+                        }
+                        Yaml::String(s) => s.clone().into_identifier_with(options)?,
+
+                        Yaml::Mapping(m) => {
+                            if misc.is_some() && misc != Some(ModSym::Not) {
+                                return Err(crate::error::parse_invalid_ident(
+                                    Diagnostic::new("identifier.nested_with_modifier")
+                                        .with("key", format!("{:?}", k)),
+                                ));
+                            }
+                            mapping = true;
+                            // FIXME: We should be nesting at the end of the squence, currently we
+                            // have to shake to remove this...
+                            rest.push(Expression::Nested(
+                                f.to_owned(),
+                                Box::new(parse_mapping(m, options)?),
+                            ));
+                            continue;
+                        }
+                        _ => {
+                            return Err(crate::error::parse_invalid_ident(
+                                Diagnostic::new("identifier.expected_mapping_or_string")
+                                    .with("key", format!("{:?}", k)),
+                            ))
+                        }
+                    };
+                    if let Some(ref m) = misc {
+                        if let ModSym::Str = m {
+                            cast = true;
+                        }
+                        match &identifier.pattern {
+                            Pattern::Any
+                            | Pattern::Regex(_)
+                            | Pattern::Contains(_)
+                            | Pattern::EndsWith(_)
+                            | Pattern::Exact(_)
+                            | Pattern::Fuzzy(_, _)
+                            | Pattern::Glob(_)
+                            | Pattern::Hex(_)
+                            | Pattern::Lookup(_)
+                            | Pattern::StartsWith(_) => {
+                                if let ModSym::Int | ModSym::Len = m {
+                                    return Err(crate::error::parse_invalid_ident(
+                                        Diagnostic::new("identifier.string_to_integer")
+                                            .with("key", format!("{:?}", k)),
+                                    ));
+                                }
+                            }
+                            #[cfg(feature = "fancy_regex")]
+                            Pattern::FancyRegex(_) => {
+                                if let ModSym::Int | ModSym::Len = m {
+                                    return Err(crate::error::parse_invalid_ident(
+                                        Diagnostic::new("identifier.string_to_integer")
+                                            .with("key", format!("{:?}", k)),
+                                    ));
+                                }
+                            }
+                            Pattern::Equal(_)
+                            | Pattern::GreaterThan(_)
+                            | Pattern::GreaterThanOrEqual(_)
+                            | Pattern::LessThan(_)
+                            | Pattern::LessThanOrEqual(_)
+                            | Pattern::Range(_, _)
+                            | Pattern::FEqual(_)
+                            | Pattern::FGreaterThan(_)
+                            | Pattern::FGreaterThanOrEqual(_)
+                            | Pattern::FLessThan(_)
+                            | Pattern::FLessThanOrEqual(_) => {
+                                if let ModSym::Str = m {
+                                    return Err(crate::error::parse_invalid_ident(
+                                        Diagnostic::new("identifier.integer_to_string")
+                                            .with("key", format!("{:?}", k)),
+                                    ));
+                                }
+                            }
+                        }
+                    }
+                    let identifiers = if let Some(ModSym::Windash) = misc {
+                        identifier.windash_with(&options.complexity)?
+                    } else {
+                        vec![identifier]
+                    };
+                    for identifier in identifiers {
+                        match identifier.pattern {
+                            Pattern::Exact(_) => {
+                                string = true;
+                                exact.push(identifier)
+                            }
+                            Pattern::StartsWith(_) => {
+                                string = true;
+                                starts_with.push(identifier)
+                            }
+                            Pattern::EndsWith(_) => {
+                                string = true;
+                                ends_with.push(identifier)
+                            }
+                            Pattern::Contains(_) => {
+                                string = true;
+                                contains.push(identifier)
+                            }
+                            Pattern::Regex(_) => {
+                                string = true;
+                                regex.push(identifier)
+                            }
+                            #[cfg(feature = "fancy_regex")]
+                            Pattern::FancyRegex(r) => {
+                                string = true;
+                                rest.push(Expression::Search(
+                                    Search::FancyRegex(r, identifier.ignore_case),
+                                    f.to_owned(),
+                                    cast,
+                                ))
+                            }
+                            Pattern::Any => {
+                                string = true;
+                                rest.push(Expression::Search(Search::Any, f.to_owned(), cast))
+                            }
+                            Pattern::Hex(h) => {
+                                string = true;
+                                rest.push(Expression::Search(Search::Hex(h), f.to_owned(), cast))
+                            }
+                            Pattern::Fuzzy(s, d) => {
+                                string = true;
+                                rest.push(Expression::Search(
+                                    Search::Fuzzy(s, d, identifier.ignore_case),
+                                    f.to_owned(),
+                                    cast,
+                                ))
+                            }
+                            Pattern::Glob(g) => {
+                                string = true;
+                                rest.push(Expression::Search(
+                                    Search::Glob(g, identifier.ignore_case),
+                                    f.to_owned(),
+                                    cast,
+                                ))
+                            }
+                            Pattern::Lookup(l) => {
+                                string = true;
+                                rest.push(Expression::Search(
+                                    Search::Lookup(l, identifier.ignore_case),
+                                    f.to_owned(),
+                                    cast,
+                                ))
+                            }
+                            Pattern::Equal(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::Equal,
+                                    Box::new(Expression::Integer(i)),
+                                ))
+                            }
+                            Pattern::Range(x, y) => {
+                                number = true;
+                                rest.push(Expression::Range(Box::new(unmatched_e.clone()), x, y))
+                            }
+                            Pattern::GreaterThan(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::GreaterThan,
+                                    Box::new(Expression::Integer(i)),
+                                ))
+                            }
+                            Pattern::GreaterThanOrEqual(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::GreaterThanOrEqual,
+                                    Box::new(Expression::Integer(i)),
+                                ))
+                            }
+                            Pattern::LessThan(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::LessThan,
+                                    Box::new(Expression::Integer(i)),
+                                ))
+                            }
+                            Pattern::LessThanOrEqual(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::LessThanOrEqual,
+                                    Box::new(Expression::Integer(i)),
+                                ))
+                            }
+                            Pattern::FEqual(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::Equal,
+                                    Box::new(Expression::Float(i)),
+                                ))
+                            }
+                            Pattern::FGreaterThan(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::GreaterThan,
+                                    Box::new(Expression::Float(i)),
+                                ))
                             }
-                            mapping = true;
-                            // FIXME: We should be nesting at the end of the squence, currently we
-                            // have to shake to remove this...
-                            rest.push(Expression::Nested(
-                                f.to_owned(),
-                                Box::new(parse_mapping(m)?),
-                            ));
-                            continue;
-                        }
-                        _ => {
-                            return Err(crate::error::parse_invalid_ident(format!(
-                                "value must be a mapping or string, encountered - {:?}",
-                                k
-                            )))
-                        }
-                    };
-                    if let Some(ref m) = misc {
-                        if let ModSym::Str = m {
-                            cast = true;
-                        }
-                        match &identifier.pattern {
-                            Pattern::Any
-                            | Pattern::Regex(_)
-                            | Pattern::Contains(_)
-                            | Pattern::EndsWith(_)
-                            | Pattern::Exact(_)
-                            | Pattern::StartsWith(_) => {
-                                if let ModSym::Int = m {
-                                    return Err(crate::error::parse_invalid_ident(format!(
-                                        "cannot cast string to integer, encountered - {:?}",
-                                        k
-                                    )));
-                                }
+                            Pattern::FGreaterThanOrEqual(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::GreaterThanOrEqual,
+                                    Box::new(Expression::Float(i)),
+                                ))
                             }
-                            Pattern::Equal(_)
-                            | Pattern::GreaterThan(_)
-                            | Pattern::GreaterThanOrEqual(_)
-                            | Pattern::LessThan(_)
-                            | Pattern::LessThanOrEqual(_)
-                            | Pattern::FEqual(_)
-                            | Pattern::FGreaterThan(_)
-                            | Pattern::FGreaterThanOrEqual(_)
-                            | Pattern::FLessThan(_)
-                            | Pattern::FLessThanOrEqual(_) => {
-                                if let ModSym::Str = m {
-                                    return Err(crate::error::parse_invalid_ident(format!(
-                                        "cannot cast integer to string, encountered - {:?}",
-                                        k
-                                    )));
-                                }
+                            Pattern::FLessThan(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::LessThan,
+                                    Box::new(Expression::Float(i)),
+                                ))
+                            }
+                            Pattern::FLessThanOrEqual(i) => {
+                                number = true;
+                                rest.push(Expression::BooleanExpression(
+                                    Box::new(e.clone()),
+                                    BoolSym::LessThanOrEqual,
+                                    Box::new(Expression::Float(i)),
+                                ))
                             }
-                        }
-                    }
-                    match identifier.pattern {
-                        Pattern::Exact(_) => {
-                            string = true;
-                            exact.push(identifier)
-                        }
-                        Pattern::StartsWith(_) => {
-                            string = true;
-                            starts_with.push(identifier)
-                        }
-                        Pattern::EndsWith(_) => {
-                            string = true;
-                            ends_with.push(identifier)
-                        }
-                        Pattern::Contains(_) => {
-                            string = true;
-                            contains.push(identifier)
-                        }
-                        Pattern::Regex(_) => {
-                            string = true;
-                            regex.push(identifier)
-                        }
-                        Pattern::Any => {
-                            string = true;
-                            rest.push(Expression::Search(Search::Any, f.to_owned(), cast))
-                        }
-                        Pattern::Equal(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::Equal,
-                                Box::new(Expression::Integer(i)),
-                            ))
-                        }
-                        Pattern::GreaterThan(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::GreaterThan,
-                                Box::new(Expression::Integer(i)),
-                            ))
-                        }
-                        Pattern::GreaterThanOrEqual(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::GreaterThanOrEqual,
-                                Box::new(Expression::Integer(i)),
-                            ))
-                        }
-                        Pattern::LessThan(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::LessThan,
-                                Box::new(Expression::Integer(i)),
-                            ))
-                        }
-                        Pattern::LessThanOrEqual(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::LessThanOrEqual,
-                                Box::new(Expression::Integer(i)),
-                            ))
-                        }
-                        Pattern::FEqual(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::Equal,
-                                Box::new(Expression::Float(i)),
-                            ))
-                        }
-                        Pattern::FGreaterThan(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::GreaterThan,
-                                Box::new(Expression::Float(i)),
-                            ))
-                        }
-                        Pattern::FGreaterThanOrEqual(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::GreaterThanOrEqual,
-                                Box::new(Expression::Float(i)),
-                            ))
-                        }
-                        Pattern::FLessThan(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::LessThan,
-                                Box::new(Expression::Float(i)),
-                            ))
-                        }
-                        Pattern::FLessThanOrEqual(i) => {
-                            number = true;
-                            rest.push(Expression::BooleanExpression(
-                                Box::new(e.clone()),
-                                BoolSym::LessThanOrEqual,
-                                Box::new(Expression::Float(i)),
-                            ))
                         }
                     }
                 }
@@ -1295,14 +2217,12 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 let mut context: Vec<MatchType> = vec![];
                 let mut needles: Vec<String> = vec![];
                 let mut icontext: Vec<MatchType> = vec![];
-                let mut ineedles: Vec<String> = vec![];
                 let mut regex_set: Vec<Regex> = vec![];
                 let mut iregex_set: Vec<Regex> = vec![];
                 for i in starts_with.into_iter() {
                     if let Pattern::StartsWith(s) = i.pattern {
                         if i.ignore_case {
-                            icontext.push(MatchType::StartsWith(s.clone()));
-                            ineedles.push(s);
+                            icontext.push(MatchType::StartsWith(s));
                         } else {
                             context.push(MatchType::StartsWith(s.clone()));
                             needles.push(s);
@@ -1312,8 +2232,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 for i in contains.into_iter() {
                     if let Pattern::Contains(s) = i.pattern {
                         if i.ignore_case {
-                            icontext.push(MatchType::Contains(s.clone()));
-                            ineedles.push(s);
+                            icontext.push(MatchType::Contains(s));
                         } else {
                             context.push(MatchType::Contains(s.clone()));
                             needles.push(s);
@@ -1323,8 +2242,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 for i in ends_with.into_iter() {
                     if let Pattern::EndsWith(s) = i.pattern {
                         if i.ignore_case {
-                            icontext.push(MatchType::EndsWith(s.clone()));
-                            ineedles.push(s);
+                            icontext.push(MatchType::EndsWith(s));
                         } else {
                             context.push(MatchType::EndsWith(s.clone()));
                             needles.push(s);
@@ -1338,8 +2256,7 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         if s.is_empty() {
                             group.push(Expression::Search(Search::Exact(s), f.to_owned(), cast));
                         } else if i.ignore_case {
-                            icontext.push(MatchType::Exact(s.clone()));
-                            ineedles.push(s);
+                            icontext.push(MatchType::Exact(s));
                         } else {
                             context.push(MatchType::Exact(s.clone()));
                             needles.push(s);
@@ -1356,19 +2273,24 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     }
                 }
                 if !needles.is_empty() {
-                    if needles.len() == 1 {
-                        let s = match context.into_iter().next().expect("failed to get context") {
-                            MatchType::Contains(c) => Search::Contains(c),
-                            MatchType::EndsWith(c) => Search::EndsWith(c),
-                            MatchType::Exact(c) => Search::Exact(c),
-                            MatchType::StartsWith(c) => Search::StartsWith(c),
-                        };
-                        group.push(Expression::Search(s, f.to_owned(), cast));
+                    if needles.len() == 1 || !options.aho_corasick {
+                        for c in context {
+                            let s = match c {
+                                MatchType::Contains(c) => Search::Contains(c),
+                                MatchType::EndsWith(c) => Search::EndsWith(c),
+                                MatchType::Exact(c) => Search::Exact(c),
+                                MatchType::StartsWith(c) => Search::StartsWith(c),
+                            };
+                            group.push(Expression::Search(s, f.to_owned(), cast));
+                        }
                     } else {
                         multiple = true;
+                        options
+                            .complexity
+                            .needles(needles.iter().map(String::len).sum())?;
                         group.push(Expression::Search(
                             Search::AhoCorasick(
-                                Box::new(AhoCorasickBuilder::new().dfa(true).build(needles)),
+                                Box::new(options.automaton(needles, false)),
                                 context,
                                 false,
                             ),
@@ -1377,45 +2299,46 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         ));
                     }
                 }
-                if !ineedles.is_empty() {
+                if !icontext.is_empty() {
                     multiple = true;
-                    group.push(Expression::Search(
-                        Search::AhoCorasick(
-                            Box::new(
-                                AhoCorasickBuilder::new()
-                                    .ascii_case_insensitive(true)
-                                    .dfa(true)
-                                    .build(ineedles),
-                            ),
-                            icontext,
-                            true,
-                        ),
-                        f.to_owned(),
-                        cast,
-                    ));
-                }
-                if !regex_set.is_empty() {
-                    if regex_set.len() == 1 {
+                    options
+                        .complexity
+                        .needles(icontext.iter().map(|c| c.value().len()).sum())?;
+                    if options.aho_corasick {
                         group.push(Expression::Search(
-                            Search::Regex(
-                                regex_set.into_iter().next().expect("failed to get regex"),
-                                false,
-                            ),
+                            insensitive(icontext, options),
                             f.to_owned(),
                             cast,
                         ));
+                    } else {
+                        for c in icontext {
+                            group.push(Expression::Search(
+                                insensitive(vec![c], options),
+                                f.to_owned(),
+                                cast,
+                            ));
+                        }
+                    }
+                }
+                if !regex_set.is_empty() {
+                    if regex_set.len() == 1 || !options.regex_set {
+                        for r in regex_set {
+                            group.push(Expression::Search(
+                                Search::Regex(r, false),
+                                f.to_owned(),
+                                cast,
+                            ));
+                        }
                     } else {
                         multiple = true;
                         group.push(Expression::Search(
                             Search::RegexSet(
-                                RegexSetBuilder::new(
-                                    regex_set
-                                        .into_iter()
-                                        .map(|r| r.as_str().to_string())
-                                        .collect::<Vec<_>>(),
-                                )
-                                .build()
-                                .expect("could not build regex set"),
+                                options
+                                    .regex_set(
+                                        regex_set.into_iter().map(|r| r.as_str().to_string()),
+                                        false,
+                                    )
+                                    .map_err(crate::error::parse_invalid_ident)?,
                                 false,
                             ),
                             f.to_owned(),
@@ -1424,28 +2347,24 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                     }
                 }
                 if !iregex_set.is_empty() {
-                    if iregex_set.len() == 1 {
-                        group.push(Expression::Search(
-                            Search::Regex(
-                                iregex_set.into_iter().next().expect("failed to get regex"),
-                                true,
-                            ),
-                            f.to_owned(),
-                            cast,
-                        ));
+                    if iregex_set.len() == 1 || !options.regex_set {
+                        for r in iregex_set {
+                            group.push(Expression::Search(
+                                Search::Regex(r, true),
+                                f.to_owned(),
+                                cast,
+                            ));
+                        }
                     } else {
                         multiple = true;
                         group.push(Expression::Search(
                             Search::RegexSet(
-                                RegexSetBuilder::new(
-                                    iregex_set
-                                        .into_iter()
-                                        .map(|r| r.as_str().to_string())
-                                        .collect::<Vec<_>>(),
-                                )
-                                .case_insensitive(true)
-                                .build()
-                                .expect("could not build regex set"),
+                                options
+                                    .regex_set(
+                                        iregex_set.into_iter().map(|r| r.as_str().to_string()),
+                                        true,
+                                    )
+                                    .map_err(crate::error::parse_invalid_ident)?,
                                 true,
                             ),
                             f.to_owned(),
@@ -1453,32 +2372,50 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                         ));
                     }
                 }
+                // NOTE: Integers are checked with a single membership test, apart from within a
+                // match where each needs to be counted separately.
+                if integers.len() > 1 && !matches!(e, Expression::Match(_, _)) {
+                    integers.sort_unstable();
+                    integers.dedup();
+                    group.push(Expression::In(Box::new(e.clone()), integers));
+                } else {
+                    for i in integers {
+                        group.push(Expression::BooleanExpression(
+                            Box::new(e.clone()),
+                            BoolSym::Equal,
+                            Box::new(Expression::Integer(i)),
+                        ));
+                    }
+                }
                 group.extend(rest);
                 if let Expression::Match(Match::All, _) | Expression::Match(Match::Of(_), _) = &e {
                     if boolean as i32 + mapping as i32 + number as i32 + string as i32 > 1 {
-                        return Err(crate::error::parse_invalid_ident(
-                            "when using sequence modifiers the all expressions must be of the same type",
-                        ));
+                        return Err(crate::error::parse_invalid_ident(Diagnostic::new(
+                            "identifier.mixed_match_types",
+                        )));
                     }
                 }
                 if let Some(misc) = &misc {
-                    if let ModSym::Int = misc {
+                    if let ModSym::Int | ModSym::Len = misc {
                         if boolean || mapping || string {
                             return Err(crate::error::parse_invalid_ident(
-                                "when casting to int all expressions must be of type int",
+                                Diagnostic::new("identifier.cast_requires_integers")
+                                    .with("cast", misc),
                             ));
                         }
                     }
                     if let ModSym::Str = &misc {
                         if boolean || mapping || number {
-                            return Err(crate::error::parse_invalid_ident(
-                                "when casting to str all expressions must be of type str",
-                            ));
+                            return Err(crate::error::parse_invalid_ident(Diagnostic::new(
+                                "identifier.cast_requires_strings",
+                            )));
                         }
                     }
                 }
                 if group.is_empty() {
-                    return Err(crate::error::parse_invalid_ident("failed to parse mapping"));
+                    return Err(crate::error::parse_invalid_ident(Diagnostic::new(
+                        "identifier.empty_mapping",
+                    )));
                 } else if !multiple && group.len() == 1 {
                     group.into_iter().next().expect("could not get expression")
                 } else if let Expression::Match(m, _) = e {
@@ -1488,21 +2425,305 @@ fn parse_mapping(mapping: &Mapping) -> crate::Result<Expression> {
                 }
             }
         };
+        let expression = match every {
+            Some(_) if elementwise(&expression) => {
+                Expression::Match(Match::Every, Box::new(expression))
+            }
+            Some(_) => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.every_not_numeric").with("key", format!("{:?}", k)),
+                ))
+            }
+            None => expression,
+        };
         if let Some(ModSym::Not) = misc {
             expressions.push(Expression::Negate(Box::new(expression)));
+        } else if let Some(ModSym::Skeleton) = misc {
+            expressions.push(Skeleton.fold_expression(expression));
         } else {
             expressions.push(expression);
         }
     }
     if expressions.is_empty() {
-        return Err(crate::error::parse_invalid_ident("failed to parse mapping"));
+        return Err(crate::error::parse_invalid_ident(Diagnostic::new(
+            "identifier.empty_mapping",
+        )));
     } else if expressions.len() == 1 {
         return Ok(expressions.into_iter().next().expect("missing expression"));
     }
     Ok(Expression::BooleanGroup(BoolSym::And, expressions))
 }
 
-#[cfg(test)]
+// Returns true if the expression is a numeric comparison that can be evaluated for every element
+// of an array, see `Match::Every`.
+#[cfg(feature = "std")]
+fn elementwise(expression: &Expression) -> bool {
+    let operand = |e: &Expression| {
+        matches!(
+            e,
+            Expression::Cast(_, ModSym::Int)
+                | Expression::Field(_)
+                | Expression::Float(_)
+                | Expression::Integer(_)
+        )
+    };
+    match expression {
+        Expression::BooleanExpression(left, symbol, right) => {
+            matches!(
+                symbol,
+                BoolSym::Equal
+                    | BoolSym::GreaterThan
+                    | BoolSym::GreaterThanOrEqual
+                    | BoolSym::LessThan
+                    | BoolSym::LessThanOrEqual
+            ) && operand(left)
+                && operand(right)
+        }
+        Expression::In(e, _) | Expression::Range(e, _, _) => operand(e),
+        _ => false,
+    }
+}
+
+// Wraps the searches of a field with the skeleton modifier, see `Search::Skeleton`.
+#[cfg(feature = "std")]
+struct Skeleton;
+
+#[cfg(feature = "std")]
+impl Folder for Skeleton {
+    fn fold_search(&mut self, search: Search) -> Search {
+        Search::Skeleton(Box::new(search))
+    }
+}
+
+// Builds the searches for a list of keywords, which are not tied to a field and match if any string
+// in the document contains one of them. The needles are merged into a single automaton, so that
+// each string is only scanned once, apart from when case sensitive and insensitive needles are
+// mixed.
+#[cfg(feature = "std")]
+fn parse_keywords(sequence: &[Yaml], options: &ParserOptions) -> crate::Result<Expression> {
+    let mut context = vec![];
+    let mut icontext = vec![];
+    for value in sequence {
+        let keyword = match value {
+            Yaml::String(s) => s.clone(),
+            _ => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.expected_sequence_of_strings")
+                        .with("value", format!("{:?}", sequence)),
+                ))
+            }
+        };
+        let identifier = keyword.clone().into_identifier_with(options)?;
+        let needle = match identifier.pattern {
+            Pattern::Contains(s) | Pattern::Exact(s) if !s.is_empty() => s,
+            _ => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.invalid_keyword").with("keyword", keyword),
+                ))
+            }
+        };
+        if identifier.ignore_case {
+            icontext.push(MatchType::Contains(needle));
+        } else {
+            context.push(MatchType::Contains(needle));
+        }
+    }
+    options.complexity.needles(
+        context
+            .iter()
+            .chain(&icontext)
+            .map(|c| c.value().len())
+            .sum(),
+    )?;
+    let mut group = vec![];
+    if !context.is_empty() {
+        let needles: Vec<String> = context.iter().map(|c| c.value().clone()).collect();
+        group.push(Expression::Keywords(Search::AhoCorasick(
+            Box::new(options.automaton(needles, false)),
+            context,
+            false,
+        )));
+    }
+    if !icontext.is_empty() {
+        group.push(Expression::Keywords(insensitive(icontext, options)));
+    }
+    if group.len() == 1 {
+        return Ok(group.remove(0));
+    }
+    Ok(Expression::BooleanGroup(BoolSym::Or, group))
+}
+
+// Builds a case insensitive literal search. As the automaton only folds ASCII, needles containing
+// anything else are instead lowercased and searched for in the lowercase shadow of the field, so
+// that they fold the same way as case insensitive regexes.
+#[cfg(feature = "std")]
+fn insensitive(context: Vec<MatchType>, options: &ParserOptions) -> Search {
+    let needles: Vec<String> = context.iter().map(|c| c.value().clone()).collect();
+    let ascii = needles.iter().all(|n| n.is_ascii());
+    let search = Search::AhoCorasick(Box::new(options.automaton(needles, true)), context, true);
+    if ascii {
+        search
+    } else {
+        optimiser::shadow_search(search, options)
+    }
+}
+
+// Builds the searches for a field with the wide modifier, where each needle must be a literal.
+// Case sensitive and insensitive needles are kept apart, as the automaton folds all or nothing.
+#[cfg(feature = "std")]
+fn wide(field: &str, needles: Vec<String>, options: &ParserOptions) -> crate::Result<Expression> {
+    let mut context = vec![];
+    let mut icontext = vec![];
+    for needle in needles {
+        let identifier = needle.into_identifier_with(options)?;
+        let m = match identifier.pattern {
+            Pattern::Contains(s) => MatchType::Contains(s),
+            Pattern::EndsWith(s) => MatchType::EndsWith(s),
+            Pattern::Exact(s) => MatchType::Exact(s),
+            Pattern::StartsWith(s) => MatchType::StartsWith(s),
+            pattern => {
+                return Err(crate::error::parse_invalid_ident(
+                    Diagnostic::new("identifier.wide_not_literal")
+                        .with("pattern", format!("{:?}", pattern)),
+                ))
+            }
+        };
+        // NOTE: The automaton folds bytes, which is only sound when every folded byte is a whole
+        // code unit, i.e. when the needle is ASCII.
+        if identifier.ignore_case && !m.value().is_ascii() {
+            return Err(crate::error::parse_invalid_ident(
+                Diagnostic::new("identifier.wide_insensitive_unicode")
+                    .with("pattern", format!("{:?}", m)),
+            ));
+        } else if identifier.ignore_case {
+            icontext.push(m);
+        } else {
+            context.push(m);
+        }
+    }
+    // NOTE: The needles are encoded as UTF-16LE, doubling their length.
+    options.complexity.needles(
+        context
+            .iter()
+            .chain(&icontext)
+            .map(|c| c.value().len() * 2)
+            .sum(),
+    )?;
+    let mut group: Vec<Expression> = vec![(context, false), (icontext, true)]
+        .into_iter()
+        .filter(|(context, _)| !context.is_empty())
+        .map(|(context, insensitive)| {
+            let needles: Vec<Vec<u8>> = context.iter().map(|c| utf16le(c.value())).collect();
+            Expression::Search(
+                Search::Wide(
+                    Box::new(options.automaton(needles, insensitive)),
+                    context,
+                    insensitive,
+                ),
+                field.to_owned(),
+                false,
+            )
+        })
+        .collect();
+    if group.is_empty() {
+        Err(crate::error::parse_invalid_ident(Diagnostic::new(
+            "identifier.empty_mapping",
+        )))
+    } else if group.len() == 1 {
+        Ok(group.remove(0))
+    } else {
+        Ok(Expression::BooleanGroup(BoolSym::Or, group))
+    }
+}
+
+// Counts the values that will be built as regexes, so that they can be checked against the
+// complexity limits before any are built.
+#[cfg(feature = "std")]
+fn regexes(values: &[Yaml]) -> usize {
+    values
+        .iter()
+        .filter_map(|v| v.as_str())
+        .filter(|s| {
+            if cfg!(feature = "ignore_case") {
+                s.starts_with('?')
+            } else {
+                s.strip_prefix('i').unwrap_or(s).starts_with('?')
+            }
+        })
+        .count()
+}
+
+// Encodes a string as UTF-16LE bytes, as used by Windows for wide strings.
+pub(crate) fn utf16le(value: &str) -> Vec<u8> {
+    value.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+}
+
+// Parses the value for a field cast to a timestamp, where values are of the form `ts`, `>ts`,
+// `>=ts`, `<ts`, `<=ts` or `ts..ts`, with the latter being an inclusive range.
+#[cfg(feature = "std")]
+fn parse_time(field: &Expression, value: &Yaml) -> crate::Result<Expression> {
+    let parse = |s: &str| {
+        timestamp::parse(s).ok_or_else(|| {
+            crate::error::parse_invalid_ident(
+                Diagnostic::new("identifier.invalid_timestamp").with("value", format!("{:?}", s)),
+            )
+        })
+    };
+    let compare = |symbol: BoolSym, time: SystemTime| {
+        Expression::BooleanExpression(
+            Box::new(field.clone()),
+            symbol,
+            Box::new(Expression::Timestamp(time)),
+        )
+    };
+    match value {
+        Yaml::String(s) => {
+            if let Some((from, to)) = s.split_once("..") {
+                return Ok(Expression::BooleanGroup(
+                    BoolSym::And,
+                    vec![
+                        compare(BoolSym::GreaterThanOrEqual, parse(from)?),
+                        compare(BoolSym::LessThanOrEqual, parse(to)?),
+                    ],
+                ));
+            }
+            let (symbol, s) = if let Some(s) = s.strip_prefix(">=") {
+                (BoolSym::GreaterThanOrEqual, s)
+            } else if let Some(s) = s.strip_prefix('>') {
+                (BoolSym::GreaterThan, s)
+            } else if let Some(s) = s.strip_prefix("<=") {
+                (BoolSym::LessThanOrEqual, s)
+            } else if let Some(s) = s.strip_prefix('<') {
+                (BoolSym::LessThan, s)
+            } else if let Some(s) = s.strip_prefix('=') {
+                (BoolSym::Equal, s)
+            } else {
+                (BoolSym::Equal, &s[..])
+            };
+            Ok(compare(symbol, parse(s)?))
+        }
+        Yaml::Sequence(sequence) => {
+            let mut group = sequence
+                .iter()
+                .map(|v| parse_time(field, v))
+                .collect::<crate::Result<Vec<_>>>()?;
+            if group.is_empty() {
+                Err(crate::error::parse_invalid_ident(Diagnostic::new(
+                    "identifier.empty_mapping",
+                )))
+            } else if group.len() == 1 {
+                Ok(group.remove(0))
+            } else {
+                Ok(Expression::BooleanGroup(BoolSym::Or, group))
+            }
+        }
+        _ => Err(crate::error::parse_invalid_ident(
+            Diagnostic::new("identifier.time_not_string").with("value", format!("{:?}", value)),
+        )),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -1545,7 +2766,7 @@ mod tests {
 
     #[test]
     fn parse_bool_expr() {
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Identifier("foo".to_string()),
             Token::Operator(BoolSym::And),
             Token::Identifier("bar".to_string()),
@@ -1563,7 +2784,7 @@ mod tests {
 
     #[test]
     fn parse_cast() {
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Modifier(ModSym::Int),
             Token::Delimiter(DelSym::LeftParenthesis),
             Token::Identifier("identifier".to_owned()),
@@ -1572,7 +2793,7 @@ mod tests {
         .unwrap();
         assert_eq!(Expression::Cast("identifier".to_string(), ModSym::Int), e);
 
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Modifier(ModSym::Not),
             Token::Delimiter(DelSym::LeftParenthesis),
             Token::Identifier("identifier".to_owned()),
@@ -1581,7 +2802,7 @@ mod tests {
         .unwrap();
         assert_eq!(Expression::Cast("identifier".to_string(), ModSym::Not), e);
 
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Modifier(ModSym::Str),
             Token::Delimiter(DelSym::LeftParenthesis),
             Token::Identifier("identifier".to_owned()),
@@ -1593,19 +2814,55 @@ mod tests {
 
     #[test]
     fn parse_identifier() {
-        let e = parse(&vec![Token::Identifier("condition".to_string())]).unwrap();
+        let e = parse(&[Token::Identifier("condition".to_string())]).unwrap();
         assert_eq!(Expression::Identifier("condition".to_string()), e);
     }
 
+    #[test]
+    fn parse_glob() {
+        let glob = Glob::parse(r"C:\Windows\**\*.ex[!_]").unwrap();
+        assert_eq!(glob.to_string(), r"C:\Windows\**\*.ex[!_]");
+        assert!(glob.is_match(r"C:\Windows\cmd.exe"));
+        assert!(glob.is_match(r"C:\Windows\System32\drivers\foo.exe"));
+        assert!(!glob.is_match(r"C:\Windows\cmd.ex_"));
+        assert!(!glob.is_match(r"C:\Windows\cmd.exe.bak"));
+        assert!(!glob.is_match(r"D:\Windows\cmd.exe"));
+
+        let glob = Glob::parse("/home/*/.ssh/id_?sa").unwrap();
+        assert!(glob.is_match("/home/alice/.ssh/id_rsa"));
+        assert!(!glob.is_match("/home/alice/bob/.ssh/id_rsa"));
+        assert!(!glob.is_match("/home/alice/.ssh/id_/sa"));
+
+        let glob = Glob::parse("[]a-c*]?").unwrap();
+        assert!(glob.is_match("]x"));
+        assert!(glob.is_match("bx"));
+        assert!(glob.is_match("*x"));
+        assert!(!glob.is_match("dx"));
+
+        assert!(Glob::parse("foo[ab").is_err());
+    }
+
+    #[test]
+    fn parse_hex() {
+        let hex = Hex::parse("{4d 5A ?? ?0}").unwrap();
+        assert_eq!(hex.to_string(), "{ 4D 5A ?? ?0 }");
+        assert!(hex.is_match(&[0x00, 0x4d, 0x5a, 0x90, 0x00, 0xff]));
+        assert!(!hex.is_match(&[0x4d, 0x5a, 0x90, 0x01]));
+        assert!(!hex.is_match(&[0x4d, 0x5a, 0x90]));
+        assert!(Hex::parse("{}").is_none());
+        assert!(Hex::parse("{6AF0698E-D558-4F6D-9B24-A2B2A7C0A7F3}").is_none());
+        assert!(Hex::parse("{ 4D 5G }").is_none());
+    }
+
     #[test]
     fn parse_integer() {
-        let e = parse(&vec![Token::Integer(1)]).unwrap();
+        let e = parse(&[Token::Integer(1)]).unwrap();
         assert_eq!(Expression::Integer(1), e);
     }
 
     #[test]
     fn parse_negate() {
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Miscellaneous(MiscSym::Not),
             Token::Delimiter(DelSym::LeftParenthesis),
             Token::Identifier("foo".to_string()),
@@ -1642,9 +2899,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_integers() {
+        let identifier = r"{port: 1024..49151, status: [403, 400, 401, 400]}";
+        let yaml: Yaml = serde_yaml::from_str(identifier).unwrap();
+        let options = ParserOptions {
+            range: true,
+            ..Default::default()
+        };
+        let e = super::parse_identifier_with(&yaml, &options).unwrap();
+        assert_eq!(
+            Expression::BooleanGroup(
+                BoolSym::And,
+                vec![
+                    Expression::Range(Box::new(Expression::Field("port".to_owned())), 1024, 49151),
+                    Expression::In(
+                        Box::new(Expression::Field("status".to_owned())),
+                        vec![400, 401, 403]
+                    ),
+                ]
+            ),
+            e
+        );
+    }
+
     #[test]
     fn parse_expression_0() {
-        let t = parse(&vec![
+        let t = parse(&[
             Token::Delimiter(DelSym::LeftParenthesis),
             Token::Identifier("foo".to_string()),
             Token::Operator(BoolSym::And),
@@ -1668,9 +2949,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn pretty_expression() {
+        let e = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Identifier("A".to_string()),
+                Expression::Negate(Box::new(Expression::Match(
+                    Match::Of(2),
+                    Box::new(Expression::Identifier("B".to_string())),
+                ))),
+                Expression::BooleanExpression(
+                    Box::new(Expression::Cast("size".to_string(), ModSym::Int)),
+                    BoolSym::GreaterThan,
+                    Box::new(Expression::Integer(10)),
+                ),
+            ],
+        );
+        assert_eq!(
+            e.to_pretty_string(),
+            "&&\n  identifier: A\n  not\n    of: 2\n      identifier: B\n  >\n    cast: int(size)\n    int: 10\n"
+        );
+
+        let mut identifiers = HashMap::new();
+        identifiers.insert(
+            "A".to_string(),
+            Expression::Search(Search::Contains("foo".to_string()), "bar".to_string(), true),
+        );
+        assert_eq!(
+            e.to_pretty_string_with(&identifiers)
+                .lines()
+                .take(3)
+                .collect::<Vec<_>>(),
+            vec![
+                "&&",
+                "  identifier: A",
+                "    search: str(bar) contains(foo)"
+            ]
+        );
+    }
+
     #[test]
     fn parse_expression_1() {
-        let t = parse(&vec![
+        let t = parse(&[
             Token::Identifier("foo".to_string()),
             Token::Operator(BoolSym::And),
             Token::Delimiter(DelSym::LeftParenthesis),
@@ -1696,7 +3017,7 @@ mod tests {
 
     #[test]
     fn parse_expression_2() {
-        let t = parse(&vec![
+        let t = parse(&[
             Token::Identifier("foo".to_string()),
             Token::Operator(BoolSym::And),
             Token::Delimiter(DelSym::LeftParenthesis),
@@ -1729,7 +3050,7 @@ mod tests {
     #[test]
     fn parse_identifiers_0() {
         let identifier = "[foo: bar]";
-        let yaml: Yaml = serde_yaml::from_str(&identifier).unwrap();
+        let yaml: Yaml = serde_yaml::from_str(identifier).unwrap();
         let e = super::parse_identifier(&yaml).unwrap();
         assert_eq!(
             Expression::BooleanGroup(
@@ -1746,7 +3067,7 @@ mod tests {
 
     #[test]
     fn parse_invalid_0() {
-        let e = parse(&vec![
+        let e = parse(&[
             Token::Miscellaneous(MiscSym::Not),
             Token::Modifier(ModSym::Int),
             Token::Delimiter(DelSym::LeftParenthesis),
@@ -1755,4 +3076,73 @@ mod tests {
         ]);
         assert!(e.is_err());
     }
+
+    #[test]
+    fn fold_fields() {
+        struct Prefix;
+        impl Folder for Prefix {
+            fn fold_field(&mut self, field: String) -> String {
+                format!("event.{}", field)
+            }
+        }
+        let expression = Expression::BooleanGroup(
+            BoolSym::And,
+            vec![
+                Expression::Search(Search::Exact("a".to_owned()), "foo".to_owned(), false),
+                Expression::Negate(Box::new(Expression::BooleanExpression(
+                    Box::new(Expression::Field("bar".to_owned())),
+                    BoolSym::Equal,
+                    Box::new(Expression::Integer(1)),
+                ))),
+            ],
+        );
+        assert_eq!(
+            Prefix.fold_expression(expression),
+            Expression::BooleanGroup(
+                BoolSym::And,
+                vec![
+                    Expression::Search(
+                        Search::Exact("a".to_owned()),
+                        "event.foo".to_owned(),
+                        false
+                    ),
+                    Expression::Negate(Box::new(Expression::BooleanExpression(
+                        Box::new(Expression::Field("event.bar".to_owned())),
+                        BoolSym::Equal,
+                        Box::new(Expression::Integer(1)),
+                    ))),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn visit_searches() {
+        #[derive(Default)]
+        struct Count {
+            fields: Vec<String>,
+            searches: usize,
+        }
+        impl Visitor for Count {
+            fn visit_field(&mut self, field: &str) {
+                self.fields.push(field.to_owned());
+            }
+            fn visit_search(&mut self, _: &Search) {
+                self.searches += 1;
+            }
+        }
+        let yaml = serde_yaml::from_str(
+            r#"
+            foo: [a, b]
+            bar:
+              baz: c
+            "#,
+        )
+        .unwrap();
+        let expression = super::parse_identifier(&yaml).unwrap();
+        let mut count = Count::default();
+        count.visit_expression(&expression);
+        assert_eq!(count.fields, vec!["foo", "bar", "baz"]);
+        assert_eq!(count.searches, 2);
+    }
 }
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use serde_yaml::Value as Yaml;
+
+type Provider = Box<dyn Fn(&str) -> Option<Vec<String>> + Send + Sync>;
+
+/// The placeholders that can be substituted into a rule's identifiers when it is loaded.
+#[derive(Default)]
+pub(crate) struct Placeholders {
+    pub values: HashMap<String, Vec<String>>,
+    pub provider: Option<Provider>,
+}
+
+impl Placeholders {
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.provider.is_none()
+    }
+
+    /// Substitutes the placeholders into the rule's detection and filter, leaving the condition
+    /// untouched.
+    pub fn substitute(&self, value: Yaml) -> Yaml {
+        if self.is_empty() {
+            return value;
+        }
+        let mut rule = match value {
+            Yaml::Mapping(rule) => rule,
+            value => return value,
+        };
+        if let Some(Yaml::Mapping(detection)) = rule.get_mut(&Yaml::String("detection".to_owned()))
+        {
+            for (k, v) in detection.iter_mut() {
+                if k.as_str() != Some("condition") {
+                    *v = self.expand(std::mem::replace(v, Yaml::Null));
+                }
+            }
+        }
+        if let Some(filter) = rule.get_mut(&Yaml::String("filter".to_owned())) {
+            *filter = self.expand(std::mem::replace(filter, Yaml::Null));
+        }
+        Yaml::Mapping(rule)
+    }
+
+    fn expand(&self, value: Yaml) -> Yaml {
+        match value {
+            Yaml::Mapping(mapping) => Yaml::Mapping(
+                mapping
+                    .into_iter()
+                    .map(|(k, v)| (k, self.expand(v)))
+                    .collect(),
+            ),
+            Yaml::Sequence(sequence) => {
+                let mut expanded = Vec::with_capacity(sequence.len());
+                for value in sequence {
+                    match value {
+                        Yaml::String(s) => match self.expand_str(&s) {
+                            Some(values) => expanded.extend(values.into_iter().map(Yaml::String)),
+                            None => expanded.push(Yaml::String(s)),
+                        },
+                        value => expanded.push(self.expand(value)),
+                    }
+                }
+                Yaml::Sequence(expanded)
+            }
+            Yaml::String(s) => match self.expand_str(&s) {
+                Some(mut values) if values.len() == 1 => Yaml::String(values.remove(0)),
+                Some(values) => Yaml::Sequence(values.into_iter().map(Yaml::String).collect()),
+                None => Yaml::String(s),
+            },
+            value => value,
+        }
+    }
+
+    // Expands the placeholders in the string, returning `None` if it contains none that are known.
+    fn expand_str(&self, s: &str) -> Option<Vec<String>> {
+        // NOTE: A value that is only a placeholder expands into exact matches, so that the values
+        // can not be mistaken for patterns.
+        if let Some(name) = s.strip_prefix('%').and_then(|s| s.strip_suffix('%')) {
+            if !name.contains('%') {
+                if let Some(values) = self.get(name) {
+                    return Some(values.into_iter().map(|v| format!("'{}'", v)).collect());
+                }
+            }
+        }
+        let mut expanded = vec![String::new()];
+        let mut found = false;
+        let mut rest = s;
+        while let Some(start) = rest.find('%') {
+            let after = &rest[start + 1..];
+            let end = match after.find('%') {
+                Some(end) => end,
+                None => break,
+            };
+            match self.get(&after[..end]) {
+                Some(values) => {
+                    found = true;
+                    let prefix = &rest[..start];
+                    expanded = expanded
+                        .iter()
+                        .flat_map(|e| values.iter().map(move |v| format!("{}{}{}", e, prefix, v)))
+                        .collect();
+                    rest = &after[end + 1..];
+                }
+                None => {
+                    for e in &mut expanded {
+                        e.push_str(&rest[..=start]);
+                    }
+                    rest = after;
+                }
+            }
+        }
+        if !found {
+            return None;
+        }
+        for e in &mut expanded {
+            e.push_str(rest);
+        }
+        Some(expanded)
+    }
+
+    fn get(&self, name: &str) -> Option<Vec<String>> {
+        if name.is_empty() {
+            return None;
+        }
+        match self.values.get(name) {
+            Some(values) => Some(values.clone()),
+            None => self.provider.as_ref().and_then(|p| p(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let mut placeholders = Placeholders::default();
+        placeholders.values.insert(
+            "admins".to_owned(),
+            vec!["alice".to_owned(), "bob".to_owned()],
+        );
+        placeholders.provider = Some(Box::new(|name| match name {
+            "domain" => Some(vec!["corp".to_owned()]),
+            _ => None,
+        }));
+
+        assert_eq!(
+            placeholders.expand_str("%admins%"),
+            Some(vec!["'alice'".to_owned(), "'bob'".to_owned()])
+        );
+        assert_eq!(
+            placeholders.expand_str("%domain%\\%admins%*"),
+            Some(vec!["corp\\alice*".to_owned(), "corp\\bob*".to_owned()])
+        );
+        assert_eq!(
+            placeholders.expand_str("%SystemRoot%\\%domain%"),
+            Some(vec!["%SystemRoot%\\corp".to_owned()])
+        );
+        assert_eq!(placeholders.expand_str("%SystemRoot%\\cmd.exe"), None);
+        assert_eq!(placeholders.expand_str("100%"), None);
+
+        let rule: Yaml = serde_yaml::from_str(
+            r#"
+            detection:
+              A:
+                user:
+                - '%admins%'
+                - root
+                host: '%domain%'
+              condition: A and '%admins%'
+            "#,
+        )
+        .unwrap();
+        let expected: Yaml = serde_yaml::from_str(
+            r#"
+            detection:
+              A:
+                user:
+                - "'alice'"
+                - "'bob'"
+                - root
+                host: "'corp'"
+              condition: A and '%admins%'
+            "#,
+        )
+        .unwrap();
+        assert_eq!(placeholders.substitute(rule), expected);
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value as Yaml;
+
+use crate::document::Document;
+use crate::rule::Rule;
+use crate::ruleset::RuleSet;
+
+/// The action taken when a rule in a `Policy` matches.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Let the document through.
+    #[default]
+    Allow,
+    /// Let the document through, but flag it.
+    Alert,
+    /// Block the document.
+    Deny,
+}
+
+/// How a `Policy` picks the rule that decides its verdict when more than one matches.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Resolution {
+    /// The first rule to match, in the order that they were inserted, decides.
+    #[default]
+    FirstMatch,
+    /// The most severe rule to match decides, with rules without a severity being the least
+    /// severe, and ties going to the first that was inserted.
+    MostSevere,
+}
+
+/// The outcome of evaluating a document against a `Policy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Verdict<'a> {
+    /// The action to take.
+    pub action: Action,
+    /// The id of the rule that decided the action, or `None` when no rule matched and the
+    /// policy's fallback was used.
+    pub rule: Option<&'a str>,
+    /// The routing metadata of the rule that decided the action, if it has any.
+    pub routing: Option<&'a Yaml>,
+    /// The response actions of the rule that decided the action, if it has any.
+    pub actions: Option<&'a Yaml>,
+}
+
+/// A set of rules that carry actions, turning the engine into a decision point for inline
+/// enforcement.
+///
+/// Rules declare their action with the `action` key, one of `allow`, `alert` or `deny`, where
+/// rules without one are treated as `alert`. Evaluation stops as soon as the deciding rule is
+/// found, and when no rule matches the policy's fallback action is returned.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Action, Policy, Resolution, Rule};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let mut policy = Policy::new().resolution(Resolution::FirstMatch);
+/// policy.insert("block", Rule::from_str(r#"
+/// detection:
+///   A:
+///     user: root
+///   condition: A
+/// action: deny
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap());
+///
+/// let document: Yaml = serde_yaml::from_str("user: root").unwrap();
+/// let verdict = policy.evaluate(document.as_mapping().unwrap());
+/// assert_eq!(verdict.action, Action::Deny);
+/// assert_eq!(verdict.rule, Some("block"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    fallback: Action,
+    resolution: Resolution,
+    rules: RuleSet,
+}
+
+impl Policy {
+    /// Create an empty `Policy` that allows everything.
+    pub fn new() -> Self {
+        Policy::default()
+    }
+
+    /// Set the action to take when no rule matches, defaults to `Action::Allow`.
+    pub fn fallback(mut self, action: Action) -> Self {
+        self.fallback = action;
+        self
+    }
+
+    /// Set how the deciding rule is picked, defaults to `Resolution::FirstMatch`.
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Inserts a rule into the policy, if a rule already exists with the same id it is replaced
+    /// and returned.
+    pub fn insert<S: Into<String>>(&mut self, id: S, rule: Rule) -> Option<Rule> {
+        self.rules.insert(id, rule)
+    }
+
+    /// Returns the rules that make up the policy.
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    /// Evaluates the policy against the provided `Document`, returning its verdict.
+    pub fn evaluate(&self, document: &dyn Document) -> Verdict<'_> {
+        let decided = match self.resolution {
+            Resolution::FirstMatch => self.rules.matches_iter(document).next(),
+            Resolution::MostSevere => self.rules.first_match_by_severity(document),
+        };
+        match decided.and_then(|id| self.rules.get(id).map(|r| (id, r))) {
+            Some((id, rule)) => Verdict {
+                action: rule.action.unwrap_or(Action::Alert),
+                rule: Some(id),
+                routing: rule.routing.as_ref(),
+                actions: rule.actions.as_ref(),
+            },
+            None => Verdict {
+                action: self.fallback,
+                rule: None,
+                routing: None,
+                actions: None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::{Mapping, Value as Yaml};
+
+    use crate::rule::Severity;
+
+    fn rule(value: &str, action: Option<Action>, severity: Option<Severity>) -> Rule {
+        let mut rule = Rule::from_str(&format!(
+            r#"
+            detection:
+              A:
+                foo: {}
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+            value
+        ))
+        .unwrap();
+        rule.action = action;
+        rule.severity = severity;
+        rule
+    }
+
+    fn document(value: &str) -> Mapping {
+        serde_yaml::from_str::<Yaml>(&format!("foo: {}", value))
+            .unwrap()
+            .as_mapping()
+            .unwrap()
+            .clone()
+    }
+
+    fn policy(resolution: Resolution) -> Policy {
+        let mut policy = Policy::new().fallback(Action::Deny).resolution(resolution);
+        policy.insert("allow", rule("'foo*'", Some(Action::Allow), None));
+        let mut deny = rule("'*bar'", Some(Action::Deny), Some(Severity::High));
+        deny.routing = Some(Yaml::from("soc"));
+        deny.actions = Some(Yaml::from("contain"));
+        policy.insert("deny", deny);
+        policy.insert("alert", rule("'*baz'", None, Some(Severity::Low)));
+        policy
+    }
+
+    #[test]
+    fn first_match() {
+        let policy = policy(Resolution::FirstMatch);
+        assert_eq!(
+            policy.evaluate(&document("foobar")),
+            Verdict {
+                action: Action::Allow,
+                rule: Some("allow"),
+                routing: None,
+                actions: None,
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&document("baz")),
+            Verdict {
+                action: Action::Alert,
+                rule: Some("alert"),
+                routing: None,
+                actions: None,
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&document("qux")),
+            Verdict {
+                action: Action::Deny,
+                rule: None,
+                routing: None,
+                actions: None,
+            }
+        );
+    }
+
+    #[test]
+    fn most_severe() {
+        let policy = policy(Resolution::MostSevere);
+        let routing = Yaml::from("soc");
+        let actions = Yaml::from("contain");
+        assert_eq!(
+            policy.evaluate(&document("foobar")),
+            Verdict {
+                action: Action::Deny,
+                rule: Some("deny"),
+                routing: Some(&routing),
+                actions: Some(&actions),
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&document("foobaz")),
+            Verdict {
+                action: Action::Alert,
+                rule: Some("alert"),
+                routing: None,
+                actions: None,
+            }
+        );
+        assert_eq!(
+            policy.evaluate(&document("fooqux")),
+            Verdict {
+                action: Action::Allow,
+                rule: Some("allow"),
+                routing: None,
+                actions: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use aho_corasick::{AhoCorasick, AhoCorasickKind};
+
+use crate::document::Document;
+use crate::parser::{Expression, Match, Search};
+use crate::rule::Detection;
+use crate::tokeniser::BoolSym;
+use crate::value::Value;
+
+// A field and a literal that can be found within its value.
+type Literal = (String, String);
+
+// An index over the literals that rules search for, used to find the rules that could match a
+// document without evaluating them.
+//
+// A rule is only indexed when it can't be true unless at least one of its literals is found in the
+// field that it is searched for in, every other rule is always a candidate. As a literal that is
+// found only makes its rules candidates, each field is searched ASCII case insensitively, which
+// covers both the case sensitive and insensitive literals at the cost of some false candidates.
+//
+// The index can be updated a rule at a time, in which case only the automatons of the fields whose
+// literals changed are rebuilt.
+#[derive(Clone, Debug)]
+pub(crate) struct Prefilter {
+    // The rules that could match without any literal being found, in order.
+    always: Vec<usize>,
+    // The fields with literals, along with an automaton over them.
+    fields: BTreeMap<String, Field>,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Field {
+    automaton: AhoCorasick,
+    // The literals in the order of the automaton's patterns, along with the rules for each.
+    literals: Vec<(String, Vec<usize>)>,
+}
+
+impl Field {
+    fn build(literals: Vec<(String, Vec<usize>)>) -> Self {
+        let automaton = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .kind(Some(AhoCorasickKind::DFA))
+            .build(literals.iter().map(|(l, _)| l))
+            .expect("could not build aho-corasick automaton");
+        Self {
+            automaton,
+            literals,
+        }
+    }
+}
+
+impl Prefilter {
+    pub(crate) fn new<'a, I>(detections: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Detection>,
+    {
+        let mut always = vec![];
+        let mut fields: BTreeMap<String, BTreeMap<String, Vec<usize>>> = BTreeMap::new();
+        let mut len = 0;
+        for (i, detection) in detections.into_iter().enumerate() {
+            len += 1;
+            match literals(&detection.expression, &detection.identifiers) {
+                Some(literals) => {
+                    for (field, literal) in literals {
+                        let rules = fields.entry(field).or_default().entry(literal).or_default();
+                        if rules.last() != Some(&i) {
+                            rules.push(i);
+                        }
+                    }
+                }
+                None => always.push(i),
+            }
+        }
+        let fields = fields
+            .into_iter()
+            .map(|(field, literals)| (field, Field::build(literals.into_iter().collect())))
+            .collect();
+        Self {
+            always,
+            fields,
+            len,
+        }
+    }
+
+    // Indexes the detection as the rule at the index, which is either an existing rule that is
+    // being replaced or a new rule at the end of the set.
+    pub(crate) fn insert(&mut self, i: usize, detection: &Detection) {
+        if i < self.len {
+            self.unindex(i, false);
+        } else {
+            self.len += 1;
+        }
+        let literals = match literals(&detection.expression, &detection.identifiers) {
+            Some(literals) => literals,
+            None => {
+                if let Err(p) = self.always.binary_search(&i) {
+                    self.always.insert(p, i);
+                }
+                return;
+            }
+        };
+        let mut changed: BTreeMap<String, Vec<(String, Vec<usize>)>> = BTreeMap::new();
+        for (field, literal) in literals {
+            let current = match changed.get_mut(&field) {
+                Some(current) => current,
+                None => {
+                    let current = match self.fields.get(&field) {
+                        Some(f) => f.literals.clone(),
+                        None => vec![],
+                    };
+                    changed.entry(field).or_insert(current)
+                }
+            };
+            match current.binary_search_by(|(l, _)| l.as_str().cmp(&literal)) {
+                Ok(p) => {
+                    if let Err(q) = current[p].1.binary_search(&i) {
+                        current[p].1.insert(q, i);
+                    }
+                }
+                Err(p) => current.insert(p, (literal, vec![i])),
+            }
+        }
+        for (field, literals) in changed {
+            // NOTE: Only rebuild the automaton when a literal was added, not just a rule.
+            match self.fields.get_mut(&field) {
+                Some(f) if f.literals.len() == literals.len() => f.literals = literals,
+                _ => {
+                    self.fields.insert(field, Field::build(literals));
+                }
+            }
+        }
+    }
+
+    // Removes the rule at the index, shifting the rules after it down by one.
+    pub(crate) fn remove(&mut self, i: usize) {
+        self.unindex(i, true);
+        self.len -= 1;
+    }
+
+    // Removes the rule at the index from the index, optionally shifting the rules after it down by
+    // one, and rebuilds the automatons of any fields that lost a literal.
+    fn unindex(&mut self, i: usize, shift: bool) {
+        let update = |rules: &mut Vec<usize>| {
+            rules.retain(|r| *r != i);
+            if shift {
+                for r in rules.iter_mut().filter(|r| **r > i) {
+                    *r -= 1;
+                }
+            }
+        };
+        update(&mut self.always);
+        let mut changed = vec![];
+        for (field, f) in self.fields.iter_mut() {
+            let len = f.literals.len();
+            for (_, rules) in f.literals.iter_mut() {
+                update(rules);
+            }
+            f.literals.retain(|(_, rules)| !rules.is_empty());
+            if f.literals.len() != len {
+                changed.push(field.clone());
+            }
+        }
+        for field in changed {
+            let literals = match self.fields.remove(&field) {
+                Some(f) => f.literals,
+                None => continue,
+            };
+            if !literals.is_empty() {
+                self.fields.insert(field, Field::build(literals));
+            }
+        }
+    }
+
+    // Returns whether each rule could match the document, a rule that can't does not need to be
+    // evaluated.
+    pub(crate) fn candidates(&self, document: &dyn Document) -> Vec<bool> {
+        let mut candidates = vec![false; self.len];
+        for i in &self.always {
+            candidates[*i] = true;
+        }
+        for (field, f) in &self.fields {
+            let mut scan = |value: &Value| {
+                if let Some(text) = text(value) {
+                    for m in f.automaton.find_overlapping_iter(text.as_ref()) {
+                        for i in &f.literals[m.pattern().as_usize()].1 {
+                            candidates[*i] = true;
+                        }
+                    }
+                }
+            };
+            match document.find(field) {
+                Some(Value::Array(a)) => {
+                    for value in a.iter() {
+                        scan(&value);
+                    }
+                }
+                Some(value) => scan(&value),
+                None => {}
+            }
+        }
+        candidates
+    }
+}
+
+// Returns the literals of which at least one must be found for the expression to be true, or
+// `None` if it could be true without any of them.
+fn literals(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+) -> Option<Vec<Literal>> {
+    match expression {
+        Expression::BooleanExpression(left, BoolSym::And, right) => {
+            every([left.as_ref(), right.as_ref()], identifiers)
+        }
+        Expression::BooleanExpression(left, BoolSym::Or, right) => {
+            any([left.as_ref(), right.as_ref()], identifiers)
+        }
+        Expression::BooleanGroup(BoolSym::And, group) => every(group, identifiers),
+        Expression::BooleanGroup(BoolSym::Or, group) => any(group, identifiers),
+        Expression::Identifier(i) => literals(identifiers.get(i)?, identifiers),
+        Expression::Match(Match::Of(0), _) => None,
+        Expression::Match(m, expression) => {
+            let expression = match expression.as_ref() {
+                Expression::Identifier(i) => identifiers.get(i)?,
+                expression => expression,
+            };
+            // NOTE: A match over a group counts its members, so `all` needs every member to be
+            // true while `of` only needs one of them to be.
+            match (m, expression) {
+                (Match::All, Expression::BooleanGroup(_, group)) => every(group, identifiers),
+                (_, Expression::BooleanGroup(_, group)) => any(group, identifiers),
+                (_, expression) => literals(expression, identifiers),
+            }
+        }
+        Expression::Search(search, field, _) => {
+            let values = match search {
+                Search::AhoCorasick(_, contexts, _) => contexts.iter().map(|c| c.value()).collect(),
+                Search::Contains(s)
+                | Search::EndsWith(s)
+                | Search::Exact(s)
+                | Search::StartsWith(s) => vec![s],
+                _ => return None,
+            };
+            if values.is_empty() || values.iter().any(|v| v.is_empty()) {
+                return None;
+            }
+            Some(
+                values
+                    .into_iter()
+                    .map(|v| (field.clone(), v.clone()))
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+// The literals of a disjunction, which are only required if every member has some.
+fn any<'a, I>(expressions: I, identifiers: &HashMap<String, Expression>) -> Option<Vec<Literal>>
+where
+    I: IntoIterator<Item = &'a Expression>,
+{
+    let mut any = vec![];
+    for expression in expressions {
+        any.extend(literals(expression, identifiers)?);
+    }
+    if any.is_empty() {
+        return None;
+    }
+    Some(any)
+}
+
+// The literals of a conjunction, where those of any member will do so we take the fewest.
+fn every<'a, I>(expressions: I, identifiers: &HashMap<String, Expression>) -> Option<Vec<Literal>>
+where
+    I: IntoIterator<Item = &'a Expression>,
+{
+    expressions
+        .into_iter()
+        .filter_map(|e| literals(e, identifiers))
+        .min_by_key(|l| l.len())
+}
+
+// Returns the text that a search could be run against for the value, which includes the values
+// that are cast to strings.
+fn text<'a>(value: &'a Value) -> Option<Cow<'a, str>> {
+    match value {
+        Value::String(s) => Some(Cow::Borrowed(s.as_ref())),
+        Value::Bool(x) => Some(Cow::Owned(x.to_string())),
+        Value::Float(x) => Some(Cow::Owned(x.to_string())),
+        Value::Int(x) => Some(Cow::Owned(x.to_string())),
+        Value::UInt(x) => Some(Cow::Owned(x.to_string())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::Rule;
+
+    fn rule(detection: &str) -> Rule {
+        Rule::from_str(&format!(
+            "detection:\n{}\ntrue_positives: []\ntrue_negatives: []\n",
+            detection
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn prefilter() {
+        let rules = [
+            // 0: a literal.
+            rule("  A:\n    foo: bar\n  condition: A"),
+            // 1: either of two literals on different fields.
+            rule("  A:\n    foo: '*baz*'\n  B:\n    qux: 'i*quux'\n  condition: A or B"),
+            // 2: a conjunction only needs one of its literals.
+            rule("  A:\n    foo: 'lorem*'\n  B:\n    qux: '?ipsum'\n  condition: A and B"),
+            // 3: a regex can't be indexed.
+            rule("  A:\n    foo: '?bar'\n  condition: A"),
+            // 4: a negation can't be indexed.
+            rule("  A:\n    foo: bar\n  condition: not A"),
+            // 5: a disjunction is only indexed when every member is.
+            rule("  A:\n    foo: bar\n  B:\n    qux: '?quux'\n  condition: A or B"),
+            // 6: a match over a group needs one member.
+            rule("  A:\n    foo: bar\n    qux: 1\n  condition: all(A)"),
+            // 7: a literal against a cast value.
+            rule("  A:\n    str(foo): '*42'\n  condition: A"),
+        ];
+        let prefilter = Prefilter::new(rules.iter().map(|r| &r.detection));
+        assert_eq!(prefilter.always, vec![3, 4, 5]);
+
+        let candidates = |document: &str| {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            let candidates = prefilter.candidates(document.as_mapping().unwrap());
+            candidates
+                .into_iter()
+                .enumerate()
+                .filter(|(_, c)| *c)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(candidates("{}"), vec![3, 4, 5]);
+        assert_eq!(candidates("{foo: bar}"), vec![0, 3, 4, 5, 6]);
+        assert_eq!(candidates("{foo: [abazb, lorem]}"), vec![1, 2, 3, 4, 5]);
+        assert_eq!(candidates("{qux: QUUX}"), vec![1, 3, 4, 5]);
+        assert_eq!(candidates("{foo: 1042}"), vec![3, 4, 5, 7]);
+    }
+}
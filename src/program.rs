@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+
+use crate::clock::Instant;
+use crate::document::Document;
+use crate::parser::Expression;
+use crate::rule::Detection;
+use crate::solver::{self, SolverResult};
+use crate::stats::{ExpressionStats, Stats};
+use crate::tokeniser::BoolSym;
+
+#[derive(Clone, Debug)]
+enum Instruction {
+    // Sets the result to a constant.
+    Constant(SolverResult),
+    // Evaluates a leaf expression, setting the result.
+    Evaluate(usize),
+    // Jumps to the instruction when the result is not true.
+    JumpUnlessTrue(usize),
+    // Negates the result, where missing becomes false.
+    Negate,
+    // Starts a disjunction, pushing its initial result onto the stack.
+    OrStart,
+    // Folds the result into the disjunction on the stack, when it is true the disjunction is
+    // popped and we jump to the instruction.
+    OrStep(usize),
+    // Ends a disjunction, popping its result off the stack.
+    OrEnd,
+}
+
+/// A detection that has been compiled into a flat list of instructions.
+///
+/// Solving a `Detection` walks its expression tree recursively, whereas a `Program` evaluates the
+/// boolean logic of the detection in a simple loop, only handing the leaves of the tree, i.e.
+/// searches and comparisons, to the solver. This improves cache locality for large detections,
+/// while giving the exact same results.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::Rule;
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     foo: bar
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+/// let program = rule.compile();
+///
+/// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+/// assert!(program.matches(document.as_mapping().unwrap()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Program {
+    // The deepest that disjunctions are nested, used to size the stack.
+    depth: usize,
+    identifiers: HashMap<String, Expression>,
+    instructions: Vec<Instruction>,
+    leaves: Vec<Expression>,
+}
+
+impl Program {
+    pub(crate) fn compile(detection: &Detection) -> Self {
+        let mut compiler = Compiler {
+            depth: 0,
+            identifiers: &detection.identifiers,
+            instructions: vec![],
+            leaves: vec![],
+            nesting: 0,
+        };
+        compiler.lower(&detection.expression);
+        Self {
+            depth: compiler.depth,
+            identifiers: detection.identifiers.clone(),
+            instructions: compiler.instructions,
+            leaves: compiler.leaves,
+        }
+    }
+
+    /// Evaluates the program against the provided `Document`, returning true if it has matched.
+    pub fn matches(&self, document: &dyn Document) -> bool {
+        self.solve(document) == SolverResult::True
+    }
+
+    /// Evaluates the program against the provided `Document`, returning true if it has matched,
+    /// while recording the evaluation of each node in the stats.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the stats were not created by this program's `stats`.
+    pub fn matches_with_stats(&self, document: &dyn Document, stats: &mut Stats) -> bool {
+        assert_eq!(
+            stats.expressions.len(),
+            self.leaves.len(),
+            "stats were not created for this program"
+        );
+        let result = self.run(|i| {
+            let start = Instant::now();
+            let result = solver::solve_expression(&self.leaves[i], &self.identifiers, document);
+            let stats = &mut stats.expressions[i];
+            stats.duration += start.elapsed();
+            stats.evaluations += 1;
+            if result == SolverResult::True {
+                stats.matches += 1;
+            }
+            result
+        });
+        result == SolverResult::True
+    }
+
+    /// Creates empty stats for the nodes of this program, see `matches_with_stats`.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            expressions: self
+                .leaves
+                .iter()
+                .map(|e| ExpressionStats {
+                    expression: e.to_string(),
+                    evaluations: 0,
+                    matches: 0,
+                    duration: Default::default(),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn solve(&self, document: &dyn Document) -> SolverResult {
+        self.run(|i| solver::solve_expression(&self.leaves[i], &self.identifiers, document))
+    }
+
+    // Runs the instructions, handing the evaluation of the leaves to the provided function.
+    #[inline]
+    fn run<F: FnMut(usize) -> SolverResult>(&self, mut evaluate: F) -> SolverResult {
+        let mut result = SolverResult::Missing;
+        let mut stack = Vec::with_capacity(self.depth);
+        let mut pc = 0;
+        while let Some(instruction) = self.instructions.get(pc) {
+            pc += 1;
+            match *instruction {
+                Instruction::Constant(r) => result = r,
+                Instruction::Evaluate(i) => result = evaluate(i),
+                Instruction::JumpUnlessTrue(to) => {
+                    if result != SolverResult::True {
+                        pc = to;
+                    }
+                }
+                Instruction::Negate => {
+                    result = match result {
+                        SolverResult::True => SolverResult::False,
+                        SolverResult::False => SolverResult::True,
+                        SolverResult::Missing => SolverResult::False,
+                    }
+                }
+                Instruction::OrStart => stack.push(SolverResult::Missing),
+                Instruction::OrStep(to) => match result {
+                    SolverResult::True => {
+                        stack.pop();
+                        pc = to;
+                    }
+                    SolverResult::False => {
+                        *stack.last_mut().expect("disjunction is on the stack") =
+                            SolverResult::False
+                    }
+                    SolverResult::Missing => {}
+                },
+                Instruction::OrEnd => {
+                    result = stack.pop().expect("disjunction is on the stack");
+                }
+            }
+        }
+        result
+    }
+}
+
+struct Compiler<'a> {
+    depth: usize,
+    identifiers: &'a HashMap<String, Expression>,
+    instructions: Vec<Instruction>,
+    leaves: Vec<Expression>,
+    nesting: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn lower(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Boolean(b) => self.instructions.push(Instruction::Constant(if *b {
+                SolverResult::True
+            } else {
+                SolverResult::False
+            })),
+            Expression::BooleanGroup(BoolSym::And, group) => self.and(group.iter()),
+            Expression::BooleanExpression(left, BoolSym::And, right) => {
+                self.and(vec![left.as_ref(), right.as_ref()].into_iter())
+            }
+            Expression::BooleanGroup(BoolSym::Or, group) => self.or(group.iter()),
+            Expression::BooleanExpression(left, BoolSym::Or, right) => {
+                self.or(vec![left.as_ref(), right.as_ref()].into_iter())
+            }
+            // NOTE: Identifiers are inlined, apart from within a match where they are needed to
+            // work out how the match is applied.
+            Expression::Identifier(i) => match self.identifiers.get(i) {
+                Some(e) => self.lower(e),
+                None => self.leaf(expression),
+            },
+            Expression::Negate(e) => {
+                self.lower(e);
+                self.instructions.push(Instruction::Negate);
+            }
+            _ => self.leaf(expression),
+        }
+    }
+
+    fn and<'e, I: ExactSizeIterator<Item = &'e Expression>>(&mut self, expressions: I) {
+        if expressions.len() == 0 {
+            self.instructions
+                .push(Instruction::Constant(SolverResult::True));
+            return;
+        }
+        let last = expressions.len() - 1;
+        let mut jumps = vec![];
+        for (i, expression) in expressions.enumerate() {
+            self.lower(expression);
+            if i != last {
+                jumps.push(self.instructions.len());
+                self.instructions.push(Instruction::JumpUnlessTrue(0));
+            }
+        }
+        let end = self.instructions.len();
+        for jump in jumps {
+            self.instructions[jump] = Instruction::JumpUnlessTrue(end);
+        }
+    }
+
+    fn or<'e, I: ExactSizeIterator<Item = &'e Expression>>(&mut self, expressions: I) {
+        self.nesting += 1;
+        self.depth = self.depth.max(self.nesting);
+        self.instructions.push(Instruction::OrStart);
+        let mut jumps = vec![];
+        for expression in expressions {
+            self.lower(expression);
+            jumps.push(self.instructions.len());
+            self.instructions.push(Instruction::OrStep(0));
+        }
+        self.instructions.push(Instruction::OrEnd);
+        let end = self.instructions.len();
+        for jump in jumps {
+            self.instructions[jump] = Instruction::OrStep(end);
+        }
+        self.nesting -= 1;
+    }
+
+    fn leaf(&mut self, expression: &Expression) {
+        self.instructions
+            .push(Instruction::Evaluate(self.leaves.len()));
+        self.leaves.push(expression.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::Value as Yaml;
+
+    use crate::rule::Rule;
+
+    #[test]
+    fn missing() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: foo
+              B:
+                bar: bar
+              C:
+                baz: baz
+              condition: not (A or B) and (not C or A)
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let program = rule.compile();
+        for document in [
+            "{}",
+            "{foo: foo}",
+            "{foo: bar}",
+            "{bar: foo}",
+            "{foo: bar, bar: foo}",
+            "{foo: bar, baz: baz}",
+            "{foo: bar, bar: foo, baz: qux}",
+        ] {
+            let document = serde_yaml::from_str::<Yaml>(document).unwrap();
+            let document = document.as_mapping().unwrap();
+            assert_eq!(
+                program.solve(document),
+                solver::solve_expression(
+                    &rule.detection.expression,
+                    &rule.detection.identifiers,
+                    document
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn stats() {
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: foo
+              B:
+                bar: bar
+              condition: A or B
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        let program = rule.compile();
+        let mut stats = program.stats();
+        for document in ["{foo: foo}", "{bar: bar}", "{}"] {
+            let document = serde_yaml::from_str::<Yaml>(document).unwrap();
+            let document = document.as_mapping().unwrap();
+            assert_eq!(
+                program.matches_with_stats(document, &mut stats),
+                program.matches(document)
+            );
+        }
+        let expressions = stats.expressions();
+        assert_eq!(expressions.len(), 2);
+        assert_eq!(expressions[0].expression, "search(foo, exact(foo), false)");
+        assert_eq!((expressions[0].evaluations, expressions[0].matches), (3, 1));
+        assert_eq!((expressions[1].evaluations, expressions[1].matches), (2, 1));
+        assert_eq!(stats.evaluations(), 5);
+        assert_eq!(stats.hottest().len(), 2);
+    }
+}
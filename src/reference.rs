@@ -0,0 +1,846 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use regex::RegexBuilder;
+
+use crate::document::Document;
+use crate::parser::{self, Expression, Hex, Match, MatchType, Search};
+use crate::rule::Detection;
+use crate::skeleton::skeleton;
+use crate::solver::SolverResult;
+use crate::tokeniser::{BoolSym, ModSym};
+use crate::value::Value;
+
+/// A disagreement between the reference evaluator and the production solvers, see
+/// `RuleSet::differential`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Divergence<'a> {
+    /// The index of the document within the corpus.
+    pub document: usize,
+    /// The id of the rule.
+    pub rule: &'a str,
+    /// Whether the rule matched with the reference evaluator.
+    pub reference: bool,
+    /// Whether the rule matched with the solver.
+    pub solver: bool,
+    /// Whether the rule matched with the rule's compiled `Program`.
+    pub compiled: bool,
+}
+
+/// Evaluates a `Detection` against a `Document` using the reference evaluator.
+///
+/// The reference evaluator is a slow but obviously correct implementation of the engine's
+/// semantics, intended as an oracle for testing the solver and the optimiser rather than for use
+/// in production. It evaluates every operand instead of short-circuiting, and expands the searches
+/// that the optimiser merges, such as Aho-Corasick automata and regex sets, back into their
+/// individual patterns, which are then checked with plain string operations.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{solve_reference, Rule};
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     foo: bar
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+/// assert!(solve_reference(&rule.detection, document.as_mapping().unwrap()));
+/// ```
+pub fn solve_reference(detection: &Detection, document: &dyn Document) -> bool {
+    evaluate(&detection.expression, &detection.identifiers, document) == SolverResult::True
+}
+
+fn evaluate(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+) -> SolverResult {
+    match expression {
+        Expression::BooleanGroup(BoolSym::And, group) => {
+            let results: Vec<SolverResult> = group
+                .iter()
+                .map(|e| evaluate(e, identifiers, document))
+                .collect();
+            conjunction(&results)
+        }
+        Expression::BooleanGroup(BoolSym::Or, group) => {
+            let results: Vec<SolverResult> = group
+                .iter()
+                .map(|e| evaluate(e, identifiers, document))
+                .collect();
+            disjunction(&results)
+        }
+        Expression::BooleanExpression(left, BoolSym::And, right) => conjunction(&[
+            evaluate(left, identifiers, document),
+            evaluate(right, identifiers, document),
+        ]),
+        Expression::BooleanExpression(left, BoolSym::Or, right) => disjunction(&[
+            evaluate(left, identifiers, document),
+            evaluate(right, identifiers, document),
+        ]),
+        Expression::BooleanExpression(left, op, right) => compare(left, op, right, document, false),
+        Expression::Boolean(b) => result(*b),
+        Expression::Identifier(i) => evaluate(&identifiers[i], identifiers, document),
+        Expression::Match(Match::All, e) => match members(e, identifiers) {
+            Some(group) => {
+                let results: Vec<SolverResult> = group
+                    .iter()
+                    .map(|e| all(e, identifiers, document))
+                    .collect();
+                conjunction(&results)
+            }
+            None => match e.as_ref() {
+                Expression::Identifier(i) => all(&identifiers[i], identifiers, document),
+                e => all(e, identifiers, document),
+            },
+        },
+        Expression::Match(Match::Every, e) => match e.as_ref() {
+            Expression::BooleanExpression(left, op, right) => {
+                compare(left, op, right, document, true)
+            }
+            Expression::In(e, integers) => membership(e, |x| integers.contains(&x), document, true),
+            Expression::Range(e, from, to) => {
+                membership(e, |x| *from <= x && x <= *to, document, true)
+            }
+            _ => SolverResult::False,
+        },
+        Expression::Match(Match::Of(count), e) if matches!(**e, Expression::Nested(_, _)) => {
+            let (field, e) = match e.as_ref() {
+                Expression::Nested(field, e) => (field, e),
+                _ => unreachable!(),
+            };
+            let satisfies = |o: &dyn Document| evaluate(e, identifiers, o) == SolverResult::True;
+            let hits = match document.find(field) {
+                None => return SolverResult::Missing,
+                Some(Value::Object(o)) => satisfies(&o) as u64,
+                Some(Value::Array(a)) => a
+                    .iter()
+                    .filter(|v| v.as_object().map(|o| satisfies(&o)).unwrap_or(false))
+                    .count() as u64,
+                Some(_) => 0,
+            };
+            result(if *count == 0 {
+                hits == 0
+            } else {
+                hits >= *count
+            })
+        }
+        Expression::Match(Match::Of(0), e) => match members(e, identifiers) {
+            Some(group) => {
+                let results: Vec<SolverResult> = group
+                    .iter()
+                    .map(|e| evaluate(e, identifiers, document))
+                    .collect();
+                if results.contains(&SolverResult::True) {
+                    SolverResult::False
+                } else if results.contains(&SolverResult::False) {
+                    SolverResult::True
+                } else {
+                    SolverResult::Missing
+                }
+            }
+            None => match evaluate(e, identifiers, document) {
+                SolverResult::True => SolverResult::False,
+                SolverResult::False => SolverResult::True,
+                SolverResult::Missing => SolverResult::Missing,
+            },
+        },
+        Expression::Match(Match::Of(count), e) => match members(e, identifiers) {
+            Some(group) => {
+                // NOTE: Members are counted in order, as a merged search whose field is missing
+                // decides the result unless enough members have already matched.
+                let mut hits = 0;
+                let mut res = SolverResult::Missing;
+                for e in group {
+                    match pattern_hits(e, document) {
+                        Some(Ok(rows)) => {
+                            hits += rows
+                                .iter()
+                                .map(|r| r.iter().filter(|h| **h).count() as u64)
+                                .max()
+                                .unwrap_or(0);
+                        }
+                        Some(Err(res)) => return res,
+                        None => match evaluate(e, identifiers, document) {
+                            SolverResult::True => hits += 1,
+                            SolverResult::False => res = SolverResult::False,
+                            SolverResult::Missing => {}
+                        },
+                    }
+                    if hits >= *count {
+                        return SolverResult::True;
+                    }
+                }
+                res
+            }
+            None => evaluate(e, identifiers, document),
+        },
+        // NOTE: A negated missing field is false, so that a rule never matches purely on the
+        // absence of data.
+        Expression::Negate(e) => match evaluate(e, identifiers, document) {
+            SolverResult::False => SolverResult::True,
+            SolverResult::True | SolverResult::Missing => SolverResult::False,
+        },
+        Expression::Nested(field, e) => match document.find(field) {
+            None => SolverResult::Missing,
+            Some(Value::Object(o)) => evaluate(e, identifiers, &o),
+            Some(Value::Array(a)) => {
+                let results: Vec<SolverResult> = a
+                    .iter()
+                    .filter_map(|v| v.as_object().map(|o| evaluate(e, identifiers, &o)))
+                    .collect();
+                result(results.contains(&SolverResult::True))
+            }
+            Some(_) => SolverResult::False,
+        },
+        Expression::In(e, integers) => membership(e, |x| integers.contains(&x), document, false),
+        Expression::Range(e, from, to) => {
+            membership(e, |x| *from <= x && x <= *to, document, false)
+        }
+        Expression::Keywords(search) => {
+            let mut values = vec![];
+            for value in document.values() {
+                strings(&value, &mut values);
+            }
+            result(values.iter().any(|v| matches(search, v)))
+        }
+        Expression::Search(Search::Hex(hex), field, _) => {
+            match blobs(field, document, |s| s.as_bytes().to_vec()) {
+                Ok(values) => result(values.iter().any(|(v, _)| hex_matches(hex, v))),
+                Err(res) => res,
+            }
+        }
+        Expression::Search(Search::Wide(_, contexts, insensitive), field, _) => {
+            match blobs(field, document, parser::utf16le) {
+                Ok(values) => result(values.iter().any(|(v, aligned)| {
+                    contexts.iter().any(|c| wide(c, v, *insensitive, *aligned))
+                })),
+                Err(res) => res,
+            }
+        }
+        Expression::Search(search, field, cast) => match candidates(field, *cast, document) {
+            Ok(values) => result(values.iter().any(|v| matches(search, v))),
+            Err(res) => res,
+        },
+        Expression::BooleanGroup(..)
+        | Expression::Cast(..)
+        | Expression::Field(_)
+        | Expression::Float(_)
+        | Expression::Integer(_)
+        | Expression::Null
+        | Expression::Timestamp(_) => unreachable!(),
+    }
+}
+
+// The first operand that is not true decides a conjunction.
+fn conjunction(results: &[SolverResult]) -> SolverResult {
+    results
+        .iter()
+        .copied()
+        .find(|r| *r != SolverResult::True)
+        .unwrap_or(SolverResult::True)
+}
+
+// Any true operand decides a disjunction, and it is only missing if every operand is missing.
+fn disjunction(results: &[SolverResult]) -> SolverResult {
+    if results.contains(&SolverResult::True) {
+        SolverResult::True
+    } else if results.contains(&SolverResult::False) {
+        SolverResult::False
+    } else {
+        SolverResult::Missing
+    }
+}
+
+fn result(b: bool) -> SolverResult {
+    if b {
+        SolverResult::True
+    } else {
+        SolverResult::False
+    }
+}
+
+// Returns the members of a group that a match is applied to, or `None` when it is applied to a
+// single expression.
+fn members<'a>(
+    expression: &'a Expression,
+    identifiers: &'a HashMap<String, Expression>,
+) -> Option<&'a [Expression]> {
+    match expression {
+        Expression::BooleanGroup(_, group) => Some(group),
+        Expression::Identifier(i) => match &identifiers[i] {
+            Expression::BooleanGroup(_, group) => Some(group),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Evaluates a member of an `all` match, where a merged search requires every one of its patterns
+// to match the same value.
+fn all(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+) -> SolverResult {
+    match pattern_hits(expression, document) {
+        Some(Ok(rows)) => result(rows.iter().any(|r| r.iter().all(|h| *h))),
+        Some(Err(res)) => res,
+        None => evaluate(expression, identifiers, document),
+    }
+}
+
+// Checks every pattern of a merged search against each value of its field, giving a row per value
+// of which patterns it matched, or the result to evaluate as if the field has none. `None` is
+// returned for any other expression.
+fn pattern_hits(
+    expression: &Expression,
+    document: &dyn Document,
+) -> Option<Result<Vec<Vec<bool>>, SolverResult>> {
+    let (search, field, cast) = match expression {
+        Expression::Search(search, field, cast) => (search, field, *cast),
+        _ => return None,
+    };
+    if let Search::Wide(_, contexts, insensitive) = search {
+        return Some(blobs(field, document, parser::utf16le).map(|values| {
+            values
+                .iter()
+                .map(|(v, aligned)| {
+                    contexts
+                        .iter()
+                        .map(|c| wide(c, v, *insensitive, *aligned))
+                        .collect()
+                })
+                .collect()
+        }));
+    }
+    let patterns = patterns(search)?;
+    Some(candidates(field, cast, document).map(|values| {
+        values
+            .iter()
+            .map(|v| patterns.iter().map(|p| p(v)).collect())
+            .collect()
+    }))
+}
+
+type Pattern = Box<dyn Fn(&str) -> bool>;
+
+// Expands a merged search into its individual patterns, looking through the searches that map the
+// value first, and returning `None` for any other search.
+fn patterns(search: &Search) -> Option<Vec<Pattern>> {
+    let patterns: Vec<Pattern> = match search {
+        Search::AhoCorasick(_, contexts, insensitive) => contexts
+            .iter()
+            .map(|c| {
+                let (c, insensitive) = (c.clone(), *insensitive);
+                Box::new(move |v: &str| context(&c, v, insensitive)) as Pattern
+            })
+            .collect(),
+        Search::RegexSet(set, insensitive) => set
+            .patterns()
+            .iter()
+            .map(|p| {
+                let regex = RegexBuilder::new(p)
+                    .case_insensitive(*insensitive)
+                    .build()
+                    .expect("could not build regex");
+                Box::new(move |v: &str| regex.is_match(v)) as Pattern
+            })
+            .collect(),
+        Search::Shadow(s) => patterns(s)?
+            .into_iter()
+            .map(|p| Box::new(move |v: &str| p(&v.to_lowercase())) as Pattern)
+            .collect(),
+        Search::Skeleton(s) => patterns(s)?
+            .into_iter()
+            .map(|p| Box::new(move |v: &str| p(&skeleton(v))) as Pattern)
+            .collect(),
+        _ => return None,
+    };
+    Some(patterns)
+}
+
+// Returns the strings that a search on the field is checked against, or the result to evaluate as
+// if the field has none.
+fn candidates(
+    field: &str,
+    cast: bool,
+    document: &dyn Document,
+) -> Result<Vec<String>, SolverResult> {
+    let scalar = |v: &Value| match v {
+        Value::Bool(x) => Some(x.to_string()),
+        Value::Float(x) => Some(x.to_string()),
+        Value::Int(x) => Some(x.to_string()),
+        Value::UInt(x) => Some(x.to_string()),
+        _ => None,
+    };
+    match document.find(field) {
+        None => Err(SolverResult::Missing),
+        Some(Value::String(s)) => Ok(vec![s.into_owned()]),
+        Some(Value::Array(a)) => Ok(a
+            .iter()
+            .filter_map(|v| match v.as_str() {
+                Some(s) => Some(s.to_owned()),
+                None if cast => scalar(&v),
+                None => None,
+            })
+            .collect()),
+        Some(v) if cast => scalar(&v).map(|s| vec![s]).ok_or(SolverResult::Missing),
+        Some(_) => Err(SolverResult::Missing),
+    }
+}
+
+// Collects every string within the value, including those nested in arrays and objects.
+fn strings(value: &Value, strings: &mut Vec<String>) {
+    match value {
+        Value::String(s) => strings.push(s.to_string()),
+        Value::Array(a) => {
+            for v in a.iter() {
+                self::strings(&v, strings);
+            }
+        }
+        Value::Object(o) => {
+            for k in o.keys() {
+                if let Some(v) = o.get(&k) {
+                    self::strings(&v, strings);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// Returns the bytes that a byte search on the field is checked against, and whether they were
+// encoded from a string with the provided function, or the result to evaluate as if the field has
+// none.
+fn blobs(
+    field: &str,
+    document: &dyn Document,
+    encode: fn(&str) -> Vec<u8>,
+) -> Result<Vec<(Vec<u8>, bool)>, SolverResult> {
+    let blob = |v: &Value| match v {
+        Value::Bytes(b) => Some((b.to_vec(), false)),
+        Value::String(s) => Some((encode(s), true)),
+        _ => None,
+    };
+    match document.find(field) {
+        None => Err(SolverResult::Missing),
+        Some(Value::Array(a)) => Ok(a.iter().filter_map(|v| blob(&v)).collect()),
+        Some(v) => blob(&v).map(|b| vec![b]).ok_or(SolverResult::Missing),
+    }
+}
+
+fn matches(search: &Search, value: &str) -> bool {
+    match search {
+        Search::Any => true,
+        Search::AhoCorasick(_, contexts, insensitive) => {
+            contexts.iter().any(|c| context(c, value, *insensitive))
+        }
+        Search::Contains(s) => value.contains(s.as_str()),
+        Search::EndsWith(s) => value.ends_with(s.as_str()),
+        Search::Exact(s) => value == s,
+        Search::Fuzzy(s, distance, insensitive) => {
+            if *insensitive {
+                distance_between(s, &value.to_lowercase()) <= *distance
+            } else {
+                distance_between(s, value) <= *distance
+            }
+        }
+        Search::Glob(glob, insensitive) => {
+            let pattern: Vec<char> = glob.as_str().chars().collect();
+            if *insensitive {
+                glob_matches(&pattern, &value.to_lowercase().chars().collect::<Vec<_>>())
+            } else {
+                glob_matches(&pattern, &value.chars().collect::<Vec<_>>())
+            }
+        }
+        Search::Hex(hex) => hex_matches(hex, value.as_bytes()),
+        Search::Lookup(lookup, insensitive) => {
+            if *insensitive {
+                lookup.set.contains(&value.to_lowercase())
+            } else {
+                lookup.set.contains(value)
+            }
+        }
+        Search::Regex(regex, _) => regex.is_match(value),
+        Search::RegexSet(set, insensitive) => set.patterns().iter().any(|p| {
+            RegexBuilder::new(p)
+                .case_insensitive(*insensitive)
+                .build()
+                .expect("could not build regex")
+                .is_match(value)
+        }),
+        Search::Shadow(s) => matches(s, &value.to_lowercase()),
+        Search::Skeleton(s) => matches(s, &skeleton(value)),
+        Search::StartsWith(s) => value.starts_with(s.as_str()),
+        Search::Wide(_, contexts, insensitive) => {
+            let value = parser::utf16le(value);
+            contexts.iter().any(|c| wide(c, &value, *insensitive, true))
+        }
+        #[cfg(feature = "fancy_regex")]
+        Search::FancyRegex(regex, _) => regex.is_match(value).unwrap_or(false),
+    }
+}
+
+// Checks a hex pattern by comparing it, nibble by nibble, at every byte offset.
+// Matches a glob by backtracking over the pattern as written.
+fn glob_matches(pattern: &[char], value: &[char]) -> bool {
+    let separator = |c: char| c == '/' || c == '\\';
+    match pattern.first() {
+        None => value.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            match rest.first() {
+                Some(c) if separator(*c) => (0..=value.len())
+                    .filter(|i| *i == 0 || separator(value[i - 1]))
+                    .any(|i| glob_matches(&rest[1..], &value[i..])),
+                _ => (0..=value.len()).any(|i| glob_matches(rest, &value[i..])),
+            }
+        }
+        Some('*') => (0..=value.len())
+            .take_while(|i| *i == 0 || !separator(value[i - 1]))
+            .any(|i| glob_matches(&pattern[1..], &value[i..])),
+        Some('?') => {
+            !value.is_empty() && !separator(value[0]) && glob_matches(&pattern[1..], &value[1..])
+        }
+        Some('[') => {
+            let negated = pattern.get(1) == Some(&'!');
+            let start = if negated { 2 } else { 1 };
+            let end = start
+                + 1
+                + pattern[start + 1..]
+                    .iter()
+                    .position(|c| *c == ']')
+                    .expect("glob class is terminated");
+            let members = &pattern[start..end];
+            let c = match value.first() {
+                Some(c) => *c,
+                None => return false,
+            };
+            let mut found = false;
+            let mut i = 0;
+            while i < members.len() {
+                if members.get(i + 1) == Some(&'-') && i + 2 < members.len() {
+                    found |= members[i] <= c && c <= members[i + 2];
+                    i += 3;
+                } else {
+                    found |= members[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated
+                && !(negated && separator(c))
+                && glob_matches(&pattern[end + 1..], &value[1..])
+        }
+        Some(c) => value.first() == Some(c) && glob_matches(&pattern[1..], &value[1..]),
+    }
+}
+
+fn hex_matches(hex: &Hex, value: &[u8]) -> bool {
+    let pattern: Vec<(u8, u8)> = hex.bytes().collect();
+    (0..value.len())
+        .filter(|start| start + pattern.len() <= value.len())
+        .any(|start| {
+            pattern.iter().enumerate().all(|(i, (byte, mask))| {
+                let b = value[start + i];
+                (mask & 0xf0 == 0 || b >> 4 == byte >> 4)
+                    && (mask & 0xf == 0 || b & 0xf == byte & 0xf)
+            })
+        })
+}
+
+// Checks a single pattern of a wide search by trying its encoding at every byte offset, or only at
+// every code unit when the bytes were encoded from a string.
+fn wide(context: &MatchType, value: &[u8], insensitive: bool, aligned: bool) -> bool {
+    let needle = parser::utf16le(context.value());
+    let unit = |b: &[u8]| {
+        let u = u16::from_le_bytes([b[0], b[1]]);
+        if insensitive && u < 0x80 {
+            (u as u8).to_ascii_lowercase() as u16
+        } else {
+            u
+        }
+    };
+    let equal = |window: &[u8]| {
+        window
+            .chunks(2)
+            .zip(needle.chunks(2))
+            .all(|(a, b)| unit(a) == unit(b))
+    };
+    if needle.len() > value.len() {
+        return false;
+    }
+    let step = if aligned { 2 } else { 1 };
+    (0..=value.len() - needle.len()).step_by(step).any(|start| {
+        let end = start + needle.len();
+        let positioned = match context {
+            MatchType::Contains(_) => true,
+            MatchType::EndsWith(_) => end == value.len(),
+            MatchType::Exact(_) => start == 0 && end == value.len(),
+            MatchType::StartsWith(_) => start == 0,
+        };
+        positioned && equal(&value[start..end])
+    })
+}
+
+// Checks a single pattern of an Aho-Corasick search, which is only ever ascii case insensitive.
+fn context(context: &MatchType, value: &str, insensitive: bool) -> bool {
+    let (needle, value) = if insensitive {
+        (
+            context.value().to_ascii_lowercase(),
+            value.to_ascii_lowercase(),
+        )
+    } else {
+        (context.value().clone(), value.to_owned())
+    };
+    match context {
+        MatchType::Contains(_) => value.contains(&needle),
+        MatchType::EndsWith(_) => value.ends_with(&needle),
+        MatchType::Exact(_) => value == needle,
+        MatchType::StartsWith(_) => value.starts_with(&needle),
+    }
+}
+
+// The Damerau-Levenshtein distance using optimal string alignment, computed over the full matrix.
+fn distance_between(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d: Vec<Vec<usize>> = (0..=a.len())
+        .map(|i| (0..=b.len()).map(|j| if i == 0 { j } else { i }).collect())
+        .collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+fn compare(
+    left: &Expression,
+    op: &BoolSym,
+    right: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> SolverResult {
+    let holds = |ordering: Ordering| match op {
+        BoolSym::Equal => ordering.is_eq(),
+        BoolSym::GreaterThan => ordering.is_gt(),
+        BoolSym::GreaterThanOrEqual => ordering.is_ge(),
+        BoolSym::LessThan => ordering.is_lt(),
+        BoolSym::LessThanOrEqual => ordering.is_le(),
+        _ => false,
+    };
+    let ordering = match (left, right) {
+        (Expression::Cast(x, ModSym::Str), Expression::Cast(y, ModSym::Str))
+            if matches!(
+                op,
+                BoolSym::Contains | BoolSym::EndsWith | BoolSym::StartsWith
+            ) =>
+        {
+            let (x, y) = match (string(x, document), string(y, document)) {
+                (Ok(x), Ok(y)) => (x, y),
+                (Err(res), _) | (_, Err(res)) => return res,
+            };
+            return result(match op {
+                BoolSym::Contains => x.contains(&y),
+                BoolSym::EndsWith => x.ends_with(&y),
+                _ => x.starts_with(&y),
+            });
+        }
+        (Expression::Cast(x, ModSym::Str), Expression::Cast(y, ModSym::Str)) => {
+            let x = string(x, document);
+            let y = string(y, document);
+            match (x, y) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Err(res), _) | (_, Err(res)) => return res,
+            }
+        }
+        (Expression::Field(x), Expression::Boolean(b)) if *op == BoolSym::Equal => {
+            return match document.find(x) {
+                None => SolverResult::Missing,
+                Some(v) => result(v.as_bool() == Some(*b)),
+            };
+        }
+        (Expression::Field(x), Expression::Null) if *op == BoolSym::Equal => {
+            return match document.find(x) {
+                None => SolverResult::Missing,
+                Some(v) => result(v.is_null()),
+            };
+        }
+        _ if temporal(left) || temporal(right) => {
+            match (timestamp(left, document), timestamp(right, document)) {
+                (Ok(x), Ok(y)) => x.cmp(&y),
+                (Err(res), _) | (_, Err(res)) => return res,
+            }
+        }
+        (Expression::Float(_), _) | (_, Expression::Float(_)) => {
+            return match (
+                floats(left, document, every),
+                floats(right, document, every),
+            ) {
+                (Ok(x), Ok(y)) => result(pairs(&x, &y, every, |x, y| {
+                    x.partial_cmp(&y).map(holds).unwrap_or(false)
+                })),
+                (Err(res), _) | (_, Err(res)) => res,
+            };
+        }
+        _ => {
+            return match (
+                integers(left, document, every),
+                integers(right, document, every),
+            ) {
+                (Ok(x), Ok(y)) => result(pairs(&x, &y, every, |x, y| holds(x.cmp(&y)))),
+                (Err(res), _) | (_, Err(res)) => res,
+            };
+        }
+    };
+    result(match op {
+        BoolSym::Equal => ordering.is_eq(),
+        BoolSym::GreaterThan => ordering.is_gt(),
+        BoolSym::GreaterThanOrEqual => ordering.is_ge(),
+        BoolSym::LessThan => ordering.is_lt(),
+        BoolSym::LessThanOrEqual => ordering.is_le(),
+        BoolSym::Contains | BoolSym::EndsWith | BoolSym::StartsWith => return SolverResult::False,
+        BoolSym::And | BoolSym::Or => unreachable!(),
+    })
+}
+
+fn temporal(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::Timestamp(_) | Expression::Cast(_, ModSym::Time)
+    )
+}
+
+fn string(field: &str, document: &dyn Document) -> Result<String, SolverResult> {
+    let value = document.find(field).ok_or(SolverResult::Missing)?;
+    value.to_string().ok_or(SolverResult::False)
+}
+
+fn timestamp(expression: &Expression, document: &dyn Document) -> Result<SystemTime, SolverResult> {
+    match expression {
+        Expression::Timestamp(t) => Ok(*t),
+        Expression::Cast(field, ModSym::Time) => {
+            let value = document.find(field).ok_or(SolverResult::Missing)?;
+            value.to_timestamp().ok_or(SolverResult::False)
+        }
+        _ => Err(SolverResult::False),
+    }
+}
+
+// Returns true if the predicate holds for some pair of the numbers, or for every pair of them when
+// `every` is set, where a number that could not be converted holds for nothing.
+fn pairs<T: Copy, F: Fn(T, T) -> bool>(
+    x: &[Option<T>],
+    y: &[Option<T>],
+    every: bool,
+    f: F,
+) -> bool {
+    let holds = |a: &Option<T>, b: &Option<T>| match (a, b) {
+        (Some(a), Some(b)) => f(*a, *b),
+        _ => false,
+    };
+    if every {
+        !x.is_empty() && !y.is_empty() && x.iter().all(|a| y.iter().all(|b| holds(a, b)))
+    } else {
+        x.iter().any(|a| y.iter().any(|b| holds(a, b)))
+    }
+}
+
+// Evaluates an integer membership test against the numbers of the expression.
+fn membership<F: Fn(i64) -> bool>(
+    expression: &Expression,
+    f: F,
+    document: &dyn Document,
+    every: bool,
+) -> SolverResult {
+    match integers(expression, document, every) {
+        Ok(x) => {
+            let holds = |x: &Option<i64>| x.map(&f).unwrap_or(false);
+            result(if every {
+                !x.is_empty() && x.iter().all(holds)
+            } else {
+                x.iter().any(holds)
+            })
+        }
+        Err(res) => res,
+    }
+}
+
+// Returns the numbers of an operand as floats, where an array cast with `int`, or one that every
+// element of must match, has one for each of its elements.
+fn floats(
+    expression: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> Result<Vec<Option<f64>>, SolverResult> {
+    let (field, cast) = match expression {
+        Expression::Float(f) => return Ok(vec![Some(*f)]),
+        Expression::Integer(i) => return Ok(vec![Some(*i as f64)]),
+        Expression::Cast(field, ModSym::Int) => (field, true),
+        Expression::Field(field) => (field, false),
+        _ => return Err(SolverResult::False),
+    };
+    // NOTE: An integer cast only ever yields integers, even when compared against a float.
+    let float = |value: &Value| match value {
+        Value::Bool(x) if cast => Some(if *x { 1.0 } else { 0.0 }),
+        Value::String(x) if cast => x.parse::<i64>().ok().map(|x| x as f64),
+        Value::Float(x) if !cast => Some(*x),
+        Value::Int(x) => Some(*x as f64),
+        Value::UInt(x) => Some(*x as f64),
+        _ => None,
+    };
+    match document.find(field).ok_or(SolverResult::Missing)? {
+        Value::Array(a) if cast || every => Ok(a.iter().map(|v| float(&v)).collect()),
+        value => float(&value)
+            .map(|x| vec![Some(x)])
+            .ok_or(SolverResult::False),
+    }
+}
+
+// Returns the numbers of an operand as integers, where an array cast with `int`, or one that every
+// element of must match, has one for each of its elements.
+fn integers(
+    expression: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> Result<Vec<Option<i64>>, SolverResult> {
+    let (field, cast) = match expression {
+        Expression::Integer(i) => return Ok(vec![Some(*i)]),
+        Expression::Cast(field, cast) => (field, Some(cast)),
+        Expression::Field(field) => (field, None),
+        _ => return Err(SolverResult::False),
+    };
+    let value = document.find(field).ok_or(SolverResult::Missing)?;
+    let integer = |value: &Value| match (cast, value) {
+        (Some(ModSym::Int), Value::Bool(b)) => Some(*b as i64),
+        (Some(ModSym::Int), Value::String(s)) => s.parse().ok(),
+        (Some(ModSym::Int) | None, v) => v.to_i64(),
+        (Some(_), _) => None,
+    };
+    match (cast, &value) {
+        (Some(ModSym::Len), Value::String(s)) => Ok(vec![Some(s.chars().count() as i64)]),
+        (Some(ModSym::Len), Value::Array(a)) => Ok(vec![Some(a.len() as i64)]),
+        (Some(ModSym::Len), _) => Err(SolverResult::False),
+        (Some(ModSym::Int), Value::Array(a)) => Ok(a.iter().map(|v| integer(&v)).collect()),
+        (_, Value::Array(a)) if every => Ok(a.iter().map(|v| integer(&v)).collect()),
+        (_, value) => integer(value)
+            .map(|x| vec![Some(x)])
+            .ok_or(SolverResult::False),
+    }
+}
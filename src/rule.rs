@@ -1,17 +1,53 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
 
 use serde::de::{self, Deserializer, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
-use serde_yaml::Value as Yaml;
+use serde_yaml::{Mapping, Value as Yaml};
 
+#[cfg(feature = "async")]
+use crate::asynchronous::AsyncDocument;
+use crate::correlation::{Correlation, Sequence};
+use crate::diagnostic::Diagnostic;
 use crate::document::Document;
+use crate::explain::{self, Explanation, Provenance, Redaction};
+use crate::limits::{Complexity, Cost};
+use crate::node::{self, Node};
 use crate::optimiser;
-use crate::parser::{self, Expression};
-use crate::solver;
-use crate::tokeniser::{ModSym, Token, Tokeniser};
+use crate::parser::{self, Expression, Match, ParserOptions};
+use crate::placeholder::Placeholders;
+use crate::policy::Action;
+use crate::program::Program;
+use crate::reference;
+use crate::solver::{self, Budget, Deadline, Outcome, Plan};
+use crate::template::Template;
+use crate::tokeniser::{self, BoolSym, DelSym, Dialect, Keywords, ModSym, Token};
+
+thread_local! {
+    // NOTE: Serde does not let us pass state into `Deserialize`, so the loader sets its parser
+    // options here for the duration of the deserialisation.
+    static OPTIONS: RefCell<ParserOptions> = RefCell::new(ParserOptions::default());
+}
+
+struct ScopedOptions(Option<ParserOptions>);
+
+impl ScopedOptions {
+    fn set(options: ParserOptions) -> Self {
+        Self(Some(OPTIONS.with(|o| o.replace(options))))
+    }
+}
+
+impl Drop for ScopedOptions {
+    fn drop(&mut self) {
+        if let Some(options) = self.0.take() {
+            OPTIONS.with(|o| o.replace(options));
+        }
+    }
+}
 
 /// The detection block, this contains the logic that is to be run through the solver to evaluate a
 /// `Document`.
@@ -28,6 +64,15 @@ pub struct Detection {
     expression_raw: String,
     #[serde(flatten)]
     identifiers_raw: HashMap<String, Yaml>,
+    // The options that the detection was parsed with, so that later optimisations honour them.
+    #[serde(skip)]
+    options: ParserOptions,
+    // The addressable nodes, which are collected on first use, see `Node`.
+    #[serde(skip)]
+    nodes: OnceLock<Vec<Node>>,
+    // The plan for sharing scans, which is made on first use, see `solver::Plan`.
+    #[serde(skip)]
+    plan: OnceLock<Option<Arc<Plan>>>,
 }
 
 impl fmt::Debug for Detection {
@@ -39,6 +84,234 @@ impl fmt::Debug for Detection {
     }
 }
 
+impl Detection {
+    /// Evaluates the detection against the provided `Document`, explaining the outcome of each of
+    /// its nodes.
+    ///
+    /// This is a dry run intended for showing why a document did, or did not, match, for example
+    /// in a triage UI. It is considerably slower than solving, as every node is evaluated in full.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::Rule;
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let rule = Rule::from_str(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap();
+    ///
+    /// let event: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+    /// let explanation = rule.detection.explain_evaluation(event.as_mapping().unwrap());
+    /// assert_eq!(explanation.outcome, Some(true));
+    /// ```
+    pub fn explain_evaluation(&self, document: &dyn Document) -> Explanation {
+        self.explain_evaluation_with(document, &Redaction::new())
+    }
+
+    /// Evaluates the detection against the provided `Document`, explaining the outcome of each of
+    /// its nodes, where the values in the explanation are redacted using the provided policy.
+    pub fn explain_evaluation_with(
+        &self,
+        document: &dyn Document,
+        redaction: &Redaction,
+    ) -> Explanation {
+        explain::explain(&self.expression, &self.identifiers, document, redaction)
+    }
+
+    /// Returns the values within the `Document` that the detection matched on, along with where
+    /// they were found, see `Provenance`.
+    ///
+    /// Only the nodes that were true are descended into, so nothing is returned when the
+    /// detection did not match, and the values matched by negated nodes are never included. When
+    /// a field holds an array, only the elements that match on their own are returned.
+    pub fn explain_matches(&self, document: &dyn Document) -> Vec<Provenance> {
+        self.explain_matches_with(document, &Redaction::new())
+    }
+
+    /// Returns the values within the `Document` that the detection matched on, where the values
+    /// are redacted using the provided policy.
+    pub fn explain_matches_with(
+        &self,
+        document: &dyn Document,
+        redaction: &Redaction,
+    ) -> Vec<Provenance> {
+        let mut matches = vec![];
+        explain::provenance(
+            &self.expression,
+            &self.identifiers,
+            document,
+            redaction,
+            "",
+            &mut matches,
+        );
+        matches
+    }
+
+    // Returns the error from parsing the detection, if it is well formed enough to be parsed.
+    fn failure(yaml: Yaml, options: &ParserOptions) -> Option<crate::Error> {
+        let mapping = match yaml {
+            Yaml::Mapping(mapping) => mapping,
+            _ => return None,
+        };
+        let mut expression = None;
+        let mut identifiers = vec![];
+        for (k, v) in mapping {
+            match (k, v) {
+                (Yaml::String(k), Yaml::String(v)) if k == "condition" => expression = Some(v),
+                (Yaml::String(k), v) if k != "condition" => identifiers.push((k, v)),
+                _ => return None,
+            }
+        }
+        Detection::parse(expression?, identifiers, options.clone()).err()
+    }
+
+    // Parses the detection from its condition and identifiers, which are in the order that they
+    // were written.
+    fn parse(
+        expression_raw: String,
+        raw: Vec<(String, Yaml)>,
+        options: ParserOptions,
+    ) -> crate::Result<Self> {
+        let mut identifiers: HashMap<String, Expression> = HashMap::new();
+        let mut identifiers_raw: HashMap<String, Yaml> = HashMap::new();
+        let mut cost = Cost::default();
+        for (key, v) in raw {
+            // NOTE: Check the complexity as we go, so that a rule that is too complex is rejected
+            // before all of it has been compiled.
+            cost.identifiers += 1;
+            let identifier = parser::parse_identifier_with(&v, &options)?;
+            options.complexity.charge(&mut cost, &identifier)?;
+            identifiers.insert(key.clone(), identifier);
+            identifiers_raw.insert(key, v);
+        }
+        let keywords = options.dialect.keywords(&options.keywords);
+        let (tokens, positions) = tokeniser::tokenise(&expression_raw, &keywords)?;
+
+        // Loop through the tokens making sure that all identifiers are present, this is a
+        // pain because we need to ignore fields... For now we can just check for misc
+        // symbol prefix and skip those if present
+        let mut i = 0;
+        for token in &tokens {
+            if i > 1 {
+                if let Token::Modifier(m) = &tokens[i - 2] {
+                    match m {
+                        ModSym::Int
+                        | ModSym::Len
+                        | ModSym::Not
+                        | ModSym::Skeleton
+                        | ModSym::Str
+                        | ModSym::Time
+                        | ModSym::Wide
+                        | ModSym::Windash => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                }
+            }
+            if let Token::Identifier(id) = token {
+                if !identifiers.contains_key(id) {
+                    return Err(crate::error::rule_invalid(
+                        Diagnostic::new("condition.unknown_identifier")
+                            .with("identifier", id)
+                            .at(positions[i]),
+                    ));
+                }
+            }
+            i += 1;
+        }
+
+        let mut depth: usize = 0;
+        let mut nesting = 0;
+        for token in &tokens {
+            match token {
+                Token::Delimiter(DelSym::LeftParenthesis) => {
+                    depth += 1;
+                    nesting = nesting.max(depth);
+                }
+                Token::Delimiter(DelSym::RightParenthesis) => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        options.complexity.nesting(nesting)?;
+
+        let expression = parser::parse_at(&tokens, &positions)?;
+        if !expression.is_solvable() {
+            return Err(crate::error::rule_invalid(
+                Diagnostic::new("condition.not_solvable").with("expression", &expression),
+            ));
+        }
+        let mut counted = CountedKeywords {
+            identifiers: &identifiers,
+            identifier: None,
+        };
+        parser::Visitor::visit_expression(&mut counted, &expression);
+        if let Some(identifier) = counted.identifier {
+            return Err(crate::error::rule_invalid(
+                Diagnostic::new("condition.counted_keywords").with("identifier", identifier),
+            ));
+        }
+        Ok(Detection {
+            expression,
+            identifiers,
+            expression_raw,
+            identifiers_raw,
+            nodes: OnceLock::new(),
+            options,
+            plan: OnceLock::new(),
+        })
+    }
+
+    pub(crate) fn options(&self) -> &ParserOptions {
+        &self.options
+    }
+
+    pub(crate) fn plan(&self) -> Option<&Arc<Plan>> {
+        self.plan
+            .get_or_init(|| Plan::new(self).map(Arc::new))
+            .as_ref()
+    }
+
+    /// Returns the addressable nodes within the detection's identifiers.
+    pub fn nodes(&self) -> &[Node] {
+        self.nodes
+            .get_or_init(|| node::collect(&self.identifiers_raw))
+    }
+}
+
+// Finds an identifier of keywords that a match has to count. Keywords are only searched for
+// anywhere in the document, so there is no way to tell how many of them are present.
+struct CountedKeywords<'a> {
+    identifiers: &'a HashMap<String, Expression>,
+    identifier: Option<String>,
+}
+
+impl parser::Visitor for CountedKeywords<'_> {
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Match(Match::All | Match::Of(2..), e) = expression {
+            if let Expression::Identifier(i) = e.as_ref() {
+                let keywords = match self.identifiers.get(i) {
+                    Some(Expression::Keywords(_)) => true,
+                    Some(Expression::BooleanGroup(_, group)) => {
+                        group.iter().any(|e| matches!(e, Expression::Keywords(_)))
+                    }
+                    _ => false,
+                };
+                if keywords && self.identifier.is_none() {
+                    self.identifier = Some(i.clone());
+                }
+            }
+        }
+        parser::walk_expression(self, expression);
+    }
+}
+
 impl<'de> Deserialize<'de> for Detection {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -54,8 +327,8 @@ impl<'de> Deserialize<'de> for Detection {
             where
                 V: MapAccess<'de>,
             {
-                let mut identifiers: HashMap<String, Expression> = HashMap::new();
-                let mut identifiers_raw: HashMap<String, Yaml> = HashMap::new();
+                let options = OPTIONS.with(|o| o.borrow().clone());
+                let mut identifiers: Vec<(String, Yaml)> = vec![];
                 let mut expression = None;
                 while let Some(key) = map.next_key::<String>()? {
                     match key.as_ref() {
@@ -66,110 +339,122 @@ impl<'de> Deserialize<'de> for Detection {
                             expression = Some(map.next_value::<String>()?);
                         }
                         _ => {
-                            if identifiers.get(&key).is_some() {
+                            if identifiers.iter().any(|(k, _)| k == &key) {
                                 return Err(de::Error::custom(format_args!(
                                     "duplicate field `{}`",
                                     key
                                 )));
                             }
-                            let v: Yaml = map.next_value()?;
-                            identifiers.insert(
-                                key.to_string(),
-                                parser::parse_identifier(&v).map_err(|e| {
-                                    de::Error::custom(format!(
-                                        "failed to parse identifier - {:?}",
-                                        e
-                                    ))
-                                })?,
-                            );
-                            identifiers_raw.insert(key.to_string(), v.clone());
+                            identifiers.push((key, map.next_value()?));
                         }
                     }
                 }
-                let expression_raw =
-                    expression.ok_or_else(|| de::Error::missing_field("condition"))?;
-                let tokens = match expression_raw.tokenise() {
-                    Ok(tokens) => tokens,
-                    Err(err) => {
-                        return Err(de::Error::custom(format_args!(
-                            "invalid value: condition, failed to tokenise - {}",
-                            err
-                        )));
-                    }
-                };
+                let expression = expression.ok_or_else(|| de::Error::missing_field("condition"))?;
+                Detection::parse(expression, identifiers, options).map_err(|e| {
+                    de::Error::custom(format_args!("failed to parse detection - {:?}", e))
+                })
+            }
+        }
+        const FIELDS: &[&str] = &["identifiers", "condition"];
+        deserializer.deserialize_struct("Detection", FIELDS, DetectionVisitor)
+    }
+}
 
-                // Loop through the tokens making sure that all identifiers are present, this is a
-                // pain because we need to ignore fields... For now we can just check for misc
-                // symbol prefix and skip those if present
-                let mut i = 0;
-                for token in &tokens {
-                    if i > 1 {
-                        if let Token::Modifier(m) = &tokens[i - 2] {
-                            match m {
-                                ModSym::Int | ModSym::Not | ModSym::Str => {
-                                    i += 1;
-                                    continue;
+// Resolves the YAML merge keys, `<<`, within the value, where the keys of the mapping take
+// precedence over those merged into it, and earlier mappings over later ones when merging a
+// sequence of them.
+fn merge(value: Yaml) -> crate::Result<Yaml> {
+    match value {
+        Yaml::Mapping(mapping) => {
+            let mut merged = Mapping::new();
+            let mut sources = vec![];
+            for (k, v) in mapping {
+                let v = merge(v)?;
+                if k.as_str() == Some("<<") {
+                    match v {
+                        Yaml::Mapping(m) => sources.push(m),
+                        Yaml::Sequence(s) => {
+                            for m in s {
+                                match m {
+                                    Yaml::Mapping(m) => sources.push(m),
+                                    _ => {
+                                        return Err(crate::error::rule_invalid(Diagnostic::new(
+                                            "rule.invalid_merge",
+                                        )))
+                                    }
                                 }
                             }
                         }
-                    }
-                    if let Token::Identifier(id) = token {
-                        if !identifiers.contains_key(id) {
-                            return Err(de::Error::custom(format_args!(
-                                "invalid condition: identifier not found - {}",
-                                id
-                            )));
+                        _ => {
+                            return Err(crate::error::rule_invalid(Diagnostic::new(
+                                "rule.invalid_merge",
+                            )))
                         }
                     }
-                    i += 1;
+                } else {
+                    merged.insert(k, v);
                 }
-
-                let expression = match parser::parse(&tokens) {
-                    Ok(expression) => expression,
-                    Err(err) => {
-                        return Err(de::Error::custom(format_args!(
-                            "invalid value: condition, failed to parse - {}",
-                            err
-                        )));
+            }
+            for source in sources {
+                for (k, v) in source {
+                    if !merged.contains_key(&k) {
+                        merged.insert(k, v);
                     }
-                };
-                if !expression.is_solvable() {
-                    return Err(de::Error::custom(format_args!(
-                        "invalid value: condition, not solveable - {}",
-                        expression
-                    )));
                 }
-                Ok(Detection {
-                    expression,
-                    identifiers,
-                    expression_raw,
-                    identifiers_raw,
-                })
             }
+            Ok(Yaml::Mapping(merged))
         }
-        const FIELDS: &[&str] = &["identifiers", "condition"];
-        deserializer.deserialize_struct("Detection", FIELDS, DetectionVisitor)
+        Yaml::Sequence(sequence) => Ok(Yaml::Sequence(
+            sequence
+                .into_iter()
+                .map(merge)
+                .collect::<crate::Result<_>>()?,
+        )),
+        value => Ok(value),
     }
 }
 
 // TODO: Should probably just remove this and have an optimise on the Rule where we parse optimise
 // options...
 /// A `RuleLoader` can be used to create a `Rule` with custom configuration.
+///
+/// This is also the builder for the engine's optimisations, each of which can be turned off, i.e.
+/// on targets that can't afford the memory of the Aho-Corasick automatons, regex sets or DFAs:
+///
+/// ```
+/// use tau_engine::{Rule, RuleBuilder};
+///
+/// let builder: RuleBuilder = Rule::loader()
+///     .aho_corasick(false)
+///     .dfa(false)
+///     .regex_dfa_size_limit(1 << 16)
+///     .regex_set(false)
+///     .shake(false);
+/// let rule = builder.from_str(r#"
+/// detection:
+///   A:
+///     foo: [bar, baz]
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#);
+/// assert!(rule.is_ok());
+/// ```
+#[derive(Default)]
 pub struct RuleLoader {
+    bases: HashMap<String, Yaml>,
     coalesce: bool,
+    dir: Option<PathBuf>,
+    metadata: bool,
+    options: ParserOptions,
+    placeholders: Placeholders,
     rewrite: bool,
+    shadow: bool,
     shake: bool,
 }
 
-impl Default for RuleLoader {
-    fn default() -> Self {
-        Self {
-            coalesce: false,
-            rewrite: false,
-            shake: false,
-        }
-    }
-}
+/// The builder for configuring how a `Rule` is parsed and optimised, see `RuleLoader`.
+pub type RuleBuilder = RuleLoader;
 
 impl RuleLoader {
     /// Create a new loader for configuring how a Rule is loaded.
@@ -178,63 +463,232 @@ impl RuleLoader {
     }
 
     /// Loads the rule using the configuration set on the loader.
+    ///
+    /// Any base rule that is extended by path will be resolved relative to the rule's directory.
     pub fn load(self, path: &Path) -> crate::Result<Rule> {
         let contents = fs::read_to_string(path).map_err(crate::error::rule_invalid)?;
-        self.from_str(&contents)
+        let value: Yaml = serde_yaml::from_str(&contents).map_err(crate::error::rule_invalid)?;
+        let value = self.inherit(value, path.parent(), &mut vec![])?;
+        self.from_value(value)
+    }
+
+    /// Loads every rule in a file of `---` separated YAML documents using the configuration set on
+    /// the loader.
+    ///
+    /// Empty documents are skipped, and the error for a document that fails to load holds its
+    /// index within the file.
+    pub fn load_all(self, path: &Path) -> crate::Result<Vec<Rule>> {
+        let contents = fs::read_to_string(path).map_err(crate::error::rule_invalid)?;
+        self.documents(&contents, path.parent())
     }
 
     /// Loads the rule from a YAML string using the configuration set on the loader.
+    #[allow(clippy::should_implement_trait, clippy::wrong_self_convention)]
     pub fn from_str(self, s: &str) -> crate::Result<Rule> {
-        let rule: Rule = serde_yaml::from_str(s).map_err(crate::error::rule_invalid)?;
+        let value: Yaml = serde_yaml::from_str(s).map_err(crate::error::rule_invalid)?;
+        self.from_value(value)
+    }
+
+    /// Loads every rule in a string of `---` separated YAML documents using the configuration set
+    /// on the loader, see `load_all`.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_str_all(self, s: &str) -> crate::Result<Vec<Rule>> {
+        self.documents(s, None)
+    }
+
+    /// Loads the rule from a YAML string using the configuration set on the loader.
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_value(self, value: serde_yaml::Value) -> crate::Result<Rule> {
+        self.build(value)
+    }
+
+    fn documents(&self, s: &str, dir: Option<&Path>) -> crate::Result<Vec<Rule>> {
+        let mut rules = vec![];
+        for (index, document) in serde_yaml::Deserializer::from_str(s).enumerate() {
+            let value = Yaml::deserialize(document).map_err(crate::error::rule_invalid)?;
+            if value.is_null() {
+                continue;
+            }
+            let rule = self
+                .inherit(value, dir, &mut vec![])
+                .and_then(|value| self.build(value))
+                .map_err(|e| {
+                    crate::error::rule_invalid(
+                        Diagnostic::new("rule.invalid_document")
+                            .with("index", index)
+                            .with("error", e),
+                    )
+                })?;
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
+    fn build(&self, value: Yaml) -> crate::Result<Rule> {
+        let value = self.inherit(value, None, &mut vec![])?;
+        let value = self.placeholders.substitute(value);
+        if self.metadata {
+            if let Yaml::Mapping(mapping) = &value {
+                Metadata::read(mapping, true)?.check()?;
+            }
+        }
+        // NOTE: Serde reduces errors to messages, so when a rule fails to load its detection is
+        // parsed again to return the structured error, should the detection be at fault.
+        let detection = value.get("detection").cloned();
+        let rule: Rule = {
+            let _options = ScopedOptions::set(self.options.clone());
+            serde_yaml::from_value(value).map_err(|e| {
+                detection
+                    .and_then(|d| Detection::failure(d, &self.options))
+                    .unwrap_or_else(|| crate::error::rule_invalid(e))
+            })?
+        };
+        for (key, examples) in [
+            ("false_positives", &rule.false_positives),
+            ("true_negatives", &rule.true_negatives),
+            ("true_positives", &rule.true_positives),
+        ] {
+            if let Some(index) = examples.iter().position(|e| !e.is_mapping()) {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.invalid_example")
+                        .with("key", key)
+                        .with("index", index),
+                ));
+            }
+        }
         // FIXME: If we debug with these there will be confusion, as the raw values will be
         // incorrect.
         let mut detection = rule.detection;
+        if let Some(filter) = &rule.filter {
+            let filter = parser::parse_identifier_with(filter, &self.options).map_err(|e| {
+                crate::error::rule_invalid(
+                    Diagnostic::new("rule.invalid_filter").with("error", format!("{:?}", e)),
+                )
+            })?;
+            detection.expression = Expression::BooleanExpression(
+                Box::new(detection.expression),
+                BoolSym::And,
+                Box::new(Expression::Negate(Box::new(filter))),
+            );
+        }
+        for (identifier, weight) in &rule.weights {
+            if !detection.identifiers.contains_key(identifier) {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.weight_unknown_identifier")
+                        .with("identifier", identifier),
+                ));
+            }
+            if !weight.is_finite() || *weight < 0.0 {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.invalid_weight").with("weight", weight),
+                ));
+            }
+        }
+        let mut sequence = rule.sequence;
+        if let Some(sequence) = &mut sequence {
+            if rule.correlate.is_some() {
+                return Err(crate::error::rule_invalid(Diagnostic::new(
+                    "rule.correlation_and_sequence",
+                )));
+            }
+            sequence.resolve(&detection.identifiers)?;
+        }
         if self.coalesce {
             detection.expression =
                 optimiser::coalesce(detection.expression, &detection.identifiers);
             detection.identifiers.clear();
         }
         if self.shake {
-            detection.expression = optimiser::shake(detection.expression, self.rewrite);
+            let matched = optimiser::matched(&detection.expression);
+            detection.expression =
+                optimiser::shake_with(detection.expression, self.rewrite, &self.options);
             detection.identifiers = detection
                 .identifiers
                 .into_iter()
-                .map(|(k, v)| (k, optimiser::shake(v, self.rewrite)))
+                .map(|(k, v)| {
+                    let v = if matched.contains(&k) {
+                        optimiser::shake_match(v, self.rewrite, &self.options)
+                    } else {
+                        optimiser::shake_with(v, self.rewrite, &self.options)
+                    };
+                    (k, v)
+                })
                 .collect();
+            if let Some(sequence) = &mut sequence {
+                sequence.map(|e| optimiser::shake_with(e, self.rewrite, &self.options));
+            }
         }
-        Ok(Rule {
-            detection,
-            true_negatives: rule.true_negatives,
-            true_positives: rule.true_positives,
-        })
-    }
-
-    /// Loads the rule from a YAML string using the configuration set on the loader.
-    pub fn from_value(self, value: serde_yaml::Value) -> crate::Result<Rule> {
-        let rule: Rule = serde_yaml::from_value(value).map_err(crate::error::rule_invalid)?;
-        // FIXME: If we debug with these there will be confusion, as the raw values will be
-        // incorrect.
-        let mut detection = rule.detection;
-        if self.coalesce {
-            detection.expression =
-                optimiser::coalesce(detection.expression, &detection.identifiers);
-            detection.identifiers.clear();
-        }
-        if self.shake {
-            detection.expression = optimiser::shake(detection.expression, self.rewrite);
+        if self.shadow {
+            detection.expression = optimiser::shadow_with(detection.expression, &self.options);
             detection.identifiers = detection
                 .identifiers
                 .into_iter()
-                .map(|(k, v)| (k, optimiser::shake(v, self.rewrite)))
+                .map(|(k, v)| (k, optimiser::shadow_with(v, &self.options)))
                 .collect();
+            if let Some(sequence) = &mut sequence {
+                sequence.map(|e| optimiser::shadow_with(e, &self.options));
+            }
         }
+        self.options
+            .complexity
+            .check(&detection.expression, &detection.identifiers)?;
         Ok(Rule {
+            action: rule.action,
+            actions: rule.actions,
+            correlate: rule.correlate,
             detection,
+            false_positives: rule.false_positives,
+            filter: rule.filter,
+            metadata: rule.metadata,
+            routing: rule.routing,
+            sequence,
+            severity: rule.severity,
             true_negatives: rule.true_negatives,
             true_positives: rule.true_positives,
+            weights: rule.weights,
         })
     }
 
+    /// Register a base rule that other rules can extend by its id.
+    ///
+    /// Bases are only used to resolve `extends`, they are never loaded as rules themselves, and
+    /// so they need not be complete. When a rule extends a value that is not a registered id, it
+    /// is treated as a path to the base rule instead, see `RuleLoader::base_dir`.
+    pub fn base<S: Into<String>>(mut self, id: S, rule: Yaml) -> Self {
+        self.bases.insert(id.into(), rule);
+        self
+    }
+
+    /// Set the directory that bases extended by path are resolved relative to, for rules that are
+    /// not loaded from a file.
+    ///
+    /// Rules loaded with `load` resolve such bases relative to their own directory instead. Paths
+    /// are never resolved relative to the working directory, so without this a rule loaded from a
+    /// string can only extend the bases registered with `RuleLoader::base`.
+    pub fn base_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Set the limits on the complexity of the rule, see `Complexity`.
+    ///
+    /// A rule that exceeds any of the limits will fail to load. By default nothing is limited.
+    pub fn complexity(mut self, complexity: Complexity) -> Self {
+        self.options.complexity = complexity;
+        self
+    }
+
+    /// Allow Tau to merge literal searches on the same field into a single Aho-Corasick automaton.
+    ///
+    /// When disabled each literal is searched for on its own, which is slower for long lists of
+    /// needles but avoids building the automatons.
+    ///
+    /// This option is enabled by default.
+    pub fn aho_corasick(mut self, yes: bool) -> Self {
+        self.options.aho_corasick = yes;
+        self
+    }
+
     /// Allow Tau to coalesce the identifier's expressions into the condition.
     ///
     /// This allows for the identifier's expressions to be embedded for increased speed at the cost
@@ -246,6 +700,114 @@ impl RuleLoader {
         self
     }
 
+    /// Allow Tau to coerce values when comparing fields against numbers.
+    ///
+    /// Integer comparisons, i.e. `port: 443` or `status: 200..299`, are evaluated as if the field
+    /// had been cast with `int`, so that numeric strings and booleans are compared by their value
+    /// rather than never matching, and arrays are compared element by element. Float comparisons
+    /// and those that are already cast are left as written.
+    ///
+    /// This option is disabled by default.
+    pub fn coerce(mut self, yes: bool) -> Self {
+        self.options.coerce = yes;
+        self
+    }
+
+    /// Allow Tau to build Aho-Corasick automatons as DFAs.
+    ///
+    /// A DFA is faster to search with, but can use many times the memory of the NFA that is built
+    /// otherwise, which matters for rules with many needles on memory constrained targets.
+    ///
+    /// This option is enabled by default.
+    pub fn dfa(mut self, yes: bool) -> Self {
+        self.options.dfa = yes;
+        self
+    }
+
+    /// Allow Tau to parse values prefixed with `~` as fuzzy searches.
+    ///
+    /// When disabled such values are matched as literals, as they were before fuzzy searches
+    /// existed.
+    ///
+    /// This option is disabled by default.
+    pub fn fuzzy(mut self, yes: bool) -> Self {
+        self.options.fuzzy = yes;
+        self
+    }
+
+    /// Allow Tau to parse values prefixed with `^` as globs.
+    ///
+    /// When disabled such values are matched as literals, as they were before globs existed.
+    ///
+    /// This option is disabled by default.
+    pub fn glob(mut self, yes: bool) -> Self {
+        self.options.glob = yes;
+        self
+    }
+
+    /// Allow Tau to parse values such as `{ 4D 5A }` as hex patterns.
+    ///
+    /// When disabled such values are matched as literals, as they were before hex patterns
+    /// existed.
+    ///
+    /// This option is disabled by default.
+    pub fn hex(mut self, yes: bool) -> Self {
+        self.options.hex = yes;
+        self
+    }
+
+    /// Set a named set of strings that values can be tested for membership of.
+    ///
+    /// Lookups are referenced in identifiers as `@name`, and match when the value is in the set,
+    /// with the test taking constant time regardless of the size of the set. This makes them
+    /// suitable for large lists, such as known bad hashes, that would be slow to load as exact
+    /// matches. When the lookup is made case insensitive, i.e. `i@name`, the value is lowercased
+    /// before the test, so the set should only contain lowercase strings.
+    ///
+    /// As the set is shared, the same set can be given to many loaders without being copied.
+    /// Until a lookup is set, values prefixed with `@` are matched as literals, as they were
+    /// before lookups existed.
+    pub fn lookup<S: Into<String>>(mut self, name: S, set: Arc<HashSet<String>>) -> Self {
+        self.options.lookups.insert(name.into(), set);
+        self
+    }
+
+    /// Allow Tau to check the rule's metadata when loaded.
+    ///
+    /// When enabled a rule whose metadata has a value of the wrong type, an empty `id`, `title` or
+    /// tag, or a duplicated tag, fails to load. When disabled values of the wrong type are skipped,
+    /// as rules could hold any top level keys before metadata was read from them.
+    ///
+    /// This option is disabled by default.
+    pub fn metadata(mut self, yes: bool) -> Self {
+        self.metadata = yes;
+        self
+    }
+
+    /// Set the values for a placeholder that can be used in the rule's identifiers.
+    ///
+    /// Placeholders are written as `%name%` and are substituted when the rule is loaded. A value
+    /// that is only a placeholder expands into a list of exact matches, i.e. `user: '%admins%'`
+    /// matches any of the admins, otherwise each value is substituted into the string, so that
+    /// `*%domain%` matches strings ending with any of the domains. Placeholders that are not set
+    /// are left as is, so that values such as `%SystemRoot%` need no escaping.
+    pub fn placeholder<S: Into<String>>(mut self, name: S, values: Vec<String>) -> Self {
+        self.placeholders.values.insert(name.into(), values);
+        self
+    }
+
+    /// Set a provider for the values of placeholders that have not been set with `placeholder`.
+    ///
+    /// The provider is called with the name of every placeholder that is encountered, returning
+    /// `None` if it is unknown. See `placeholder` for how the values are substituted.
+    pub fn placeholders<F>(mut self, provider: F) -> Self
+    where
+        F: Fn(&str) -> Option<Vec<String>> + Send + Sync + 'static,
+    {
+        self.placeholders.provider = Some(Box::new(provider));
+        self
+    }
+
     /// Allow Tau to rewrite inefficient string searches.
     ///
     /// This option is disabled by default. This option is only applied if shaking is enabled.
@@ -254,6 +816,82 @@ impl RuleLoader {
         self
     }
 
+    /// Set the dialect that the rule is written in.
+    ///
+    /// This allows for rules written in the legacy syntax to be loaded alongside those written in
+    /// the current syntax, so that they can be migrated gradually.
+    ///
+    /// This option is set to `Dialect::Current` by default.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.options.dialect = dialect;
+        self
+    }
+
+    /// Set alternative spellings for the keywords used in conditions.
+    ///
+    /// As aliases can change the meaning of a condition, all rules that are to be used together
+    /// should be loaded with the same keywords.
+    pub fn keywords(mut self, keywords: Keywords) -> Self {
+        self.options.keywords = keywords;
+        self
+    }
+
+    /// Allow Tau to parse values such as `1..10` as integer ranges.
+    ///
+    /// When disabled such values are matched as literals, as they were before ranges existed.
+    ///
+    /// This option is disabled by default.
+    pub fn range(mut self, yes: bool) -> Self {
+        self.options.range = yes;
+        self
+    }
+
+    /// Allow Tau to merge regexes on the same field into a single regex set.
+    ///
+    /// Regexes that would exceed the size limits once merged are always left apart.
+    ///
+    /// This option is enabled by default.
+    pub fn regex_set(mut self, yes: bool) -> Self {
+        self.options.regex_set = yes;
+        self
+    }
+
+    /// Set the approximate size limit, in bytes, of the cache used by each regex's lazy DFA.
+    ///
+    /// When the limit is hit the regex falls back to a slower engine rather than consuming more
+    /// memory. By default the `regex` crate's limit is used.
+    pub fn regex_dfa_size_limit(mut self, bytes: usize) -> Self {
+        self.options.regex_dfa_size_limit = Some(bytes);
+        self
+    }
+
+    /// Set the approximate size limit, in bytes, of each compiled regex.
+    ///
+    /// A rule containing a regex that exceeds this limit will fail to load. By default the `regex`
+    /// crate's limit is used.
+    pub fn regex_size_limit(mut self, bytes: usize) -> Self {
+        self.options.regex_size_limit = Some(bytes);
+        self
+    }
+
+    /// Allow Tau to evaluate case insensitive string searches against lowercase shadows of fields.
+    ///
+    /// Rather than folding case on every comparison, shadowed searches compare lowercase needles
+    /// against a lowercase copy of the field. When documents are wrapped in a `CachedDocument`, as
+    /// they are by `RuleSet`, the copy is made on first access and then shared by every shadowed
+    /// search for that document, trading one allocation for many comparisons. This pays off when
+    /// many rules search the same fields insensitively.
+    ///
+    /// NOTE: Shadowed searches fold case using Unicode rather than ASCII rules, which only changes
+    /// the result for ASCII needles against values containing characters such as the Kelvin sign,
+    /// as needles containing non-ASCII characters are always shadowed.
+    ///
+    /// This option is disabled by default.
+    pub fn shadow(mut self, yes: bool) -> Self {
+        self.shadow = yes;
+        self
+    }
+
     /// Allow Tau to optimise the rule when loaded.
     ///
     /// This option is disabled by default.
@@ -261,17 +899,234 @@ impl RuleLoader {
         self.shake = yes;
         self
     }
-}
 
-/// A rule used by the solver to evaluate a `Document`.
-///
-/// A rule contains the detection logic, along with the true positive and negative tests. The
-/// inclusion of these basic test allows for a basic level of verification to be ensured.
-///
-/// Rules are written in YAML and have a simple but powerful syntax.
-///
-/// # Syntax
-///
+    // Resolves the base that the rule extends, if any, returning the merged rule. This is done on
+    // the raw YAML so that the merged rule is parsed, and optimised, as if it were written out.
+    fn inherit(
+        &self,
+        value: Yaml,
+        dir: Option<&Path>,
+        seen: &mut Vec<String>,
+    ) -> crate::Result<Yaml> {
+        let mut rule = match merge(value)? {
+            Yaml::Mapping(rule) => rule,
+            value => return Ok(value),
+        };
+        let id = match rule.remove(&Yaml::String("extends".to_owned())) {
+            Some(Yaml::String(id)) => id,
+            Some(_) => {
+                return Err(crate::error::rule_invalid(Diagnostic::new(
+                    "rule.extends_not_string",
+                )))
+            }
+            None => return Ok(Yaml::Mapping(rule)),
+        };
+        if seen.contains(&id) {
+            return Err(crate::error::rule_invalid(
+                Diagnostic::new("rule.extends_itself").with("id", id),
+            ));
+        }
+        let (base, dir) = match self.bases.get(&id) {
+            Some(base) => (base.clone(), dir.map(Path::to_path_buf)),
+            None => {
+                let path = match dir.or(self.dir.as_deref()) {
+                    Some(dir) => dir.join(&id),
+                    None => {
+                        return Err(crate::error::rule_invalid(
+                            Diagnostic::new("rule.extends_unknown").with("id", id),
+                        ))
+                    }
+                };
+                let contents = fs::read_to_string(&path).map_err(|e| {
+                    crate::error::rule_invalid(
+                        Diagnostic::new("rule.extends_unreadable")
+                            .with("id", &id)
+                            .with("error", e),
+                    )
+                })?;
+                let base = serde_yaml::from_str(&contents).map_err(crate::error::rule_invalid)?;
+                (base, path.parent().map(Path::to_path_buf))
+            }
+        };
+        seen.push(id);
+        let mut base = match self.inherit(base, dir.as_deref(), seen)? {
+            Yaml::Mapping(base) => base,
+            _ => {
+                return Err(crate::error::rule_invalid(Diagnostic::new(
+                    "rule.extends_not_mapping",
+                )))
+            }
+        };
+        // NOTE: The detection is merged by key, so that identifiers and the condition can be
+        // overridden or added individually, everything else is replaced wholesale.
+        for (k, v) in rule {
+            match (base.get_mut(&k), v) {
+                (Some(Yaml::Mapping(detection)), Yaml::Mapping(overrides))
+                    if k.as_str() == Some("detection") =>
+                {
+                    for (k, v) in overrides {
+                        detection.insert(k, v);
+                    }
+                }
+                (_, v) => {
+                    base.insert(k, v);
+                }
+            }
+        }
+        Ok(Yaml::Mapping(base))
+    }
+}
+
+/// The severity of a rule, ordered from least to most severe.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Informational,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// The maturity of a rule.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Experimental,
+    Test,
+    Stable,
+    Deprecated,
+    Unsupported,
+}
+
+/// The descriptive metadata of a rule.
+///
+/// Metadata has no effect on evaluation, it is carried with the rule so that the context for an
+/// alert can be taken from a match, see `RuleSet::route`, rather than by parsing the rule's YAML
+/// again. The fields are read from the top level of the rule:
+///
+/// ```text
+/// id: 5f2c6f4e-3a7b-4d8e-9c1a-2b3d4e5f6a7b
+/// title: Encoded PowerShell
+/// status: stable
+/// tags:
+/// - attack.execution
+/// extra:
+///   author: someone
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    /// The rule's own identifier, which is independent of its id within a `RuleSet`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// A short, human readable, description of what the rule detects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The maturity of the rule.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+    /// The tags used to categorise the rule, i.e. `attack.execution`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Any other metadata, which is carried verbatim for the consumer to interpret.
+    #[serde(default, skip_serializing_if = "Mapping::is_empty")]
+    pub extra: Mapping,
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // NOTE: Rules could hold any top level keys before metadata was read from them, so values
+        // of the wrong type are skipped rather than failing the rule, see `RuleLoader::metadata`.
+        let mapping = Mapping::deserialize(deserializer)?;
+        Ok(Metadata::read(&mapping, false).unwrap_or_default())
+    }
+}
+
+impl Metadata {
+    // Reads the metadata from the top level of a rule, where a value of the wrong type is an error
+    // when strict and is skipped otherwise.
+    fn read(mapping: &Mapping, strict: bool) -> crate::Result<Self> {
+        let invalid = |field: &str, expected: &str| {
+            if strict {
+                Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.invalid_metadata")
+                        .with("field", field)
+                        .with("expected", expected),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        let mut metadata = Metadata::default();
+        for (field, value) in [("id", &mut metadata.id), ("title", &mut metadata.title)] {
+            match mapping.get(&Yaml::from(field)) {
+                Some(Yaml::String(s)) => *value = Some(s.clone()),
+                Some(_) => invalid(field, "a string")?,
+                None => {}
+            }
+        }
+        if let Some(status) = mapping.get(&Yaml::from("status")) {
+            match serde_yaml::from_value(status.clone()) {
+                Ok(status) => metadata.status = Some(status),
+                Err(_) => invalid(
+                    "status",
+                    "one of experimental, test, stable, deprecated or unsupported",
+                )?,
+            }
+        }
+        if let Some(tags) = mapping.get(&Yaml::from("tags")) {
+            match serde_yaml::from_value(tags.clone()) {
+                Ok(tags) => metadata.tags = tags,
+                Err(_) => invalid("tags", "a sequence of strings")?,
+            }
+        }
+        match mapping.get(&Yaml::from("extra")) {
+            Some(Yaml::Mapping(extra)) => metadata.extra = extra.clone(),
+            Some(_) => invalid("extra", "a mapping")?,
+            None => {}
+        }
+        Ok(metadata)
+    }
+
+    fn check(&self) -> crate::Result<()> {
+        for (field, value) in [("id", &self.id), ("title", &self.title)] {
+            if let Some(value) = value {
+                if value.trim().is_empty() {
+                    return Err(crate::error::rule_invalid(
+                        Diagnostic::new("rule.empty_metadata").with("field", field),
+                    ));
+                }
+            }
+        }
+        let mut tags = HashSet::new();
+        for tag in &self.tags {
+            if tag.trim().is_empty() {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.empty_metadata").with("field", "tags"),
+                ));
+            }
+            if !tags.insert(tag) {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("rule.duplicate_tag").with("tag", tag),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A rule used by the solver to evaluate a `Document`.
+///
+/// A rule contains the detection logic, along with the true positive and negative tests. The
+/// inclusion of these basic test allows for a basic level of verification to be ensured.
+///
+/// Rules are written in YAML and have a simple but powerful syntax.
+///
+/// # Syntax
+///
 /// There are two parts to a rule's logic: the condition & the identifiers.
 ///
 /// ## Condition
@@ -398,6 +1253,27 @@ impl RuleLoader {
 ///             </td>
 ///         </tr>
 ///         <tr>
+///             <td>_ <code>contains</code> _</td>
+///             <td>
+///                 <span>True if the left operand contains the right, where both operands are
+///                 <code>str(field)</code>, a field that should be cast as a string.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td>_ <code>endswith</code> _</td>
+///             <td>
+///                 <span>True if the left operand ends with the right, where both operands are
+///                 <code>str(field)</code>, a field that should be cast as a string.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td>_ <code>startswith</code> _</td>
+///             <td>
+///                 <span>True if the left operand starts with the right, where both operands are
+///                 <code>str(field)</code>, a field that should be cast as a string.</span>
+///             </td>
+///         </tr>
+///         <tr>
 ///             <td><code>all(i)</code></td>
 ///             <td>
 ///                 <span>An identifier mutator that evaluates to true only if all conditions for identifier <code>i</code> match.</span>
@@ -420,6 +1296,14 @@ impl RuleLoader {
 ///     </tbody>
 /// </table>
 ///
+/// The comparisons, `==`, `>`, `>=`, `<` and `<=`, also accept the following operands:
+///
+/// - `len(field)`: the length of a field, in characters for a string or items for an array.
+/// - `str(field)`: a field that should be cast as a string, which the ordering comparisons
+///   compare lexicographically.
+/// - `timestamp`: an RFC 3339 timestamp, i.e. `2024-01-01T00:00:00Z`.
+/// - `time(field)`: a field that should be cast as a timestamp.
+///
 /// # Identifiers
 ///
 /// Identifiers are used to describe the matching logic for the values contained within documents.
@@ -453,6 +1337,10 @@ impl RuleLoader {
 ///
 /// Identifiers are unique keys that can be referenced in the `condition`.
 ///
+/// When the value of a nested key is an array, the nested pairs match if any element of the array
+/// satisfies them, and so negating them with `not(k)`, or with `not` in the condition, means that
+/// no element satisfies them.
+///
 /// Keys are used to get the values from documents. Keys can be wrapped in the following modifiers:
 ///
 /// <table>
@@ -472,7 +1360,43 @@ impl RuleLoader {
 ///         <tr>
 ///             <td><code style="white-space:nowrap">of(k, x)</code></td>
 ///             <td>
-///                 <span>A key mutator that evaluates to true only if a minimum of <code>x</code> matches for key <code>k</code> match.</span>
+///                 <span>A key mutator that evaluates to true only if a minimum of <code>x</code> matches for key <code>k</code> match. For nested pairs it counts the elements of an array that satisfy them, where <code>of(k, 0)</code> means that no element does.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>len(k)</code></td>
+///             <td>
+///                 <span>A key mutator that compares the length of the values for key <code>k</code>, in characters for strings or items for arrays, where the matches are integers optionally prefixed by one of <code>=</code>, <code>&gt</code>, <code>&gt=</code>, <code>&lt</code> or <code>&lt=</code>.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>not(k)</code></td>
+///             <td>
+///                 <span>A key mutator that negates the matches for key <code>k</code>, so that it evaluates to true only if none of them match. For nested pairs this means that no element of an array satisfies them.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>skeleton(k)</code></td>
+///             <td>
+///                 <span>A key mutator that maps confusable characters in both the matches and the values for key <code>k</code> to their ASCII skeleton before matching, so that spoofed values match, i.e. a Cyrillic <code>а</code> matches <code>a</code>. Full-width letters are mapped to ASCII and invisible characters, such as zero-width joiners, are removed. Only the literal text of a match is mapped, once its pattern has been parsed, so a full-width <code>＊</code> is matched as a <code>*</code> rather than as a wildcard, and regexes are matched as written.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>time(k)</code></td>
+///             <td>
+///                 <span>A key mutator that casts the values for key <code>k</code> as timestamps, where the matches are an RFC 3339 timestamp optionally prefixed by one of <code>=</code>, <code>&gt</code>, <code>&gt=</code>, <code>&lt</code> or <code>&lt=</code>, or a range <code>a..b</code> which is inclusive of both ends.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>wide(k)</code></td>
+///             <td>
+///                 <span>A key mutator that encodes the literal matches for key <code>k</code> as UTF-16LE, so that Windows wide strings can be found within byte values at any offset, i.e. registry or memory blobs. String values are encoded in the same way before matching, so matches within them must start on a character. Matches with the <code>i</code> prefix must be ASCII.</span>
+///             </td>
+///         </tr>
+///         <tr>
+///             <td><code>windash(k)</code></td>
+///             <td>
+///                 <span>A key mutator that expands any flags in the matches for key <code>k</code> so that they match when prefixed by any of <code>-</code>, <code>/</code>, <code>–</code>, <code>—</code> or <code>―</code>. At most five flags can be expanded in each match.</span>
 ///             </td>
 ///         </tr>
 ///     </tbody>
@@ -510,14 +1434,159 @@ impl RuleLoader {
 ///             <td><span>Regex</span></td>
 ///         </tr>
 ///         <tr>
+///             <td><code>~foo</code></td>
+///             <td><span>Fuzzy, within a Damerau-Levenshtein distance of 1, or of <code>n</code>, which is at most 3, when written as <code>~n~foo</code>, see <code>RuleLoader::fuzzy</code></span></td>
+///         </tr>
+///         <tr>
+///             <td><code>^foo/**/*.txt</code></td>
+///             <td><span>Glob, matching the whole value where <code>?</code> and <code>*</code> do not cross path separators but <code>**</code> does, and <code>[abc]</code> matches a character class, see <code>RuleLoader::glob</code></span></td>
+///         </tr>
+///         <tr>
+///             <td><code>@foo</code></td>
+///             <td><span>In the lookup named <code>foo</code>, see <code>RuleLoader::lookup</code></span></td>
+///         </tr>
+///         <tr>
+///             <td><code>{ 4D 5A ?? }</code></td>
+///             <td><span>Hex, contains the bytes where <code>?</code> is a wildcard nibble, evaluated against the raw bytes of byte values or the UTF-8 bytes of strings, see <code>RuleLoader::hex</code></span></td>
+///         </tr>
+///         <tr>
+///             <td><code>1..10</code></td>
+///             <td><span>An integer within the range, which is inclusive of both ends, see <code>RuleLoader::range</code></span></td>
+///         </tr>
+///         <tr>
 ///             <td><code>i</code>_</td>
-///             <td><span>A prefix to convert the match into a case insensitive match.</span></td>
+///             <td><span>A prefix to convert the match into a case insensitive match, which folds case using Unicode rules for every type of match.</span></td>
 ///         </tr>
 ///     </tbody>
 /// </table>
 ///
 /// To escape any of the above in order to achieve literal string matching, combinations of `'` and `"` can be used.
 ///
+/// A sequence of integers, such as `status: [400, 401, 403]`, is evaluated as a single membership
+/// test rather than as a disjunction of equality checks.
+///
+/// When a field cast with `int` holds an array, i.e. `int(ports): '>1024'`, a numeric comparison
+/// holds if any of its elements satisfy it, while wrapping the key in `every`, i.e.
+/// `every(ports): '>1024'`, requires all of them to, and never holds for an empty array. Numeric
+/// strings are only compared by their value when cast with `int`, or when the rule is loaded with
+/// `RuleLoader::coerce`.
+///
+/// ## Filter
+///
+/// A rule can optionally declare a `filter` block, written in the same way as an identifier, to
+/// suppress matches that are known to be benign. The filter is combined with the condition when
+/// the rule is loaded, exactly as if `and not filter` had been appended to the condition, so it
+/// is optimised along with the rest of the detection.
+///
+/// ```text
+/// detection:
+///   A:
+///     process: "*\\cmd.exe"
+///
+///   condition: A
+///
+/// filter:
+///   - user: SYSTEM
+///   - parent: "*\\services.exe"
+/// ```
+///
+/// ## Correlation
+///
+/// A rule can optionally declare a `correlate` key, such as `count() by host.name > 5 within 10m`,
+/// in which case it only matches once its detection has matched enough documents within the
+/// window. See `Correlation` for the syntax and `Correlator` for how these rules are solved.
+///
+/// Similarly, a rule can declare a `sequence` key, in which case it only matches once documents
+/// have matched a series of its identifiers in order. See `Sequence` for the syntax.
+///
+/// ## Severity
+///
+/// A rule can optionally declare a `severity` key, one of `informational`, `low`, `medium`,
+/// `high` or `critical`, which allows a `RuleSet` to evaluate its most severe rules first, see
+/// `RuleSet::first_match_at_least`.
+///
+/// ## Metadata
+///
+/// A rule can optionally declare descriptive metadata: an `id`, a `title`, a `status`, one of
+/// `experimental`, `test`, `stable`, `deprecated` or `unsupported`, a list of `tags` and an
+/// `extra` mapping for anything else. The metadata is only checked when the rule is loaded with
+/// `RuleLoader::metadata`, and is carried into the results of `RuleSet::route`, see `Metadata`.
+///
+/// ## Action
+///
+/// A rule can optionally declare an `action` key, one of `allow`, `alert` or `deny`, which is the
+/// action taken when it decides the verdict of a `Policy`.
+///
+/// ## Routing
+///
+/// A rule can optionally declare a `routing` key, holding any metadata that downstream
+/// orchestration needs in order to deliver a match, such as where to send notifications, and an
+/// `actions` key, holding the response to take, such as whether to contain the host. The engine
+/// does not interpret either, they are carried verbatim into the results of `RuleSet::route` and
+/// `Policy::evaluate`.
+///
+/// ```text
+/// routing:
+///   notify: "#soc-alerts"
+/// actions:
+///   - contain: true
+///   - playbook: isolate-host
+/// ```
+///
+/// ## Weights
+///
+/// A rule can optionally declare a `weights` mapping, from identifiers to positive numbers, which
+/// is used when scoring how closely a document matched, see `Rule::score`. Identifiers without a
+/// weight default to `1.0`.
+///
+/// ```text
+/// detection:
+///   A:
+///     process: "*\\cmd.exe"
+///   B:
+///     command_line: "*whoami*"
+///
+///   condition: A and B
+///
+/// weights:
+///   B: 3
+/// ```
+///
+/// ## Examples
+///
+/// A rule must declare `true_positives` and `true_negatives`, lists of example documents that the
+/// rule must and must not match respectively, and can optionally declare `false_positives`,
+/// documents that once matched in error and so must not match again. Every example must be a
+/// mapping. The examples are checked by `Rule::validate`, which turns each rule into its own
+/// regression test.
+///
+/// Unlike Sigma's `falsepositives`, which describes benign activity in prose, false positives are
+/// documents that the rule is evaluated against, so they are not carried over when converting
+/// Sigma rules, see `SigmaConverter`.
+///
+/// ```text
+/// false_positives:
+/// - process: C:\Windows\System32\cmd.exe
+///   command_line: cmd.exe /c echo whoami
+/// ```
+///
+/// ## Inheritance
+///
+/// A rule can optionally declare an `extends` key, naming a base rule by either its id, as
+/// registered with `RuleLoader::base`, or its path, relative to the rule's file or to
+/// `RuleLoader::base_dir`. The rule is merged into its base before it is
+/// parsed: identifiers and the condition override those of the same name in the base, new
+/// identifiers are added, and any other key replaces the base's entirely. Bases can themselves
+/// extend other bases.
+///
+/// ```text
+/// extends: base/cmd.yml
+///
+/// detection:
+///   A:
+///     process: "*\\powershell.exe"
+/// ```
+///
 /// # Examples
 ///
 /// Here is a very simple rule example:
@@ -565,13 +1634,52 @@ impl RuleLoader {
 /// - foo: the quick brown BEAR
 /// ```
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
 pub struct Rule {
     pub detection: Detection,
+    #[serde(flatten)]
+    pub(crate) metadata: Metadata,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) action: Option<Action>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) actions: Option<Yaml>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) correlate: Option<Correlation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) false_positives: Vec<Yaml>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) filter: Option<Yaml>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) routing: Option<Yaml>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) sequence: Option<Sequence>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) severity: Option<Severity>,
     pub true_positives: Vec<Yaml>,
     pub true_negatives: Vec<Yaml>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub(crate) weights: HashMap<String, f64>,
 }
 
 impl Rule {
+    /// Returns a commented YAML scaffold for a new rule of the given kind.
+    ///
+    /// The scaffolds share the standard metadata and identifier layout, with the event type, the
+    /// activity and its exclusions in separate identifiers, and they load and validate as is.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, Template};
+    ///
+    /// let scaffold = Rule::template(Template::ProcessCreation);
+    /// let rule = Rule::from_str(scaffold).unwrap();
+    /// assert!(rule.validate().unwrap());
+    /// ```
+    pub fn template(kind: Template) -> &'static str {
+        kind.scaffold()
+    }
+
     /// Creates a `RuleLoader` to configure a `Rule`.
     ///
     /// This is the same as `RuleLoader::new()`.
@@ -584,16 +1692,90 @@ impl Rule {
         RuleLoader::new().load(path)
     }
 
+    /// Load every rule from a YAML file of `---` separated documents.
+    pub fn load_all(path: &Path) -> crate::Result<Vec<Self>> {
+        RuleLoader::new().load_all(path)
+    }
+
     /// Load a rule from a YAML string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> crate::Result<Self> {
         RuleLoader::new().from_str(s)
     }
 
+    /// Load every rule from a YAML string of `---` separated documents.
+    pub fn from_str_all(s: &str) -> crate::Result<Vec<Self>> {
+        RuleLoader::new().from_str_all(s)
+    }
+
     /// Load a rule from a YAML Value.
     pub fn from_value(value: serde_yaml::Value) -> crate::Result<Self> {
         RuleLoader::new().from_value(value)
     }
 
+    /// Get a node in the rule's detection by its id.
+    pub fn node(&self, id: &str) -> Option<&Node> {
+        self.nodes().iter().find(|n| n.id == id)
+    }
+
+    /// Returns the addressable nodes within the rule's detection.
+    ///
+    /// The nodes are collected on first use and then cached. See `Node` for details on how nodes
+    /// are identified.
+    pub fn nodes(&self) -> &[Node] {
+        self.detection.nodes()
+    }
+
+    /// Returns the policy action for the rule, see `Policy`.
+    pub fn action(&self) -> Option<Action> {
+        self.action
+    }
+
+    /// Returns the actions carried alongside the rule's routing, see `Routed`.
+    pub fn actions(&self) -> Option<&Yaml> {
+        self.actions.as_ref()
+    }
+
+    /// Returns the rule's correlation, if it has one.
+    pub fn correlate(&self) -> Option<&Correlation> {
+        self.correlate.as_ref()
+    }
+
+    /// Returns the documents that are known to match but are not wanted.
+    pub fn false_positives(&self) -> &[Yaml] {
+        &self.false_positives
+    }
+
+    /// Returns the rule's filter, as it was written, if it has one.
+    pub fn filter(&self) -> Option<&Yaml> {
+        self.filter.as_ref()
+    }
+
+    /// Returns the rule's metadata.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Returns the rule's routing metadata, if it has any.
+    pub fn routing(&self) -> Option<&Yaml> {
+        self.routing.as_ref()
+    }
+
+    /// Returns the rule's sequence, if it has one.
+    pub fn sequence(&self) -> Option<&Sequence> {
+        self.sequence.as_ref()
+    }
+
+    /// Returns the rule's severity, if it has one.
+    pub fn severity(&self) -> Option<Severity> {
+        self.severity
+    }
+
+    /// Returns the weights of the rule's identifiers, see `Rule::score`.
+    pub fn weights(&self) -> &HashMap<String, f64> {
+        &self.weights
+    }
+
     /// Allow Tau to optimise the rule when loaded.
     ///
     /// # Options
@@ -605,43 +1787,150 @@ impl Rule {
                 optimiser::coalesce(self.detection.expression, &self.detection.identifiers);
             self.detection.identifiers.clear();
         }
-        self.detection.expression = optimiser::shake(self.detection.expression, rewrite);
+        self.detection.plan = OnceLock::new();
+        let options = &self.detection.options;
+        let matched = optimiser::matched(&self.detection.expression);
+        self.detection.expression =
+            optimiser::shake_with(self.detection.expression, rewrite, options);
         self.detection.identifiers = self
             .detection
             .identifiers
             .into_iter()
-            .map(|(k, v)| (k, optimiser::shake(v, rewrite)))
+            .map(|(k, v)| {
+                let v = if matched.contains(&k) {
+                    optimiser::shake_match(v, rewrite, options)
+                } else {
+                    optimiser::shake_with(v, rewrite, options)
+                };
+                (k, v)
+            })
             .collect();
         self
     }
 
+    /// Returns what the rule's condition compiled into, after any optimisations, as an indented
+    /// tree with its identifiers resolved.
+    ///
+    /// This is intended for rule authors, to check that a condition means what they think it
+    /// does, and the format is not stable.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::Rule;
+    ///
+    /// let rule = Rule::from_str(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: not A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap();
+    ///
+    /// assert_eq!(
+    ///     rule.explain(),
+    ///     "condition: not A\nnot\n  identifier: A\n    search: foo exact(bar)\n"
+    /// );
+    /// ```
+    pub fn explain(&self) -> String {
+        format!(
+            "condition: {}\n{}",
+            self.detection.expression_raw,
+            self.detection
+                .expression
+                .to_pretty_string_with(&self.detection.identifiers)
+        )
+    }
+
+    /// Compiles the rule's detection into a `Program`, for faster evaluation of large rules.
+    pub fn compile(&self) -> Program {
+        Program::compile(&self.detection)
+    }
+
     /// Evaluates the rule against the provided `Document`, returning true if it has matched.
     #[inline]
     pub fn matches(&self, document: &dyn Document) -> bool {
         solver::solve(&self.detection, document)
     }
 
-    /// Validates the rule's detection logic against the provided true positives and negatives.
+    /// Evaluates the rule against the provided `AsyncDocument`, see `solve_async`.
+    #[cfg(feature = "async")]
+    pub async fn matches_async(&self, document: &dyn AsyncDocument) -> bool {
+        crate::asynchronous::solve_async(&self.detection, document).await
+    }
+
+    /// Evaluates the rule against the provided `Document` with the reference evaluator, see
+    /// `solve_reference`.
+    ///
+    /// This is much slower than `matches` and is intended for testing.
+    pub fn matches_reference(&self, document: &dyn Document) -> bool {
+        reference::solve_reference(&self.detection, document)
+    }
+
+    /// Evaluates the rule against the provided `Document`, giving up once the budget has been
+    /// exhausted.
+    ///
+    /// This protects against rules that are expensive to evaluate, as the caller can decide how
+    /// to handle a rule that exceeded its budget rather than stalling on it.
+    #[inline]
+    pub fn matches_with_budget(&self, document: &dyn Document, budget: &mut Budget) -> Outcome {
+        solver::solve_with_budget(&self.detection, document, budget)
+    }
+
+    /// Evaluates the rule against the provided `Document`, giving up once the deadline passes or
+    /// the evaluation is cancelled.
+    ///
+    /// This stops a pathological rule, or an enormous document, from blocking the caller
+    /// indefinitely, see `Deadline` for when the deadline is checked.
+    #[inline]
+    pub fn matches_with_deadline(
+        &self,
+        document: &dyn Document,
+        deadline: &mut Deadline,
+    ) -> Outcome {
+        solver::solve_with_deadline(&self.detection, document, deadline)
+    }
+
+    /// Evaluates the rule against the provided `Document`, returning the weighted fraction of its
+    /// conditions that matched, from `0.0` to `1.0`.
+    ///
+    /// This allows documents that almost matched to be ranked when hunting, see `solve_scored`
+    /// for how the score is calculated.
+    #[inline]
+    pub fn score(&self, document: &dyn Document) -> f64 {
+        solver::solve_scored(&self.detection, &self.weights, document)
+    }
+
+    /// Validates the rule's detection logic against the provided examples.
+    ///
+    /// Every true positive must match, while every true negative and false positive must not.
+    /// When any of them fail, the returned error holds a diagnostic for each failure, with the
+    /// index of the example within its list and the example itself.
     pub fn validate(&self) -> crate::Result<bool> {
         let mut errors = vec![];
-        for test in &self.true_positives {
-            if !(solver::solve(&self.detection, test.as_mapping().unwrap())) {
-                errors.push(format!(
-                    "failed to validate true positive check '{:?}'",
-                    test
-                ));
-            }
-        }
-        for test in &self.true_negatives {
-            if solver::solve(&self.detection, test.as_mapping().unwrap()) {
-                errors.push(format!(
-                    "failed to validate true negative check '{:?}'",
-                    test
-                ));
+        for (code, examples, expected) in [
+            ("validation.true_positive", &self.true_positives, true),
+            ("validation.true_negative", &self.true_negatives, false),
+            ("validation.false_positive", &self.false_positives, false),
+        ] {
+            for (index, test) in examples.iter().enumerate() {
+                let matched = match test.as_mapping() {
+                    Some(document) => solver::solve(&self.detection, document),
+                    None => !expected,
+                };
+                if matched != expected {
+                    errors.push(
+                        Diagnostic::new(code)
+                            .with("index", index)
+                            .with("document", format!("{:?}", test)),
+                    );
+                }
             }
         }
         if !errors.is_empty() {
-            return Err(crate::Error::new(crate::error::Kind::Validation).with(errors.join(";")));
+            return Err(crate::Error::new(crate::error::Kind::Validation)
+                .with(Diagnostic::new("validation.failed").related(errors)));
         }
         Ok(true)
     }
@@ -651,6 +1940,12 @@ impl Rule {
 mod tests {
     use super::*;
 
+    use std::time::Duration;
+
+    use crate::parser::{Search, Visitor};
+    use crate::solver::Cancellation;
+    use crate::value::Value;
+
     #[test]
     fn rule() {
         let rule = r#"
@@ -676,6 +1971,747 @@ mod tests {
           foobar: barfoo
         "#;
         let rule = Rule::from_str(rule).unwrap();
-        assert_eq!(rule.validate().unwrap(), true);
+        assert!(rule.validate().unwrap());
+    }
+
+    #[test]
+    fn wide() {
+        struct Blob(Vec<u8>);
+        impl Document for Blob {
+            fn find(&self, key: &str) -> Option<Value<'_>> {
+                match key {
+                    "blob" => Some(Value::Bytes(std::borrow::Cow::Borrowed(&self.0))),
+                    _ => None,
+                }
+            }
+        }
+        let wide = |s: &str, offset: usize| {
+            let mut blob = vec![0xff; offset];
+            blob.extend(s.encode_utf16().flat_map(|u| u.to_le_bytes()));
+            blob.push(0xff);
+            Blob(blob)
+        };
+
+        let rule = r#"
+        detection:
+          A:
+            wide(blob): i*admin*
+
+          condition: A
+
+        true_positives: []
+
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        for offset in 0..2 {
+            assert!(rule.matches(&wide("net user ADMIN /add", offset)));
+            assert!(rule.matches_reference(&wide("net user ADMIN /add", offset)));
+        }
+        assert!(Rule::from_str(
+            "detection:\n  A:\n    wide(blob): i*Ł*\n  condition: A\ntrue_positives: []\ntrue_negatives: []\n"
+        )
+        .is_err());
+        assert!(
+            !Rule::from_str("detection:\n  A:\n    blob: '*admin*'\n  condition: A\ntrue_positives: []\ntrue_negatives: []\n")
+                .unwrap()
+                .matches(&wide("admin", 0))
+        );
+    }
+
+    #[test]
+    fn extends() {
+        let base: Yaml = serde_yaml::from_str(
+            r#"
+            detection:
+              A:
+                foo: bar
+              B:
+                baz: qux
+              condition: A and B
+            "#,
+        )
+        .unwrap();
+        let rule = r#"
+        extends: base
+        detection:
+          B:
+            baz: quux
+          C:
+            qux: foo
+          condition: A and B and not C
+        true_positives:
+        - foo: bar
+          baz: quux
+          qux: bar
+        true_negatives:
+        - foo: bar
+          baz: qux
+          qux: bar
+        - foo: bar
+          baz: quux
+          qux: foo
+        "#;
+        let rule = Rule::loader()
+            .base("base", base.clone())
+            .from_str(rule)
+            .unwrap();
+        assert_eq!(rule.detection.identifiers.len(), 3);
+        assert!(rule.validate().unwrap());
+
+        let rule = "extends: missing\ntrue_positives: []\ntrue_negatives: []";
+        assert!(Rule::loader().base("base", base).from_str(rule).is_err());
+
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/rules");
+        let rule = fs::read_to_string(dir.join("extends.yml")).unwrap();
+        assert!(Rule::from_str(&rule).is_err());
+        let rule = Rule::loader().base_dir(dir).from_str(&rule).unwrap();
+        assert!(rule.validate().unwrap());
+    }
+
+    #[test]
+    fn lookup() {
+        let rule = r#"
+        detection:
+          A:
+            hash: '@bad'
+          B:
+            user: i@admins
+          condition: A or B
+        true_positives:
+        - hash: abc
+        - user: Alice
+        true_negatives:
+        - hash: abcd
+        - user: mallory
+        "#;
+        let bad: HashSet<String> = vec!["abc".to_owned(), "def".to_owned()]
+            .into_iter()
+            .collect();
+        let admins: HashSet<String> = vec!["alice".to_owned()].into_iter().collect();
+        let rule = Rule::loader()
+            .lookup("bad", Arc::new(bad))
+            .lookup("admins", Arc::new(admins))
+            .from_str(rule)
+            .unwrap();
+        assert!(rule.validate().unwrap());
+
+        let rule = "detection:\n  A:\n    hash: '@missing'\n  condition: A\ntrue_positives: []\ntrue_negatives: []";
+        assert!(Rule::loader()
+            .lookup("bad", Arc::new(HashSet::new()))
+            .from_str(rule)
+            .is_err());
+
+        let rule = "detection:\n  A:\n    hash: '@missing'\n  condition: A\ntrue_positives:\n- hash: '@missing'\ntrue_negatives: []";
+        assert!(Rule::from_str(rule).unwrap().validate().unwrap());
+    }
+
+    #[test]
+    fn placeholder() {
+        let rule = r#"
+        detection:
+          A:
+            user: '%admins%'
+          condition: A
+        true_positives:
+        - user: alice
+        - user: bob
+        true_negatives:
+        - user: mallory
+        - user: "'alice'"
+        "#;
+        let rule = Rule::loader()
+            .placeholder("admins", vec!["alice".to_owned(), "bob".to_owned()])
+            .from_str(rule)
+            .unwrap();
+        assert!(rule.validate().unwrap());
+    }
+
+    #[test]
+    fn rule_metadata() {
+        let rule = r#"
+        id: 5f2c6f4e
+        title: Foo
+        status: stable
+        severity: high
+        tags:
+        - attack.execution
+        - attack.t1059
+        extra:
+          author: someone
+        detection:
+          A:
+            foo: bar
+          condition: A
+        true_positives: []
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        assert_eq!(rule.metadata.id.as_deref(), Some("5f2c6f4e"));
+        assert_eq!(rule.metadata.title.as_deref(), Some("Foo"));
+        assert_eq!(rule.metadata.status, Some(Status::Stable));
+        assert_eq!(rule.severity, Some(Severity::High));
+        assert_eq!(
+            rule.metadata.tags,
+            vec!["attack.execution".to_owned(), "attack.t1059".to_owned()]
+        );
+        assert_eq!(
+            rule.metadata.extra.get(&Yaml::from("author")),
+            Some(&Yaml::from("someone"))
+        );
+
+        let rule = Rule::from_str(
+            r#"
+            detection:
+              A:
+                foo: bar
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rule.metadata, Metadata::default());
+
+        for invalid in [
+            "id: 123",
+            "status: done",
+            "status: production",
+            "title: 42",
+            "title: ''",
+            "tags: foo",
+            "tags: [foo, foo]",
+            "tags: ['']",
+            "extra: [foo]",
+        ] {
+            let rule = format!(
+                "{}\ndetection:\n  A:\n    foo: bar\n  condition: A\ntrue_positives: []\ntrue_negatives: []\n",
+                invalid
+            );
+            assert!(Rule::from_str(&rule).is_ok(), "{}", invalid);
+            assert!(
+                Rule::loader().metadata(true).from_str(&rule).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        let rule = Rule::from_str(
+            "id: 123\ntitle: Foo\ndetection:\n  A:\n    foo: bar\n  condition: A\ntrue_positives: []\ntrue_negatives: []\n",
+        )
+        .unwrap();
+        assert_eq!(rule.metadata.id, None);
+        assert_eq!(rule.metadata.title.as_deref(), Some("Foo"));
+    }
+
+    #[test]
+    fn rule_validate() {
+        let rule = r#"
+        detection:
+          A:
+            foo: 'foo*'
+          condition: A
+        true_positives:
+        - foo: foobar
+        - foo: barfoo
+        true_negatives:
+        - foo: bar
+        false_positives:
+        - foo: baz
+        - foo: foobaz
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        let error = rule.validate().unwrap_err();
+        let failures: Vec<_> = error
+            .diagnostic()
+            .unwrap()
+            .causes()
+            .iter()
+            .map(|d| (d.code(), d.param("index").unwrap()))
+            .collect();
+        assert_eq!(
+            failures,
+            vec![
+                ("validation.true_positive", "1"),
+                ("validation.false_positive", "1")
+            ]
+        );
+
+        let rule = r#"
+        detection:
+          A:
+            foo: 'foo*'
+          condition: A
+        true_positives: []
+        true_negatives: []
+        false_positives:
+        - foo
+        "#;
+        let error = Rule::from_str(rule).unwrap_err();
+        assert_eq!(error.diagnostic().unwrap().code(), "rule.invalid_example");
+    }
+
+    #[test]
+    fn rule_invalid_detection() {
+        let rule = r#"
+        detection:
+          A:
+            foo: foo
+          condition: A and B
+        true_positives: []
+        true_negatives: []
+        "#;
+        let error = Rule::from_str(rule).unwrap_err();
+        assert_eq!(
+            error.diagnostic().unwrap().code(),
+            "condition.unknown_identifier"
+        );
+
+        // Without the loader the error can only be carried in serde's message
+        let error = serde_yaml::from_str::<Rule>(rule).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("detection: failed to parse detection - tau_engine::Error {"));
+    }
+
+    #[test]
+    fn rule_counted_keywords() {
+        let rule = |condition: &str| {
+            Rule::from_str(&format!(
+                "detection:\n  A: [foo, ibar]\n  condition: {}\ntrue_positives: []\ntrue_negatives: []\n",
+                condition
+            ))
+        };
+        for condition in ["all(A)", "of(A, 2)", "not all(A)"] {
+            let error = rule(condition).unwrap_err();
+            assert_eq!(
+                error.diagnostic().unwrap().code(),
+                "condition.counted_keywords"
+            );
+        }
+        assert!(rule("of(A, 0)").is_ok());
+        assert!(rule("of(A, 1)").is_ok());
+    }
+
+    #[test]
+    fn rule_budget() {
+        let rule = r#"
+        detection:
+          A:
+            foo: 'foo*'
+          B:
+            bar:
+            - foo
+            - bar
+          condition: A and B
+        true_positives: []
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        let document: Yaml = serde_yaml::from_str("{foo: foobar, bar: [foo, bar]}").unwrap();
+        let document = document.as_mapping().unwrap();
+
+        let mut budget = Budget::new(100);
+        assert_eq!(
+            rule.matches_with_budget(document, &mut budget),
+            Outcome::Matched
+        );
+        assert!(!budget.exceeded());
+
+        let mut budget = Budget::new(2);
+        assert_eq!(
+            rule.matches_with_budget(document, &mut budget),
+            Outcome::BudgetExceeded
+        );
+        assert!(budget.exceeded());
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[test]
+    fn rule_deadline() {
+        let rule = r#"
+        detection:
+          A:
+            foo: 'foo*'
+          B:
+            bar:
+            - foo
+            - bar
+          condition: A and B
+        true_positives: []
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        let document: Yaml = serde_yaml::from_str("{foo: foobar, bar: [foo, bar]}").unwrap();
+        let document = document.as_mapping().unwrap();
+
+        let cancellation = Cancellation::new();
+        let mut deadline =
+            Deadline::new(Duration::from_secs(60)).cancellation(cancellation.clone());
+        assert_eq!(
+            rule.matches_with_deadline(document, &mut deadline),
+            Outcome::Matched
+        );
+        assert!(!deadline.timed_out());
+
+        cancellation.cancel();
+        assert_eq!(
+            rule.matches_with_deadline(document, &mut deadline),
+            Outcome::TimedOut
+        );
+        assert!(deadline.timed_out());
+
+        let mut deadline = Deadline::new(Duration::ZERO);
+        assert_eq!(
+            rule.matches_with_deadline(document, &mut deadline),
+            Outcome::TimedOut
+        );
+
+        let mut deadline = Deadline::never();
+        assert_eq!(
+            rule.matches_with_deadline(document, &mut deadline),
+            Outcome::Matched
+        );
+    }
+
+    #[test]
+    fn rule_score() {
+        let rule = r#"
+        detection:
+          A:
+            foo: foo
+          B:
+            bar: bar
+          C:
+            baz: baz
+          D:
+            qux: qux
+          condition: A and B and not C and (D or B)
+        weights:
+          B: 3
+          C: 0
+        true_positives: []
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        let score = |document: &str| {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            rule.score(document.as_mapping().unwrap())
+        };
+        assert_eq!(score("{foo: foo, bar: bar, qux: qux}"), 1.0);
+        assert_eq!(score("{foo: foo, bar: bar, baz: baz}"), 1.0);
+        assert_eq!(score("{bar: bar}"), 0.8);
+        assert_eq!(score("{foo: foo, qux: qux}"), 0.4);
+        assert_eq!(score("{}"), 0.0);
+
+        let rule = r#"
+        detection:
+          A:
+            foo: foo
+          condition: A
+        weights:
+          B: 1
+        true_positives: []
+        true_negatives: []
+        "#;
+        assert!(Rule::from_str(rule).is_err());
+    }
+
+    #[test]
+    fn rule_keywords() {
+        let rule = r#"
+        detection:
+          A:
+            foo: foo
+          B:
+            bar: bar
+          condition: A && !B
+        true_positives:
+        - foo: foo
+          bar: foo
+        true_negatives:
+        - foo: foo
+          bar: bar
+        "#;
+        assert!(Rule::from_str(rule).is_err());
+        let rule = Rule::loader()
+            .keywords(Keywords::new().and("&&").not("!"))
+            .from_str(rule)
+            .unwrap();
+        assert!(rule.validate().unwrap());
+    }
+
+    #[test]
+    fn rule_legacy() {
+        let rule = r#"
+        detection:
+          A:
+            integer(foo): 1
+          condition: A and (integer(bar) = 2)
+        true_positives:
+        - foo: "1"
+          bar: "2"
+        true_negatives:
+        - foo: "1"
+          bar: "3"
+        "#;
+        assert!(Rule::from_str(rule).is_err());
+        let rule = Rule::loader()
+            .dialect(Dialect::Legacy)
+            .from_str(rule)
+            .unwrap();
+        assert!(rule.validate().unwrap());
+    }
+
+    #[test]
+    fn rule_regex_size_limit() {
+        let rule = r#"
+        detection:
+          A:
+            foo: '?\w{100}'
+          condition: A
+        true_positives: []
+        true_negatives: []
+        "#;
+        assert!(Rule::from_str(rule).is_ok());
+        assert!(Rule::loader()
+            .regex_size_limit(1024)
+            .from_str(rule)
+            .is_err());
+    }
+
+    #[test]
+    fn rule_optimisations() {
+        let rule = r#"
+        detection:
+          A:
+            foo:
+            - 'a*'
+            - 'i*b'
+            - 'i*c'
+            bar:
+            - '?x.'
+            - '?y.'
+          B:
+            foo: 'd*'
+          condition: A or B
+        true_positives: []
+        true_negatives: []
+        "#;
+        #[derive(Default)]
+        struct Merged(bool, bool);
+        impl Visitor for Merged {
+            fn visit_search(&mut self, search: &Search) {
+                match search {
+                    Search::AhoCorasick(_, contexts, _) => self.0 |= contexts.len() > 1,
+                    Search::RegexSet(_, _) => self.1 = true,
+                    _ => {}
+                }
+            }
+        }
+        let merged = |rule: &Rule| {
+            let mut merged = Merged::default();
+            merged.visit_expression(&rule.detection.expression);
+            for expression in rule.detection.identifiers.values() {
+                merged.visit_expression(expression);
+            }
+            (merged.0, merged.1)
+        };
+        let loader = || Rule::loader().coalesce(true).shake(true);
+        assert_eq!(merged(&Rule::from_str(rule).unwrap()), (true, true));
+        assert_eq!(merged(&loader().from_str(rule).unwrap()), (true, true));
+        let unmerged = loader()
+            .aho_corasick(false)
+            .regex_set(false)
+            .from_str(rule)
+            .unwrap();
+        assert_eq!(merged(&unmerged), (false, false));
+        let unmerged = Rule::loader()
+            .aho_corasick(false)
+            .regex_set(false)
+            .from_str(rule)
+            .unwrap();
+        assert_eq!(merged(&unmerged), (false, false));
+        let unmerged = Rule::loader()
+            .aho_corasick(false)
+            .regex_set(false)
+            .shake(false)
+            .from_str(rule)
+            .unwrap()
+            .optimise(true, true);
+        assert_eq!(merged(&unmerged), (false, false));
+
+        let nfa = loader().dfa(false).from_str(rule).unwrap();
+        for (document, expected) in [
+            ("{foo: apple, bar: xz}", true),
+            ("{foo: SUB, bar: yz}", true),
+            ("{foo: date}", true),
+            ("{foo: apple, bar: zz}", false),
+        ] {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            let document = document.as_mapping().unwrap();
+            assert_eq!(unmerged.matches(document), expected);
+            assert_eq!(nfa.matches(document), expected);
+        }
+    }
+
+    #[test]
+    fn rule_condition_position() {
+        let rule = |condition: &str| {
+            let condition = condition.replace('\n', "\n            ");
+            Rule::from_str(&format!(
+                r#"
+        detection:
+          A:
+            foo: bar
+          B:
+            bar: baz
+          condition: |
+            {}
+        true_positives: []
+        true_negatives: []
+        "#,
+                condition
+            ))
+            .err()
+            .unwrap()
+        };
+        let e = rule("A and\n# B\nnot C");
+        assert!(e.to_string().contains("at line 3, column 5"), "{}", e);
+        let e = rule("(A or\n  B) B");
+        assert!(e.to_string().contains("at line 2, column 6"), "{}", e);
+    }
+
+    #[test]
+    fn rule_coerce() {
+        let rule = r#"
+        detection:
+          A:
+            port: 443
+            size: '>1.5'
+            status: [200, 204]
+          condition: A
+        true_positives: []
+        true_negatives: []
+        "#;
+        let strict = Rule::from_str(rule).unwrap();
+        let coerced = Rule::loader().coerce(true).from_str(rule).unwrap();
+        for (document, expected) in [
+            ("{port: 443, size: 2.0, status: 200}", true),
+            ("{port: '443', size: 2.5, status: '204'}", true),
+            ("{port: ['80', '443'], size: 3, status: [200]}", true),
+            ("{port: '443', size: '1.0', status: '200'}", false),
+            ("{port: '443', size: '2.5', status: '200'}", false),
+            ("{port: https, size: 2.0, status: 200}", false),
+        ] {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            let document = document.as_mapping().unwrap();
+            assert_eq!(coerced.matches(document), expected, "{:?}", document);
+            assert_eq!(
+                coerced.matches_reference(document),
+                expected,
+                "{:?}",
+                document
+            );
+        }
+        for document in [
+            "{port: '443', size: 2.0, status: 200}",
+            "{port: [80, 443], size: 2.0, status: 200}",
+        ] {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            assert!(!strict.matches(document.as_mapping().unwrap()));
+        }
+    }
+
+    #[test]
+    fn rule_every() {
+        let rule = r#"
+        detection:
+          A:
+            int(size): '>1.5'
+          B:
+            every(ports): '>1024'
+          condition: A or B
+        true_positives: []
+        true_negatives: []
+        "#;
+        let rule = Rule::from_str(rule).unwrap();
+        for (document, expected) in [
+            ("{size: '2'}", true),
+            ("{size: '2.5'}", false),
+            ("{size: 2.5}", false),
+            ("{ports: [8080, 8443]}", true),
+            ("{ports: [80, 8443]}", false),
+        ] {
+            let document: Yaml = serde_yaml::from_str(document).unwrap();
+            let document = document.as_mapping().unwrap();
+            assert_eq!(rule.matches(document), expected, "{:?}", document);
+            assert_eq!(rule.matches_reference(document), expected, "{:?}", document);
+        }
+        let rule = r#"
+        detection:
+          A:
+            every(name): foo
+          condition: A
+        true_positives: []
+        true_negatives: []
+        "#;
+        let e = Rule::from_str(rule).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("every requires a numeric comparison"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn rule_documents() {
+        let rules = Rule::from_str_all(
+            "
+detection:
+  A: &base
+    user: admin
+    host: '*.internal'
+  B:
+    <<: *base
+    user: root
+  condition: A or B
+true_positives: []
+true_negatives: []
+---
+---
+detection:
+  A:
+    user: guest
+  condition: A
+true_positives: []
+true_negatives: []
+",
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 2);
+        let document: Yaml = serde_yaml::from_str("{user: root, host: db.internal}").unwrap();
+        assert!(rules[0].matches(document.as_mapping().unwrap()));
+        assert!(!rules[1].matches(document.as_mapping().unwrap()));
+
+        let error = Rule::from_str_all(
+            "
+detection:
+  A:
+    user: guest
+  condition: A
+true_positives: []
+true_negatives: []
+---
+detection:
+  A:
+    <<: guest
+  condition: A
+true_positives: []
+true_negatives: []
+",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("document 1"), "{}", error);
     }
 }
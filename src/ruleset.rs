@@ -0,0 +1,1261 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::OnceLock;
+#[cfg(feature = "json")]
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde_yaml::Value as Yaml;
+
+#[cfg(feature = "json")]
+use crate::clock::Instant;
+use crate::correlation::Correlator;
+use crate::document::{CachedDocument, Document};
+use crate::limits::{Limits, Rejection};
+use crate::parser::{walk_expression, Expression, Visitor};
+use crate::prefilter::Prefilter;
+use crate::program::Program;
+use crate::reference::{self, Divergence};
+use crate::rule::{Detection, Metadata, Rule, Severity};
+use crate::solver::{Deadline, Outcome, Traced, TRACE_TARGET};
+use crate::stats::Stats;
+use crate::value::Object;
+
+#[derive(Clone, Debug)]
+struct Entry {
+    id: String,
+    rule: Rule,
+    // The document fields that the rule depends on.
+    fields: HashSet<String>,
+    // Whether the rule searches for keywords, which depend on every field.
+    keywords: bool,
+    traced: bool,
+}
+
+impl Entry {
+    fn new(id: String, rule: Rule) -> Self {
+        let mut fields = HashSet::new();
+        collect_fields(
+            &rule.detection.expression,
+            &rule.detection.identifiers,
+            &mut fields,
+        );
+        Self {
+            id,
+            keywords: has_keywords(&rule.detection),
+            rule,
+            fields,
+            traced: false,
+        }
+    }
+
+    // Runs the evaluation, tracing it if tracing is enabled for the rule.
+    fn trace<F: FnOnce() -> bool>(&self, f: F) -> bool {
+        if !self.traced {
+            return f();
+        }
+        let _traced = Traced::enter(&self.id);
+        let matched = f();
+        tracing::debug!(target: TRACE_TARGET, "rule {} evaluated to {}", self.id, matched);
+        matched
+    }
+
+    // Returns true if the rule depends on any of the provided fields, a field is dependent on
+    // another when either is a parent of the other, i.e. `foo` and `foo.bar`.
+    fn depends_on(&self, fields: &[&str]) -> bool {
+        if self.keywords {
+            return !fields.is_empty();
+        }
+        fields.iter().any(|field| {
+            self.fields.iter().any(|f| {
+                f == field
+                    || (f.starts_with(field) && f[field.len()..].starts_with('.'))
+                    || (field.starts_with(f.as_str()) && field[f.len()..].starts_with('.'))
+            })
+        })
+    }
+}
+
+/// A rule that matched, along with its routing metadata, see `RuleSet::route`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Routed<'a> {
+    /// The rule's response actions, carried verbatim from its `actions` key.
+    pub actions: Option<&'a Yaml>,
+    /// The id of the rule that matched.
+    pub id: &'a str,
+    /// The rule's descriptive metadata, see `Metadata`.
+    pub metadata: &'a Metadata,
+    /// The rule's routing metadata, carried verbatim from its `routing` key.
+    pub routing: Option<&'a Yaml>,
+    /// The rule's severity.
+    pub severity: Option<Severity>,
+}
+
+/// The rules that matched a document when evaluated under a `Deadline`, along with those that
+/// timed out, see `RuleSet::matches_with_deadline`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Evaluation<'a> {
+    /// The ids of the rules that matched.
+    pub matched: Vec<&'a str>,
+    /// The ids of the rules that ran out of time, or were cancelled, before they could complete.
+    pub timed_out: Vec<&'a str>,
+}
+
+/// A collection of rules, keyed by a unique id.
+///
+/// Rules are kept in the order that they were inserted, which is also the order in which they are
+/// evaluated.
+#[derive(Clone, Debug, Default)]
+pub struct RuleSet {
+    index: HashMap<String, usize>,
+    limits: Limits,
+    // The literal index over the rules, which is built on first use and then kept up to date as
+    // the set changes.
+    literals: OnceLock<Prefilter>,
+    prefilter: bool,
+    // The indexes of the rules with a severity, from most to least severe and then in insertion
+    // order.
+    priority: Vec<usize>,
+    rules: Vec<Entry>,
+}
+
+impl RuleSet {
+    /// Create an empty `RuleSet`.
+    pub fn new() -> Self {
+        RuleSet::default()
+    }
+
+    /// Set the limits that documents must be within to be evaluated by `evaluate`.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Enables or disables the literal prefilter.
+    ///
+    /// When enabled, the literals that the rules search for are indexed across the whole set, so
+    /// that each document is scanned for all of them at once, and then only the rules with a
+    /// literal that was found, along with those that could match without one, are evaluated. This
+    /// greatly reduces the time taken by large sets where most rules search for literals that are
+    /// rarely found. The index is built by the first evaluation, and is then updated in place as
+    /// rules are inserted and removed.
+    ///
+    /// The prefilter is used by `matches`, `matches_iter`, `route`, `evaluate`,
+    /// `first_match_at_least` and `scan`, along with any `Policy` built from the set.
+    ///
+    /// This option is disabled by default.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new().prefilter(true);
+    /// for (id, value) in [("foo", "'foo*'"), ("bar", "'*bar'")] {
+    ///     rules.insert(id, Rule::from_str(&format!(r#"
+    /// detection:
+    ///   A:
+    ///     foo: {}
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#, value)).unwrap());
+    /// }
+    ///
+    /// let document: Yaml = serde_yaml::from_str("foo: foobaz").unwrap();
+    /// assert_eq!(rules.matches(document.as_mapping().unwrap()), vec!["foo"]);
+    /// ```
+    pub fn prefilter(mut self, yes: bool) -> Self {
+        self.prefilter = yes;
+        self
+    }
+
+    /// Get a rule by its id.
+    pub fn get(&self, id: &str) -> Option<&Rule> {
+        self.index.get(id).map(|i| &self.rules[*i].rule)
+    }
+
+    /// Inserts a rule into the set, if a rule already exists with the same id it is replaced and
+    /// returned.
+    ///
+    /// The set is updated in place, so that rules can be reloaded individually by long running
+    /// services, with the prefilter, if it has been built, only being rebuilt for the fields whose
+    /// literals changed.
+    pub fn insert<S: Into<String>>(&mut self, id: S, rule: Rule) -> Option<Rule> {
+        let id = id.into();
+        let (i, replaced) = match self.index.get(&id) {
+            Some(i) => {
+                let i = *i;
+                let mut entry = Entry::new(id, rule);
+                entry.traced = self.rules[i].traced;
+                let entry = std::mem::replace(&mut self.rules[i], entry);
+                self.priority.retain(|p| *p != i);
+                (i, Some(entry.rule))
+            }
+            None => {
+                let i = self.rules.len();
+                self.index.insert(id.clone(), i);
+                self.rules.push(Entry::new(id, rule));
+                (i, None)
+            }
+        };
+        if let Some(prefilter) = self.literals.get_mut() {
+            prefilter.insert(i, &self.rules[i].rule.detection);
+        }
+        if let Some(severity) = self.rules[i].rule.severity {
+            let rules = &self.rules;
+            let position = self.priority.partition_point(|p| {
+                let other = rules[*p].rule.severity;
+                other > Some(severity) || (other == Some(severity) && *p < i)
+            });
+            self.priority.insert(position, i);
+        }
+        replaced
+    }
+
+    /// Replaces the rule with the id, returning the rule that was replaced.
+    ///
+    /// Unlike `insert`, the set is left unchanged and `None` is returned if there is no rule with
+    /// the id, so that a reload can't add a rule that was meant to have been removed.
+    pub fn replace(&mut self, id: &str, rule: Rule) -> Option<Rule> {
+        if !self.index.contains_key(id) {
+            return None;
+        }
+        self.insert(id, rule)
+    }
+
+    /// Removes the rule with the id from the set, returning it if it was present.
+    ///
+    /// The rules after it keep their order, and the prefilter, if it has been built, is only
+    /// rebuilt for the fields that lost a literal.
+    pub fn remove(&mut self, id: &str) -> Option<Rule> {
+        let i = self.index.remove(id)?;
+        let entry = self.rules.remove(i);
+        for index in self.index.values_mut().filter(|index| **index > i) {
+            *index -= 1;
+        }
+        self.priority.retain(|p| *p != i);
+        for p in self.priority.iter_mut().filter(|p| **p > i) {
+            *p -= 1;
+        }
+        if let Some(prefilter) = self.literals.get_mut() {
+            prefilter.remove(i);
+        }
+        Some(entry.rule)
+    }
+
+    /// Enables or disables tracing for a rule, returning false if there is no rule with the id.
+    ///
+    /// While a rule is traced, the solver logs the evaluation of each of its expressions to the
+    /// `tau_engine::trace` target at debug level, within a `rule` span holding the rule's id. This
+    /// allows a single misbehaving rule to be diagnosed by enabling just that target, without also
+    /// enabling the solver's logging for every other rule.
+    pub fn trace(&mut self, id: &str, enabled: bool) -> bool {
+        match self.index.get(id) {
+            Some(i) => {
+                self.rules[*i].traced = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if the set contains no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns an iterator over the rules and their ids.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Rule)> {
+        self.rules.iter().map(|e| (e.id.as_str(), &e.rule))
+    }
+
+    /// Returns the number of rules in the set.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Evaluates all rules against the provided `Document`, returning the ids of those that
+    /// matched.
+    pub fn matches(&self, document: &dyn Document) -> Vec<&str> {
+        self.matches_iter(document).collect()
+    }
+
+    /// Evaluates the rules against the provided `Document` on demand, returning an iterator over
+    /// the ids of those that matched.
+    ///
+    /// Rules are only evaluated as the iterator is advanced, so callers that only need the first
+    /// match, i.e. when routing a document, do not pay for evaluating the rest of the set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// for (id, value) in [("foo", "'foo*'"), ("bar", "'*bar'")] {
+    ///     rules.insert(id, Rule::from_str(&format!(r#"
+    /// detection:
+    ///   A:
+    ///     foo: {}
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#, value)).unwrap());
+    /// }
+    ///
+    /// let document: Yaml = serde_yaml::from_str("foo: foobar").unwrap();
+    /// let document = document.as_mapping().unwrap();
+    /// assert_eq!(rules.matches_iter(document).next(), Some("foo"));
+    /// ```
+    pub fn matches_iter<'a, 'd>(&'a self, document: &'d dyn Document) -> Matches<'a, 'd> {
+        let document = CachedDocument::new(document);
+        Matches {
+            candidates: self.candidates(&document),
+            document,
+            rules: self.rules.iter().enumerate(),
+        }
+    }
+
+    /// Evaluates the rules with at least the given severity against the provided `Document`, from
+    /// most to least severe, returning the id of the first that matched.
+    ///
+    /// Evaluation stops at the first match, so this is intended for inline decisions where only
+    /// the most severe verdict matters. Rules of the same severity are evaluated in the order that
+    /// they were inserted, and rules without a severity are never evaluated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet, Severity};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// for (id, severity) in [("low", "low"), ("high", "high")] {
+    ///     rules.insert(id, Rule::from_str(&format!(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// severity: {}
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#, severity)).unwrap());
+    /// }
+    ///
+    /// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+    /// let document = document.as_mapping().unwrap();
+    /// assert_eq!(rules.first_match_at_least(Severity::Low, document), Some("high"));
+    /// assert_eq!(rules.first_match_at_least(Severity::Critical, document), None);
+    /// ```
+    pub fn first_match_at_least(
+        &self,
+        severity: Severity,
+        document: &dyn Document,
+    ) -> Option<&str> {
+        let document = CachedDocument::new(document);
+        let candidates = self.candidates(&document);
+        self.priority
+            .iter()
+            .take_while(|i| self.rules[**i].rule.severity >= Some(severity))
+            .filter(|i| candidate(&candidates, **i))
+            .map(|i| &self.rules[*i])
+            .find(|e| e.trace(|| e.rule.matches(&document)))
+            .map(|e| e.id.as_str())
+    }
+
+    // Evaluates the rules against the provided `Document` from most to least severe, followed by
+    // those without a severity, returning the id of the first that matched.
+    pub(crate) fn first_match_by_severity(&self, document: &dyn Document) -> Option<&str> {
+        let document = CachedDocument::new(document);
+        let candidates = self.candidates(&document);
+        self.priority
+            .iter()
+            .copied()
+            .chain((0..self.rules.len()).filter(|i| self.rules[*i].rule.severity.is_none()))
+            .filter(|i| candidate(&candidates, *i))
+            .map(|i| &self.rules[i])
+            .find(|e| e.trace(|| e.rule.matches(&document)))
+            .map(|e| e.id.as_str())
+    }
+
+    /// Evaluates all rules against the provided `Document`, returning those that matched along
+    /// with their routing and descriptive metadata.
+    ///
+    /// This allows downstream orchestration to act upon a match, and to give it context, without
+    /// having to look up how to respond to the rule.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// rules.insert("foo", Rule::from_str(r#"
+    /// title: Foo is bar
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// routing:
+    ///   notify: soc
+    /// actions:
+    ///   contain: true
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap());
+    ///
+    /// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+    /// let routed = rules.route(document.as_mapping().unwrap());
+    /// assert_eq!(routed[0].id, "foo");
+    /// assert_eq!(routed[0].metadata.title.as_deref(), Some("Foo is bar"));
+    /// assert_eq!(routed[0].routing.unwrap()["notify"], Yaml::from("soc"));
+    /// assert_eq!(routed[0].actions.unwrap()["contain"], Yaml::from(true));
+    /// ```
+    pub fn route(&self, document: &dyn Document) -> Vec<Routed<'_>> {
+        self.matches_iter(document)
+            .filter_map(|id| {
+                let rule = self.get(id)?;
+                Some(Routed {
+                    actions: rule.actions.as_ref(),
+                    id,
+                    metadata: &rule.metadata,
+                    routing: rule.routing.as_ref(),
+                    severity: rule.severity,
+                })
+            })
+            .collect()
+    }
+
+    /// Evaluates all rules against the provided `Object`, returning the ids of those that matched,
+    /// or the reason that it was rejected if it exceeds the set's limits.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Limits, Rejection, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let rules = RuleSet::new().limits(Limits::new().bytes(1024));
+    ///
+    /// let event: Yaml = serde_yaml::from_str(&format!("foo: {}", "a".repeat(2048))).unwrap();
+    /// assert_eq!(rules.evaluate(event.as_mapping().unwrap()), Err(Rejection::Bytes(1024)));
+    /// ```
+    pub fn evaluate(&self, object: &dyn Object) -> Result<Vec<&str>, Rejection> {
+        self.limits.check(object)?;
+        Ok(self.matches(&object))
+    }
+
+    /// Evaluates all rules against the provided `Document` under a deadline, returning the ids of
+    /// those that matched along with the ids of those that timed out.
+    ///
+    /// Each rule is given the deadline's full timeout, so a single pathological rule cannot starve
+    /// the rest of the set. Once the deadline's cancellation token has been cancelled the remaining
+    /// rules are reported as timed out without being evaluated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// use tau_engine::{Cancellation, Deadline, Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// rules.insert("foo", Rule::from_str(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap());
+    ///
+    /// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+    /// let document = document.as_mapping().unwrap();
+    ///
+    /// let cancellation = Cancellation::new();
+    /// let mut deadline = Deadline::new(Duration::from_secs(1)).cancellation(cancellation.clone());
+    /// assert_eq!(rules.matches_with_deadline(document, &mut deadline).matched, vec!["foo"]);
+    ///
+    /// cancellation.cancel();
+    /// assert_eq!(rules.matches_with_deadline(document, &mut deadline).timed_out, vec!["foo"]);
+    /// ```
+    pub fn matches_with_deadline(
+        &self,
+        document: &dyn Document,
+        deadline: &mut Deadline,
+    ) -> Evaluation<'_> {
+        let document = CachedDocument::new(document);
+        let candidates = self.candidates(&document);
+        let mut evaluation = Evaluation::default();
+        for (i, e) in self.rules.iter().enumerate() {
+            if !candidate(&candidates, i) {
+                continue;
+            }
+            let mut outcome = Outcome::NotMatched;
+            e.trace(|| {
+                outcome = e.rule.matches_with_deadline(&document, deadline);
+                outcome == Outcome::Matched
+            });
+            match outcome {
+                Outcome::Matched => evaluation.matched.push(&e.id),
+                Outcome::TimedOut => evaluation.timed_out.push(&e.id),
+                _ => {}
+            }
+        }
+        evaluation
+    }
+
+    /// Evaluates all rules against a stream of documents, returning an iterator over the matches
+    /// as pairs of the document's index in the stream and the id of the rule that matched it.
+    ///
+    /// Unlike calling `matches` in a loop, the rules are compiled once up front and reused for
+    /// every document, making this the preferred way to evaluate large numbers of documents.
+    /// Documents are consumed lazily, so the stream can be unbounded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// rules.insert("foo", Rule::from_str(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap());
+    ///
+    /// let documents = vec!["foo: baz", "foo: bar"]
+    ///     .into_iter()
+    ///     .map(|s| serde_yaml::from_str::<Yaml>(s).unwrap().as_mapping().unwrap().clone());
+    /// let matches: Vec<_> = rules.scan(documents).collect();
+    /// assert_eq!(matches, vec![(1, "foo")]);
+    /// ```
+    pub fn scan<I, D>(&self, documents: I) -> Scan<'_, I::IntoIter>
+    where
+        I: IntoIterator<Item = D>,
+        D: Document,
+    {
+        Scan {
+            documents: documents.into_iter(),
+            index: 0,
+            matches: VecDeque::new(),
+            programs: self.rules.iter().map(|e| (e, e.rule.compile())).collect(),
+            set: self,
+        }
+    }
+
+    /// Evaluates all rules against a corpus of documents, returning the stats for each rule so
+    /// that those which are hurting throughput can be found, see `Stats`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let mut rules = RuleSet::new();
+    /// rules.insert("foo", Rule::from_str(r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: A
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#).unwrap());
+    ///
+    /// let documents = vec!["foo: baz", "foo: bar"]
+    ///     .into_iter()
+    ///     .map(|s| serde_yaml::from_str::<Yaml>(s).unwrap().as_mapping().unwrap().clone());
+    /// let stats = rules.profile(documents);
+    /// assert_eq!(stats[0].0, "foo");
+    /// assert_eq!(stats[0].1.evaluations(), 2);
+    /// ```
+    pub fn profile<I, D>(&self, documents: I) -> Vec<(&str, Stats)>
+    where
+        I: IntoIterator<Item = D>,
+        D: Document,
+    {
+        let programs: Vec<_> = self.rules.iter().map(|e| e.rule.compile()).collect();
+        let mut stats: Vec<Stats> = programs.iter().map(|p| p.stats()).collect();
+        for document in documents {
+            let document = CachedDocument::new(&document);
+            for (program, stats) in programs.iter().zip(stats.iter_mut()) {
+                program.matches_with_stats(&document, stats);
+            }
+        }
+        self.rules
+            .iter()
+            .map(|e| e.id.as_str())
+            .zip(stats)
+            .collect()
+    }
+
+    /// Evaluates all rules against a corpus of documents with the solver, the compiled programs
+    /// and the reference evaluator, returning every case where they disagree, see
+    /// `solve_reference`.
+    ///
+    /// The reference evaluator is run against the rule with the same id in `reference`, which
+    /// should hold the same rules loaded without any optimisation, i.e. with
+    /// `Rule::loader().shake(false)`, as otherwise a bug in the optimiser would be shared by both
+    /// sides. Rules that are not in `reference` are evaluated against their own detection.
+    ///
+    /// This is a safety net for changes to the optimiser and the solver, as any divergence is a
+    /// bug in one of them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use tau_engine::{Rule, RuleSet};
+    /// # use serde_yaml::Value as Yaml;
+    ///
+    /// let rule = r#"
+    /// detection:
+    ///   A:
+    ///     foo: bar
+    ///   condition: not (A and not A)
+    /// true_positives: []
+    /// true_negatives: []
+    /// "#;
+    /// let mut rules = RuleSet::new();
+    /// rules.insert("foo", Rule::loader().shake(true).from_str(rule).unwrap());
+    /// let mut reference = RuleSet::new();
+    /// reference.insert("foo", Rule::loader().shake(false).from_str(rule).unwrap());
+    ///
+    /// let documents = vec!["foo: baz", "foo: bar", "bar: foo"]
+    ///     .into_iter()
+    ///     .map(|s| serde_yaml::from_str::<Yaml>(s).unwrap().as_mapping().unwrap().clone());
+    /// assert!(rules.differential(&reference, documents).is_empty());
+    /// ```
+    pub fn differential<I, D>(&self, reference: &RuleSet, documents: I) -> Vec<Divergence<'_>>
+    where
+        I: IntoIterator<Item = D>,
+        D: Document,
+    {
+        let programs: Vec<_> = self.rules.iter().map(|e| e.rule.compile()).collect();
+        let detections: Vec<_> = self
+            .rules
+            .iter()
+            .map(|e| &reference.get(&e.id).unwrap_or(&e.rule).detection)
+            .collect();
+        let mut divergences = vec![];
+        for (index, document) in documents.into_iter().enumerate() {
+            let cached = CachedDocument::new(&document);
+            for ((entry, program), detection) in self.rules.iter().zip(&programs).zip(&detections) {
+                let reference = reference::solve_reference(detection, &document);
+                let solver = entry.rule.matches(&cached);
+                let compiled = program.matches(&cached);
+                if reference != solver || reference != compiled {
+                    divergences.push(Divergence {
+                        document: index,
+                        rule: &entry.id,
+                        reference,
+                        solver,
+                        compiled,
+                    });
+                }
+            }
+        }
+        divergences
+    }
+
+    /// Evaluates all rules against the provided `Document` at the given time, returning the ids of
+    /// those that matched.
+    ///
+    /// Rules with a correlation are solved using the state held in the `Correlator`, which should
+    /// be reused for every document from the same stream.
+    pub fn correlate(
+        &self,
+        correlator: &mut Correlator,
+        document: &dyn Document,
+        timestamp: SystemTime,
+    ) -> Vec<&str> {
+        let document = CachedDocument::new(document);
+        self.rules
+            .iter()
+            .filter(|e| e.trace(|| correlator.solve(&e.id, &e.rule, &document, timestamp)))
+            .map(|e| e.id.as_str())
+            .collect()
+    }
+
+    /// Returns the ids of the rules that depend on any of the provided fields, where a rule that
+    /// searches for keywords depends on every field.
+    pub fn affected(&self, fields: &[&str]) -> Vec<&str> {
+        self.rules
+            .iter()
+            .filter(|e| e.depends_on(fields))
+            .map(|e| e.id.as_str())
+            .collect()
+    }
+
+    /// Evaluates only the rules that depend on any of the provided fields against the `Document`,
+    /// returning the ids of those that matched.
+    ///
+    /// This is intended for documents that gain fields over time, as a rule that does not depend
+    /// on the fields that were added cannot change its outcome and so does not need to be
+    /// evaluated again.
+    pub fn reevaluate(&self, document: &dyn Document, fields: &[&str]) -> Vec<&str> {
+        let document = CachedDocument::new(document);
+        self.rules
+            .iter()
+            .filter(|e| e.depends_on(fields) && e.trace(|| e.rule.matches(&document)))
+            .map(|e| e.id.as_str())
+            .collect()
+    }
+
+    // Evaluates the rules against the document in the same way as `matches`, returning whether
+    // each rule matched and how long it took, or `None` for those skipped by the prefilter, see
+    // `Harness`.
+    //
+    // NOTE: The document is expected to already be cached, so that the scans it shares between
+    // the rules are timed as they would be in `matches`.
+    #[cfg(feature = "json")]
+    pub(crate) fn timed(&self, document: &dyn Document) -> Vec<Option<(bool, Duration)>> {
+        let candidates = self.candidates(document);
+        self.rules
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                if !candidate(&candidates, i) {
+                    return None;
+                }
+                let start = Instant::now();
+                let matched = e.trace(|| e.rule.matches(document));
+                Some((matched, start.elapsed()))
+            })
+            .collect()
+    }
+
+    // Returns whether each rule could match the document when the prefilter is enabled.
+    fn candidates(&self, document: &dyn Document) -> Option<Vec<bool>> {
+        if !self.prefilter {
+            return None;
+        }
+        let prefilter = self
+            .literals
+            .get_or_init(|| Prefilter::new(self.rules.iter().map(|e| &e.rule.detection)));
+        Some(prefilter.candidates(document))
+    }
+}
+
+#[inline]
+fn candidate(candidates: &Option<Vec<bool>>, i: usize) -> bool {
+    candidates.as_ref().map_or(true, |c| c[i])
+}
+
+/// A lazy iterator over the ids of the rules in a `RuleSet` that match a document, see
+/// `RuleSet::matches_iter`.
+pub struct Matches<'a, 'd> {
+    candidates: Option<Vec<bool>>,
+    document: CachedDocument<'d>,
+    rules: std::iter::Enumerate<std::slice::Iter<'a, Entry>>,
+}
+
+impl<'a, 'd> Iterator for Matches<'a, 'd> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let candidates = &self.candidates;
+        let document = &self.document;
+        self.rules
+            .find(|(i, e)| candidate(candidates, *i) && e.trace(|| e.rule.matches(document)))
+            .map(|(_, e)| e.id.as_str())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.rules.size_hint().1)
+    }
+}
+
+/// An iterator over the matches of a `RuleSet` against a stream of documents, see
+/// `RuleSet::scan`.
+pub struct Scan<'a, I> {
+    documents: I,
+    index: usize,
+    matches: VecDeque<(usize, &'a str)>,
+    programs: Vec<(&'a Entry, Program)>,
+    set: &'a RuleSet,
+}
+
+impl<'a, I, D> Iterator for Scan<'a, I>
+where
+    I: Iterator<Item = D>,
+    D: Document,
+{
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(m) = self.matches.pop_front() {
+                return Some(m);
+            }
+            let document = self.documents.next()?;
+            let document = CachedDocument::new(&document);
+            let candidates = self.set.candidates(&document);
+            for (i, (entry, program)) in self.programs.iter().enumerate() {
+                if candidate(&candidates, i) && entry.trace(|| program.matches(&document)) {
+                    self.matches.push_back((self.index, &entry.id));
+                }
+            }
+            self.index += 1;
+        }
+    }
+}
+
+pub(crate) fn collect_fields<S: BuildHasher>(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    fields: &mut HashSet<String, S>,
+) {
+    struct Fields<'a, S> {
+        identifiers: &'a HashMap<String, Expression>,
+        fields: &'a mut HashSet<String, S>,
+    }
+    impl<'a, S: BuildHasher> Visitor for Fields<'a, S> {
+        fn visit_expression(&mut self, expression: &Expression) {
+            // NOTE: Nested expressions are evaluated against the nested value so we only care
+            // about the field that is used to get it.
+            if let Expression::Nested(field, _) = expression {
+                self.fields.insert(field.clone());
+                return;
+            }
+            walk_expression(self, expression);
+        }
+        fn visit_field(&mut self, field: &str) {
+            self.fields.insert(field.to_owned());
+        }
+        fn visit_identifier(&mut self, identifier: &str) {
+            if let Some(expression) = self.identifiers.get(identifier) {
+                self.visit_expression(expression);
+            }
+        }
+    }
+    Fields {
+        identifiers,
+        fields,
+    }
+    .visit_expression(expression);
+}
+
+// Whether any of the detection's expressions is a keyword search.
+pub(crate) fn has_keywords(detection: &Detection) -> bool {
+    struct Keywords(bool);
+    impl Visitor for Keywords {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::Keywords(_) = expression {
+                self.0 = true;
+            }
+            walk_expression(self, expression);
+        }
+    }
+    let mut visitor = Keywords(false);
+    visitor.visit_expression(&detection.expression);
+    for expression in detection.identifiers.values() {
+        visitor.visit_expression(expression);
+    }
+    visitor.0
+}
+
+/// The difference in matches for a rule between two `RuleSet`s.
+///
+/// Documents are referenced by their index in the corpus that was compared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Delta {
+    /// The id of the rule.
+    pub id: String,
+    /// The documents that only matched in the second `RuleSet`.
+    pub gained: Vec<usize>,
+    /// The documents that only matched in the first `RuleSet`.
+    pub lost: Vec<usize>,
+}
+
+/// Compares two `RuleSet`s over a corpus of documents, returning the rules whose matches differ.
+///
+/// Rules are paired by id, a rule that is only present in one of the sets is treated as never
+/// matching in the other. The deltas are returned ordered by rule id, and rules that matched the
+/// same documents in both sets are omitted.
+pub fn compare<'a, I, D>(a: &RuleSet, b: &RuleSet, corpus: I) -> Vec<Delta>
+where
+    I: IntoIterator<Item = &'a D>,
+    D: Document + 'a,
+{
+    let mut deltas: BTreeMap<&str, Delta> = BTreeMap::new();
+    for (index, document) in corpus.into_iter().enumerate() {
+        let x: HashSet<&str> = a.matches(document).into_iter().collect();
+        let y: HashSet<&str> = b.matches(document).into_iter().collect();
+        for id in x.difference(&y) {
+            deltas
+                .entry(id)
+                .or_insert_with(|| Delta {
+                    id: id.to_string(),
+                    ..Default::default()
+                })
+                .lost
+                .push(index);
+        }
+        for id in y.difference(&x) {
+            deltas
+                .entry(id)
+                .or_insert_with(|| Delta {
+                    id: id.to_string(),
+                    ..Default::default()
+                })
+                .gained
+                .push(index);
+        }
+    }
+    deltas.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_yaml::{Mapping, Value as Yaml};
+
+    fn rule(value: &str) -> Rule {
+        Rule::from_str(&format!(
+            r#"
+            detection:
+              A:
+                foo: {}
+              condition: A
+            true_positives: []
+            true_negatives: []
+            "#,
+            value
+        ))
+        .unwrap()
+    }
+
+    fn document(value: &str) -> Mapping {
+        serde_yaml::from_str::<Yaml>(&format!("foo: {}", value))
+            .unwrap()
+            .as_mapping()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn compare_rulesets() {
+        let mut a = RuleSet::new();
+        a.insert("changed", rule("'foo*'"));
+        a.insert("removed", rule("foobar"));
+        a.insert("same", rule("'*bar'"));
+        let mut b = RuleSet::new();
+        b.insert("added", rule("baz"));
+        b.insert("changed", rule("'*bar'"));
+        b.insert("same", rule("'*bar'"));
+
+        let corpus = vec![document("foobar"), document("foobaz"), document("baz")];
+        let deltas = compare(&a, &b, &corpus);
+
+        assert_eq!(
+            deltas,
+            vec![
+                Delta {
+                    id: "added".to_owned(),
+                    gained: vec![2],
+                    lost: vec![],
+                },
+                Delta {
+                    id: "changed".to_owned(),
+                    gained: vec![],
+                    lost: vec![1],
+                },
+                Delta {
+                    id: "removed".to_owned(),
+                    gained: vec![],
+                    lost: vec![0],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reevaluate_affected() {
+        let mut rules = RuleSet::new();
+        rules.insert("foo", rule("foo"));
+        rules.insert(
+            "bar",
+            Rule::from_str(
+                r#"
+                detection:
+                  A:
+                    bar.baz: foo
+                  condition: A
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(rules.affected(&["foo"]), vec!["foo"]);
+        assert_eq!(rules.affected(&["bar"]), vec!["bar"]);
+        assert_eq!(rules.affected(&["bar.baz.qux"]), vec!["bar"]);
+        assert!(rules.affected(&["ba"]).is_empty());
+
+        let document = serde_yaml::from_str::<Yaml>("{foo: foo, bar: {baz: foo}}").unwrap();
+        let document = document.as_mapping().unwrap();
+        assert_eq!(rules.reevaluate(document, &["bar"]), vec!["bar"]);
+        assert_eq!(
+            rules.reevaluate(document, &["foo", "bar"]),
+            vec!["foo", "bar"]
+        );
+
+        rules.insert(
+            "baz",
+            Rule::from_str(
+                r#"
+                detection:
+                  A:
+                    - mimikatz
+                  condition: A
+                true_positives: []
+                true_negatives: []
+                "#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(rules.affected(&["qux"]), vec!["baz"]);
+        assert!(rules.affected(&[]).is_empty());
+
+        let document = serde_yaml::from_str::<Yaml>("{foo: foo, qux: [mimikatz.exe]}").unwrap();
+        let document = document.as_mapping().unwrap();
+        assert_eq!(rules.reevaluate(document, &["qux"]), vec!["baz"]);
+        assert_eq!(rules.reevaluate(document, &["foo"]), vec!["foo", "baz"]);
+    }
+
+    #[test]
+    fn scan() {
+        let mut rules = RuleSet::new();
+        rules.insert("bar", rule("'*bar'"));
+        rules.insert("foo", rule("'foo*'"));
+
+        let corpus = vec![document("foobar"), document("baz"), document("foobaz")];
+        let matches: Vec<_> = rules.scan(corpus).collect();
+        assert_eq!(matches, vec![(0, "bar"), (0, "foo"), (2, "foo")]);
+
+        let mut scan = rules.scan((0..).map(|i| document(&format!("foo{}", i))));
+        assert_eq!(scan.next(), Some((0, "foo")));
+        assert_eq!(scan.nth(99), Some((100, "foo")));
+    }
+
+    #[test]
+    fn matches_iter() {
+        let mut rules = RuleSet::new();
+        rules.insert("bar", rule("'*bar'"));
+        rules.insert("baz", rule("'*baz'"));
+        rules.insert("foo", rule("'foo*'"));
+
+        let document = document("foobar");
+        let mut matches = rules.matches_iter(&document);
+        assert_eq!(matches.next(), Some("bar"));
+        // Only the rules after the first match are left to evaluate.
+        assert_eq!(matches.size_hint(), (0, Some(2)));
+        assert_eq!(matches.collect::<Vec<_>>(), vec!["foo"]);
+        assert_eq!(rules.matches(&document), vec!["bar", "foo"]);
+    }
+
+    #[test]
+    fn first_match_at_least() {
+        let severe = |value: &str, severity: Severity| {
+            let mut rule = rule(value);
+            rule.severity = Some(severity);
+            rule
+        };
+        let mut rules = RuleSet::new();
+        rules.insert("any", rule("'foo*'"));
+        rules.insert("low", severe("'foo*'", Severity::Low));
+        rules.insert("high", severe("'*baz'", Severity::High));
+        rules.insert("critical", severe("'*bar'", Severity::Critical));
+        rules.insert("medium", severe("'foo*'", Severity::Medium));
+
+        let foobar = document("foobar");
+        let foobaz = document("foobaz");
+        assert_eq!(
+            rules.first_match_at_least(Severity::Low, &foobar),
+            Some("critical")
+        );
+        assert_eq!(
+            rules.first_match_at_least(Severity::Low, &foobaz),
+            Some("high")
+        );
+        assert_eq!(
+            rules.first_match_at_least(Severity::Critical, &foobaz),
+            None
+        );
+        assert_eq!(
+            rules.first_match_at_least(Severity::Informational, &document("qux")),
+            None
+        );
+
+        // Replacing a rule moves it to its new severity.
+        rules.insert("critical", severe("'*bar'", Severity::Low));
+        assert_eq!(
+            rules.first_match_at_least(Severity::Low, &foobar),
+            Some("medium")
+        );
+        rules.insert("medium", rule("'foo*'"));
+        assert_eq!(
+            rules.first_match_at_least(Severity::Low, &foobar),
+            Some("low")
+        );
+    }
+
+    #[test]
+    fn evaluate_limits() {
+        let mut rules = RuleSet::new().limits(Limits::new().bytes(16));
+        rules.insert("a", rule("foo*"));
+        assert_eq!(rules.evaluate(&document("foobar")), Ok(vec!["a"]));
+        assert_eq!(
+            rules.evaluate(&document("foobarbazquxquux")),
+            Err(Rejection::Bytes(16))
+        );
+    }
+
+    #[test]
+    fn matches_with_deadline() {
+        let mut rules = RuleSet::new();
+        rules.insert("a", rule("'foo*'"));
+        rules.insert("b", rule("bar"));
+        rules.insert("c", rule("'*bar'"));
+
+        let mut deadline = Deadline::new(std::time::Duration::from_secs(60));
+        assert_eq!(
+            rules.matches_with_deadline(&document("foobar"), &mut deadline),
+            Evaluation {
+                matched: vec!["a", "c"],
+                timed_out: vec![],
+            }
+        );
+        assert!(!deadline.timed_out());
+
+        let mut deadline = Deadline::new(std::time::Duration::ZERO);
+        assert_eq!(
+            rules.matches_with_deadline(&document("foobar"), &mut deadline),
+            Evaluation {
+                matched: vec![],
+                timed_out: vec!["a", "b", "c"],
+            }
+        );
+        assert!(deadline.timed_out());
+    }
+
+    #[test]
+    fn trace() {
+        let mut rules = RuleSet::new();
+        rules.insert("a", rule("foo"));
+        rules.insert("b", rule("bar"));
+        assert!(rules.trace("a", true));
+        assert!(!rules.trace("c", true));
+        rules.insert("a", rule("bar"));
+        assert!(rules.rules[0].traced);
+        assert!(!rules.rules[1].traced);
+
+        assert!(rules.rules[0].trace(crate::solver::traced));
+        assert!(!rules.rules[1].trace(crate::solver::traced));
+        assert_eq!(rules.matches(&document("bar")), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut rules = RuleSet::new();
+        assert!(rules.insert("a", rule("foo")).is_none());
+        assert!(rules.insert("a", rule("bar")).is_some());
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules.matches(&document("bar")), vec!["a"]);
+    }
+
+    #[test]
+    fn prefilter() {
+        let values = [
+            "bar",
+            "'*bar'",
+            "'foo*'",
+            "'i*BAZ*'",
+            "'?ba.'",
+            "['', qux]",
+            "[foo, '?quux']",
+        ];
+        let mut plain = RuleSet::new();
+        let mut prefiltered = RuleSet::new().prefilter(true);
+        for (i, value) in values.iter().enumerate() {
+            plain.insert(i.to_string(), rule(value));
+            prefiltered.insert(i.to_string(), rule(value));
+        }
+        let corpus: Vec<_> = [
+            "foobar",
+            "bar",
+            "baz",
+            "xBaZx",
+            "''",
+            "qux",
+            "quux",
+            "[bar, foo]",
+        ]
+        .iter()
+        .map(|v| document(v))
+        .collect();
+        for document in &corpus {
+            assert_eq!(prefiltered.matches(document), plain.matches(document));
+            assert_eq!(
+                prefiltered.first_match_by_severity(document),
+                plain.first_match_by_severity(document)
+            );
+        }
+        assert_eq!(
+            prefiltered.scan(corpus.clone()).collect::<Vec<_>>(),
+            plain.scan(corpus.clone()).collect::<Vec<_>>()
+        );
+
+        // The index is updated as the set changes.
+        assert_eq!(prefiltered.matches(&document("lorem")), Vec::<&str>::new());
+        prefiltered.insert("lorem", rule("lorem"));
+        assert_eq!(prefiltered.matches(&document("lorem")), vec!["lorem"]);
+    }
+
+    #[test]
+    fn remove_replace() {
+        let mut rules = RuleSet::new().prefilter(true);
+        for (id, value) in [("a", "foo"), ("b", "'*bar'"), ("c", "'?ba.'"), ("d", "foo")] {
+            rules.insert(id, rule(value));
+        }
+        assert_eq!(rules.matches(&document("foo")), vec!["a", "d"]);
+
+        assert!(rules.replace("e", rule("foo")).is_none());
+        assert!(rules.get("e").is_none());
+        assert!(rules.replace("b", rule("'baz*'")).is_some());
+        assert!(rules.remove("a").is_some());
+        assert!(rules.remove("a").is_none());
+        rules.insert("e", rule("'*bar'"));
+        assert_eq!(
+            rules.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec!["b", "c", "d", "e"]
+        );
+
+        // The updated index must be the same as one built from scratch.
+        let rebuilt = Prefilter::new(rules.rules.iter().map(|e| &e.rule.detection));
+        for value in ["foo", "foobar", "bazqux", "bar", "xbax"] {
+            let document = document(value);
+            assert_eq!(
+                rules.literals.get().unwrap().candidates(&document),
+                rebuilt.candidates(&document),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(rules.matches(&document("foo")), vec!["d"]);
+        assert_eq!(rules.matches(&document("bazbar")), vec!["b", "c", "e"]);
+        assert_eq!(rules.matches(&document("xbax")), vec!["c"]);
+    }
+}
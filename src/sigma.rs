@@ -0,0 +1,848 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value as Yaml};
+
+use crate::diagnostic::Diagnostic;
+use crate::identifier;
+use crate::loss::{Loss, LossKind};
+use crate::node;
+use crate::parser::Hex;
+use crate::rule::Rule;
+
+/// A Sigma rule that has been converted into a Tau rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conversion {
+    /// The converted rule as YAML, or `None` if the rule contained unsupported constructs.
+    pub rule: Option<String>,
+    /// The constructs that could not be represented exactly.
+    pub losses: Vec<Loss>,
+}
+
+/// The outcome of converting a directory of Sigma rules.
+///
+/// Rules are referenced by their path relative to the directory that was converted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// The rules that were converted.
+    pub converted: Vec<PathBuf>,
+    /// The constructs that could not be represented exactly, for both the rules that were
+    /// converted and those that were skipped.
+    pub losses: Vec<(PathBuf, Vec<Loss>)>,
+    /// The rules that could not be converted, along with the reason why.
+    pub skipped: Vec<(PathBuf, String)>,
+}
+
+/// Converts Sigma rules into Tau rules.
+///
+/// Only the detection block of a Sigma rule is converted, and the converted rule is given empty
+/// `true_positives` and `true_negatives`. Rules that use features without an equivalent in Tau,
+/// such as keyword searches, aggregations and encoding modifiers, are not converted, instead each
+/// of these constructs is reported as a `Loss`.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::SigmaConverter;
+///
+/// let converter = SigmaConverter::new().field("CommandLine", "process.command_line");
+/// let conversion = converter.convert(r#"
+/// title: Whoami
+/// detection:
+///   selection:
+///     CommandLine|contains: whoami
+///   condition: selection
+/// "#).unwrap();
+/// assert!(conversion.rule.unwrap().contains("process.command_line: i*whoami*"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Converter {
+    fields: HashMap<String, String>,
+}
+
+impl Converter {
+    /// Create a new converter without any field mappings.
+    pub fn new() -> Self {
+        Converter::default()
+    }
+
+    /// Map a Sigma field to the field that it should be renamed to in the converted rules.
+    ///
+    /// Once a mapping has been provided, any field without one is reported as a lossy conversion.
+    pub fn field<A: Into<String>, B: Into<String>>(mut self, from: A, to: B) -> Self {
+        self.fields.insert(from.into(), to.into());
+        self
+    }
+
+    /// Converts a Sigma rule into a Tau rule.
+    ///
+    /// An error is only returned when the Sigma rule is malformed, constructs that cannot be
+    /// represented are reported in the returned `Conversion`.
+    pub fn convert(&self, sigma: &str) -> crate::Result<Conversion> {
+        let yaml: Yaml = serde_yaml::from_str(sigma).map_err(crate::error::rule_invalid)?;
+        let detection = match yaml.get("detection") {
+            Some(Yaml::Mapping(m)) => m,
+            _ => {
+                return Err(crate::error::rule_invalid(Diagnostic::new(
+                    "sigma.invalid_detection",
+                )))
+            }
+        };
+        let mut losses = vec![];
+        let mut identifiers = Mapping::new();
+        let mut names = vec![];
+        let mut condition = None;
+        for (k, v) in detection {
+            let name = match k.as_str() {
+                Some(s) => s,
+                None => {
+                    return Err(crate::error::rule_invalid(
+                        Diagnostic::new("sigma.invalid_detection_key")
+                            .with("key", format!("{:?}", k)),
+                    ))
+                }
+            };
+            let location = format!("/detection/{}", node::escape(name));
+            match name {
+                "condition" => condition = Some(v),
+                "timeframe" => losses.push(Loss::unsupported(
+                    location,
+                    name,
+                    "correlations over time are not supported",
+                )),
+                _ => {
+                    names.push(name);
+                    if !name
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || c == '_' || c == '.' || c == '#')
+                    {
+                        losses.push(Loss::unsupported(
+                            location,
+                            name,
+                            "identifiers can only contain alphanumerics, '_', '.' and '#'",
+                        ));
+                        continue;
+                    }
+                    if let Some(selection) = self.selection(v, &location, &mut losses)? {
+                        identifiers.insert(Yaml::String(name.to_owned()), selection);
+                    }
+                }
+            }
+        }
+        let location = "/detection/condition";
+        let condition = match condition {
+            Some(Yaml::String(s)) => self::condition(s, &names, location, &mut losses)?,
+            Some(Yaml::Sequence(s)) => {
+                let mut conditions = vec![];
+                for (i, c) in s.iter().enumerate() {
+                    let location = format!("{}/{}", location, i);
+                    match c.as_str() {
+                        Some(c) => {
+                            if let Some(c) = self::condition(c, &names, &location, &mut losses)? {
+                                conditions.push(format!("({})", c));
+                            }
+                        }
+                        None => {
+                            return Err(crate::error::rule_invalid(
+                                Diagnostic::new("sigma.invalid_condition")
+                                    .with("condition", format!("{:?}", c)),
+                            ))
+                        }
+                    }
+                }
+                Some(conditions.join(" or "))
+            }
+            _ => {
+                return Err(crate::error::rule_invalid(Diagnostic::new(
+                    "sigma.missing_condition",
+                )))
+            }
+        };
+        if losses.iter().any(|l| l.kind == LossKind::Unsupported) {
+            return Ok(Conversion { rule: None, losses });
+        }
+        identifiers.insert(
+            Yaml::String("condition".to_owned()),
+            Yaml::String(condition.expect("condition was converted")),
+        );
+
+        let mut rule = Mapping::new();
+        rule.insert(
+            Yaml::String("detection".to_owned()),
+            Yaml::Mapping(identifiers),
+        );
+        rule.insert(
+            Yaml::String("true_positives".to_owned()),
+            Yaml::Sequence(vec![]),
+        );
+        rule.insert(
+            Yaml::String("true_negatives".to_owned()),
+            Yaml::Sequence(vec![]),
+        );
+        let rule = serde_yaml::to_string(&rule).map_err(crate::error::rule_invalid)?;
+        let rule = match rule.strip_prefix("---\n") {
+            Some(rule) => rule.to_owned(),
+            None => rule,
+        };
+        // NOTE: Make sure that we never hand back a rule that cannot be loaded.
+        Rule::from_str(&rule)?;
+        Ok(Conversion {
+            rule: Some(rule),
+            losses,
+        })
+    }
+
+    /// Converts every Sigma rule (`.yml` or `.yaml`) within the `src` directory, writing the
+    /// converted rules to the same relative path within the `dst` directory.
+    ///
+    /// Rules that cannot be converted are skipped and recorded in the returned `Report`, an error
+    /// is only returned when the directories cannot be read from or written to.
+    ///
+    /// This is also available from the command line as `tau convert`.
+    pub fn convert_dir(&self, src: &Path, dst: &Path) -> crate::Result<Report> {
+        let mut paths = vec![];
+        walk(src, &mut paths)?;
+        paths.sort();
+        let mut report = Report::default();
+        for path in paths {
+            let relative = path
+                .strip_prefix(src)
+                .expect("path is within the source directory")
+                .to_path_buf();
+            let conversion = fs::read_to_string(&path)
+                .map_err(crate::error::rule_invalid)
+                .and_then(|s| self.convert(&s));
+            let conversion = match conversion {
+                Ok(conversion) => conversion,
+                Err(e) => {
+                    let reason = match e.source() {
+                        Some(source) => source.to_string(),
+                        None => e.to_string(),
+                    };
+                    report.skipped.push((relative, reason));
+                    continue;
+                }
+            };
+            match conversion.rule {
+                Some(rule) => {
+                    let out = dst.join(&relative).with_extension("yml");
+                    if let Some(parent) = out.parent() {
+                        fs::create_dir_all(parent).map_err(crate::error::rule_invalid)?;
+                    }
+                    fs::write(&out, rule).map_err(crate::error::rule_invalid)?;
+                    report.converted.push(relative.clone());
+                }
+                None => {
+                    let unsupported = conversion
+                        .losses
+                        .iter()
+                        .filter(|l| l.kind == LossKind::Unsupported)
+                        .map(|l| l.construct.as_str())
+                        .collect::<Vec<_>>();
+                    report.skipped.push((
+                        relative.clone(),
+                        format!("unsupported constructs - {}", unsupported.join(", ")),
+                    ));
+                }
+            }
+            if !conversion.losses.is_empty() {
+                report.losses.push((relative, conversion.losses));
+            }
+        }
+        Ok(report)
+    }
+
+    fn selection(
+        &self,
+        yaml: &Yaml,
+        location: &str,
+        losses: &mut Vec<Loss>,
+    ) -> crate::Result<Option<Yaml>> {
+        match yaml {
+            Yaml::Mapping(mapping) => {
+                let mut converted = Mapping::new();
+                for (k, v) in mapping {
+                    let key = match k.as_str() {
+                        Some(s) => s,
+                        None => {
+                            return Err(crate::error::rule_invalid(
+                                Diagnostic::new("sigma.invalid_selection_key")
+                                    .with("key", format!("{:?}", k)),
+                            ))
+                        }
+                    };
+                    let location = format!("{}/{}", location, node::escape(key));
+                    if let Some((k, v)) = self.search(key, v, &location, losses) {
+                        converted.insert(k, v);
+                    }
+                }
+                Ok(Some(Yaml::Mapping(converted)))
+            }
+            Yaml::Sequence(sequence) if sequence.iter().all(|s| s.is_mapping()) => {
+                let mut converted = vec![];
+                for (i, s) in sequence.iter().enumerate() {
+                    let location = format!("{}/{}", location, i);
+                    if let Some(s) = self.selection(s, &location, losses)? {
+                        converted.push(s);
+                    }
+                }
+                Ok(Some(Yaml::Sequence(converted)))
+            }
+            _ => {
+                losses.push(Loss::unsupported(
+                    location,
+                    "keywords",
+                    "keyword searches are not supported",
+                ));
+                Ok(None)
+            }
+        }
+    }
+
+    fn search(
+        &self,
+        key: &str,
+        value: &Yaml,
+        location: &str,
+        losses: &mut Vec<Loss>,
+    ) -> Option<(Yaml, Yaml)> {
+        let mut parts = key.split('|');
+        let field = parts.next().unwrap_or_default();
+        let field = match self.fields.get(field) {
+            Some(f) => f.clone(),
+            None => {
+                if !self.fields.is_empty() {
+                    losses.push(Loss::approximated(
+                        location,
+                        field,
+                        "the field has no mapping so has been left as is",
+                    ));
+                }
+                field.to_owned()
+            }
+        };
+        let mut all = false;
+        let mut cased = false;
+        let mut windash = false;
+        let mut modifier = Modifier::Exact;
+        for part in parts {
+            modifier = match (part, modifier) {
+                ("all", m) => {
+                    all = true;
+                    m
+                }
+                ("cased", m) => {
+                    cased = true;
+                    m
+                }
+                ("windash", m) => {
+                    windash = true;
+                    m
+                }
+                ("contains", Modifier::Exact) => Modifier::Contains,
+                ("endswith", Modifier::Exact) => Modifier::EndsWith,
+                ("startswith", Modifier::Exact) => Modifier::StartsWith,
+                ("gt", Modifier::Exact) => Modifier::Compare(">"),
+                ("gte", Modifier::Exact) => Modifier::Compare(">="),
+                ("lt", Modifier::Exact) => Modifier::Compare("<"),
+                ("lte", Modifier::Exact) => Modifier::Compare("<="),
+                ("re", Modifier::Exact) => Modifier::Regex(String::new()),
+                ("i", Modifier::Regex(f))
+                | ("m", Modifier::Regex(f))
+                | ("s", Modifier::Regex(f)) => Modifier::Regex(f + part),
+                (m, _) => {
+                    losses.push(Loss::unsupported(
+                        location,
+                        m,
+                        "the modifier has no equivalent",
+                    ));
+                    return None;
+                }
+            };
+        }
+        if cased && cfg!(feature = "ignore_case") {
+            losses.push(Loss::approximated(
+                location,
+                "cased",
+                "all searches are case insensitive",
+            ));
+        }
+        let key = match (all, windash) {
+            (true, true) => {
+                losses.push(Loss::unsupported(
+                    location,
+                    "all|windash",
+                    "the modifiers cannot be combined",
+                ));
+                return None;
+            }
+            (true, false) => format!("all({})", field),
+            (false, true) => format!("windash({})", field),
+            (false, false) => field,
+        };
+        let unsupported = |losses: &mut Vec<Loss>, value: &Yaml| {
+            losses.push(Loss::unsupported(
+                location,
+                format!("{:?}", value),
+                "values must be scalars",
+            ));
+        };
+        let value = match value {
+            Yaml::Sequence(sequence) => {
+                let mut values = vec![];
+                for v in sequence {
+                    match self::value(v, &modifier, cased) {
+                        Some(v) => values.push(v),
+                        None => {
+                            unsupported(losses, v);
+                            return None;
+                        }
+                    }
+                }
+                Yaml::Sequence(values)
+            }
+            // NOTE: Tau only supports `all` on sequences
+            v => match self::value(v, &modifier, cased) {
+                Some(v) if all => Yaml::Sequence(vec![v]),
+                Some(v) => v,
+                None => {
+                    unsupported(losses, v);
+                    return None;
+                }
+            },
+        };
+        Some((Yaml::String(key), value))
+    }
+}
+
+#[derive(Clone, PartialEq)]
+enum Modifier {
+    Compare(&'static str),
+    Contains,
+    EndsWith,
+    Exact,
+    Regex(String),
+    StartsWith,
+}
+
+#[derive(Debug, PartialEq)]
+enum Glob {
+    Any,
+    Literal(String),
+    One,
+}
+
+// Converts a Sigma condition, expanding any `x of` quantifiers into the identifiers they refer to.
+fn condition(
+    condition: &str,
+    names: &[&str],
+    location: &str,
+    losses: &mut Vec<Loss>,
+) -> crate::Result<Option<String>> {
+    let mut tokens = vec![];
+    for word in condition.split_whitespace() {
+        let mut word = word;
+        while let Some(w) = word.strip_prefix('(') {
+            tokens.push("(");
+            word = w;
+        }
+        let mut closing = 0;
+        while let Some(w) = word.strip_suffix(')') {
+            closing += 1;
+            word = w;
+        }
+        if !word.is_empty() {
+            tokens.push(word);
+        }
+        tokens.resize(tokens.len() + closing, ")");
+    }
+    let mut converted = String::new();
+    let mut iter = tokens.into_iter().peekable();
+    while let Some(token) = iter.next() {
+        let part = match token {
+            "(" | ")" | "and" | "not" | "or" => token.to_owned(),
+            "|" => {
+                let aggregation = iter.next().unwrap_or_default();
+                let aggregation = aggregation.split('(').next().unwrap_or_default();
+                losses.push(Loss::unsupported(
+                    location,
+                    aggregation,
+                    "aggregations are not supported",
+                ));
+                return Ok(None);
+            }
+            quantifier if iter.peek() == Some(&"of") => {
+                iter.next();
+                let target = match iter.next() {
+                    Some(t) => t,
+                    None => {
+                        return Err(crate::error::rule_invalid(Diagnostic::new(
+                            "sigma.quantifier_without_target",
+                        )))
+                    }
+                };
+                let matched = names
+                    .iter()
+                    .filter(|n| {
+                        if target == "them" {
+                            !n.starts_with('_')
+                        } else {
+                            glob(target, n)
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                let symbol = match quantifier {
+                    "1" => "or",
+                    "all" => "and",
+                    q if q.parse::<usize>() == Ok(matched.len()) => "and",
+                    q => {
+                        losses.push(Loss::unsupported(
+                            location,
+                            format!("{} of {}", q, target),
+                            "only `1 of` and `all of` are supported",
+                        ));
+                        return Ok(None);
+                    }
+                };
+                match matched.len() {
+                    0 => {
+                        return Err(crate::error::rule_invalid(
+                            Diagnostic::new("sigma.no_matching_identifiers")
+                                .with("pattern", target),
+                        ))
+                    }
+                    1 => matched[0].to_string(),
+                    _ => format!(
+                        "({})",
+                        matched
+                            .iter()
+                            .map(|n| n.to_string())
+                            .collect::<Vec<_>>()
+                            .join(&format!(" {} ", symbol))
+                    ),
+                }
+            }
+            name if names.contains(&name) => name.to_owned(),
+            name => {
+                return Err(crate::error::rule_invalid(
+                    Diagnostic::new("sigma.unknown_identifier").with("identifier", name),
+                ))
+            }
+        };
+        if !(converted.is_empty() || converted.ends_with('(') || part == ")") {
+            converted.push(' ');
+        }
+        converted.push_str(&part);
+    }
+    Ok(Some(converted))
+}
+
+// Returns true if the name matches the Sigma identifier pattern, where `*` matches anything.
+fn glob(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => match name.strip_prefix(prefix) {
+            Some(name) => (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| glob(rest, &name[i..])),
+            None => false,
+        },
+    }
+}
+
+// Splits a Sigma string into its literals and wildcards.
+fn parse(value: &str) -> Vec<Glob> {
+    let mut globs = vec![];
+    let mut literal = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        let glob = match c {
+            '\\' => {
+                match chars.peek() {
+                    Some(n) if ['*', '?', '\\'].contains(n) => {
+                        literal.push(*n);
+                        chars.next();
+                    }
+                    _ => literal.push(c),
+                }
+                continue;
+            }
+            '*' => Glob::Any,
+            '?' => Glob::One,
+            _ => {
+                literal.push(c);
+                continue;
+            }
+        };
+        if !literal.is_empty() {
+            globs.push(Glob::Literal(std::mem::take(&mut literal)));
+        }
+        if !(glob == Glob::Any && globs.last() == Some(&Glob::Any)) {
+            globs.push(glob);
+        }
+    }
+    if !literal.is_empty() {
+        globs.push(Glob::Literal(literal));
+    }
+    globs
+}
+
+// Converts a Sigma value into the equivalent Tau value.
+fn value(yaml: &Yaml, modifier: &Modifier, cased: bool) -> Option<Yaml> {
+    let string = match yaml {
+        Yaml::Bool(b) if *modifier == Modifier::Exact => return Some(Yaml::Bool(*b)),
+        Yaml::Null if *modifier == Modifier::Exact => return Some(Yaml::Null),
+        Yaml::Number(n) if *modifier == Modifier::Exact => return Some(Yaml::Number(n.clone())),
+        Yaml::Bool(b) => b.to_string(),
+        Yaml::Number(n) => n.to_string(),
+        Yaml::String(s) => s.clone(),
+        _ => return None,
+    };
+    // NOTE: Sigma is case insensitive by default, unless the regex modifier is being used.
+    let insensitive = if cased || cfg!(feature = "ignore_case") {
+        ""
+    } else {
+        "i"
+    };
+    let pattern = match modifier {
+        Modifier::Compare(symbol) => format!("{}{}", symbol, string),
+        Modifier::Regex(flags) => {
+            let (insensitive, flags) = match flags.contains('i') {
+                true if !cfg!(feature = "ignore_case") => ("i", flags.replace('i', "")),
+                _ => ("", flags.clone()),
+            };
+            if flags.is_empty() {
+                format!("{}?{}", insensitive, string)
+            } else {
+                format!("{}?(?{}){}", insensitive, flags, string)
+            }
+        }
+        Modifier::Contains => wildcard(&format!("*{}*", string), insensitive),
+        Modifier::EndsWith => wildcard(&format!("*{}", string), insensitive),
+        Modifier::Exact => wildcard(&string, insensitive),
+        Modifier::StartsWith => wildcard(&format!("{}*", string), insensitive),
+    };
+    Some(Yaml::String(pattern))
+}
+
+// Converts a Sigma string with wildcards into a Tau pattern, falling back to a regex when there is
+// no direct equivalent.
+fn wildcard(value: &str, insensitive: &str) -> String {
+    // NOTE: Patterns that would be misread when parsed need to be quoted or made into a regex.
+    let prefixed = |s: &str| {
+        s.starts_with(|c| "*<=>?@~^".contains(c))
+            || identifier::range(s).is_some()
+            || Hex::parse(s).is_some()
+            || (insensitive.is_empty() && !cfg!(feature = "ignore_case") && s.starts_with('i'))
+    };
+    let globs = parse(value);
+    match globs.as_slice() {
+        [] => format!("{}\"\"", insensitive),
+        [Glob::Any] => "*".to_owned(),
+        [Glob::Literal(s)] => {
+            if prefixed(s) || s.starts_with(['"', '\'']) || s.ends_with('*') {
+                format!("{}\"{}\"", insensitive, s)
+            } else {
+                format!("{}{}", insensitive, s)
+            }
+        }
+        [Glob::Any, Glob::Literal(s), Glob::Any] => format!("{}*{}*", insensitive, s),
+        [Glob::Any, Glob::Literal(s)] if !s.ends_with('*') => format!("{}*{}", insensitive, s),
+        [Glob::Literal(s), Glob::Any] if !prefixed(s) => format!("{}{}*", insensitive, s),
+        globs => {
+            let mut regex = String::from("^");
+            for glob in globs {
+                match glob {
+                    Glob::Any => regex.push_str(".*"),
+                    Glob::Literal(s) => regex.push_str(&regex::escape(s)),
+                    Glob::One => regex.push('.'),
+                }
+            }
+            regex.push('$');
+            format!("{}?(?s){}", insensitive, regex)
+        }
+    }
+}
+
+fn walk(dir: &Path, paths: &mut Vec<PathBuf>) -> crate::Result<()> {
+    for entry in fs::read_dir(dir).map_err(crate::error::rule_invalid)? {
+        let path = entry.map_err(crate::error::rule_invalid)?.path();
+        if path.is_dir() {
+            walk(&path, paths)?;
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("yml") | Some("yaml")
+        ) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert() {
+        let converter = Converter::new()
+            .field("Image", "process.image")
+            .field("CommandLine", "process.command_line");
+        let conversion = converter
+            .convert(
+                r#"
+                title: Test
+                detection:
+                  selection_img:
+                    Image|endswith: '\cmd.exe'
+                  selection_cli:
+                    CommandLine|contains|all:
+                    - '/c'
+                    - 'whoami'
+                  filter:
+                    User: SYSTEM
+                  condition: all of selection_* and not filter
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            conversion.losses,
+            vec![Loss::approximated(
+                "/detection/filter/User",
+                "User",
+                "the field has no mapping so has been left as is"
+            )]
+        );
+
+        let rule = Rule::from_str(&conversion.rule.unwrap()).unwrap();
+        assert_eq!(
+            rule.node("/selection_cli").unwrap().yaml,
+            serde_yaml::from_str::<Yaml>("all(process.command_line): [i*/c*, i*whoami*]").unwrap()
+        );
+        let document = serde_yaml::from_str::<Yaml>(
+            r#"{process: {image: 'C:\Windows\CMD.exe', command_line: 'cmd /c WHOAMI'}, User: admin}"#,
+        )
+        .unwrap();
+        assert!(rule.matches(document.as_mapping().unwrap()));
+        let document = serde_yaml::from_str::<Yaml>(
+            r#"{process: {image: 'C:\Windows\cmd.exe', command_line: 'cmd /c whoami'}, User: SYSTEM}"#,
+        )
+        .unwrap();
+        assert!(!rule.matches(document.as_mapping().unwrap()));
+    }
+
+    #[test]
+    fn condition_quantifiers() {
+        let names = ["sel1", "sel2", "_hidden", "filter"];
+        let mut losses = vec![];
+        let mut convert = |c| condition(c, &names, "/", &mut losses);
+        assert_eq!(
+            convert("1 of sel* and not filter").unwrap().unwrap(),
+            "(sel1 or sel2) and not filter"
+        );
+        assert_eq!(
+            convert("all of them").unwrap().unwrap(),
+            "(sel1 and sel2 and filter)"
+        );
+        assert_eq!(
+            convert("(sel1 or sel2) and not 1 of filter*")
+                .unwrap()
+                .unwrap(),
+            "(sel1 or sel2) and not filter"
+        );
+        assert!(convert("missing").is_err());
+        assert!(convert("sel1 | count() > 5").unwrap().is_none());
+        assert_eq!(losses[0].construct, "count");
+    }
+
+    #[test]
+    fn losses() {
+        let conversion = Converter::new()
+            .convert(
+                r#"
+                detection:
+                  keywords:
+                  - evil
+                  selection:
+                    Payload|base64offset|contains: evil
+                    Image|endswith: '\cmd.exe'
+                  timeframe: 5m
+                  condition: selection and keywords | near other
+                "#,
+            )
+            .unwrap();
+        assert!(conversion.rule.is_none());
+        assert_eq!(
+            conversion
+                .losses
+                .iter()
+                .map(|l| (l.location.as_str(), l.construct.as_str(), l.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                ("/detection/keywords", "keywords", LossKind::Unsupported),
+                (
+                    "/detection/selection/Payload|base64offset|contains",
+                    "base64offset",
+                    LossKind::Unsupported
+                ),
+                ("/detection/timeframe", "timeframe", LossKind::Unsupported),
+                ("/detection/condition", "near", LossKind::Unsupported),
+            ]
+        );
+        assert_eq!(
+            conversion.losses[3].to_string(),
+            "/detection/condition: unsupported 'near' - aggregations are not supported"
+        );
+    }
+
+    #[test]
+    fn convert_dir() {
+        let root = std::env::temp_dir().join(format!("tau-engine-sigma-{}", std::process::id()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(
+            src.join("nested").join("ok.yaml"),
+            "detection:\n  selection:\n    foo: bar\n  condition: selection\n",
+        )
+        .unwrap();
+        fs::write(
+            src.join("keywords.yml"),
+            "detection:\n  keywords:\n  - foo\n  condition: keywords\n",
+        )
+        .unwrap();
+        fs::write(src.join("README.md"), "not a rule").unwrap();
+
+        let report = Converter::new().convert_dir(&src, &dst).unwrap();
+        let converted = fs::read_to_string(dst.join("nested").join("ok.yml"));
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(report.converted, vec![Path::new("nested").join("ok.yaml")]);
+        assert_eq!(
+            report.skipped,
+            vec![(
+                PathBuf::from("keywords.yml"),
+                "unsupported constructs - keywords".to_owned()
+            )]
+        );
+        assert_eq!(report.losses.len(), 1);
+        assert!(Rule::from_str(&converted.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert_eq!(wildcard("foo", "i"), "ifoo");
+        assert_eq!(wildcard("*foo*", "i"), "i*foo*");
+        assert_eq!(wildcard("?foo", "i"), "i?(?s)^.foo$");
+        assert_eq!(wildcard(r"\?foo", "i"), "i\"?foo\"");
+        assert_eq!(wildcard("foo*bar", ""), "?(?s)^foo.*bar$");
+        assert_eq!(wildcard("ifoo*", ""), "?(?s)^ifoo.*$");
+        assert_eq!(wildcard(r"foo\*", "i"), "i\"foo*\"");
+        assert_eq!(wildcard("**", "i"), "*");
+        assert_eq!(wildcard("^foo", ""), "\"^foo\"");
+        assert_eq!(wildcard("1..4", "i"), "i\"1..4\"");
+        assert_eq!(wildcard("{ 4D 5A }", ""), "\"{ 4D 5A }\"");
+    }
+}
@@ -0,0 +1,134 @@
+// Maps confusable characters to their ASCII skeleton, so that spoofed values such as `pаypal.com`,
+// with a Cyrillic `а`, are matched by rules written in plain ASCII.
+//
+// This is a subset of the Unicode confusables, see UTS #39, limited to the characters that map to a
+// single ASCII character and are commonly abused to spoof domains and process names: Cyrillic and
+// Greek lookalikes, full-width forms and invisible formatting characters, which are removed.
+
+use alloc::borrow::Cow;
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// Returns the ASCII skeleton of the value, borrowing it when there is nothing to map.
+pub(crate) fn skeleton(value: &str) -> Cow<'_, str> {
+    if value.is_ascii() {
+        return Cow::Borrowed(value);
+    }
+    let mut skeleton = String::with_capacity(value.len());
+    for c in value.chars() {
+        if invisible(c) {
+            continue;
+        }
+        skeleton.push(ascii(c).unwrap_or(c));
+    }
+    Cow::Owned(skeleton)
+}
+
+#[inline]
+fn invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}' // Soft hyphen
+            | '\u{034F}' // Combining grapheme joiner
+            | '\u{180E}' // Mongolian vowel separator
+            | '\u{200B}'..='\u{200F}' // Zero width space, joiners and direction marks
+            | '\u{202A}'..='\u{202E}' // Direction embeddings and overrides
+            | '\u{2060}'..='\u{2064}' // Word joiner and invisible operators
+            | '\u{FEFF}' // Zero width no-break space
+    )
+}
+
+#[inline]
+fn ascii(c: char) -> Option<char> {
+    let c = match c {
+        // Full-width forms
+        '\u{FF01}'..='\u{FF5E}' => return char::from_u32(c as u32 - 0xFEE0),
+        '\u{3000}' => ' ',
+        // Cyrillic
+        'а' => 'a',
+        'е' | 'ё' => 'e',
+        'һ' => 'h',
+        'і' | 'ї' => 'i',
+        'ј' => 'j',
+        'к' => 'k',
+        'ӏ' => 'l',
+        'о' => 'o',
+        'р' => 'p',
+        'ԛ' => 'q',
+        'с' => 'c',
+        'ѕ' => 's',
+        'у' => 'y',
+        'ԝ' => 'w',
+        'х' => 'x',
+        'ԁ' => 'd',
+        'А' => 'A',
+        'В' => 'B',
+        'Е' | 'Ё' => 'E',
+        'Н' => 'H',
+        'І' | 'Ї' => 'I',
+        'Ј' => 'J',
+        'К' => 'K',
+        'М' => 'M',
+        'О' => 'O',
+        'Р' => 'P',
+        'С' => 'C',
+        'Ѕ' => 'S',
+        'Т' => 'T',
+        'Х' => 'X',
+        'Ү' => 'Y',
+        'Ԝ' => 'W',
+        // Greek
+        'α' => 'a',
+        'ι' => 'i',
+        'κ' => 'k',
+        'ν' => 'v',
+        'ο' => 'o',
+        'ρ' => 'p',
+        'υ' => 'u',
+        'Α' => 'A',
+        'Β' => 'B',
+        'Ε' => 'E',
+        'Ζ' => 'Z',
+        'Η' => 'H',
+        'Ι' => 'I',
+        'Κ' => 'K',
+        'Μ' => 'M',
+        'Ν' => 'N',
+        'Ο' => 'O',
+        'Ρ' => 'P',
+        'Τ' => 'T',
+        'Υ' => 'Y',
+        'Χ' => 'X',
+        // Latin
+        'ı' => 'i',
+        'ɑ' => 'a',
+        'ɡ' => 'g',
+        'ǀ' | 'ℓ' => 'l',
+        _ => return None,
+    };
+    Some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skeleton_ascii() {
+        assert!(matches!(
+            skeleton("paypal.com"),
+            Cow::Borrowed("paypal.com")
+        ));
+    }
+
+    #[test]
+    fn skeleton_confusables() {
+        assert_eq!(skeleton("pаypаl.com"), "paypal.com");
+        assert_eq!(skeleton("ѕvсһоѕt.ехе"), "svchost.exe");
+        assert_eq!(skeleton("ΑΡΡLΕ"), "APPLE");
+        assert_eq!(skeleton("ｐｏｗｅｒｓｈｅｌｌ．ｅｘｅ"), "powershell.exe");
+        assert_eq!(skeleton("power\u{200D}shell\u{200B}.exe"), "powershell.exe");
+        assert_eq!(skeleton("naïve"), "naïve");
+    }
+}
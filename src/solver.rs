@@ -1,17 +1,98 @@
-use std::collections::HashMap;
-use std::fmt;
+#[cfg(feature = "std")]
+use alloc::sync::Arc;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::cell::Cell;
 
+#[cfg(feature = "aho_corasick")]
 use aho_corasick::AhoCorasick;
-use tracing::debug;
+#[cfg(feature = "std")]
+use tracing::span::EnteredSpan;
 
+#[cfg(feature = "std")]
+use crate::clock::Instant;
+use crate::collections::HashMap;
 use crate::document::Document;
-use crate::parser::{Expression, Match, MatchType, Search};
+#[cfg(feature = "std")]
+use crate::parser::Visitor;
+use crate::parser::{self, Expression, Match, MatchType, Search};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
 use crate::rule::Detection;
+use crate::skeleton::skeleton;
+use crate::timestamp::SystemTime;
 use crate::tokeniser::{BoolSym, ModSym};
+#[cfg(not(feature = "aho_corasick"))]
+use crate::unsupported::AhoCorasick;
 use crate::value::Value;
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum SolverResult {
+/// The `tracing` target that the solver logs to when evaluating a traced rule.
+pub const TRACE_TARGET: &str = "tau_engine::trace";
+
+#[cfg(feature = "std")]
+thread_local! {
+    static TRACED: Cell<bool> = const { Cell::new(false) };
+}
+
+// NOTE: Shadows `tracing::debug` so that events emitted while evaluating a traced rule are sent to
+// the trace target, allowing them to be enabled without enabling them for every rule.
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if traced() {
+            tracing::debug!(target: TRACE_TARGET, $($arg)*)
+        } else {
+            tracing::debug!($($arg)*)
+        }
+    };
+}
+
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn traced() -> bool {
+    TRACED.with(|t| t.get())
+}
+
+// NOTE: Without std there are no thread locals to mark, so no rule is ever traced.
+#[cfg(not(feature = "std"))]
+#[inline(always)]
+pub(crate) fn traced() -> bool {
+    false
+}
+
+// Marks the current thread as evaluating a traced rule until dropped.
+#[cfg(feature = "std")]
+pub(crate) struct Traced {
+    previous: bool,
+    _span: EnteredSpan,
+}
+
+#[cfg(feature = "std")]
+impl Traced {
+    pub(crate) fn enter(id: &str) -> Self {
+        let previous = TRACED.with(|t| t.replace(true));
+        Self {
+            previous,
+            _span: tracing::debug_span!(target: TRACE_TARGET, "rule", id = %id).entered(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Traced {
+    fn drop(&mut self) {
+        TRACED.with(|t| t.set(self.previous));
+    }
+}
+
+/// The result of evaluating an expression, where `Missing` means that the document did not contain
+/// the data required to evaluate it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SolverResult {
     True,
     False,
     Missing,
@@ -26,23 +107,566 @@ impl fmt::Display for SolverResult {
     }
 }
 
+/// A limit on the amount of work that the solver will perform during an evaluation.
+///
+/// Every expression evaluated, and every element searched within an array, costs a single step.
+/// Once the budget has been spent the solver will stop evaluating and the evaluation will be
+/// reported as `Outcome::BudgetExceeded`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct Budget {
+    remaining: u64,
+    exceeded: bool,
+}
+
+#[cfg(feature = "std")]
+impl Budget {
+    /// Create a budget that allows for the given number of steps.
+    pub fn new(steps: u64) -> Self {
+        Self {
+            remaining: steps,
+            exceeded: false,
+        }
+    }
+
+    /// Returns true if the budget was exhausted during an evaluation.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    /// Returns the number of steps left in the budget.
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+}
+
+/// A token that allows evaluations to be cancelled from another thread, see `Deadline`.
+///
+/// Clones share the same state, so cancelling any of them cancels them all.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "std")]
+impl Cancellation {
+    /// Create a token that has not been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel every evaluation that is bound by this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// The number of steps between reads of the clock when solving with a `Deadline`.
+#[cfg(feature = "std")]
+const DEADLINE_INTERVAL: u32 = 64;
+
+/// A limit on how long the solver will spend on an evaluation, which can also be cancelled.
+///
+/// The timeout applies to each evaluation separately, starting from when it begins, so when a
+/// `RuleSet` is evaluated every rule is given the full timeout. The solver checks the deadline
+/// between expressions and array elements, once the timeout has elapsed or the cancellation token
+/// has been cancelled it will stop evaluating and the evaluation will be reported as
+/// `Outcome::TimedOut`.
+///
+/// A single search is never interrupted, so the deadline can be overrun by the time that it takes
+/// to run one regex against one value. Builds with the `deterministic` feature never read the
+/// clock, so in them a non-zero timeout never elapses.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct Deadline {
+    cancellation: Option<Cancellation>,
+    started: Option<Instant>,
+    steps: u32,
+    timed_out: bool,
+    timeout: Option<Duration>,
+}
+
+#[cfg(feature = "std")]
+impl Deadline {
+    /// Create a deadline that allows each evaluation to run for the given duration.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    /// Create a deadline without a timeout, for evaluations that should only stop once cancelled.
+    pub fn never() -> Self {
+        Self::default()
+    }
+
+    /// Bind the evaluations to the provided cancellation token.
+    pub fn cancellation(mut self, cancellation: Cancellation) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    /// Returns true if the last evaluation ran out of time or was cancelled.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    // Restarts the deadline for a new evaluation.
+    pub(crate) fn start(&mut self) {
+        self.started = self.timeout.map(|_| Instant::now());
+        self.steps = 0;
+        self.timed_out = false;
+    }
+}
+
+/// The outcome of an evaluation that is bound by a `Budget` or a `Deadline`.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Outcome {
+    /// The document matched.
+    Matched,
+    /// The document did not match.
+    NotMatched,
+    /// The budget was exhausted before the evaluation could complete.
+    BudgetExceeded,
+    /// The deadline passed, or the evaluation was cancelled, before it could complete.
+    TimedOut,
+}
+
+// A guard is consulted by the solver before each step, when it returns false the solver will bail
+// out of the evaluation as quickly as possible.
+pub(crate) trait Guard {
+    fn step(&mut self) -> bool;
+
+    // The scans that are shared within the evaluation, if any, see `Plan`.
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    fn scans(&mut self) -> Option<&mut Scans> {
+        None
+    }
+}
+
+#[cfg(feature = "std")]
+impl Guard for Budget {
+    #[inline]
+    fn step(&mut self) -> bool {
+        if self.remaining == 0 {
+            self.exceeded = true;
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+#[cfg(feature = "std")]
+impl Guard for Deadline {
+    #[inline]
+    fn step(&mut self) -> bool {
+        if self.timed_out {
+            return false;
+        }
+        if let Some(cancellation) = &self.cancellation {
+            if cancellation.is_cancelled() {
+                self.timed_out = true;
+                return false;
+            }
+        }
+        // NOTE: Reading the clock is far more expensive than a step, so it is only read on the
+        // first step and then at intervals.
+        if self.steps % DEADLINE_INTERVAL == 0 {
+            if let (Some(started), Some(timeout)) = (&self.started, self.timeout) {
+                if started.elapsed() >= timeout {
+                    self.timed_out = true;
+                    return false;
+                }
+            }
+        }
+        self.steps = self.steps.wrapping_add(1);
+        true
+    }
+}
+
+pub(crate) struct Unguarded;
+
+impl Guard for Unguarded {
+    #[inline(always)]
+    fn step(&mut self) -> bool {
+        true
+    }
+}
+
+// The regexes that a detection searches for more than once on the same field, whether on their
+// own or as part of a regex set, which only need to be run once per document.
+//
+// NOTE: Searches within nested expressions are evaluated against the nested objects, rather than
+// the document, so they are never shared.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub(crate) struct Plan {
+    // The index of each shared pattern by field, split by case sensitivity.
+    fields: HashMap<String, [HashMap<String, usize>; 2]>,
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl Plan {
+    // Returns the plan for the detection, or `None` if no regexes are shared.
+    pub(crate) fn new(detection: &Detection) -> Option<Self> {
+        let mut plan = Plan::default();
+        plan.visit_expression(&detection.expression);
+        for expression in detection.identifiers.values() {
+            plan.visit_expression(expression);
+        }
+        // NOTE: While visiting, the indexes are used to count the uses of each pattern.
+        let mut len = 0;
+        for patterns in plan.fields.values_mut() {
+            for patterns in patterns.iter_mut() {
+                patterns.retain(|_, uses| *uses > 1);
+                for index in patterns.values_mut() {
+                    *index = len;
+                    len += 1;
+                }
+            }
+        }
+        plan.fields
+            .retain(|_, [s, i]| !s.is_empty() || !i.is_empty());
+        if len == 0 {
+            return None;
+        }
+        plan.len = len;
+        Some(plan)
+    }
+
+    fn index(&self, field: &str, pattern: &str, insensitive: bool) -> Option<usize> {
+        self.fields.get(field)?[insensitive as usize]
+            .get(pattern)
+            .copied()
+    }
+
+    fn uses(&mut self, field: &str, pattern: &str, insensitive: bool) {
+        let patterns = match self.fields.get_mut(field) {
+            Some(patterns) => patterns,
+            None => self.fields.entry(field.to_owned()).or_default(),
+        };
+        *patterns[insensitive as usize]
+            .entry(pattern.to_owned())
+            .or_default() += 1;
+    }
+}
+
+#[cfg(feature = "std")]
+impl Visitor for Plan {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Nested(_, _) => {}
+            Expression::Search(Search::Regex(r, i), f, _) => self.uses(f, r.as_str(), *i),
+            Expression::Search(Search::RegexSet(s, i), f, _) => {
+                for pattern in s.patterns() {
+                    self.uses(f, pattern, *i);
+                }
+            }
+            _ => parser::walk_expression(self, expression),
+        }
+    }
+}
+
+// The results of the shared scans for a document, see `Plan`.
+#[cfg(feature = "std")]
+pub(crate) struct Scans {
+    // NOTE: Only used to tell the document apart from the nested objects within it.
+    document: *const (),
+    plan: Arc<Plan>,
+    results: Vec<Option<bool>>,
+}
+
+#[cfg(feature = "std")]
+impl Scans {
+    fn new(plan: Arc<Plan>, document: &dyn Document) -> Self {
+        Self {
+            document: document as *const dyn Document as *const (),
+            results: vec![None; plan.len],
+            plan,
+        }
+    }
+
+    // Runs the regex search against the value, reusing and recording the results of the patterns
+    // that are shared, or returns `None` if the search can't be shared.
+    fn search(
+        &mut self,
+        search: &Search,
+        field: &str,
+        value: &str,
+        document: &dyn Document,
+    ) -> Option<SolverResult> {
+        if self.document != document as *const dyn Document as *const () {
+            return None;
+        }
+        let matched = match search {
+            Search::Regex(r, i) => {
+                let index = self.plan.index(field, r.as_str(), *i)?;
+                *self.results[index].get_or_insert_with(|| r.is_match(value))
+            }
+            Search::RegexSet(s, i) => {
+                let indexes: Vec<Option<usize>> = s
+                    .patterns()
+                    .iter()
+                    .map(|p| self.plan.index(field, p, *i))
+                    .collect();
+                if indexes.iter().all(|i| i.is_none()) {
+                    return None;
+                }
+                if indexes
+                    .iter()
+                    .flatten()
+                    .any(|i| self.results[*i] == Some(true))
+                {
+                    true
+                } else {
+                    // NOTE: Every pattern is run so that the results of the shared ones are known.
+                    let matches = s.matches(value);
+                    for (pattern, index) in indexes.into_iter().enumerate() {
+                        if let Some(index) = index {
+                            self.results[index] = Some(matches.matched(pattern));
+                        }
+                    }
+                    matches.matched_any()
+                }
+            }
+            _ => return None,
+        };
+        Some(if matched {
+            SolverResult::True
+        } else {
+            SolverResult::False
+        })
+    }
+}
+
+// A guard that also shares the results of scans between searches.
+#[cfg(feature = "std")]
+struct Planned<'a, G> {
+    guard: &'a mut G,
+    scans: Scans,
+}
+
+#[cfg(feature = "std")]
+impl<G: Guard> Guard for Planned<'_, G> {
+    #[inline]
+    fn step(&mut self) -> bool {
+        self.guard.step()
+    }
+
+    #[inline]
+    fn scans(&mut self) -> Option<&mut Scans> {
+        Some(&mut self.scans)
+    }
+}
+
+// Solves the detection, sharing the results of its scans when it has a plan.
+#[cfg(feature = "std")]
+fn solve_planned<G: Guard>(
+    detection: &Detection,
+    document: &dyn Document,
+    guard: &mut G,
+) -> SolverResult {
+    match detection.plan() {
+        Some(plan) => {
+            let mut planned = Planned {
+                guard,
+                scans: Scans::new(plan.clone(), document),
+            };
+            solve_guarded(
+                &detection.expression,
+                &detection.identifiers,
+                document,
+                &mut planned,
+            )
+        }
+        None => solve_guarded(
+            &detection.expression,
+            &detection.identifiers,
+            document,
+            guard,
+        ),
+    }
+}
+
 /// Evalutes a `Document` with a provided detection, returning true if the detection solves.
+#[cfg(feature = "std")]
 pub fn solve(detection: &Detection, document: &dyn Document) -> bool {
-    match solve_expression(&detection.expression, &detection.identifiers, document) {
+    match solve_planned(detection, document, &mut Unguarded) {
         SolverResult::True => true,
         SolverResult::False | SolverResult::Missing => false,
     }
 }
 
+/// Evalutes a `Document` with a provided detection, stopping if the budget is exhausted.
+#[cfg(feature = "std")]
+pub fn solve_with_budget(
+    detection: &Detection,
+    document: &dyn Document,
+    budget: &mut Budget,
+) -> Outcome {
+    let res = solve_planned(detection, document, budget);
+    if budget.exceeded {
+        return Outcome::BudgetExceeded;
+    }
+    match res {
+        SolverResult::True => Outcome::Matched,
+        SolverResult::False | SolverResult::Missing => Outcome::NotMatched,
+    }
+}
+
+/// Evalutes a `Document` with a provided detection, stopping if the deadline passes or the
+/// evaluation is cancelled.
+#[cfg(feature = "std")]
+pub fn solve_with_deadline(
+    detection: &Detection,
+    document: &dyn Document,
+    deadline: &mut Deadline,
+) -> Outcome {
+    deadline.start();
+    let res = solve_planned(detection, document, deadline);
+    if deadline.timed_out {
+        return Outcome::TimedOut;
+    }
+    match res {
+        SolverResult::True => Outcome::Matched,
+        SolverResult::False | SolverResult::Missing => Outcome::NotMatched,
+    }
+}
+
+/// Evaluates a `Document` with a provided detection, returning the weighted fraction of its
+/// conditions that matched, from `0.0` to `1.0`.
+///
+/// The conjunctions in the condition are averaged using the weights of the identifiers that they
+/// reference, defaulting to `1.0`, while disjunctions take the highest score of their branches.
+/// Everything else, including the identifiers themselves, scores either `0.0` or `1.0`.
+#[cfg(feature = "std")]
+pub fn solve_scored(
+    detection: &Detection,
+    weights: &HashMap<String, f64>,
+    document: &dyn Document,
+) -> f64 {
+    score(
+        &detection.expression,
+        &detection.identifiers,
+        weights,
+        document,
+    )
+}
+
+#[cfg(feature = "std")]
+fn score(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    weights: &HashMap<String, f64>,
+    document: &dyn Document,
+) -> f64 {
+    let score = |e: &Expression| score(e, identifiers, weights, document);
+    match expression {
+        Expression::BooleanGroup(BoolSym::And, _)
+        | Expression::BooleanExpression(_, BoolSym::And, _) => {
+            let mut terms = vec![];
+            conjunction(expression, &mut terms);
+            let (mut total, mut matched) = (0.0, 0.0);
+            for term in terms {
+                let weight = weight(term, weights);
+                if weight > 0.0 {
+                    total += weight;
+                    matched += weight * score(term);
+                }
+            }
+            if total > 0.0 {
+                matched / total
+            } else {
+                0.0
+            }
+        }
+        Expression::BooleanGroup(BoolSym::Or, group) => group.iter().map(score).fold(0.0, f64::max),
+        Expression::BooleanExpression(left, BoolSym::Or, right) => score(left).max(score(right)),
+        _ => match solve_expression(expression, identifiers, document) {
+            SolverResult::True => 1.0,
+            SolverResult::False | SolverResult::Missing => 0.0,
+        },
+    }
+}
+
+// Collects the terms of a conjunction, flattening any conjunctions nested within it.
+#[cfg(feature = "std")]
+fn conjunction<'a>(expression: &'a Expression, terms: &mut Vec<&'a Expression>) {
+    match expression {
+        Expression::BooleanGroup(BoolSym::And, group) => {
+            for expression in group {
+                conjunction(expression, terms);
+            }
+        }
+        Expression::BooleanExpression(left, BoolSym::And, right) => {
+            conjunction(left, terms);
+            conjunction(right, terms);
+        }
+        _ => terms.push(expression),
+    }
+}
+
+// Returns the weight of an expression, which is that of the identifier it references or negates.
+#[cfg(feature = "std")]
+fn weight(expression: &Expression, weights: &HashMap<String, f64>) -> f64 {
+    match expression {
+        Expression::Identifier(i) => weights.get(i).copied().unwrap_or(1.0),
+        Expression::Negate(e) => match e.as_ref() {
+            Expression::Identifier(i) => weights.get(i).copied().unwrap_or(1.0),
+            _ => 1.0,
+        },
+        _ => 1.0,
+    }
+}
+
+#[inline]
 pub(crate) fn solve_expression(
     expression: &Expression,
     identifiers: &HashMap<String, Expression>,
     document: &dyn Document,
 ) -> SolverResult {
+    solve_guarded(expression, identifiers, document, &mut Unguarded)
+}
+
+pub(crate) fn solve_guarded<G: Guard>(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    guard: &mut G,
+) -> SolverResult {
+    let res = evaluate(expression, identifiers, document, guard);
+    if traced() {
+        tracing::debug!(target: TRACE_TARGET, "evaluated {} as {}", expression, res);
+    }
+    res
+}
+
+fn evaluate<G: Guard>(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    guard: &mut G,
+) -> SolverResult {
+    if !guard.step() {
+        debug!("evaluating missing, guard exhausted for {}", expression);
+        return SolverResult::Missing;
+    }
     match *expression {
         Expression::BooleanGroup(BoolSym::And, ref group) => {
             for expression in group {
-                match solve_expression(expression, identifiers, document) {
+                match solve_guarded(expression, identifiers, document, guard) {
                     SolverResult::True => {}
                     SolverResult::False => return SolverResult::False,
                     SolverResult::Missing => return SolverResult::Missing,
@@ -53,7 +677,7 @@ pub(crate) fn solve_expression(
         Expression::BooleanGroup(BoolSym::Or, ref group) => {
             let mut res = SolverResult::Missing;
             for expression in group {
-                match solve_expression(expression, identifiers, document) {
+                match solve_guarded(expression, identifiers, document, guard) {
                     SolverResult::True => return SolverResult::True,
                     SolverResult::False => res = SolverResult::False,
                     SolverResult::Missing => {}
@@ -66,7 +690,14 @@ pub(crate) fn solve_expression(
             match (&**left, op, &**right) {
                 (
                     Expression::Cast(ref left, ModSym::Str),
-                    BoolSym::Equal,
+                    BoolSym::Contains
+                    | BoolSym::EndsWith
+                    | BoolSym::Equal
+                    | BoolSym::GreaterThan
+                    | BoolSym::GreaterThanOrEqual
+                    | BoolSym::LessThan
+                    | BoolSym::LessThanOrEqual
+                    | BoolSym::StartsWith,
                     Expression::Cast(ref right, ModSym::Str),
                 ) => {
                     let x = match document.find(left) {
@@ -103,7 +734,19 @@ pub(crate) fn solve_expression(
                             return SolverResult::False;
                         }
                     };
-                    if x == y {
+                    // NOTE: Strings are compared lexicographically by their bytes.
+                    let res = match *op {
+                        BoolSym::Contains => x.contains(y.as_str()),
+                        BoolSym::EndsWith => x.ends_with(y.as_str()),
+                        BoolSym::Equal => x == y,
+                        BoolSym::GreaterThan => x > y,
+                        BoolSym::GreaterThanOrEqual => x >= y,
+                        BoolSym::LessThan => x < y,
+                        BoolSym::LessThanOrEqual => x <= y,
+                        BoolSym::StartsWith => x.starts_with(y.as_str()),
+                        _ => unreachable!(),
+                    };
+                    if res {
                         return SolverResult::True;
                     } else {
                         return SolverResult::False;
@@ -156,68 +799,25 @@ pub(crate) fn solve_expression(
                 | BoolSym::GreaterThanOrEqual
                 | BoolSym::LessThan
                 | BoolSym::LessThanOrEqual => {
-                    // FIXME: Some very basic float support...
-                    let float = match (left.as_ref(), right.as_ref()) {
-                        (Expression::Float(_), _) | (_, Expression::Float(_)) => true,
-                        (_, _) => false,
-                    };
-                    if float {
-                        let x = match left.as_ref() {
-                            Expression::Field(f) => {
-                                let i = match document.find(f) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i.as_f64() {
-                                    Some(v) => v,
-                                    None => {
-                                        debug!(
-                                            "evaluating false, no left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Float(f) => *f,
-                            _ => {
-                                debug!("encountered invalid left hand side for {}", expression);
-                                return SolverResult::False;
+                    if temporal(left, right) {
+                        let x = match temporal_operand(left, document) {
+                            Ok(x) => x,
+                            Err(res) => {
+                                debug!(
+                                    "evaluating {}, could not get left hand side as a timestamp for {}",
+                                    res, expression
+                                );
+                                return res;
                             }
                         };
-                        let y = match right.as_ref() {
-                            Expression::Field(f) => {
-                                let i = match document.find(f) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no right hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i.as_f64() {
-                                    Some(v) => v,
-                                    None => {
-                                        debug!(
-                                            "evaluating false, no right hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Float(i) => *i,
-                            _ => {
-                                debug!("encountered invalid right hand side for {}", expression);
-                                return SolverResult::False;
+                        let y = match temporal_operand(right, document) {
+                            Ok(y) => y,
+                            Err(res) => {
+                                debug!(
+                                    "evaluating {}, could not get right hand side as a timestamp for {}",
+                                    res, expression
+                                );
+                                return res;
                             }
                         };
                         let res = match *op {
@@ -228,171 +828,24 @@ pub(crate) fn solve_expression(
                             BoolSym::LessThanOrEqual => x <= y,
                             _ => unreachable!(),
                         };
-                        match res {
+                        return match res {
                             true => SolverResult::True,
                             _ => SolverResult::False,
-                        }
-                    } else {
-                        let x = match left.as_ref() {
-                            Expression::Field(f) => {
-                                let i = match document.find(f) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i.to_i64() {
-                                    Some(v) => v,
-                                    None => {
-                                        debug!(
-                                            "evaluating false, no left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Cast(field, ModSym::Int) => {
-                                let i = match document.find(field) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i {
-                                    Value::Bool(x) => {
-                                        if x {
-                                            1
-                                        } else {
-                                            0
-                                        }
-                                    }
-                                    Value::Int(x) => x,
-                                    Value::String(x) => match x.parse::<i64>() {
-                                        Ok(i) => i,
-                                        Err(e) => {
-                                            debug!(
-                                                "evaluating false, could not cast left hand side for {} - {}",
-                                                expression, e
-                                            );
-                                            return SolverResult::False;
-                                        }
-                                    },
-                                    Value::UInt(x) => {
-                                        if x <= i64::MAX as u64 {
-                                            x as i64
-                                        } else {
-                                            debug!(
-                                                "evaluating false, could not cast left hand side for {} - {}",
-                                                expression, x
-                                            );
-                                            return SolverResult::False;
-                                        }
-                                    }
-                                    _ => {
-                                        debug!(
-                                            "evaluating false, invalid type on left hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Integer(i) => *i,
-                            _ => {
-                                debug!("encountered invalid left hand side for {}", expression);
-                                return SolverResult::False;
-                            }
-                        };
-                        let y = match right.as_ref() {
-                            Expression::Field(f) => {
-                                let i = match document.find(f) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no right hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i.to_i64() {
-                                    Some(v) => v,
-                                    None => {
-                                        debug!(
-                                            "evaluating false, no right hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Cast(field, ModSym::Int) => {
-                                let i = match document.find(field) {
-                                    Some(i) => i,
-                                    None => {
-                                        debug!(
-                                            "evaluating missing, no right hand side for {}",
-                                            expression
-                                        );
-                                        return SolverResult::Missing;
-                                    }
-                                };
-                                match i.to_string() {
-                                    Some(v) => match v.parse::<i64>() {
-                                        Ok(i) => i,
-                                        Err(e) => {
-                                            debug!(
-                                            "evaluating false, could not cast right hand side for {} - {}",
-                                            expression, e
-                                        );
-                                            return SolverResult::False;
-                                        }
-                                    },
-                                    None => {
-                                        debug!(
-                                        "evaluating false, invalid type on right hand side for {}",
-                                        expression
-                                    );
-                                        return SolverResult::False;
-                                    }
-                                }
-                            }
-                            Expression::Integer(i) => *i,
-                            _ => {
-                                debug!("encountered invalid right hand side for {}", expression);
-                                return SolverResult::False;
-                            }
-                        };
-                        let res = match *op {
-                            BoolSym::Equal => x == y,
-                            BoolSym::GreaterThan => x > y,
-                            BoolSym::GreaterThanOrEqual => x >= y,
-                            BoolSym::LessThan => x < y,
-                            BoolSym::LessThanOrEqual => x <= y,
-                            _ => unreachable!(),
                         };
-                        match res {
-                            true => SolverResult::True,
-                            _ => SolverResult::False,
-                        }
                     }
+                    numeric(expression, left, *op, right, document, false)
+                }
+                BoolSym::Contains | BoolSym::EndsWith | BoolSym::StartsWith => {
+                    debug!("encountered invalid operands for {}", expression);
+                    SolverResult::False
                 }
                 BoolSym::And => {
-                    let x = match solve_expression(&*left, identifiers, document) {
+                    let x = match solve_guarded(left, identifiers, document, guard) {
                         SolverResult::True => (true, false),
                         SolverResult::False => return SolverResult::False,
                         SolverResult::Missing => return SolverResult::Missing,
                     };
-                    let y = match solve_expression(&*right, identifiers, document) {
+                    let y = match solve_guarded(right, identifiers, document, guard) {
                         SolverResult::True => (true, false),
                         SolverResult::False => (false, false),
                         SolverResult::Missing => (false, true),
@@ -412,12 +865,12 @@ pub(crate) fn solve_expression(
                     }
                 }
                 BoolSym::Or => {
-                    let x = match solve_expression(&*left, identifiers, document) {
+                    let x = match solve_guarded(left, identifiers, document, guard) {
                         SolverResult::True => return SolverResult::True,
                         SolverResult::False => (false, false),
                         SolverResult::Missing => (false, true),
                     };
-                    let y = match solve_expression(&*right, identifiers, document) {
+                    let y = match solve_guarded(right, identifiers, document, guard) {
                         SolverResult::True => (true, false),
                         SolverResult::False => (false, false),
                         SolverResult::Missing => (false, true),
@@ -438,22 +891,30 @@ pub(crate) fn solve_expression(
                 }
             }
         }
+        Expression::Boolean(b) => {
+            if b {
+                SolverResult::True
+            } else {
+                SolverResult::False
+            }
+        }
         Expression::Identifier(ref i) => match identifiers.get(i) {
-            Some(e) => solve_expression(e, identifiers, document),
+            Some(e) => solve_guarded(e, identifiers, document, guard),
             None => unreachable!(),
         },
         Expression::Match(Match::All, ref e) => {
-            let (_, group) = match **e {
+            // NOTE: Anything other than a group is treated as a group of one.
+            let group = match **e {
                 Expression::Identifier(ref i) => match identifiers.get(i) {
-                    Some(Expression::BooleanGroup(o, g)) => (o, g),
-                    Some(e) => return solve_expression(e, identifiers, document),
+                    Some(Expression::BooleanGroup(_, g)) => g.as_slice(),
+                    Some(e) => core::slice::from_ref(e),
                     _ => unreachable!(),
                 },
-                Expression::BooleanGroup(ref o, ref g) => (o, g),
-                _ => return match_all(e, identifiers, document),
+                Expression::BooleanGroup(_, ref g) => g.as_slice(),
+                _ => core::slice::from_ref(&**e),
             };
             for expression in group {
-                match match_all(expression, identifiers, document) {
+                match match_all(expression, identifiers, document, guard) {
                     SolverResult::True => {}
                     SolverResult::False => return SolverResult::False,
                     SolverResult::Missing => return SolverResult::Missing,
@@ -461,234 +922,52 @@ pub(crate) fn solve_expression(
             }
             SolverResult::True
         }
+        Expression::Match(Match::Every, ref e) => {
+            let res = every(e, document);
+            debug!("evaluating {} for every element of {}", res, expression);
+            res
+        }
         Expression::Match(Match::Of(c), ref e) => {
-            let (_, group) = match **e {
+            let group = match **e {
                 Expression::Identifier(ref identifier) => match identifiers.get(identifier) {
-                    Some(Expression::BooleanGroup(o, g)) => (o, g),
-                    Some(e) => {
-                        return match solve_expression(e, identifiers, document) {
-                            SolverResult::True => {
-                                if c == 0 {
-                                    SolverResult::False
-                                } else {
-                                    SolverResult::True
-                                }
-                            }
-                            SolverResult::False => {
-                                if c == 0 {
-                                    SolverResult::True
-                                } else {
-                                    SolverResult::False
-                                }
-                            }
-                            SolverResult::Missing => SolverResult::Missing,
-                        }
-                    }
-                    _ => {
-                        unreachable!();
-                    }
+                    Some(Expression::BooleanGroup(_, g)) => g,
+                    Some(e) => return match_one(e, c, identifiers, document, guard),
+                    _ => unreachable!(),
                 },
-                Expression::BooleanGroup(ref o, ref g) => (o, g),
-                _ => {
-                    return match solve_expression(e, identifiers, document) {
-                        SolverResult::True => {
-                            if c == 0 {
-                                SolverResult::False
-                            } else {
-                                SolverResult::True
-                            }
-                        }
-                        SolverResult::False => {
-                            if c == 0 {
-                                SolverResult::True
-                            } else {
-                                SolverResult::False
-                            }
-                        }
-                        SolverResult::Missing => SolverResult::Missing,
-                    };
+                Expression::BooleanGroup(_, ref g) => g,
+                Expression::Nested(ref field, ref e) => {
+                    return match_nested(field, e, c, identifiers, document, guard);
                 }
+                _ => return match_one(e, c, identifiers, document, guard),
             };
             let mut count = 0;
             let mut res = SolverResult::Missing;
             for expression in group {
                 if c == 0 {
-                    match solve_expression(expression, identifiers, document) {
+                    match solve_guarded(expression, identifiers, document, guard) {
                         SolverResult::True => return SolverResult::False,
                         SolverResult::False => {
                             res = SolverResult::True;
                         }
-                        SolverResult::Missing => {}
-                    }
-                // NOTE: Because of needle optimisation we have to handle aho in a `slow` fashion here...
-                } else if let Expression::Search(Search::AhoCorasick(a, m, _), i, cast) = expression
-                {
-                    let value = match document.find(i) {
-                        Some(v) => v,
-                        None => {
-                            debug!("evaluating missing, field not found for {}", expression);
-                            return SolverResult::Missing;
-                        }
-                    };
-                    match (value, cast) {
-                        (Value::String(ref x), _) => {
-                            count += slow_aho(a, m, x);
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Array(x), _) => {
-                            let mut max = 0;
-                            for v in x.iter() {
-                                if let Some(x) = v.as_str() {
-                                    let hits = slow_aho(a, m, x);
-                                    if count + hits >= c {
-                                        return SolverResult::True;
-                                    } else if hits > max {
-                                        max = hits;
-                                    }
-                                } else if *cast {
-                                    let x = match v {
-                                        Value::Bool(x) => x.to_string(),
-                                        Value::Float(x) => x.to_string(),
-                                        Value::Int(x) => x.to_string(),
-                                        Value::UInt(x) => x.to_string(),
-                                        _ => continue,
-                                    };
-                                    let hits = slow_aho(a, m, x.as_str());
-                                    if count + hits >= c {
-                                        return SolverResult::True;
-                                    } else if hits > max {
-                                        max = hits;
-                                    }
-                                }
-                            }
-                            count += max;
-                        }
-                        (Value::Bool(x), true) => {
-                            let x = x.to_string();
-                            count += slow_aho(a, m, x.as_str());
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Float(x), true) => {
-                            let x = x.to_string();
-                            count += slow_aho(a, m, x.as_str());
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Int(x), true) => {
-                            let x = x.to_string();
-                            count += slow_aho(a, m, x.as_str());
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::UInt(x), true) => {
-                            let x = x.to_string();
-                            count += slow_aho(a, m, x.as_str());
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        _ => {
-                            debug!(
-                                "evaluating false, field is not an array of strings, or a string for {}",
-                                expression
-                            );
-                            return SolverResult::Missing;
-                        }
-                    }
-                } else if let Expression::Search(Search::RegexSet(s, _), i, cast) = expression {
-                    let value = match document.find(i) {
-                        Some(v) => v,
-                        None => {
-                            debug!("evaluating missing, field not found for {}", expression);
-                            return SolverResult::Missing;
-                        }
-                    };
-                    match (value, cast) {
-                        (Value::String(ref x), _) => {
-                            for _ in s.matches(x).iter() {
-                                count += 1;
-                            }
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Array(x), _) => {
-                            let mut max = 0;
-                            for v in x.iter() {
-                                if let Some(x) = v.as_str() {
-                                    let mut hits = 0;
-                                    for _ in s.matches(x).iter() {
-                                        hits += 1;
-                                    }
-                                    if count + hits >= c {
-                                        return SolverResult::True;
-                                    } else if hits > max {
-                                        max = hits;
-                                    }
-                                } else if *cast {
-                                    let x = match v {
-                                        Value::Bool(x) => x.to_string(),
-                                        Value::Float(x) => x.to_string(),
-                                        Value::Int(x) => x.to_string(),
-                                        Value::UInt(x) => x.to_string(),
-                                        _ => continue,
-                                    };
-                                    let mut hits = 0;
-                                    for _ in s.matches(x.as_str()).iter() {
-                                        hits += 1;
-                                    }
-                                    if count + hits >= c {
-                                        return SolverResult::True;
-                                    } else if hits > max {
-                                        max = hits;
-                                    }
-                                }
-                            }
-                            count += max;
-                        }
-                        (Value::Bool(x), true) => {
-                            let x = x.to_string();
-                            for _ in s.matches(x.as_str()).iter() {
-                                count += 1;
-                            }
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Float(x), true) => {
-                            let x = x.to_string();
-                            for _ in s.matches(x.as_str()).iter() {
-                                count += 1;
-                            }
-                            if count >= c {
-                                return SolverResult::True;
-                            }
-                        }
-                        (Value::Int(x), true) => {
-                            let x = x.to_string();
-                            for _ in s.matches(x.as_str()).iter() {
-                                count += 1;
-                            }
-                            if count >= c {
-                                return SolverResult::True;
-                            }
+                        SolverResult::Missing => {}
+                    }
+                // NOTE: Because of needle optimisation we have to count the patterns in a `slow` fashion here...
+                } else if let Some((s, i, cast, _)) = patterned(expression) {
+                    let value = match document.find(i) {
+                        Some(v) => v,
+                        None => {
+                            debug!("evaluating missing, field not found for {}", expression);
+                            return SolverResult::Missing;
                         }
-                        (Value::UInt(x), true) => {
-                            let x = x.to_string();
-                            for _ in s.matches(x.as_str()).iter() {
-                                count += 1;
-                            }
+                    };
+                    match count_patterns(s, &value, cast, c - count, guard) {
+                        Some(hits) => {
+                            count += hits;
                             if count >= c {
                                 return SolverResult::True;
                             }
                         }
-                        _ => {
+                        None => {
                             debug!(
                                 "evaluating false, field is not an array of strings, or a string for {}",
                                 expression
@@ -697,7 +976,7 @@ pub(crate) fn solve_expression(
                         }
                     }
                 } else {
-                    match solve_expression(expression, identifiers, document) {
+                    match solve_guarded(expression, identifiers, document, guard) {
                         SolverResult::True => {
                             count += 1;
                             if count >= c {
@@ -712,7 +991,7 @@ pub(crate) fn solve_expression(
             res
         }
         Expression::Negate(ref e) => {
-            let res = match solve_expression(e.as_ref(), identifiers, document) {
+            let res = match solve_guarded(e.as_ref(), identifiers, document, guard) {
                 SolverResult::True => SolverResult::False,
                 SolverResult::False => SolverResult::True,
                 SolverResult::Missing => SolverResult::False,
@@ -729,11 +1008,14 @@ pub(crate) fn solve_expression(
                 }
             };
             match value {
-                Value::Object(o) => solve_expression(e, identifiers, &o),
+                Value::Object(o) => solve_guarded(e, identifiers, &o, guard),
                 Value::Array(a) => {
                     for v in a.iter() {
+                        if !guard.step() {
+                            return SolverResult::Missing;
+                        }
                         if let Some(x) = v.as_object() {
-                            if solve_expression(e, identifiers, &x) == SolverResult::True {
+                            if solve_guarded(e, identifiers, &x, guard) == SolverResult::True {
                                 return SolverResult::True;
                             }
                         }
@@ -749,7 +1031,64 @@ pub(crate) fn solve_expression(
                 }
             }
         }
+        Expression::In(ref e, ref integers) => {
+            let x = match integer_operands(e, document, false) {
+                Ok(x) => x,
+                Err(res) => {
+                    debug!(
+                        "evaluating {}, could not get the value as an integer for {}",
+                        res, expression
+                    );
+                    return res;
+                }
+            };
+            let res = match x.test(false, |x| integers.binary_search(&x).is_ok()) {
+                true => SolverResult::True,
+                _ => SolverResult::False,
+            };
+            debug!("evaluating {} for {}", res, expression);
+            res
+        }
+        Expression::Range(ref e, from, to) => {
+            let x = match integer_operands(e, document, false) {
+                Ok(x) => x,
+                Err(res) => {
+                    debug!(
+                        "evaluating {}, could not get the value as an integer for {}",
+                        res, expression
+                    );
+                    return res;
+                }
+            };
+            let res = match x.test(false, |x| from <= x && x <= to) {
+                true => SolverResult::True,
+                _ => SolverResult::False,
+            };
+            debug!("evaluating {} for {}", res, expression);
+            res
+        }
+        Expression::Keywords(ref s) => {
+            let mut res = SolverResult::False;
+            for value in document.values() {
+                match keywords(s, &value, guard) {
+                    SolverResult::False => {}
+                    r => {
+                        res = r;
+                        break;
+                    }
+                }
+            }
+            debug!("evaluating {} for {}", res, expression);
+            res
+        }
         Expression::Search(ref s, ref f, ref c) => {
+            if let Search::Shadow(ref s) = s {
+                if let Some(x) = document.find_shadow(f) {
+                    let res = search(s, &x);
+                    debug!("evaluating {} for {}", res, expression);
+                    return res;
+                }
+            }
             let value = match document.find(f) {
                 Some(v) => v,
                 None => {
@@ -757,16 +1096,36 @@ pub(crate) fn solve_expression(
                     return SolverResult::Missing;
                 }
             };
+            #[cfg(feature = "std")]
+            if let (Value::String(ref x), Some(scans)) = (&value, guard.scans()) {
+                if let Some(res) = scans.search(s, f, x, document) {
+                    debug!("evaluating {} for {}", res, expression);
+                    return res;
+                }
+            }
             let res = match (value, c) {
                 (Value::String(ref x), _) => search(s, x),
+                (Value::Bytes(ref x), _) if matches!(s, Search::Hex(_) | Search::Wide(..)) => {
+                    search_bytes(s, x, false)
+                }
                 (Value::Array(a), _) => {
                     let mut res = SolverResult::False;
                     for v in a.iter() {
+                        if !guard.step() {
+                            return SolverResult::Missing;
+                        }
                         if let Some(x) = v.as_str() {
                             if search(s, x) == SolverResult::True {
                                 res = SolverResult::True;
                                 break;
                             }
+                        } else if let (Some(x), Search::Hex(_) | Search::Wide(..)) =
+                            (v.as_bytes(), s)
+                        {
+                            if search_bytes(s, x, false) == SolverResult::True {
+                                res = SolverResult::True;
+                                break;
+                            }
                         } else if *c {
                             let x = match v {
                                 Value::Bool(x) => x.to_string(),
@@ -811,95 +1170,97 @@ pub(crate) fn solve_expression(
             res
         }
         Expression::BooleanGroup(_, _)
-        | Expression::Boolean(_)
         | Expression::Cast(_, _)
         | Expression::Field(_)
         | Expression::Float(_)
         | Expression::Integer(_)
-        | Expression::Null => unreachable!(),
+        | Expression::Null
+        | Expression::Timestamp(_) => unreachable!(),
     }
 }
 
 #[inline]
-fn match_all(
+// Evaluates a match over a single expression, which counts as one match.
+fn match_one<G: Guard>(
     expression: &Expression,
+    count: u64,
     identifiers: &HashMap<String, Expression>,
     document: &dyn Document,
+    guard: &mut G,
 ) -> SolverResult {
-    // NOTE: Because of needle optimisation we have to handle aho in a `slow` fashion here...
-    if let Expression::Search(Search::AhoCorasick(a, m, _), i, c) = expression {
-        let value = match document.find(i) {
-            Some(v) => v,
-            None => {
-                debug!("evaluating missing, field not found for {}", expression);
-                return SolverResult::Missing;
+    match (
+        solve_guarded(expression, identifiers, document, guard),
+        count,
+    ) {
+        (SolverResult::True, 0) | (SolverResult::False, 1..) => SolverResult::False,
+        (SolverResult::False, 0) | (SolverResult::True, 1..) => SolverResult::True,
+        (SolverResult::Missing, _) => SolverResult::Missing,
+    }
+}
+
+// Counts the elements of a nested array that satisfy the expression, stopping once there are
+// enough of them, where an object is treated as an array of one.
+fn match_nested<G: Guard>(
+    field: &str,
+    expression: &Expression,
+    count: u64,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    guard: &mut G,
+) -> SolverResult {
+    let value = match document.find(field) {
+        Some(v) => v,
+        None => {
+            debug!("evaluating missing, field not found for {}", field);
+            return SolverResult::Missing;
+        }
+    };
+    let mut hits = 0;
+    match value {
+        Value::Object(o) => {
+            if solve_guarded(expression, identifiers, &o, guard) == SolverResult::True {
+                hits += 1;
             }
-        };
-        match (value, c) {
-            (Value::String(ref x), _) => {
-                if slow_aho(a, m, x) != m.len() as u64 {
-                    return SolverResult::False;
+        }
+        Value::Array(a) => {
+            for v in a.iter() {
+                if !guard.step() {
+                    return SolverResult::Missing;
                 }
-            }
-            (Value::Array(x), _) => {
-                let mut found = false;
-                for v in x.iter() {
-                    if let Some(x) = v.as_str() {
-                        if slow_aho(a, m, x) == m.len() as u64 {
-                            found = true;
-                            break;
-                        }
-                    } else if *c {
-                        let x = match v {
-                            Value::Bool(x) => x.to_string(),
-                            Value::Float(x) => x.to_string(),
-                            Value::Int(x) => x.to_string(),
-                            Value::UInt(x) => x.to_string(),
-                            _ => continue,
-                        };
-                        if slow_aho(a, m, x.as_str()) == m.len() as u64 {
-                            found = true;
-                            break;
+                if let Some(x) = v.as_object() {
+                    if solve_guarded(expression, identifiers, &x, guard) == SolverResult::True {
+                        hits += 1;
+                        if count > 0 && hits >= count {
+                            return SolverResult::True;
                         }
                     }
                 }
-                if !found {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Bool(x), true) => {
-                let x = x.to_string();
-                if slow_aho(a, m, x.as_str()) != m.len() as u64 {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Float(x), true) => {
-                let x = x.to_string();
-                if slow_aho(a, m, x.as_str()) != m.len() as u64 {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Int(x), true) => {
-                let x = x.to_string();
-                if slow_aho(a, m, x.as_str()) != m.len() as u64 {
-                    return SolverResult::False;
-                }
-            }
-            (Value::UInt(x), true) => {
-                let x = x.to_string();
-                if slow_aho(a, m, x.as_str()) != m.len() as u64 {
-                    return SolverResult::False;
-                }
-            }
-            (_, _) => {
-                debug!(
-                    "evaluating false, field is not an array of strings, or a string for {}",
-                    expression
-                );
-                return SolverResult::Missing;
             }
         }
-    } else if let Expression::Search(Search::RegexSet(s, _), i, c) = expression {
+        _ => {
+            debug!(
+                "evaluating no elements, field is not an array of objects or object for {}",
+                field
+            );
+        }
+    }
+    match (count, hits) {
+        (0, 0) => SolverResult::True,
+        (0, _) => SolverResult::False,
+        (c, h) if h >= c => SolverResult::True,
+        _ => SolverResult::False,
+    }
+}
+
+#[inline]
+fn match_all<G: Guard>(
+    expression: &Expression,
+    identifiers: &HashMap<String, Expression>,
+    document: &dyn Document,
+    guard: &mut G,
+) -> SolverResult {
+    // NOTE: Because of needle optimisation we have to count the patterns in a `slow` fashion here...
+    if let Some((s, i, c, patterns)) = patterned(expression) {
         let value = match document.find(i) {
             Some(v) => v,
             None => {
@@ -907,91 +1268,10 @@ fn match_all(
                 return SolverResult::Missing;
             }
         };
-        match (value, c) {
-            (Value::String(ref x), _) => {
-                let mut hits = 0;
-                for _ in s.matches(x).iter() {
-                    hits += 1;
-                }
-                if hits != s.patterns().len() {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Array(x), _) => {
-                let mut found = false;
-                for v in x.iter() {
-                    if let Some(x) = v.as_str() {
-                        let mut hits = 0;
-                        for _ in s.matches(x).iter() {
-                            hits += 1;
-                        }
-                        if hits == s.patterns().len() {
-                            found = true;
-                            break;
-                        }
-                    } else if *c {
-                        let x = match v {
-                            Value::Bool(x) => x.to_string(),
-                            Value::Float(x) => x.to_string(),
-                            Value::Int(x) => x.to_string(),
-                            Value::UInt(x) => x.to_string(),
-                            _ => continue,
-                        };
-                        let mut hits = 0;
-                        for _ in s.matches(x.as_str()).iter() {
-                            hits += 1;
-                        }
-                        if hits == s.patterns().len() {
-                            found = true;
-                            break;
-                        }
-                    }
-                }
-                if !found {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Bool(x), true) => {
-                let x = x.to_string();
-                let mut hits = 0;
-                for _ in s.matches(x.as_str()).iter() {
-                    hits += 1;
-                }
-                if hits != s.patterns().len() {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Float(x), true) => {
-                let x = x.to_string();
-                let mut hits = 0;
-                for _ in s.matches(x.as_str()).iter() {
-                    hits += 1;
-                }
-                if hits != s.patterns().len() {
-                    return SolverResult::False;
-                }
-            }
-            (Value::Int(x), true) => {
-                let x = x.to_string();
-                let mut hits = 0;
-                for _ in s.matches(x.as_str()).iter() {
-                    hits += 1;
-                }
-                if hits != s.patterns().len() {
-                    return SolverResult::False;
-                }
-            }
-            (Value::UInt(x), true) => {
-                let x = x.to_string();
-                let mut hits = 0;
-                for _ in s.matches(x.as_str()).iter() {
-                    hits += 1;
-                }
-                if hits != s.patterns().len() {
-                    return SolverResult::False;
-                }
-            }
-            _ => {
+        match count_patterns(s, &value, c, patterns, guard) {
+            Some(hits) if hits == patterns => {}
+            Some(_) => return SolverResult::False,
+            None => {
                 debug!(
                     "evaluating false, field is not an array of strings, or a string for {}",
                     expression
@@ -1000,7 +1280,7 @@ fn match_all(
             }
         }
     } else {
-        match solve_expression(expression, identifiers, document) {
+        match solve_guarded(expression, identifiers, document, guard) {
             SolverResult::True => {}
             SolverResult::False => return SolverResult::False,
             SolverResult::Missing => return SolverResult::Missing,
@@ -1009,6 +1289,253 @@ fn match_all(
     SolverResult::True
 }
 
+// Returns true if the Damerau-Levenshtein distance, using optimal string alignment, between the
+// strings is no more than the maximum.
+fn within_distance(a: &str, b: &str, max: usize) -> bool {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return false;
+    }
+    // NOTE: Only the last two rows are needed, the one before that to account for transpositions.
+    let mut two_ago: Vec<usize> = vec![0; b.len() + 1];
+    let mut one_ago: Vec<usize> = (0..=b.len()).collect();
+    let mut current: Vec<usize> = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        let mut lowest = i;
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            let mut d = (one_ago[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(one_ago[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d = d.min(two_ago[j - 2] + 1);
+            }
+            current[j] = d;
+            lowest = lowest.min(d);
+        }
+        // NOTE: The lowest distance in a row never decreases, so we can bail early.
+        if lowest > max {
+            return false;
+        }
+        core::mem::swap(&mut two_ago, &mut one_ago);
+        core::mem::swap(&mut one_ago, &mut current);
+    }
+    one_ago[b.len()] <= max
+}
+
+// Returns the length of a string in characters, or of an array in items.
+fn length(value: &Value<'_>) -> Option<i64> {
+    match value {
+        Value::String(s) => Some(s.chars().count() as i64),
+        Value::Array(a) => Some(a.len() as i64),
+        _ => None,
+    }
+}
+
+// The operands of a numeric comparison, where an array is compared element by element and an
+// element that is not a number never matches.
+enum Numbers<T> {
+    One(T),
+    Many(Vec<Option<T>>),
+}
+
+impl<T: Copy> Numbers<T> {
+    // Returns true if the predicate holds for any of the numbers, or for all of them when `every`
+    // is set, in which case an empty array never holds.
+    fn test<F: Fn(T) -> bool>(&self, every: bool, f: F) -> bool {
+        match self {
+            Self::One(x) => f(*x),
+            Self::Many(xs) if every => {
+                !xs.is_empty() && xs.iter().all(|x| x.map(&f).unwrap_or(false))
+            }
+            Self::Many(xs) => xs.iter().any(|x| x.map(&f).unwrap_or(false)),
+        }
+    }
+}
+
+// Returns the value as an integer, where an integer cast also parses numeric strings and booleans.
+fn integer(cast: bool, value: &Value) -> Option<i64> {
+    match (cast, value) {
+        (true, Value::Bool(x)) => Some(*x as i64),
+        (true, Value::String(x)) => x.parse::<i64>().ok(),
+        (_, value) => value.to_i64(),
+    }
+}
+
+// Returns the operands of an integer comparison, or the result to evaluate as if it has none.
+//
+// NOTE: Arrays are only compared element by element when the field is cast with `int`, or when
+// every element must match, otherwise they evaluate false as any other non integer would.
+fn integer_operands(
+    expression: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> Result<Numbers<i64>, SolverResult> {
+    let (field, symbol) = match expression {
+        Expression::Integer(i) => return Ok(Numbers::One(*i)),
+        Expression::Cast(field, symbol) => (field, Some(symbol)),
+        Expression::Field(field) => (field, None),
+        _ => return Err(SolverResult::False),
+    };
+    let value = document.find(field).ok_or(SolverResult::Missing)?;
+    let cast = symbol == Some(&ModSym::Int);
+    match (symbol, value) {
+        (Some(ModSym::Len), value) => length(&value).map(Numbers::One).ok_or(SolverResult::False),
+        (_, Value::Array(a)) if cast || every => {
+            Ok(Numbers::Many(a.iter().map(|v| integer(cast, &v)).collect()))
+        }
+        (_, value) => integer(cast, &value)
+            .map(Numbers::One)
+            .ok_or(SolverResult::False),
+    }
+}
+
+// Returns the operands of a float comparison, or the result to evaluate as if it has none.
+//
+// NOTE: Integers are widened so that they can be compared against floats, while an integer cast
+// only ever yields integers, so that `int(x) > 1.5` does not match `2.5`.
+fn float_operands(
+    expression: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> Result<Numbers<f64>, SolverResult> {
+    let (field, cast) = match expression {
+        Expression::Float(f) => return Ok(Numbers::One(*f)),
+        Expression::Integer(i) => return Ok(Numbers::One(*i as f64)),
+        Expression::Cast(field, ModSym::Int) => (field, true),
+        Expression::Field(field) => (field, false),
+        _ => return Err(SolverResult::False),
+    };
+    let value = document.find(field).ok_or(SolverResult::Missing)?;
+    let float = |value: &Value| match (cast, value) {
+        (true, value) => integer(true, value).map(|x| x as f64),
+        (false, Value::Float(x)) => Some(*x),
+        (false, Value::Int(x)) => Some(*x as f64),
+        (false, Value::UInt(x)) => Some(*x as f64),
+        _ => None,
+    };
+    match value {
+        Value::Array(a) if cast || every => {
+            Ok(Numbers::Many(a.iter().map(|v| float(&v)).collect()))
+        }
+        value => float(&value).map(Numbers::One).ok_or(SolverResult::False),
+    }
+}
+
+// Evaluates a numeric comparison, where an array satisfies it if any of its elements do, or if
+// every one of them does when `every` is set.
+fn numeric(
+    expression: &Expression,
+    left: &Expression,
+    op: BoolSym,
+    right: &Expression,
+    document: &dyn Document,
+    every: bool,
+) -> SolverResult {
+    fn compare<T: PartialOrd>(x: T, op: BoolSym, y: T) -> bool {
+        match op {
+            BoolSym::Equal => x == y,
+            BoolSym::GreaterThan => x > y,
+            BoolSym::GreaterThanOrEqual => x >= y,
+            BoolSym::LessThan => x < y,
+            BoolSym::LessThanOrEqual => x <= y,
+            _ => unreachable!(),
+        }
+    }
+    let float = matches!(
+        (left, right),
+        (Expression::Float(_), _) | (_, Expression::Float(_))
+    );
+    let res = if float {
+        let (x, y) = match (
+            float_operands(left, document, every),
+            float_operands(right, document, every),
+        ) {
+            (Ok(x), Ok(y)) => (x, y),
+            (Err(res), _) | (_, Err(res)) => {
+                debug!(
+                    "evaluating {}, could not get the operands as floats for {}",
+                    res, expression
+                );
+                return res;
+            }
+        };
+        x.test(every, |x| y.test(every, |y| compare(x, op, y)))
+    } else {
+        let (x, y) = match (
+            integer_operands(left, document, every),
+            integer_operands(right, document, every),
+        ) {
+            (Ok(x), Ok(y)) => (x, y),
+            (Err(res), _) | (_, Err(res)) => {
+                debug!(
+                    "evaluating {}, could not get the operands as integers for {}",
+                    res, expression
+                );
+                return res;
+            }
+        };
+        x.test(every, |x| y.test(every, |y| compare(x, op, y)))
+    };
+    match res {
+        true => SolverResult::True,
+        _ => SolverResult::False,
+    }
+}
+
+// Evaluates a numeric expression wrapped in `every`, which must hold for every element of an
+// array, see `Match::Every`.
+fn every(expression: &Expression, document: &dyn Document) -> SolverResult {
+    let res = match expression {
+        Expression::BooleanExpression(left, op, right) => {
+            return numeric(expression, left, *op, right, document, true)
+        }
+        Expression::In(e, integers) => match integer_operands(e, document, true) {
+            Ok(x) => x.test(true, |x| integers.binary_search(&x).is_ok()),
+            Err(res) => return res,
+        },
+        Expression::Range(e, from, to) => match integer_operands(e, document, true) {
+            Ok(x) => x.test(true, |x| *from <= x && x <= *to),
+            Err(res) => return res,
+        },
+        _ => {
+            debug!("encountered invalid expression for every {}", expression);
+            false
+        }
+    };
+    match res {
+        true => SolverResult::True,
+        _ => SolverResult::False,
+    }
+}
+
+fn temporal(left: &Expression, right: &Expression) -> bool {
+    matches!(
+        (left, right),
+        (Expression::Timestamp(_), _)
+            | (_, Expression::Timestamp(_))
+            | (Expression::Cast(_, ModSym::Time), _)
+            | (_, Expression::Cast(_, ModSym::Time))
+    )
+}
+
+// Returns the operand of a temporal comparison, or the result to evaluate as if it has none.
+fn temporal_operand(
+    expression: &Expression,
+    document: &dyn Document,
+) -> Result<SystemTime, SolverResult> {
+    match expression {
+        Expression::Timestamp(t) => Ok(*t),
+        Expression::Cast(field, ModSym::Time) => match document.find(field) {
+            Some(v) => v.to_timestamp().ok_or(SolverResult::False),
+            None => Err(SolverResult::Missing),
+        },
+        _ => Err(SolverResult::False),
+    }
+}
+
 #[inline]
 fn search(kind: &Search, value: &str) -> SolverResult {
     match kind {
@@ -1020,6 +1547,41 @@ fn search(kind: &Search, value: &str) -> SolverResult {
                 return SolverResult::True;
             }
         }
+        Search::Fuzzy(ref i, distance, insensitive) => {
+            let within = if *insensitive {
+                within_distance(i, &value.to_lowercase(), *distance)
+            } else {
+                within_distance(i, value, *distance)
+            };
+            if within {
+                return SolverResult::True;
+            }
+        }
+        Search::Glob(ref g, insensitive) => {
+            let found = if *insensitive {
+                g.is_match(&value.to_lowercase())
+            } else {
+                g.is_match(value)
+            };
+            if found {
+                return SolverResult::True;
+            }
+        }
+        Search::Hex(ref h) => {
+            if h.is_match(value.as_bytes()) {
+                return SolverResult::True;
+            }
+        }
+        Search::Lookup(ref l, insensitive) => {
+            let found = if *insensitive {
+                l.set.contains(&value.to_lowercase())
+            } else {
+                l.set.contains(value)
+            };
+            if found {
+                return SolverResult::True;
+            }
+        }
         Search::Contains(ref i) => {
             if value.contains(i) {
                 return SolverResult::True;
@@ -1030,6 +1592,12 @@ fn search(kind: &Search, value: &str) -> SolverResult {
                 return SolverResult::True;
             }
         }
+        Search::Shadow(ref i) => {
+            return search(i, &value.to_lowercase());
+        }
+        Search::Skeleton(ref i) => {
+            return search(i, &skeleton(value));
+        }
         Search::StartsWith(ref i) => {
             if value.starts_with(i) {
                 return SolverResult::True;
@@ -1045,9 +1613,17 @@ fn search(kind: &Search, value: &str) -> SolverResult {
                 return SolverResult::True;
             }
         }
+        #[cfg(feature = "fancy_regex")]
+        Search::FancyRegex(ref i, _) => match i.is_match(value) {
+            Ok(true) => return SolverResult::True,
+            Ok(false) => {}
+            Err(e) => {
+                debug!("evaluating false, regex failed to complete - {}", e);
+            }
+        },
         Search::AhoCorasick(ref a, ref m, _) => {
             for i in a.find_overlapping_iter(value) {
-                match m[i.pattern()] {
+                match m[i.pattern().as_usize()] {
                     MatchType::Contains(_) => return SolverResult::True,
                     MatchType::EndsWith(_) => {
                         if i.end() == value.len() {
@@ -1068,18 +1644,168 @@ fn search(kind: &Search, value: &str) -> SolverResult {
             }
             return SolverResult::False;
         }
+        Search::Wide(..) => {
+            return search_bytes(kind, &parser::utf16le(value), true);
+        }
+    }
+    SolverResult::False
+}
+
+// Searches every string within the value, descending into arrays and objects, stopping at the first
+// that matches.
+fn keywords<G: Guard>(kind: &Search, value: &Value<'_>, guard: &mut G) -> SolverResult {
+    if !guard.step() {
+        return SolverResult::Missing;
+    }
+    let values: Box<dyn Iterator<Item = Value<'_>>> = match value {
+        Value::String(x) => return search(kind, x),
+        Value::Array(a) => a.iter(),
+        Value::Object(o) => Box::new(o.keys().into_iter().filter_map(move |k| o.get(&k))),
+        _ => return SolverResult::False,
+    };
+    for value in values {
+        match keywords(kind, &value, guard) {
+            SolverResult::False => {}
+            r => return r,
+        }
+    }
+    SolverResult::False
+}
+
+// Searches raw bytes with a hex pattern, or for the UTF-16LE needles of a wide search, any other
+// search is false. Wide needles can be found at any offset unless the bytes are `aligned`, as they
+// are when encoded from a string, in which case they must start on a code unit.
+#[inline]
+fn search_bytes(kind: &Search, value: &[u8], aligned: bool) -> SolverResult {
+    if let Search::Hex(ref h) = kind {
+        if h.is_match(value) {
+            return SolverResult::True;
+        }
+    } else if let Search::Wide(ref a, ref m, _) = kind {
+        for i in a.find_overlapping_iter(value) {
+            // NOTE: A match that starts part way through a code unit spans the halves of two
+            // different characters, so is not a match of the decoded string.
+            if aligned && i.start() % 2 != 0 {
+                continue;
+            }
+            match m[i.pattern().as_usize()] {
+                MatchType::Contains(_) => return SolverResult::True,
+                MatchType::EndsWith(_) => {
+                    if i.end() == value.len() {
+                        return SolverResult::True;
+                    }
+                }
+                MatchType::Exact(_) => {
+                    if i.start() == 0 && i.end() == value.len() {
+                        return SolverResult::True;
+                    }
+                }
+                MatchType::StartsWith(_) => {
+                    if i.start() == 0 {
+                        return SolverResult::True;
+                    }
+                }
+            }
+        }
     }
     SolverResult::False
 }
 
+// Returns the expression's search when it is one over several patterns, along with how many
+// patterns there are, as needle optimisation may have merged the searches of a group into it.
+fn patterned(expression: &Expression) -> Option<(&Search, &str, bool, u64)> {
+    match expression {
+        Expression::Search(s, i, c) => s.patterns().map(|p| (s, i.as_str(), *c, p as u64)),
+        _ => None,
+    }
+}
+
+// Counts the distinct patterns of a search that match the value, where an array counts as its
+// element with the most, stopping once there are `enough`. `None` is returned if the value cannot
+// be searched, or the guard runs out.
+fn count_patterns<G: Guard>(
+    kind: &Search,
+    value: &Value,
+    cast: bool,
+    enough: u64,
+    guard: &mut G,
+) -> Option<u64> {
+    let hits = match (value, cast) {
+        (Value::String(ref x), _) => hits(kind, x),
+        (Value::Bytes(ref x), _) => hits_bytes(kind, x)?,
+        (Value::Array(a), _) => {
+            let mut max = 0;
+            for v in a.iter() {
+                if !guard.step() {
+                    return None;
+                }
+                let hits = if let Some(x) = v.as_str() {
+                    hits(kind, x)
+                } else if let Some(x) = v.as_bytes().and_then(|x| hits_bytes(kind, x)) {
+                    x
+                } else if cast {
+                    match v {
+                        Value::Bool(x) => hits(kind, &x.to_string()),
+                        Value::Float(x) => hits(kind, &x.to_string()),
+                        Value::Int(x) => hits(kind, &x.to_string()),
+                        Value::UInt(x) => hits(kind, &x.to_string()),
+                        _ => continue,
+                    }
+                } else {
+                    continue;
+                };
+                if hits >= enough {
+                    return Some(hits);
+                } else if hits > max {
+                    max = hits;
+                }
+            }
+            max
+        }
+        (Value::Bool(x), true) => hits(kind, &x.to_string()),
+        (Value::Float(x), true) => hits(kind, &x.to_string()),
+        (Value::Int(x), true) => hits(kind, &x.to_string()),
+        (Value::UInt(x), true) => hits(kind, &x.to_string()),
+        _ => return None,
+    };
+    Some(hits)
+}
+
+// Counts the distinct patterns of a search that match the string, unwrapping the searches that
+// transform the value before handing it to their automaton.
+fn hits(kind: &Search, value: &str) -> u64 {
+    match kind {
+        Search::AhoCorasick(a, m, _) => slow_aho(a, m, value.as_bytes(), false),
+        Search::RegexSet(s, _) => s.matches(value).iter().count() as u64,
+        Search::Shadow(s) => hits(s, &value.to_lowercase()),
+        Search::Skeleton(s) => hits(s, &skeleton(value)),
+        Search::Wide(a, m, _) => slow_aho(a, m, &parser::utf16le(value), true),
+        _ => (search(kind, value) == SolverResult::True) as u64,
+    }
+}
+
+// Counts the distinct UTF-16LE needles of a wide search that match the raw bytes, any other search
+// cannot be applied to them.
+fn hits_bytes(kind: &Search, value: &[u8]) -> Option<u64> {
+    match kind {
+        Search::Wide(a, m, _) => Some(slow_aho(a, m, value, false)),
+        _ => None,
+    }
+}
+
+// Counts the distinct patterns of the automaton that match the value, see `search_bytes` for what
+// it means to be `aligned`.
 #[inline]
-fn slow_aho(a: &AhoCorasick, m: &[MatchType], value: &str) -> u64 {
+fn slow_aho(a: &AhoCorasick, m: &[MatchType], value: &[u8], aligned: bool) -> u64 {
     // TODO: Benchmark properly to work out whether the bitmap really is better on average
     let len = m.len();
     if len < 64 {
         let mut map = 0;
         for i in a.find_overlapping_iter(value) {
-            let p = i.pattern();
+            if aligned && i.start() % 2 != 0 {
+                continue;
+            }
+            let p = i.pattern().as_usize();
             match m[p] {
                 MatchType::Contains(_) => {
                     map |= 1 << p;
@@ -1107,30 +1833,33 @@ fn slow_aho(a: &AhoCorasick, m: &[MatchType], value: &str) -> u64 {
         }
         hits
     } else {
-        let mut hits = std::collections::HashSet::with_capacity(len);
+        let mut hits = vec![false; len];
         for i in a.find_overlapping_iter(value) {
-            let p = i.pattern();
+            if aligned && i.start() % 2 != 0 {
+                continue;
+            }
+            let p = i.pattern().as_usize();
             match m[p] {
                 MatchType::Contains(_) => {
-                    hits.insert(p);
+                    hits[p] = true;
                 }
                 MatchType::EndsWith(_) => {
                     if i.end() == value.len() {
-                        hits.insert(p);
+                        hits[p] = true;
                     }
                 }
                 MatchType::Exact(_) => {
                     if i.start() == 0 && i.end() == value.len() {
-                        hits.insert(p);
+                        hits[p] = true;
                     }
                 }
                 MatchType::StartsWith(_) => {
                     if i.start() == 0 {
-                        hits.insert(p);
+                        hits[p] = true;
                     }
                 }
             }
         }
-        hits.len() as u64
+        hits.iter().filter(|h| **h).count() as u64
     }
 }
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+/// The statistics for a single node of a compiled detection, i.e. a search or comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionStats {
+    /// The node that was evaluated.
+    pub expression: String,
+    /// The number of times that the node was evaluated.
+    pub evaluations: u64,
+    /// The number of times that the node evaluated to true.
+    pub matches: u64,
+    /// The total time spent evaluating the node.
+    pub duration: Duration,
+}
+
+/// Statistics on the evaluation of a `Program`, counting how often each of its nodes was
+/// evaluated and how long they took.
+///
+/// These are collected with `Program::matches_with_stats`, or across a corpus with
+/// `RuleSet::profile`, and are intended for finding the expressions that are hurting throughput.
+/// As each node is timed, collecting them slows down evaluation, so they should not be collected
+/// in production.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::Rule;
+/// # use serde_yaml::Value as Yaml;
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     foo: bar
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+/// let program = rule.compile();
+/// let mut stats = program.stats();
+///
+/// let document: Yaml = serde_yaml::from_str("foo: bar").unwrap();
+/// assert!(program.matches_with_stats(document.as_mapping().unwrap(), &mut stats));
+/// assert_eq!(stats.expressions()[0].evaluations, 1);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub(crate) expressions: Vec<ExpressionStats>,
+}
+
+impl Stats {
+    /// Returns the statistics for each node, in the order that they appear in the detection.
+    pub fn expressions(&self) -> &[ExpressionStats] {
+        &self.expressions
+    }
+
+    /// Returns the nodes from the most to the least time spent evaluating them.
+    pub fn hottest(&self) -> Vec<&ExpressionStats> {
+        let mut expressions: Vec<&ExpressionStats> = self.expressions.iter().collect();
+        expressions.sort_by_key(|e| std::cmp::Reverse(e.duration));
+        expressions
+    }
+
+    /// Returns the total number of node evaluations.
+    pub fn evaluations(&self) -> u64 {
+        self.expressions.iter().map(|e| e.evaluations).sum()
+    }
+
+    /// Returns the total time spent evaluating nodes.
+    pub fn duration(&self) -> Duration {
+        self.expressions.iter().map(|e| e.duration).sum()
+    }
+}
@@ -0,0 +1,241 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::diagnostic::Diagnostic;
+
+/// The kinds of rule that can be scaffolded with `Rule::template`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Template {
+    /// A rule over logon events, i.e. brute forcing or logons from unexpected sources.
+    Authentication,
+    /// A rule over network connections, i.e. traffic to unusual ports or destinations.
+    Network,
+    /// A rule over process creation events, i.e. suspicious command lines.
+    ProcessCreation,
+}
+
+impl Template {
+    /// Returns every template.
+    pub fn all() -> &'static [Template] {
+        &[
+            Template::Authentication,
+            Template::Network,
+            Template::ProcessCreation,
+        ]
+    }
+
+    /// Returns the name of the template, as accepted by `FromStr`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Template::Authentication => "authentication",
+            Template::Network => "network",
+            Template::ProcessCreation => "process-creation",
+        }
+    }
+
+    pub(crate) fn scaffold(&self) -> &'static str {
+        match self {
+            Template::Authentication => AUTHENTICATION,
+            Template::Network => NETWORK,
+            Template::ProcessCreation => PROCESS_CREATION,
+        }
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Template {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        Template::all()
+            .iter()
+            .find(|t| t.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                crate::error::rule_invalid(
+                    Diagnostic::new("template.unknown")
+                        .with("template", s)
+                        .with(
+                            "expected",
+                            Template::all()
+                                .iter()
+                                .map(|t| t.name())
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                        ),
+                )
+            })
+    }
+}
+
+// NOTE: Every scaffold must load and validate as is, so that analysts can iterate on a working
+// rule rather than fixing up the template first.
+
+const AUTHENTICATION: &str = r#"# title: <what the rule detects>
+# author: <your name>
+# description: <why the activity is suspicious, and how to triage it>
+severity: medium
+
+detection:
+  # The logon events that the rule applies to.
+  event:
+    event.category: authentication
+  # The activity being detected, prefer several small identifiers over one large one.
+  failure:
+    event.outcome: failure
+    user.name: iadministrator
+  # Known benign activity, kept separate so that exclusions are easy to review.
+  exclusion:
+    source.ip:
+    - 10.0.0.*
+
+  condition: event and failure and not exclusion
+
+# Documents that must match, and documents that must not, checked by `Rule::validate`.
+true_positives:
+- event:
+    category: authentication
+    outcome: failure
+  user:
+    name: Administrator
+  source:
+    ip: 203.0.113.10
+
+true_negatives:
+- event:
+    category: authentication
+    outcome: success
+  user:
+    name: Administrator
+  source:
+    ip: 203.0.113.10
+- event:
+    category: authentication
+    outcome: failure
+  user:
+    name: Administrator
+  source:
+    ip: 10.0.0.5
+"#;
+
+const NETWORK: &str = r#"# title: <what the rule detects>
+# author: <your name>
+# description: <why the activity is suspicious, and how to triage it>
+severity: medium
+
+detection:
+  # The connection events that the rule applies to.
+  event:
+    event.category: network
+  # The activity being detected, prefer several small identifiers over one large one.
+  destination:
+    destination.port:
+    - 4444
+    - 6667
+    - 31337
+  # Known benign activity, kept separate so that exclusions are easy to review.
+  exclusion:
+    destination.ip:
+    - 10.*
+
+  condition: event and destination and not exclusion
+
+# Documents that must match, and documents that must not, checked by `Rule::validate`.
+true_positives:
+- event:
+    category: network
+  destination:
+    ip: 203.0.113.20
+    port: 4444
+
+true_negatives:
+- event:
+    category: network
+  destination:
+    ip: 203.0.113.20
+    port: 443
+- event:
+    category: network
+  destination:
+    ip: 10.0.0.20
+    port: 4444
+"#;
+
+const PROCESS_CREATION: &str = r#"# title: <what the rule detects>
+# author: <your name>
+# description: <why the activity is suspicious, and how to triage it>
+severity: medium
+
+detection:
+  # The process creation events that the rule applies to.
+  event:
+    event.category: process
+    event.type: start
+  # The activity being detected, prefer several small identifiers over one large one.
+  process:
+    process.name: ipowershell.exe
+    process.args:
+    - i*-enc*
+    - i*-encodedcommand*
+  # Known benign activity, kept separate so that exclusions are easy to review.
+  exclusion:
+    process.parent.name: ishell_update.exe
+
+  condition: event and process and not exclusion
+
+# Documents that must match, and documents that must not, checked by `Rule::validate`.
+true_positives:
+- event:
+    category: process
+    type: start
+  process:
+    name: PowerShell.exe
+    args: -nop -enc SQBFAFgA
+    parent:
+      name: winword.exe
+
+true_negatives:
+- event:
+    category: process
+    type: start
+  process:
+    name: powershell.exe
+    args: Get-Process
+    parent:
+      name: explorer.exe
+- event:
+    category: process
+    type: start
+  process:
+    name: powershell.exe
+    args: -enc SQBFAFgA
+    parent:
+      name: shell_update.exe
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rule::Rule;
+
+    #[test]
+    fn round_trip() {
+        for template in Template::all() {
+            assert_eq!(template.name().parse::<Template>().unwrap(), *template);
+            for shake in [false, true] {
+                let rule = Rule::loader()
+                    .shake(shake)
+                    .from_str(template.scaffold())
+                    .unwrap();
+                assert!(rule.validate().unwrap(), "{} failed to validate", template);
+            }
+        }
+        assert!("unknown".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,267 @@
+// RFC 3339 parsing and formatting, without pulling in a date time crate for what is a small subset
+// of the functionality.
+
+#[cfg(feature = "std")]
+pub(crate) use std::time::SystemTime;
+#[cfg(feature = "std")]
+use std::time::{Duration, UNIX_EPOCH};
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// A point in time, for when the engine is built without std and so has no `SystemTime`.
+///
+/// It is held as the seconds either side of the UNIX epoch along with the nanoseconds into that
+/// second, so that it orders in the same way as `SystemTime`.
+#[cfg(not(feature = "std"))]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SystemTime {
+    seconds: i64,
+    nanos: u32,
+}
+
+#[cfg(not(feature = "std"))]
+impl SystemTime {
+    /// The UNIX epoch, `1970-01-01T00:00:00Z`.
+    pub const UNIX_EPOCH: SystemTime = SystemTime {
+        seconds: 0,
+        nanos: 0,
+    };
+
+    /// Returns the time at the provided offset from the UNIX epoch, where the nanoseconds are
+    /// always counted forwards, i.e. `-1` and `500_000_000` is half a second before the epoch.
+    pub fn from_unix(seconds: i64, nanos: u32) -> Option<Self> {
+        if nanos >= 1_000_000_000 {
+            return None;
+        }
+        Some(SystemTime { seconds, nanos })
+    }
+
+    /// Returns the offset of this time from the UNIX epoch, as it is given to `from_unix`.
+    pub fn to_unix(&self) -> (i64, u32) {
+        (self.seconds, self.nanos)
+    }
+}
+
+/// Returns true if the string starts like a timestamp, i.e. `YYYY-MM-DD`.
+pub(crate) fn looks_like(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 10
+        && b[..4].iter().all(u8::is_ascii_digit)
+        && b[4] == b'-'
+        && b[5..7].iter().all(u8::is_ascii_digit)
+        && b[7] == b'-'
+        && b[8..10].iter().all(u8::is_ascii_digit)
+}
+
+/// Parses an RFC 3339 timestamp, where the time and offset are optional and default to midnight
+/// and UTC respectively, i.e. `2024-01-01`, `2024-01-01T12:00:00Z` or
+/// `2024-01-01T12:00:00.5+01:00`.
+pub(crate) fn parse(s: &str) -> Option<SystemTime> {
+    if !s.is_ascii() || !looks_like(s) {
+        return None;
+    }
+    let year: i64 = s[..4].parse().ok()?;
+    let month: u32 = s[5..7].parse().ok()?;
+    let day: u32 = s[8..10].parse().ok()?;
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * SECONDS_PER_DAY;
+    let mut nanos = 0;
+    let rest = &s[10..];
+    let rest = match rest.chars().next() {
+        None => rest,
+        Some('T') | Some('t') | Some(' ') => {
+            let time = &rest[1..];
+            let b = time.as_bytes();
+            if b.len() < 5 || b[2] != b':' {
+                return None;
+            }
+            let hour: i64 = digits(&time[..2])?;
+            let minute: i64 = digits(&time[3..5])?;
+            let mut time = &time[5..];
+            let mut second = 0;
+            if let Some(t) = time.strip_prefix(':') {
+                if t.len() < 2 {
+                    return None;
+                }
+                second = digits(&t[..2])?;
+                time = &t[2..];
+                if let Some(t) = time.strip_prefix('.') {
+                    let len = t.bytes().take_while(u8::is_ascii_digit).count();
+                    if len == 0 {
+                        return None;
+                    }
+                    // NOTE: Anything beyond nanosecond precision is truncated.
+                    let fraction = &t[..len.min(9)];
+                    nanos = fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32);
+                    time = &t[len..];
+                }
+            }
+            if hour > 23 || minute > 59 || second > 60 {
+                return None;
+            }
+            seconds += hour * 3600 + minute * 60 + second;
+            time
+        }
+        Some(_) => return None,
+    };
+    match rest {
+        "" | "Z" | "z" => {}
+        offset => {
+            let sign = match offset.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            let b = offset.as_bytes();
+            if b.len() != 6 || b[3] != b':' {
+                return None;
+            }
+            let hours: i64 = digits(&offset[1..3])?;
+            let minutes: i64 = digits(&offset[4..6])?;
+            seconds -= sign * (hours * 3600 + minutes * 60);
+        }
+    }
+    from_unix(seconds, nanos)
+}
+
+#[cfg(feature = "std")]
+fn from_unix(seconds: i64, nanos: u32) -> Option<SystemTime> {
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(seconds as u64, nanos))
+    } else if nanos == 0 {
+        UNIX_EPOCH.checked_sub(Duration::new(seconds.unsigned_abs(), 0))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::new(
+            seconds.unsigned_abs() - 1,
+            1_000_000_000 - nanos,
+        ))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn from_unix(seconds: i64, nanos: u32) -> Option<SystemTime> {
+    SystemTime::from_unix(seconds, nanos)
+}
+
+#[cfg(feature = "std")]
+fn to_unix(time: SystemTime) -> (i64, u32) {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn to_unix(time: SystemTime) -> (i64, u32) {
+    time.to_unix()
+}
+
+/// Formats the time as an RFC 3339 timestamp in UTC.
+pub(crate) fn format(time: SystemTime) -> String {
+    let (seconds, nanos) = to_unix(time);
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+    let second = seconds.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let mut s = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        second / 3600,
+        second / 60 % 60,
+        second % 60
+    );
+    if nanos != 0 {
+        let fraction = format!("{:09}", nanos);
+        s.push('.');
+        s.push_str(fraction.trim_end_matches('0'));
+    }
+    s.push('Z');
+    s
+}
+
+fn digits(s: &str) -> Option<i64> {
+    if s.bytes().all(|b| b.is_ascii_digit()) {
+        s.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// NOTE: These are Howard Hinnant's algorithms, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format() {
+        let cases = [
+            ("1970-01-01", "1970-01-01T00:00:00Z"),
+            ("2024-02-29T12:34:56Z", "2024-02-29T12:34:56Z"),
+            ("2024-01-01T00:00:00.5+01:00", "2023-12-31T23:00:00.5Z"),
+            ("2024-01-01T00:00-00:30", "2024-01-01T00:30:00Z"),
+            ("1969-12-31T23:59:59.25Z", "1969-12-31T23:59:59.25Z"),
+        ];
+        for (input, output) in &cases {
+            assert_eq!(format(parse(input).unwrap()), *output);
+        }
+        assert_eq!(parse("2024-01-01T00:00:01Z"), from_unix(1_704_067_201, 0));
+        for invalid in &[
+            "2023-02-29",
+            "2024-13-01",
+            "2024-01-01T24:00:00Z",
+            "2024-01-01T00:00:00+0100",
+            "2024-01-01X",
+            "20240101",
+        ] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -1,14 +1,23 @@
-use std::fmt;
-use std::iter::Peekable;
-use std::str::Chars;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
 
 use tracing::debug;
 
+use crate::diagnostic::{Diagnostic, Position};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::timestamp::{self, SystemTime};
+
 /// Boolean symbols.
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BoolSym {
     /// `&&`
     And,
+    /// `contains`
+    Contains,
+    /// `endswith`
+    EndsWith,
     /// `==`
     Equal,
     /// `>`
@@ -21,17 +30,22 @@ pub enum BoolSym {
     LessThanOrEqual,
     /// `||`
     Or,
+    /// `startswith`
+    StartsWith,
 }
 impl fmt::Display for BoolSym {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::And => write!(f, "&&"),
+            Self::Contains => write!(f, "contains"),
+            Self::EndsWith => write!(f, "endswith"),
             Self::Equal => write!(f, "=="),
             Self::GreaterThan => write!(f, ">"),
             Self::GreaterThanOrEqual => write!(f, ">="),
             Self::LessThan => write!(f, "<"),
             Self::LessThanOrEqual => write!(f, "<="),
             Self::Or => write!(f, "||"),
+            Self::StartsWith => write!(f, "startswith"),
         }
     }
 }
@@ -52,17 +66,32 @@ pub enum DelSym {
 pub enum ModSym {
     /// `int`
     Int,
+    /// `len`
+    Len,
     /// `not`
     Not,
     /// `str`
     Str,
+    /// `skeleton`
+    Skeleton,
+    /// `time`
+    Time,
+    /// `wide`
+    Wide,
+    /// `windash`
+    Windash,
 }
 impl fmt::Display for ModSym {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Int => write!(f, "int"),
+            Self::Len => write!(f, "len"),
             Self::Not => write!(f, "not"),
+            Self::Skeleton => write!(f, "skeleton"),
             Self::Str => write!(f, "str"),
+            Self::Time => write!(f, "time"),
+            Self::Wide => write!(f, "wide"),
+            Self::Windash => write!(f, "windash"),
         }
     }
 }
@@ -100,6 +129,7 @@ pub enum Token {
     Modifier(ModSym),
     Miscellaneous(MiscSym),
     Match(MatchSym),
+    Timestamp(SystemTime),
 }
 
 impl Token {
@@ -108,26 +138,135 @@ impl Token {
             Token::Operator(ref s) => match *s {
                 BoolSym::Or => 90,
                 BoolSym::And => 80,
-                BoolSym::Equal
+                BoolSym::Contains
+                | BoolSym::EndsWith
+                | BoolSym::Equal
                 | BoolSym::GreaterThan
                 | BoolSym::GreaterThanOrEqual
                 | BoolSym::LessThan
-                | BoolSym::LessThanOrEqual => 70,
+                | BoolSym::LessThanOrEqual
+                | BoolSym::StartsWith => 70,
             },
             Token::Miscellaneous(ref m) => match *m {
                 MiscSym::Not => 95,
             },
             Token::Modifier(ref m) => match *m {
-                ModSym::Int | ModSym::Not | ModSym::Str => 60,
+                ModSym::Int
+                | ModSym::Len
+                | ModSym::Not
+                | ModSym::Skeleton
+                | ModSym::Str
+                | ModSym::Time
+                | ModSym::Wide
+                | ModSym::Windash => 60,
             },
             Token::Match(ref s) => match *s {
                 MatchSym::All | MatchSym::Of => 60,
             },
-            Token::Delimiter(_) | Token::Float(_) | Token::Identifier(_) | Token::Integer(_) => 0,
+            Token::Delimiter(_)
+            | Token::Float(_)
+            | Token::Identifier(_)
+            | Token::Integer(_)
+            | Token::Timestamp(_) => 0,
+        }
+    }
+}
+
+/// The dialect that rules are written in.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Dialect {
+    /// The current syntax.
+    #[default]
+    Current,
+    /// The legacy syntax, which is the current syntax with the following additions:
+    /// - `integer(k)`: the same as `int(k)`
+    /// - `string(k)`: the same as `str(k)`
+    /// - `=`: the same as `==`
+    Legacy,
+}
+
+impl Dialect {
+    // Returns the provided keywords extended with those required by the dialect.
+    #[cfg(feature = "std")]
+    pub(crate) fn keywords(&self, keywords: &Keywords) -> Keywords {
+        match self {
+            Self::Current => keywords.clone(),
+            Self::Legacy => keywords
+                .clone()
+                // NOTE: We have to alias `==` so that it is not consumed as two `=`.
+                .alias("==".to_owned(), Token::Operator(BoolSym::Equal))
+                .alias("=".to_owned(), Token::Operator(BoolSym::Equal))
+                .alias("integer".to_owned(), Token::Modifier(ModSym::Int)),
         }
     }
 }
 
+/// Alternative spellings for the keywords used in conditions.
+///
+/// This allows for conditions written for other query languages to be used without being
+/// rewritten, i.e. `A AND NOT B` or `A && !B`. Aliases that end in a letter or number must be
+/// followed by a character that could not continue an identifier.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Keywords {
+    aliases: Vec<(String, Token)>,
+}
+
+impl Keywords {
+    /// Create an empty set of aliases.
+    pub fn new() -> Self {
+        Keywords::default()
+    }
+
+    /// Add an alias for `and`.
+    pub fn and<S: Into<String>>(self, alias: S) -> Self {
+        self.alias(alias.into(), Token::Operator(BoolSym::And))
+    }
+
+    /// Add an alias for the `not` that negates an expression.
+    pub fn not<S: Into<String>>(self, alias: S) -> Self {
+        self.alias(alias.into(), Token::Miscellaneous(MiscSym::Not))
+    }
+
+    /// Add an alias for `or`.
+    pub fn or<S: Into<String>>(self, alias: S) -> Self {
+        self.alias(alias.into(), Token::Operator(BoolSym::Or))
+    }
+
+    fn alias(mut self, alias: String, token: Token) -> Self {
+        self.aliases.retain(|(a, _)| *a != alias);
+        self.aliases.push((alias, token));
+        // NOTE: Longest first so that the most specific alias wins.
+        self.aliases
+            .sort_by_key(|(a, _)| core::cmp::Reverse(a.chars().count()));
+        self
+    }
+
+    fn matches(&self, it: &mut Input<'_>) -> Option<(usize, &Token)> {
+        for (alias, token) in &self.aliases {
+            if !match_ahead(it, alias) {
+                continue;
+            }
+            let len = alias.chars().count();
+            if let Token::Modifier(_) = token {
+                // NOTE: Like the built in modifiers, they must be followed by a parenthesis
+                // which we leave for the parser.
+                if it.clone().nth(len) != Some('(') {
+                    continue;
+                }
+            } else if alias.ends_with(|c: char| c.is_alphanumeric()) {
+                let mut p = it.clone();
+                if let Some(c) = p.nth(len) {
+                    if c.is_alphanumeric() || c == '_' || c == '.' || c == '#' {
+                        continue;
+                    }
+                }
+            }
+            return Some((len, token));
+        }
+        None
+    }
+}
+
 /// Tokenise data into a collection of Tokens to then be used by the parser. This trait converts a
 /// tau engine condition into collection of Tokens.
 ///
@@ -135,149 +274,277 @@ impl Token {
 /// | Match | Description |
 /// |---|---|
 /// | '-', '0-9' | Integers |
+/// | 'YYYY-MM-DD' | Timestamps, in RFC 3339 |
 /// | 'a-z', 'A-Z' | Keywords & Identifiers |
-/// | ' ', '\x09'-'\x0d' | Whitespace |
+/// | ' ', '\x09'-'\x0d' | Whitespace, including new lines |
+/// | '# ' | Comments, which run to the end of the line |
 /// | '=', '==', '>', '>=', '<', '<=' | Booleans |
 /// | ',', '(', ')' | Miscellaneous |
 ///
 /// Where keywords are:
 /// - all
 /// - and
+/// - contains
+/// - endswith
 /// - int
+/// - len
 /// - not
 /// - of
 /// - or
+/// - startswith
 /// - str
 /// - string
+/// - time
+/// - wide
+/// - windash
+///
+/// Additional spellings for keywords can be provided with `Keywords`.
+///
+/// As identifiers can contain a `#`, a comment must start with a `#` that is followed by
+/// whitespace, i.e. `A and B # only on servers`.
 pub trait Tokeniser {
-    fn tokenise(&self) -> crate::Result<Vec<Token>>;
+    #[cfg_attr(not(any(feature = "core", feature = "unstable")), allow(dead_code))]
+    fn tokenise(&self) -> crate::Result<Vec<Token>> {
+        self.tokenise_with(&Keywords::default())
+    }
+    fn tokenise_with(&self, keywords: &Keywords) -> crate::Result<Vec<Token>>;
 }
 impl Tokeniser for String {
-    fn tokenise(&self) -> crate::Result<Vec<Token>> {
-        let mut it = self.chars().peekable();
-        let mut tokens: Vec<Token> = vec![];
-        while let Some(&c) = it.peek() {
-            match c {
-                '.' | '-' | '0'..='9' => {
-                    // A number
-                    let number: String = consume_while(&mut it, |a| a.is_numeric() || a == '.')
-                        .into_iter()
-                        .collect();
-                    if number.contains('.') {
-                        let float = number.parse().map_err(crate::error::token_invalid_num)?;
-                        tokens.push(Token::Float(float));
-                    } else {
-                        let integer = number.parse().map_err(crate::error::token_invalid_num)?;
-                        tokens.push(Token::Integer(integer));
-                    }
-                }
-                'a'..='z' | 'A'..='Z' | '#' => {
-                    if match_ahead(&mut it, "int(") {
-                        tokens.push(Token::Modifier(ModSym::Int));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "string(") {
-                        // NOTE: Deprecated
-                        tokens.push(Token::Modifier(ModSym::Str));
-                        it.nth(5);
-                    } else if match_ahead(&mut it, "str(") {
-                        tokens.push(Token::Modifier(ModSym::Str));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "and ") {
-                        tokens.push(Token::Operator(BoolSym::And));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "or ") {
-                        tokens.push(Token::Operator(BoolSym::Or));
-                        it.nth(1);
-                    } else if match_ahead(&mut it, "not ") {
-                        tokens.push(Token::Miscellaneous(MiscSym::Not));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "not(") {
-                        tokens.push(Token::Modifier(ModSym::Not));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "all(") {
-                        tokens.push(Token::Match(MatchSym::All));
-                        it.nth(2);
-                    } else if match_ahead(&mut it, "of(") {
-                        tokens.push(Token::Match(MatchSym::Of));
-                        it.nth(1);
-                    } else {
-                        let identifier: String = consume_while(&mut it, |a| {
-                            a.is_alphanumeric() || a == '_' || a == '.' || a == '#'
-                        })
-                        .into_iter()
-                        .collect();
-                        tokens.push(Token::Identifier(identifier));
-                    }
-                }
-                ' ' | '\x09'..='\x0d' => {
-                    it.next(); // no-op for whitespace
-                }
-                '=' => {
-                    // "=="
-                    let mut p = it.clone();
-                    p.next();
-                    if p.next().unwrap_or(' ') == '=' {
-                        tokens.push(Token::Operator(BoolSym::Equal));
-                        it.nth(1);
-                    } else {
-                        return Err(crate::error::token_invalid_char("expected '='"));
-                    }
-                }
-                '<' => {
-                    // "< | <="
-                    let mut p = it.clone();
-                    p.next();
-                    if p.next().unwrap_or(' ') == '=' {
-                        tokens.push(Token::Operator(BoolSym::LessThanOrEqual));
-                        it.next();
-                    } else {
-                        tokens.push(Token::Operator(BoolSym::LessThan));
-                    }
-                    it.next();
-                }
-                '>' => {
-                    // "> | >="
-                    let mut p = it.clone();
-                    p.next();
-                    if p.next().unwrap_or(' ') == '=' {
-                        tokens.push(Token::Operator(BoolSym::GreaterThanOrEqual));
-                        it.next();
-                    } else {
-                        tokens.push(Token::Operator(BoolSym::GreaterThan));
-                    }
-                    it.next();
-                }
-                ',' => {
-                    // ","
-                    tokens.push(Token::Delimiter(DelSym::Comma));
-                    it.next();
-                }
-                '(' => {
-                    // "("
-                    tokens.push(Token::Delimiter(DelSym::LeftParenthesis));
-                    it.next();
-                }
-                ')' => {
-                    // ")"
-                    tokens.push(Token::Delimiter(DelSym::RightParenthesis));
-                    it.next();
-                }
-                _ => {
-                    return Err(crate::error::token_invalid_char(format!(
-                        "unsupported character '{}'",
-                        c
-                    )));
+    fn tokenise_with(&self, keywords: &Keywords) -> crate::Result<Vec<Token>> {
+        tokenise(self, keywords).map(|(tokens, _)| tokens)
+    }
+}
+
+// Tokenises the condition, returning the position of each token in it alongside the tokens.
+pub(crate) fn tokenise(
+    condition: &str,
+    keywords: &Keywords,
+) -> crate::Result<(Vec<Token>, Vec<Position>)> {
+    let mut it = Input::new(condition);
+    let mut tokens: Vec<Token> = vec![];
+    let mut positions: Vec<Position> = vec![];
+    while it.peek().is_some() {
+        let position = it.position;
+        if let Some(token) = step(&mut it, keywords).map_err(|e| e.at(position))? {
+            tokens.push(token);
+            positions.push(position);
+        }
+    }
+    debug!("tokenised '{}' into '{:?}'", condition, tokens);
+
+    Ok((tokens, positions))
+}
+
+// Consumes the next token from the iterator, or the whitespace or comment before it.
+fn step(it: &mut Input<'_>, keywords: &Keywords) -> crate::Result<Option<Token>> {
+    let c = match it.peek() {
+        Some(&c) => c,
+        None => return Ok(None),
+    };
+    if let Some((len, token)) = keywords.matches(it) {
+        it.nth(len - 1);
+        return Ok(Some(token.clone()));
+    }
+    let token = match c {
+        '0'..='9' if timestamp::looks_like(&it.clone().take(10).collect::<String>()) => {
+            // A timestamp
+            let literal: String = consume_while(it, |a| {
+                a.is_ascii_alphanumeric() || a == ':' || a == '.' || a == '+' || a == '-'
+            })
+            .into_iter()
+            .collect();
+            match timestamp::parse(&literal) {
+                Some(t) => Token::Timestamp(t),
+                None => {
+                    return Err(crate::error::token_invalid_num(
+                        Diagnostic::new("token.invalid_timestamp").with("literal", literal),
+                    ))
                 }
             }
         }
-        debug!("tokenised '{}' into '{:?}'", self, tokens);
+        '.' | '-' | '0'..='9' => {
+            // A number
+            let number: String = consume_while(it, |a| a.is_numeric() || a == '.')
+                .into_iter()
+                .collect();
+            if number.contains('.') {
+                Token::Float(number.parse().map_err(crate::error::token_invalid_num)?)
+            } else {
+                Token::Integer(number.parse().map_err(crate::error::token_invalid_num)?)
+            }
+        }
+        '#' if it.clone().nth(1).map(is_whitespace).unwrap_or(true) => {
+            // A comment
+            consume_while(it, |a| a != '\n');
+            return Ok(None);
+        }
+        'a'..='z' | 'A'..='Z' | '#' => {
+            if match_ahead(it, "int(") {
+                it.nth(2);
+                Token::Modifier(ModSym::Int)
+            } else if match_ahead(it, "len(") {
+                it.nth(2);
+                Token::Modifier(ModSym::Len)
+            } else if match_ahead(it, "skeleton(") {
+                it.nth(7);
+                Token::Modifier(ModSym::Skeleton)
+            } else if match_ahead(it, "string(") {
+                // NOTE: Deprecated
+                it.nth(5);
+                Token::Modifier(ModSym::Str)
+            } else if match_ahead(it, "str(") {
+                it.nth(2);
+                Token::Modifier(ModSym::Str)
+            } else if match_ahead(it, "time(") {
+                it.nth(3);
+                Token::Modifier(ModSym::Time)
+            } else if match_ahead(it, "wide(") {
+                it.nth(3);
+                Token::Modifier(ModSym::Wide)
+            } else if match_ahead(it, "windash(") {
+                it.nth(6);
+                Token::Modifier(ModSym::Windash)
+            } else if match_word(it, "and") {
+                it.nth(2);
+                Token::Operator(BoolSym::And)
+            } else if match_word(it, "or") {
+                it.nth(1);
+                Token::Operator(BoolSym::Or)
+            } else if match_word(it, "contains") {
+                it.nth(7);
+                Token::Operator(BoolSym::Contains)
+            } else if match_word(it, "endswith") {
+                it.nth(7);
+                Token::Operator(BoolSym::EndsWith)
+            } else if match_word(it, "startswith") {
+                it.nth(9);
+                Token::Operator(BoolSym::StartsWith)
+            } else if match_word(it, "not") {
+                it.nth(2);
+                Token::Miscellaneous(MiscSym::Not)
+            } else if match_ahead(it, "not(") {
+                it.nth(2);
+                Token::Modifier(ModSym::Not)
+            } else if match_ahead(it, "all(") {
+                it.nth(2);
+                Token::Match(MatchSym::All)
+            } else if match_ahead(it, "of(") {
+                it.nth(1);
+                Token::Match(MatchSym::Of)
+            } else {
+                let identifier: String = consume_while(it, |a| {
+                    a.is_alphanumeric() || a == '_' || a == '.' || a == '#'
+                })
+                .into_iter()
+                .collect();
+                Token::Identifier(identifier)
+            }
+        }
+        c if is_whitespace(c) => {
+            it.next(); // no-op for whitespace
+            return Ok(None);
+        }
+        '=' => {
+            // "=="
+            let mut p = it.clone();
+            p.next();
+            if p.next().unwrap_or(' ') == '=' {
+                it.nth(1);
+                Token::Operator(BoolSym::Equal)
+            } else {
+                return Err(crate::error::token_invalid_char(Diagnostic::new(
+                    "token.expected_equals",
+                )));
+            }
+        }
+        '<' => {
+            // "< | <="
+            let mut p = it.clone();
+            p.next();
+            let token = if p.next().unwrap_or(' ') == '=' {
+                it.next();
+                Token::Operator(BoolSym::LessThanOrEqual)
+            } else {
+                Token::Operator(BoolSym::LessThan)
+            };
+            it.next();
+            token
+        }
+        '>' => {
+            // "> | >="
+            let mut p = it.clone();
+            p.next();
+            let token = if p.next().unwrap_or(' ') == '=' {
+                it.next();
+                Token::Operator(BoolSym::GreaterThanOrEqual)
+            } else {
+                Token::Operator(BoolSym::GreaterThan)
+            };
+            it.next();
+            token
+        }
+        ',' => {
+            // ","
+            it.next();
+            Token::Delimiter(DelSym::Comma)
+        }
+        '(' => {
+            // "("
+            it.next();
+            Token::Delimiter(DelSym::LeftParenthesis)
+        }
+        ')' => {
+            // ")"
+            it.next();
+            Token::Delimiter(DelSym::RightParenthesis)
+        }
+        _ => {
+            return Err(crate::error::token_invalid_char(
+                Diagnostic::new("token.unsupported_character").with("character", c),
+            ));
+        }
+    };
+    Ok(Some(token))
+}
+
+// The characters of a condition, which tracks the position of the next character as they are
+// consumed so that each token can be located.
+#[derive(Clone)]
+struct Input<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+}
+
+impl<'a> Input<'a> {
+    fn new(condition: &'a str) -> Self {
+        Self {
+            chars: condition.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+        }
+    }
 
-        Ok(tokens)
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+}
+
+impl Iterator for Input<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
     }
 }
 
 // Helper functions
-fn consume_while<F>(it: &mut Peekable<Chars<'_>>, condition: F) -> Vec<char>
+fn consume_while<F>(it: &mut Input<'_>, condition: F) -> Vec<char>
 where
     F: Fn(char) -> bool,
 {
@@ -293,7 +560,11 @@ where
     v
 }
 
-fn match_ahead(it: &mut Peekable<Chars<'_>>, value: &str) -> bool {
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\x09'..='\x0d')
+}
+
+fn match_ahead(it: &mut Input<'_>, value: &str) -> bool {
     let mut p = it.clone();
     for v in value.chars() {
         match p.next() {
@@ -305,7 +576,17 @@ fn match_ahead(it: &mut Peekable<Chars<'_>>, value: &str) -> bool {
     true
 }
 
-#[cfg(test)]
+// Matches a keyword that must be followed by whitespace, which can be a new line.
+fn match_word(it: &mut Input<'_>, value: &str) -> bool {
+    match_ahead(it, value)
+        && it
+            .clone()
+            .nth(value.chars().count())
+            .map(is_whitespace)
+            .unwrap_or(false)
+}
+
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -330,6 +611,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_bool_contains() {
+        let t = String::from("a contains b").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Operator(BoolSym::Contains),
+                Token::Identifier("b".to_string()),
+            ],
+            t
+        );
+    }
+
+    #[test]
+    fn tokeniser_bool_ends_with() {
+        let t = String::from("a endswith b").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Operator(BoolSym::EndsWith),
+                Token::Identifier("b".to_string()),
+            ],
+            t
+        );
+    }
+
     #[test]
     fn tokeniser_bool_equal() {
         let t = String::from("a == b").tokenise().unwrap();
@@ -408,6 +715,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_bool_starts_with() {
+        let t = String::from("a startswith b").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Identifier("a".to_string()),
+                Token::Operator(BoolSym::StartsWith),
+                Token::Identifier("b".to_string()),
+            ],
+            t
+        );
+    }
+
     #[test]
     fn tokeniser_misc_not() {
         let t = String::from("not a").tokenise().unwrap();
@@ -434,6 +754,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_mod_len() {
+        let t = String::from("len(a)").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Len),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+            ],
+            t
+        );
+    }
+
     #[test]
     fn tokeniser_mod_not() {
         let t = String::from("not(a)").tokenise().unwrap();
@@ -448,6 +782,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_mod_skeleton() {
+        let t = String::from("skeleton(a)").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Skeleton),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+            ],
+            t
+        );
+    }
+
     #[test]
     fn tokeniser_mod_str() {
         let t = String::from("str(a)").tokenise().unwrap();
@@ -462,6 +810,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_mod_wide() {
+        let t = String::from("wide(a)").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Wide),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+            ],
+            t
+        );
+    }
+
+    #[test]
+    fn tokeniser_mod_windash() {
+        let t = String::from("windash(a)").tokenise().unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Windash),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+            ],
+            t
+        );
+    }
+
     #[test]
     fn tokeniser_search_all() {
         let t = String::from("all(a)").tokenise().unwrap();
@@ -509,6 +885,125 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tokeniser_multiline() {
+        let (t, p) = tokenise(
+            "# leading\n(foo and\n  #bar) # trailing\n\tor\tnot baz #",
+            &Keywords::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("foo".to_string()),
+                Token::Operator(BoolSym::And),
+                Token::Identifier("#bar".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+                Token::Operator(BoolSym::Or),
+                Token::Miscellaneous(MiscSym::Not),
+                Token::Identifier("baz".to_string()),
+            ],
+            t
+        );
+        let p: Vec<(usize, usize)> = p.iter().map(|p| (p.line, p.column)).collect();
+        assert_eq!(
+            vec![
+                (2, 1),
+                (2, 2),
+                (2, 6),
+                (3, 3),
+                (3, 7),
+                (4, 2),
+                (4, 5),
+                (4, 9)
+            ],
+            p
+        );
+
+        let e = tokenise("foo and\n  bar & baz", &Keywords::default())
+            .err()
+            .unwrap();
+        let position = e.diagnostic().unwrap().position().unwrap();
+        assert_eq!((position.line, position.column), (2, 7));
+        assert!(e.to_string().ends_with("at line 2, column 7"), "{}", e);
+    }
+
+    #[test]
+    fn tokeniser_keywords() {
+        let keywords = Keywords::new()
+            .and("AND")
+            .and("&&")
+            .not("!")
+            .not("NOT")
+            .or("||")
+            .or("OR");
+        let t = String::from("A AND NOT B || !(ORDER && C) OR D")
+            .tokenise_with(&keywords)
+            .unwrap();
+        assert_eq!(
+            vec![
+                Token::Identifier("A".to_string()),
+                Token::Operator(BoolSym::And),
+                Token::Miscellaneous(MiscSym::Not),
+                Token::Identifier("B".to_string()),
+                Token::Operator(BoolSym::Or),
+                Token::Miscellaneous(MiscSym::Not),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("ORDER".to_string()),
+                Token::Operator(BoolSym::And),
+                Token::Identifier("C".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+                Token::Operator(BoolSym::Or),
+                Token::Identifier("D".to_string()),
+            ],
+            t
+        );
+        assert!(String::from("A && B").tokenise().is_err());
+    }
+
+    #[test]
+    fn tokeniser_legacy() {
+        let keywords = Dialect::Legacy.keywords(&Keywords::default());
+        let t = String::from("integer(a) = 1 and integer == 2")
+            .tokenise_with(&keywords)
+            .unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Int),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+                Token::Operator(BoolSym::Equal),
+                Token::Integer(1),
+                Token::Operator(BoolSym::And),
+                Token::Identifier("integer".to_string()),
+                Token::Operator(BoolSym::Equal),
+                Token::Integer(2),
+            ],
+            t
+        );
+        assert!(String::from("a = 1").tokenise().is_err());
+    }
+
+    #[test]
+    fn tokeniser_timestamp() {
+        let t = String::from("time(a) >= 2024-01-01T00:00:00Z")
+            .tokenise()
+            .unwrap();
+        assert_eq!(
+            vec![
+                Token::Modifier(ModSym::Time),
+                Token::Delimiter(DelSym::LeftParenthesis),
+                Token::Identifier("a".to_string()),
+                Token::Delimiter(DelSym::RightParenthesis),
+                Token::Operator(BoolSym::GreaterThanOrEqual),
+                Token::Timestamp(crate::timestamp::parse("2024-01-01").unwrap()),
+            ],
+            t
+        );
+        assert!(String::from("2024-01-01T99").tokenise().is_err());
+    }
+
     #[test]
     fn tokeniser_invalid_character() {
         let e = String::from("foo & bar").tokenise().err().unwrap();
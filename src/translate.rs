@@ -0,0 +1,766 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Expression, Match, MatchType, Search};
+use crate::rule::Detection;
+use crate::timestamp;
+use crate::tokeniser::{BoolSym, ModSym};
+
+/// The query languages that rules can be translated into, see `Translator`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Backend {
+    /// Elasticsearch's query DSL, rendered as JSON.
+    Elastic,
+    /// A SQL `WHERE` clause, in the ANSI dialect that PostgreSQL and SQLite follow.
+    ///
+    /// Identifiers are quoted with `"` and backslashes are not escapes within string literals, so
+    /// MySQL must be run in the `ANSI_QUOTES` and `NO_BACKSLASH_ESCAPES` modes to read the clause
+    /// as intended.
+    Sql,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Elastic => f.write_str("elastic"),
+            Self::Sql => f.write_str("sql"),
+        }
+    }
+}
+
+/// Translates the detection logic of rules into queries, so that the same rules can be pushed
+/// down to a datastore, i.e. when retro-hunting, as well as evaluated in-process.
+///
+/// Any expression can be translated, though an optimised one is usually smaller. Searches are
+/// translated as follows:
+///
+/// - SQL: exact matches use `=`, all other string matches use `LIKE`, with case insensitive
+///   matches comparing against `LOWER` of the column.
+/// - Elasticsearch: exact matches use `term`, starts with matches use `prefix` and all other
+///   string matches use `wildcard`, with case insensitive matches setting `case_insensitive`.
+///
+/// Documents are treated as flat rows, so a field is translated to the column, or field, of the
+/// same name unless it has been mapped with `field`. Unmapped SQL columns are quoted, mapped ones
+/// are used verbatim, which allows for them to be expressions such as `data->>'user'`.
+///
+/// Constructs without an equivalent in the backend, such as regexes, fuzzy matches, lookups and
+/// hex patterns, fail to translate rather than being approximated. Nested expressions can only be
+/// translated for Elasticsearch, where they become `nested` queries.
+///
+/// NOTE: Unlike the solver, datastores do not distinguish between a field that is missing and
+/// one that does not match, so negations of missing fields may differ.
+///
+/// # Example
+///
+/// ```
+/// use tau_engine::{Backend, Rule, Translator};
+///
+/// let rule = Rule::from_str(r#"
+/// detection:
+///   A:
+///     process.name: ipowershell.exe
+///     process.args: '*-enc*'
+///   condition: A
+/// true_positives: []
+/// true_negatives: []
+/// "#).unwrap();
+///
+/// let sql = Translator::new(Backend::Sql).translate(&rule.detection).unwrap();
+/// assert_eq!(
+///     sql,
+///     r#"(LOWER("process.name") = 'powershell.exe' AND "process.args" LIKE '%-enc%' ESCAPE '\')"#
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct Translator {
+    backend: Backend,
+    fields: HashMap<String, String>,
+}
+
+impl Translator {
+    /// Create a new translator for the given backend.
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Map a field to the column, or field, that it should be translated to.
+    pub fn field<A: Into<String>, B: Into<String>>(mut self, from: A, to: B) -> Self {
+        self.fields.insert(from.into(), to.into());
+        self
+    }
+
+    /// Translates the detection into a query for the backend.
+    pub fn translate(&self, detection: &Detection) -> crate::Result<String> {
+        let query = self.lower(&detection.expression, &detection.identifiers)?;
+        match self.backend {
+            Backend::Elastic => self.elastic(&query, None),
+            Backend::Sql => self.sql(&query),
+        }
+    }
+
+    fn unsupported(&self, expression: &dyn fmt::Display) -> crate::Error {
+        crate::error::rule_invalid(
+            Diagnostic::new("translate.unsupported")
+                .with("backend", self.backend)
+                .with("expression", expression),
+        )
+    }
+
+    // Lowers the expression into a query, resolving identifiers and breaking merged searches
+    // back into their needles.
+    fn lower(
+        &self,
+        expression: &Expression,
+        identifiers: &HashMap<String, Expression>,
+    ) -> crate::Result<Query> {
+        let lower = |e: &Expression| self.lower(e, identifiers);
+        let query = match expression {
+            Expression::BooleanGroup(BoolSym::And, group) => {
+                Query::All(group.iter().map(lower).collect::<crate::Result<_>>()?)
+            }
+            Expression::BooleanGroup(BoolSym::Or, group) => {
+                Query::Any(group.iter().map(lower).collect::<crate::Result<_>>()?)
+            }
+            Expression::BooleanExpression(left, BoolSym::And, right) => {
+                Query::All(vec![lower(left)?, lower(right)?])
+            }
+            Expression::BooleanExpression(left, BoolSym::Or, right) => {
+                Query::Any(vec![lower(left)?, lower(right)?])
+            }
+            Expression::BooleanExpression(left, symbol, right) => match symbol {
+                BoolSym::Equal
+                | BoolSym::GreaterThan
+                | BoolSym::GreaterThanOrEqual
+                | BoolSym::LessThan
+                | BoolSym::LessThanOrEqual => {
+                    Query::Compare(self.operand(left)?, *symbol, self.operand(right)?)
+                }
+                _ => return Err(self.unsupported(expression)),
+            },
+            Expression::Boolean(b) => Query::Constant(*b),
+            Expression::Identifier(i) => match identifiers.get(i) {
+                Some(e) => lower(e)?,
+                None => return Err(self.unsupported(expression)),
+            },
+            Expression::In(e, integers) => Query::In(self.operand(e)?, integers.clone()),
+            Expression::Match(m, e) => {
+                let e = match e.as_ref() {
+                    Expression::Identifier(i) => match identifiers.get(i) {
+                        Some(e) => e,
+                        None => return Err(self.unsupported(expression)),
+                    },
+                    e => e,
+                };
+                // NOTE: A match counts the members of a group, where a merged search counts each
+                // of its needles, while anything else counts once, see the solver.
+                match (m, e) {
+                    (m, Expression::BooleanGroup(_, group)) => {
+                        let mut members = vec![];
+                        for e in group {
+                            match self.needles(e)? {
+                                Some(needles) => members.extend(needles),
+                                None => members.push(lower(e)?),
+                            }
+                        }
+                        match m {
+                            Match::All => Query::All(members),
+                            Match::Of(0) => Query::Not(Box::new(Query::Any(members))),
+                            Match::Of(1) => Query::Any(members),
+                            Match::Of(n) => Query::AtLeast(*n, members),
+                            Match::Every => return Err(self.unsupported(expression)),
+                        }
+                    }
+                    (Match::All, Expression::Nested(_, _)) => {
+                        return Err(self.unsupported(expression))
+                    }
+                    (Match::All, e) => match self.needles(e)? {
+                        Some(needles) => Query::All(needles),
+                        None => lower(e)?,
+                    },
+                    (Match::Of(0), e) => Query::Not(Box::new(lower(e)?)),
+                    (Match::Of(1), e) => lower(e)?,
+                    (Match::Of(_), Expression::Nested(_, _)) => {
+                        return Err(self.unsupported(expression))
+                    }
+                    (Match::Of(_), e) => lower(e)?,
+                    // NOTE: The backends have no way to require every element of an array to
+                    // match.
+                    (Match::Every, _) => return Err(self.unsupported(expression)),
+                }
+            }
+            Expression::Negate(e) => Query::Not(Box::new(lower(e)?)),
+            Expression::Nested(field, e) => Query::Nested(field.clone(), Box::new(lower(e)?)),
+            Expression::Range(e, x, y) => Query::Range(self.operand(e)?, *x, *y),
+            Expression::Search(search, field, cast) => {
+                let field = Operand::Field(field.clone(), cast.then_some(ModSym::Str));
+                match search {
+                    Search::Any => Query::Exists(field),
+                    Search::AhoCorasick(_, contexts, insensitive) => any(contexts
+                        .iter()
+                        .map(|c| Query::Text(field.clone(), c.clone(), *insensitive))
+                        .collect()),
+                    Search::Contains(s) => {
+                        Query::Text(field, MatchType::Contains(s.clone()), false)
+                    }
+                    Search::EndsWith(s) => {
+                        Query::Text(field, MatchType::EndsWith(s.clone()), false)
+                    }
+                    Search::Exact(s) => Query::Text(field, MatchType::Exact(s.clone()), false),
+                    Search::StartsWith(s) => {
+                        Query::Text(field, MatchType::StartsWith(s.clone()), false)
+                    }
+                    // NOTE: A shadowed search holds lowercase needles, which is exactly how the
+                    // backends treat case insensitive searches.
+                    Search::Shadow(inner) => match inner.as_ref() {
+                        Search::AhoCorasick(_, contexts, _) => any(contexts
+                            .iter()
+                            .map(|c| Query::Text(field.clone(), c.clone(), true))
+                            .collect()),
+                        Search::Contains(s) => {
+                            Query::Text(field, MatchType::Contains(s.clone()), true)
+                        }
+                        Search::EndsWith(s) => {
+                            Query::Text(field, MatchType::EndsWith(s.clone()), true)
+                        }
+                        Search::Exact(s) => Query::Text(field, MatchType::Exact(s.clone()), true),
+                        Search::StartsWith(s) => {
+                            Query::Text(field, MatchType::StartsWith(s.clone()), true)
+                        }
+                        _ => return Err(self.unsupported(expression)),
+                    },
+                    _ => return Err(self.unsupported(expression)),
+                }
+            }
+            Expression::BooleanGroup(_, _)
+            | Expression::Cast(_, _)
+            | Expression::Field(_)
+            | Expression::Float(_)
+            | Expression::Integer(_)
+            | Expression::Keywords(_)
+            | Expression::Null
+            | Expression::Timestamp(_) => return Err(self.unsupported(expression)),
+        };
+        Ok(query)
+    }
+
+    // Returns the needles of a merged search as separate queries, so that they can be counted.
+    fn needles(&self, expression: &Expression) -> crate::Result<Option<Vec<Query>>> {
+        let (search, field, cast) = match expression {
+            Expression::Search(search, field, cast) => (search, field, cast),
+            _ => return Ok(None),
+        };
+        let (contexts, insensitive) = match search {
+            Search::AhoCorasick(_, contexts, insensitive) => (contexts, *insensitive),
+            Search::Shadow(inner) => match inner.as_ref() {
+                Search::AhoCorasick(_, contexts, _) => (contexts, true),
+                Search::RegexSet(_, _) => return Err(self.unsupported(expression)),
+                _ => return Ok(None),
+            },
+            Search::RegexSet(_, _) => return Err(self.unsupported(expression)),
+            _ => return Ok(None),
+        };
+        let field = Operand::Field(field.clone(), cast.then_some(ModSym::Str));
+        Ok(Some(
+            contexts
+                .iter()
+                .map(|c| Query::Text(field.clone(), c.clone(), insensitive))
+                .collect(),
+        ))
+    }
+
+    fn operand(&self, expression: &Expression) -> crate::Result<Operand> {
+        let operand = match expression {
+            Expression::Boolean(b) => Operand::Boolean(*b),
+            Expression::Cast(field, symbol) => Operand::Field(field.clone(), Some(symbol.clone())),
+            Expression::Field(field) => Operand::Field(field.clone(), None),
+            Expression::Float(f) => Operand::Float(*f),
+            Expression::Integer(i) => Operand::Integer(*i),
+            Expression::Null => Operand::Null,
+            Expression::Timestamp(t) => Operand::Timestamp(*t),
+            _ => return Err(self.unsupported(expression)),
+        };
+        Ok(operand)
+    }
+
+    fn sql(&self, query: &Query) -> crate::Result<String> {
+        let join = |queries: &[Query], separator: &str| -> crate::Result<String> {
+            let queries = queries
+                .iter()
+                .map(|q| self.sql(q))
+                .collect::<crate::Result<Vec<_>>>()?;
+            Ok(match queries.len() {
+                0 => unreachable!(),
+                1 => queries.into_iter().next().expect("missing query"),
+                _ => format!("({})", queries.join(separator)),
+            })
+        };
+        let sql = match query {
+            Query::All(queries) if queries.is_empty() => "TRUE".to_owned(),
+            Query::All(queries) => join(queries, " AND ")?,
+            Query::Any(queries) if queries.is_empty() => "FALSE".to_owned(),
+            Query::Any(queries) => join(queries, " OR ")?,
+            Query::AtLeast(n, queries) => {
+                let counts = queries
+                    .iter()
+                    .map(|q| Ok(format!("CASE WHEN {} THEN 1 ELSE 0 END", self.sql(q)?)))
+                    .collect::<crate::Result<Vec<_>>>()?;
+                format!("({}) >= {}", counts.join(" + "), n)
+            }
+            Query::Compare(left, BoolSym::Equal, Operand::Null)
+            | Query::Compare(Operand::Null, BoolSym::Equal, left) => {
+                format!("{} IS NULL", self.sql_operand(left))
+            }
+            Query::Compare(left, symbol, right) => {
+                let symbol = match symbol {
+                    BoolSym::Equal => "=",
+                    BoolSym::GreaterThan => ">",
+                    BoolSym::GreaterThanOrEqual => ">=",
+                    BoolSym::LessThan => "<",
+                    BoolSym::LessThanOrEqual => "<=",
+                    _ => unreachable!(),
+                };
+                format!(
+                    "{} {} {}",
+                    self.sql_operand(left),
+                    symbol,
+                    self.sql_operand(right)
+                )
+            }
+            Query::Constant(true) => "TRUE".to_owned(),
+            Query::Constant(false) => "FALSE".to_owned(),
+            Query::Exists(field) => format!("{} IS NOT NULL", self.sql_operand(field)),
+            Query::In(operand, integers) => format!(
+                "{} IN ({})",
+                self.sql_operand(operand),
+                integers
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Query::Nested(field, _) => return Err(self.unsupported(&format!("nested({})", field))),
+            Query::Not(query) => format!("NOT ({})", self.sql(query)?),
+            Query::Range(operand, x, y) => {
+                format!("{} BETWEEN {} AND {}", self.sql_operand(operand), x, y)
+            }
+            Query::Text(field, context, insensitive) => {
+                let (column, value) = if *insensitive {
+                    (
+                        format!("LOWER({})", self.sql_operand(field)),
+                        context.value().to_lowercase(),
+                    )
+                } else {
+                    (self.sql_operand(field), context.value().clone())
+                };
+                let like =
+                    |pattern: String| format!("{} LIKE {} ESCAPE '\\'", column, quote(&pattern));
+                match context {
+                    MatchType::Exact(_) => format!("{} = {}", column, quote(&value)),
+                    MatchType::Contains(_) => like(format!("%{}%", escape_like(&value))),
+                    MatchType::EndsWith(_) => like(format!("%{}", escape_like(&value))),
+                    MatchType::StartsWith(_) => like(format!("{}%", escape_like(&value))),
+                }
+            }
+        };
+        Ok(sql)
+    }
+
+    fn sql_operand(&self, operand: &Operand) -> String {
+        match operand {
+            Operand::Boolean(true) => "TRUE".to_owned(),
+            Operand::Boolean(false) => "FALSE".to_owned(),
+            Operand::Field(field, cast) => {
+                let column = match self.fields.get(field) {
+                    Some(column) => column.clone(),
+                    None => format!("\"{}\"", field.replace('"', "\"\"")),
+                };
+                match cast {
+                    Some(ModSym::Int) => format!("CAST({} AS BIGINT)", column),
+                    Some(ModSym::Len) => format!("LENGTH({})", column),
+                    Some(ModSym::Str) => format!("CAST({} AS VARCHAR)", column),
+                    Some(ModSym::Time) => format!("CAST({} AS TIMESTAMP)", column),
+                    _ => column,
+                }
+            }
+            Operand::Float(f) => f.to_string(),
+            Operand::Integer(i) => i.to_string(),
+            Operand::Null => "NULL".to_owned(),
+            Operand::Timestamp(t) => format!("TIMESTAMP '{}'", timestamp::format(*t)),
+        }
+    }
+
+    // Renders the query as JSON, where fields within a nested query are prefixed by its path.
+    fn elastic(&self, query: &Query, path: Option<&str>) -> crate::Result<String> {
+        let many = |queries: &[Query]| -> crate::Result<String> {
+            let queries = queries
+                .iter()
+                .map(|q| self.elastic(q, path))
+                .collect::<crate::Result<Vec<_>>>()?;
+            Ok(format!("[{}]", queries.join(",")))
+        };
+        let json = match query {
+            Query::All(queries) => format!(r#"{{"bool":{{"filter":{}}}}}"#, many(queries)?),
+            Query::Any(queries) => format!(
+                r#"{{"bool":{{"should":{},"minimum_should_match":1}}}}"#,
+                many(queries)?
+            ),
+            Query::AtLeast(n, queries) => format!(
+                r#"{{"bool":{{"should":{},"minimum_should_match":{}}}}}"#,
+                many(queries)?,
+                n
+            ),
+            Query::Compare(left, symbol, right) => {
+                // NOTE: Queries can only compare a field against a value, so a comparison written
+                // the other way around is flipped.
+                let (field, symbol, value) = match (left, right) {
+                    (Operand::Field(_, _), Operand::Field(_, _)) => {
+                        return Err(self.unsupported(query))
+                    }
+                    (Operand::Field(_, _), value) => (left, *symbol, value),
+                    (value, Operand::Field(_, _)) => {
+                        let symbol = match symbol {
+                            BoolSym::GreaterThan => BoolSym::LessThan,
+                            BoolSym::GreaterThanOrEqual => BoolSym::LessThanOrEqual,
+                            BoolSym::LessThan => BoolSym::GreaterThan,
+                            BoolSym::LessThanOrEqual => BoolSym::GreaterThanOrEqual,
+                            symbol => *symbol,
+                        };
+                        (right, symbol, value)
+                    }
+                    _ => return Err(self.unsupported(query)),
+                };
+                let field = self
+                    .elastic_field(field, path)
+                    .ok_or_else(|| self.unsupported(query))?;
+                let value = match value {
+                    Operand::Boolean(b) => b.to_string(),
+                    Operand::Float(f) => f.to_string(),
+                    Operand::Integer(i) => i.to_string(),
+                    Operand::Null => {
+                        return Ok(format!(
+                            r#"{{"bool":{{"must_not":[{{"exists":{{"field":{}}}}}]}}}}"#,
+                            json(&field)
+                        ))
+                    }
+                    Operand::Timestamp(t) => json(&timestamp::format(*t)),
+                    Operand::Field(_, _) => unreachable!(),
+                };
+                let range = match symbol {
+                    BoolSym::Equal => {
+                        return Ok(format!(r#"{{"term":{{{}:{}}}}}"#, json(&field), value))
+                    }
+                    BoolSym::GreaterThan => "gt",
+                    BoolSym::GreaterThanOrEqual => "gte",
+                    BoolSym::LessThan => "lt",
+                    BoolSym::LessThanOrEqual => "lte",
+                    _ => unreachable!(),
+                };
+                format!(
+                    r#"{{"range":{{{}:{{"{}":{}}}}}}}"#,
+                    json(&field),
+                    range,
+                    value
+                )
+            }
+            Query::Constant(true) => r#"{"match_all":{}}"#.to_owned(),
+            Query::Constant(false) => r#"{"match_none":{}}"#.to_owned(),
+            Query::Exists(field) => {
+                let field = self
+                    .elastic_field(field, path)
+                    .ok_or_else(|| self.unsupported(query))?;
+                format!(r#"{{"exists":{{"field":{}}}}}"#, json(&field))
+            }
+            Query::In(operand, integers) => {
+                let field = self
+                    .elastic_field(operand, path)
+                    .ok_or_else(|| self.unsupported(query))?;
+                format!(
+                    r#"{{"terms":{{{}:[{}]}}}}"#,
+                    json(&field),
+                    integers
+                        .iter()
+                        .map(|i| i.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                )
+            }
+            Query::Nested(field, query) => {
+                let field = self.prefix(field, path);
+                format!(
+                    r#"{{"nested":{{"path":{},"query":{}}}}}"#,
+                    json(&field),
+                    self.elastic(query, Some(&field))?
+                )
+            }
+            Query::Not(query) => format!(
+                r#"{{"bool":{{"must_not":[{}]}}}}"#,
+                self.elastic(query, path)?
+            ),
+            Query::Range(operand, x, y) => {
+                let field = self
+                    .elastic_field(operand, path)
+                    .ok_or_else(|| self.unsupported(query))?;
+                format!(
+                    r#"{{"range":{{{}:{{"gte":{},"lte":{}}}}}}}"#,
+                    json(&field),
+                    x,
+                    y
+                )
+            }
+            Query::Text(field, context, insensitive) => {
+                let field = self
+                    .elastic_field(field, path)
+                    .ok_or_else(|| self.unsupported(query))?;
+                let (kind, value) = match context {
+                    MatchType::Exact(s) => ("term", s.clone()),
+                    MatchType::StartsWith(s) => ("prefix", s.clone()),
+                    MatchType::Contains(s) => ("wildcard", format!("*{}*", escape_wildcard(s))),
+                    MatchType::EndsWith(s) => ("wildcard", format!("*{}", escape_wildcard(s))),
+                };
+                let insensitive = if *insensitive {
+                    r#","case_insensitive":true"#
+                } else {
+                    ""
+                };
+                format!(
+                    r#"{{"{}":{{{}:{{"value":{}{}}}}}}}"#,
+                    kind,
+                    json(&field),
+                    json(&value),
+                    insensitive
+                )
+            }
+        };
+        Ok(json)
+    }
+
+    // Returns the name of the field for the operand, casts that Elasticsearch applies implicitly
+    // are dropped while the others can't be translated.
+    fn elastic_field(&self, operand: &Operand, path: Option<&str>) -> Option<String> {
+        match operand {
+            Operand::Field(field, None | Some(ModSym::Int | ModSym::Str | ModSym::Time)) => {
+                Some(self.prefix(field, path))
+            }
+            _ => None,
+        }
+    }
+
+    fn prefix(&self, field: &str, path: Option<&str>) -> String {
+        match path {
+            Some(path) => format!("{}.{}", path, field),
+            None => self
+                .fields
+                .get(field)
+                .cloned()
+                .unwrap_or_else(|| field.to_owned()),
+        }
+    }
+}
+
+// The backend independent form of an expression, which the backends render from.
+#[derive(Debug)]
+enum Query {
+    All(Vec<Query>),
+    Any(Vec<Query>),
+    AtLeast(u64, Vec<Query>),
+    Compare(Operand, BoolSym, Operand),
+    Constant(bool),
+    Exists(Operand),
+    In(Operand, Vec<i64>),
+    Nested(String, Box<Query>),
+    Not(Box<Query>),
+    Range(Operand, i64, i64),
+    Text(Operand, MatchType, bool),
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Boolean(bool),
+    Field(String, Option<ModSym>),
+    Float(f64),
+    Integer(i64),
+    Null,
+    Timestamp(SystemTime),
+}
+
+// A disjunction of the queries, where a single query is returned as is.
+fn any(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        return queries.remove(0);
+    }
+    Query::Any(queries)
+}
+
+// Escapes the wildcards of a SQL `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if let '%' | '_' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Escapes the wildcards of an Elasticsearch `wildcard` pattern.
+fn escape_wildcard(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if let '*' | '?' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Quotes an ANSI SQL string literal, where only the quote itself needs escaping.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+// Quotes a JSON string.
+fn json(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rule::Rule;
+
+    fn rule(detection: &str) -> Rule {
+        Rule::from_str(&format!(
+            "detection:\n{}\ntrue_positives: []\ntrue_negatives: []\n",
+            detection
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn translate_sql() {
+        let rule = rule(
+            r#"
+  A:
+    name: "o'brien"
+    path: '*100%_done*'
+    size: '>=10'
+    port: [80, 443]
+  B:
+    user: null
+    tags:
+    - 'i*FOO'
+    - 'bar*'
+  condition: A and not B"#,
+        );
+        let translator = Translator::new(Backend::Sql).field("user", "data->>'user'");
+        assert_eq!(
+            translator.translate(&rule.detection).unwrap(),
+            r#"(("name" = 'o''brien' AND "path" LIKE '%100\%\_done%' ESCAPE '\' AND "size" >= 10 AND "port" IN (80, 443)) AND NOT ((data->>'user' IS NULL AND ("tags" LIKE 'bar%' ESCAPE '\' OR LOWER("tags") LIKE '%foo' ESCAPE '\'))))"#
+        );
+
+        let rule = self::rule(
+            r#"
+  A:
+    - foo: a
+    - foo: b
+    - foo: c
+  condition: of(A, 2)"#,
+        );
+        assert_eq!(
+            translator.translate(&rule.detection).unwrap(),
+            r#"(CASE WHEN "foo" = 'a' THEN 1 ELSE 0 END + CASE WHEN "foo" = 'b' THEN 1 ELSE 0 END + CASE WHEN "foo" = 'c' THEN 1 ELSE 0 END) >= 2"#
+        );
+
+        let rule = self::rule(
+            r#"
+  A:
+    word:
+    - 'i*foo*'
+    - 'i*bär*'
+  condition: all(A)"#,
+        );
+        assert_eq!(
+            translator.translate(&rule.detection).unwrap(),
+            r#"(LOWER("word") LIKE '%foo%' ESCAPE '\' AND LOWER("word") LIKE '%bär%' ESCAPE '\')"#
+        );
+    }
+
+    #[test]
+    fn translate_sql_backslashes() {
+        let rule = rule(
+            r#"
+  A:
+    path: 'C:\Windows\'
+    dir: '*\Temp\*'
+  condition: A"#,
+        );
+        assert_eq!(
+            Translator::new(Backend::Sql)
+                .translate(&rule.detection)
+                .unwrap(),
+            r#"("path" = 'C:\Windows\' AND "dir" LIKE '%\\Temp\\%' ESCAPE '\')"#
+        );
+    }
+
+    #[test]
+    fn translate_elastic() {
+        let rule = rule(
+            r#"
+  A:
+    name: 'i*.exe'
+    size: '>10'
+  B:
+    ids:
+      id: 'a*'
+  condition: A or B"#,
+        );
+        let translator = Translator::new(Backend::Elastic).field("name", "process.name");
+        assert_eq!(
+            translator.translate(&rule.detection).unwrap(),
+            r#"{"bool":{"should":[{"bool":{"filter":[{"wildcard":{"process.name":{"value":"*.exe","case_insensitive":true}}},{"range":{"size":{"gt":10}}}]}},{"nested":{"path":"ids","query":{"prefix":{"ids.id":{"value":"a"}}}}}],"minimum_should_match":1}}"#
+        );
+    }
+
+    #[test]
+    fn translate_unsupported() {
+        let rule = rule("  A:\n    name: '?foo.*'\n  condition: A");
+        for backend in [Backend::Elastic, Backend::Sql] {
+            let error = Translator::new(backend)
+                .translate(&rule.detection)
+                .unwrap_err();
+            assert_eq!(error.diagnostic().unwrap().code(), "translate.unsupported");
+        }
+        let rule = self::rule("  A:\n    ids:\n      id: a\n  condition: A");
+        assert!(Translator::new(Backend::Sql)
+            .translate(&rule.detection)
+            .is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// Stand-ins for the types of the optional search dependencies, so that the AST has the same shape
+// whichever features are enabled. They are uninhabited, as without the dependency there is no way
+// to build one, which means that the code handling them can never be reached.
+
+#![allow(dead_code)]
+
+#[cfg(not(feature = "regex"))]
+use crate::prelude::*;
+
+/// Stands in for `aho_corasick::AhoCorasick` when the `aho_corasick` feature is disabled.
+#[cfg(not(feature = "aho_corasick"))]
+#[derive(Clone, Debug)]
+pub enum AhoCorasick {}
+
+#[cfg(not(feature = "aho_corasick"))]
+impl AhoCorasick {
+    pub(crate) fn find_overlapping_iter<H: AsRef<[u8]> + ?Sized>(
+        &self,
+        _: &H,
+    ) -> core::iter::Empty<Match> {
+        match *self {}
+    }
+}
+
+// NOTE: The types returned by the stand-ins are inhabited, otherwise the compiler would flag the
+// code that uses them as unreachable.
+#[cfg(not(feature = "aho_corasick"))]
+pub(crate) struct Match;
+
+#[cfg(not(feature = "aho_corasick"))]
+impl Match {
+    pub(crate) fn end(&self) -> usize {
+        0
+    }
+
+    pub(crate) fn pattern(&self) -> PatternID {
+        PatternID
+    }
+
+    pub(crate) fn start(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(not(feature = "aho_corasick"))]
+pub(crate) struct PatternID;
+
+#[cfg(not(feature = "aho_corasick"))]
+impl PatternID {
+    pub(crate) fn as_usize(&self) -> usize {
+        0
+    }
+}
+
+/// Stands in for `regex::Regex` when the `regex` feature is disabled.
+#[cfg(not(feature = "regex"))]
+#[derive(Clone, Debug)]
+pub enum Regex {}
+
+#[cfg(not(feature = "regex"))]
+impl Regex {
+    pub(crate) fn as_str(&self) -> &str {
+        match *self {}
+    }
+
+    pub(crate) fn is_match(&self, _: &str) -> bool {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+impl core::fmt::Display for Regex {
+    fn fmt(&self, _: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match *self {}
+    }
+}
+
+/// Stands in for `regex::RegexSet` when the `regex` feature is disabled.
+#[cfg(not(feature = "regex"))]
+#[derive(Clone, Debug)]
+pub enum RegexSet {}
+
+#[cfg(not(feature = "regex"))]
+impl RegexSet {
+    pub(crate) fn is_match(&self, _: &str) -> bool {
+        match *self {}
+    }
+
+    pub(crate) fn matches(&self, _: &str) -> SetMatches {
+        match *self {}
+    }
+
+    pub(crate) fn patterns(&self) -> &[String] {
+        match *self {}
+    }
+}
+
+#[cfg(not(feature = "regex"))]
+pub(crate) struct SetMatches;
+
+#[cfg(not(feature = "regex"))]
+impl SetMatches {
+    pub(crate) fn iter(&self) -> core::iter::Empty<usize> {
+        core::iter::empty()
+    }
+}
@@ -1,5 +1,9 @@
-use std::borrow::Cow;
-use std::collections::{HashMap, HashSet};
+use alloc::borrow::Cow;
+
+use crate::collections::{HashMap, HashSet};
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use crate::timestamp::{self, SystemTime};
 
 /// A dynamic data type that the solver can reason on.
 #[derive(Clone)]
@@ -8,6 +12,8 @@ pub enum Value<'a> {
     Null,
     /// Represents a boolean.
     Bool(bool),
+    /// Represents raw bytes, i.e. a registry value or memory blob.
+    Bytes(Cow<'a, [u8]>),
     /// Represents a float.
     Float(f64),
     /// Represents an integer.
@@ -16,6 +22,8 @@ pub enum Value<'a> {
     UInt(u64),
     /// Represents a string.
     String(Cow<'a, str>),
+    /// Represents a point in time.
+    Timestamp(SystemTime),
     /// Represents an array.
     Array(&'a dyn Array),
     /// Represents an object.
@@ -35,6 +43,12 @@ impl<'a> Value<'a> {
         matches!(self, Self::Bool(_))
     }
 
+    /// Returns true if the `Value` is Bytes.
+    #[inline]
+    pub fn is_bytes(&self) -> bool {
+        matches!(self, Self::Bytes(_))
+    }
+
     /// Returns true if the `Value` is a Float.
     #[inline]
     pub fn is_f64(&self) -> bool {
@@ -65,6 +79,12 @@ impl<'a> Value<'a> {
         matches!(self, Self::String(_))
     }
 
+    /// Returns true if the `Value` is a Timestamp.
+    #[inline]
+    pub fn is_timestamp(&self) -> bool {
+        matches!(self, Self::Timestamp(_))
+    }
+
     /// Returns true if the `Value` is a UInt.
     #[inline]
     pub fn is_u64(&self) -> bool {
@@ -89,6 +109,15 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Return the associated bytes if the `Value` is Bytes.
+    #[inline]
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
     /// Return the associated f64 if the `Value` is a Float.
     #[inline]
     pub fn as_f64(&self) -> Option<f64> {
@@ -134,6 +163,15 @@ impl<'a> Value<'a> {
         }
     }
 
+    /// Return the associated time if the `Value` is a Timestamp.
+    #[inline]
+    pub fn as_timestamp(&self) -> Option<SystemTime> {
+        match self {
+            Self::Timestamp(t) => Some(*t),
+            _ => None,
+        }
+    }
+
     /// Return the associated u64 if the `Value` is a UInt.
     #[inline]
     pub fn as_u64(&self) -> Option<u64> {
@@ -163,7 +201,8 @@ impl<'a> Value<'a> {
 
     /// Returns the `Value` as a String if possible.
     ///
-    /// Currently supports: Bool, Float, Int, String & UInt.
+    /// Currently supports: Bool, Float, Int, String, Timestamp & UInt, where timestamps are
+    /// formatted as RFC 3339 in UTC.
     #[inline]
     pub fn to_string(&self) -> Option<String> {
         match self {
@@ -172,6 +211,19 @@ impl<'a> Value<'a> {
             Self::UInt(u) => Some(u.to_string()),
             Self::Float(f) => Some(f.to_string()),
             Self::String(s) => Some(s.to_string()),
+            Self::Timestamp(t) => Some(timestamp::format(*t)),
+            _ => None,
+        }
+    }
+
+    /// Returns the `Value` as a time if possible.
+    ///
+    /// Currently supports: String & Timestamp, where strings must be RFC 3339 timestamps.
+    #[inline]
+    pub fn to_timestamp(&self) -> Option<SystemTime> {
+        match self {
+            Self::String(s) => timestamp::parse(s),
+            Self::Timestamp(t) => Some(*t),
             _ => None,
         }
     }
@@ -230,6 +282,13 @@ impl AsValue for bool {
     }
 }
 
+impl AsValue for [u8] {
+    #[inline]
+    fn as_value(&self) -> Value<'_> {
+        Value::Bytes(Cow::Borrowed(self))
+    }
+}
+
 impl AsValue for str {
     #[inline]
     fn as_value(&self) -> Value<'_> {
@@ -274,6 +333,18 @@ where
     }
 }
 
+impl AsValue for Value<'_> {
+    #[inline]
+    fn as_value(&self) -> Value<'_> {
+        // NOTE: Borrow rather than clone any owned data.
+        match self {
+            Value::Bytes(b) => Value::Bytes(Cow::Borrowed(b.as_ref())),
+            Value::String(s) => Value::String(Cow::Borrowed(s.as_ref())),
+            v => v.clone(),
+        }
+    }
+}
+
 macro_rules! impl_as_value_float {
     ($ty:ty) => {
         impl AsValue for $ty {
@@ -374,6 +445,8 @@ pub trait Array {
     /// # Example
     ///
     /// ```
+    /// # #[cfg(feature = "std")]
+    /// # fn main() {
     /// use std::collections::HashSet;
     /// use tau_engine::{Array, Value};
     ///
@@ -383,6 +456,9 @@ pub trait Array {
     /// let mut value = Array::iter(&set);
     ///
     /// assert_eq!(value.next().is_some(), true);
+    /// # }
+    /// # #[cfg(not(feature = "std"))]
+    /// # fn main() {}
     /// ```
     fn iter(&self) -> Box<dyn Iterator<Item = Value<'_>> + '_>;
 
@@ -391,6 +467,8 @@ pub trait Array {
     /// # Example
     ///
     ///```
+    /// # #[cfg(feature = "std")]
+    /// # fn main() {
     /// use std::collections::HashSet;
     /// use tau_engine::{Array, Value};
     ///
@@ -400,6 +478,9 @@ pub trait Array {
     /// let len = Array::len(&set);
     ///
     /// assert_eq!(len, 1);
+    /// # }
+    /// # #[cfg(not(feature = "std"))]
+    /// # fn main() {}
     /// ```
     fn len(&self) -> usize;
 }
@@ -2,7 +2,7 @@
 
 use std::borrow::Cow;
 
-pub use serde_yaml::{Mapping, Number, Value as Yaml};
+pub use serde_yaml::{Mapping, Value as Yaml};
 
 use crate::value::{AsValue, Object, Value};
 
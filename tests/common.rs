@@ -1,31 +1,50 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use tau_engine::{Error, Rule};
 
-pub fn load_rule(name: &str) -> Result<Rule, Error> {
+// Returns the path of a rule within `tests/rules`, where the extension is optional.
+fn path(name: &str) -> PathBuf {
     let rule = if name.ends_with(".yml") {
         name.to_owned()
     } else {
         format!("{}.yml", name)
     };
     let root = env!("CARGO_MANIFEST_DIR");
-    let path = Path::new(root).join("tests/rules").join(rule);
-    Rule::loader().shake(false).load(&path)
+    Path::new(root).join("tests/rules").join(rule)
+}
+
+pub fn load_rule(name: &str) -> Result<Rule, Error> {
+    Rule::loader()
+        .fuzzy(true)
+        .glob(true)
+        .hex(true)
+        .range(true)
+        .shake(false)
+        .load(&path(name))
 }
 
 // NOTE: Warns about being dead event though it is not...
 #[allow(dead_code)]
 pub fn load_shaken_rule(name: &str) -> Result<Rule, Error> {
-    let rule = if name.ends_with(".yml") {
-        name.to_owned()
-    } else {
-        format!("{}.yml", name)
-    };
-    let root = env!("CARGO_MANIFEST_DIR");
-    let path = Path::new(root).join("tests/rules").join(rule);
     Rule::loader()
-        //.coalesce(true)
-        //.rewrite(true)
+        .coalesce(true)
+        .fuzzy(true)
+        .glob(true)
+        .hex(true)
+        .range(true)
+        .rewrite(true)
+        .shake(true)
+        .load(&path(name))
+}
+
+#[allow(dead_code)]
+pub fn load_shadowed_rule(name: &str) -> Result<Rule, Error> {
+    Rule::loader()
+        .fuzzy(true)
+        .glob(true)
+        .hex(true)
+        .range(true)
         .shake(true)
-        .load(&path)
+        .shadow(true)
+        .load(&path(name))
 }
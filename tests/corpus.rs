@@ -0,0 +1,59 @@
+#[path = "../benches/common/mod.rs"]
+mod bench;
+
+use tau_engine::Object;
+
+// Every loader configuration, and the compiled programs, must match exactly the same documents in
+// the benchmark corpus, otherwise the benchmark timings are meaningless.
+#[test]
+fn corpus_parity() {
+    let documents = bench::load_corpus();
+    let mut expected: Option<Vec<(usize, String)>> = None;
+    for (name, load) in bench::CONFIGURATIONS {
+        let rules = bench::load_corpus_rules(*load);
+        let mut found = vec![];
+        for (i, document) in documents.iter().enumerate() {
+            for id in rules.matches(document) {
+                found.push((i, id.to_owned()));
+            }
+        }
+        match &expected {
+            None => {
+                assert!(!found.is_empty(), "corpus matches no rules");
+                expected = Some(found);
+            }
+            Some(expected) => assert_eq!(expected, &found, "configuration '{}' differs", name),
+        }
+
+        let compiled: Vec<(usize, String)> = rules
+            .scan(documents.iter().map(|d| d as &dyn Object))
+            .map(|(i, id)| (i, id.to_owned()))
+            .collect();
+        assert_eq!(
+            expected.as_ref(),
+            Some(&compiled),
+            "configuration '{}' differs when compiled",
+            name
+        );
+    }
+}
+
+// The solver and compiled programs must agree with the reference evaluator, run over the
+// unoptimised rules, across the corpus for every loader configuration.
+#[test]
+fn corpus_differential() {
+    let documents = bench::load_corpus();
+    let (_, load) = bench::CONFIGURATIONS[0];
+    let reference = bench::load_corpus_rules(load);
+    for (name, load) in bench::CONFIGURATIONS {
+        let rules = bench::load_corpus_rules(*load);
+        let divergences =
+            rules.differential(&reference, documents.iter().map(|d| d as &dyn Object));
+        assert!(
+            divergences.is_empty(),
+            "configuration '{}' diverges: {:?}",
+            name,
+            divergences
+        );
+    }
+}
@@ -13,7 +13,14 @@ macro_rules! invalid_rule {
 }
 
 invalid_rule!("cast_int_nested");
+invalid_rule!("cast_len_invalid");
+invalid_rule!("cast_str_contains_invalid");
 invalid_rule!("cast_str_nested");
+invalid_rule!("extends_cycle");
 invalid_rule!("identifier_missing");
+invalid_rule!("keywords_counted");
+invalid_rule!("keywords_invalid");
 invalid_rule!("match_all_invalid");
 invalid_rule!("match_of_invalid");
+invalid_rule!("search_skeleton_invalid");
+invalid_rule!("search_wide_invalid");
